@@ -303,8 +303,11 @@ impl Game {
         event_number: usize,
         as_object: i32,
     ) -> gml::Result<()> {
-        self.exec_slice(&tree.borrow().0, this, other, event_type, event_number, as_object, false)?;
-        Ok(())
+        // Events can trigger each other without any code in between, so they count towards the call depth too
+        self.call_depth.enter()?;
+        let result = self.exec_slice(&tree.borrow().0, this, other, event_type, event_number, as_object, false);
+        self.call_depth.leave();
+        result.map(|_| ())
     }
 
     fn skip_actions(slice: &[Action]) -> usize {
@@ -2,12 +2,16 @@ use crate::{
     asset::Sprite,
     gml,
     render::{
-        atlas::{AtlasBuilder, AtlasRef},
+        atlas::{self, AtlasBuilder, AtlasRef},
         Renderer,
     },
 };
 use encoding_rs::Encoding;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[cfg(test)]
+pub mod testing;
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Font {
@@ -22,71 +26,471 @@ pub struct Font {
     pub tallest_char_height: u32,
     pub chars: Box<[Character]>,
     pub own_graphics: bool, // Does this Font own the graphics associated with it?
+    pub dirty: bool,        // Has this Font changed since the game was loaded?
+    /// Another font to borrow characters from when this one doesn't have them, such as a CJK font behind a game's
+    /// Latin-only one. Borrowed characters keep the fallback's metrics.
+    pub fallback: Option<Box<Font>>,
 }
 
-#[derive(Clone, Copy, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct Character {
     pub offset: i32,
     pub distance: i32,
+    pub yoffset: i32,
     pub atlas_ref: AtlasRef,
 }
 
+/// A font's metrics, for laying out text the way the emulator does without having the font itself.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FontMetrics {
+    pub first: u8,
+    pub last: u8,
+    /// The distance between the tops of two lines when no line height is given.
+    pub line_height: i32,
+    /// The distance from the top of a line to its baseline, and from the baseline to the bottom of the line.
+    /// Fonts don't store their baseline, so it's found the way `Font::baseline` does.
+    pub ascent: i32,
+    pub descent: i32,
+    /// How far the cursor moves past a character that isn't in `glyphs`, since it's drawn as a space.
+    pub missing_advance: i32,
+    /// Every character the font can draw, including those from its fallbacks, in byte order.
+    pub glyphs: Vec<GlyphMetrics>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GlyphMetrics {
+    /// The character's byte in the font's encoding.
+    pub index: u8,
+    pub advance: i32,
+    /// Where the glyph is drawn relative to the cursor.
+    pub distance: i32,
+    pub yoffset: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
 impl Font {
+    /// Gets a character by its byte in this font's encoding, from the fallback chain if this font doesn't have it.
+    /// Bytes only mean the same character in fonts with the same encoding, so other fallbacks are skipped.
     pub fn get_char(&self, index: u8) -> Option<Character> {
+        self.own_char(index).or_else(|| {
+            let encoding = self.get_encoding(encoding_rs::WINDOWS_1252);
+            let mut fallback = self.fallback.as_deref();
+            while let Some(font) = fallback {
+                if font.get_encoding(encoding_rs::WINDOWS_1252) == encoding {
+                    return font.get_char(index)
+                }
+                fallback = font.fallback.as_deref();
+            }
+            None
+        })
+    }
+
+    /// Gets a character by its Unicode value, from the fallback chain if this font doesn't have it.
+    /// Fonts are indexed by byte, so only characters that encode to a single byte in a font's encoding can be in it.
+    pub fn get_char_wide(&self, c: char) -> Option<Character> {
+        let mut buf = [0; 4];
+        let (bytes, _, unmappable) = self.get_encoding(encoding_rs::WINDOWS_1252).encode(c.encode_utf8(&mut buf));
+        let own = match *bytes {
+            [byte] if !unmappable => self.own_char(byte),
+            _ => None,
+        };
+        own.or_else(|| self.fallback.as_ref()?.get_char_wide(c))
+    }
+
+    /// Uploads the glyphs of this font and its fallbacks if they haven't been yet. They're only uploaded once
+    /// they're needed, so this has to be done before anything draws them or reads them back.
+    pub fn upload_glyphs(&self, renderer: &mut Renderer) {
+        let mut font = Some(self);
+        while let Some(f) = font {
+            for character in f.chars.iter() {
+                if let Err(e) = renderer.upload_deferred(&character.atlas_ref) {
+                    eprintln!("Warning: failed to upload font {}: {}", f.name, e);
+                    return
+                }
+            }
+            font = f.fallback.as_deref();
+        }
+    }
+
+    fn own_char(&self, index: u8) -> Option<Character> {
         if let Some(index) = index.checked_sub(self.first) { self.chars.get(index as usize).copied() } else { None }
     }
 
-    pub fn get_encoding(&self, default: &'static Encoding) -> &'static Encoding {
-        match self.charset {
-            0x00 => encoding_rs::WINDOWS_1252, // ANSI_CHARSET
-            0x80 => encoding_rs::SHIFT_JIS,    // SHIFTJIS_CHARSET
-            0x81 => encoding_rs::EUC_KR,       // HANGUL_CHARSET
-            0x82 => default,                   // JOHAB_CHARSET
-            0x86 => encoding_rs::GBK,          // GB2312_CHARSET
-            0x88 => encoding_rs::BIG5,         // CHINESEBIG5_CHARSET
-            0xA1 => encoding_rs::WINDOWS_1253, // GREEK_CHARSET
-            0xA2 => encoding_rs::WINDOWS_1254, // TURKISH_CHARSET
-            0xA3 => encoding_rs::WINDOWS_1258, // VIETNAMESE_CHARSET
-            0xB1 => encoding_rs::WINDOWS_1255, // HEBREW_CHARSET
-            0xB2 => encoding_rs::WINDOWS_1256, // ARABIC_CHARSET
-            0xBA => encoding_rs::WINDOWS_1257, // BALTIC_CHARSET
-            0xCC => encoding_rs::WINDOWS_1251, // RUSSIAN_CHARSET
-            0xDE => encoding_rs::WINDOWS_874,  // THAI_CHARSET
-            0xEE => encoding_rs::WINDOWS_1250, // EASTEUROPE_CHARSET
-            _ => default,
+    /// Gets how far the cursor moves past a character. Characters not in the font are drawn as spaces.
+    pub fn advance(&self, index: u8) -> i32 {
+        match self.get_char(index).or_else(|| self.get_char(self.first)) {
+            Some(character) => character.offset,
+            None => 0,
+        }
+    }
+
+    /// Gets the width of a single line of text.
+    pub fn measure(&self, text: &[u8]) -> i32 {
+        text.iter().map(|&c| self.advance(c)).sum()
+    }
+
+    /// Gets the x position of each character in a single line of text, followed by the line's full width,
+    /// so the position of any cursor index can be looked up directly.
+    pub fn prefix_widths(&self, text: &[u8]) -> Vec<i32> {
+        let mut widths = Vec::with_capacity(text.len() + 1);
+        let mut x = 0;
+        widths.push(x);
+        for &c in text {
+            x += self.advance(c);
+            widths.push(x);
+        }
+        widths
+    }
+
+    /// Gets the box around the pixels a single line of text actually covers, relative to where it's drawn, as
+    /// (left, top, right, bottom) with the right and bottom edges exclusive. Unlike `measure`, which only adds up
+    /// advances, this includes everything that hangs outside a glyph's cell, such as italic overhangs and descenders.
+    /// Text with nothing visible in it gives (0, 0, 0, 0).
+    pub fn ink_bounds(&self, text: &[u8], renderer: &mut Renderer) -> (i32, i32, i32, i32) {
+        self.upload_glyphs(renderer);
+        self.ink_bounds_with(text, |refs| renderer.dump_sprites(refs))
+    }
+
+    /// Does the same as ink_bounds, reading back the pixels of all the glyphs used with the given function.
+    pub fn ink_bounds_with(
+        &self,
+        text: &[u8],
+        dump: impl FnOnce(&[AtlasRef]) -> Vec<Box<[u8]>>,
+    ) -> (i32, i32, i32, i32) {
+        let mut glyphs = Vec::with_capacity(text.len());
+        let mut refs = Vec::new();
+        let mut x = 0;
+        for &c in text {
+            if let Some(character) = self.get_char(c) {
+                let index = refs.iter().position(|r| *r == character.atlas_ref).unwrap_or_else(|| {
+                    refs.push(character.atlas_ref);
+                    refs.len() - 1
+                });
+                glyphs.push((x, character, index));
+            }
+            x += self.advance(c);
+        }
+        let bounds = dump(&refs)
+            .iter()
+            .zip(&refs)
+            .map(|(data, r)| content_bounds(data, r.width() as u32, r.height() as u32, None))
+            .collect::<Vec<_>>();
+        glyphs
+            .into_iter()
+            .filter_map(|(x, c, index)| {
+                let b = bounds[index]?;
+                let (left, top) = (x + c.distance, c.yoffset);
+                Some((left + b.left as i32, top + b.top as i32, left + b.right as i32 + 1, top + b.bottom as i32 + 1))
+            })
+            .fold(None, |acc: Option<(i32, i32, i32, i32)>, (x0, y0, x1, y1)| match acc {
+                Some((ax0, ay0, ax1, ay1)) => Some((ax0.min(x0), ay0.min(y0), ax1.max(x1), ay1.max(y1))),
+                None => Some((x0, y0, x1, y1)),
+            })
+            .unwrap_or((0, 0, 0, 0))
+    }
+
+    /// Gets how far below the top of a line its baseline is, found from the bottom of the ink of characters which
+    /// sit on it. Fonts with none of those visible, such as most sprite fonts, use four fifths of a line.
+    pub fn baseline(&self, renderer: &mut Renderer) -> i32 {
+        self.upload_glyphs(renderer);
+        self.baseline_with(|refs| renderer.dump_sprites(refs))
+    }
+
+    /// Does the same as baseline, reading back the pixels of the glyphs used with the given function.
+    pub fn baseline_with(&self, dump: impl FnOnce(&[AtlasRef]) -> Vec<Box<[u8]>>) -> i32 {
+        match self.ink_bounds_with(b"HIMNxz", dump) {
+            (_, _, _, bottom) if bottom > 0 => bottom,
+            _ => self.tallest_char_height as i32 * 4 / 5,
+        }
+    }
+
+    /// Gets the outlines of a character's glyph, for exporting text as vector graphics. These are a trace of the
+    /// glyph's bitmap, not the outlines of the font it was rasterized from: GM8 only keeps fonts as pixels, so this
+    /// follows the edges of the pixels which are at least half covered, and comes out as blocky as the glyph is.
+    /// They're offset to where the glyph is drawn. Sprite fonts are pictures rather than text, so they and characters
+    /// the font doesn't have give None.
+    pub fn glyph_outline(&self, c: char, renderer: &mut Renderer) -> Option<Vec<Contour>> {
+        self.upload_glyphs(renderer);
+        self.glyph_outline_with(c, |atlas_ref| renderer.dump_sprite(atlas_ref))
+    }
+
+    /// Does the same as glyph_outline, reading back the glyph's pixels with the given function.
+    pub fn glyph_outline_with(&self, c: char, dump: impl FnOnce(&AtlasRef) -> Box<[u8]>) -> Option<Vec<Contour>> {
+        if !self.own_graphics {
+            return None
+        }
+        let character = self.get_char_wide(c)?;
+        let (width, height) = (character.atlas_ref.width() as u32, character.atlas_ref.height() as u32);
+        let mut contours = trace_outlines(&dump(&character.atlas_ref), width, height);
+        for contour in contours.iter_mut() {
+            contour.0.iter_mut().for_each(|(x, y)| {
+                *x += character.distance;
+                *y += character.yoffset;
+            });
+        }
+        Some(contours)
+    }
+
+    /// Width of the box drawn in place of a missing character, when that's enabled.
+    pub fn tofu_width(&self) -> i32 {
+        (self.tallest_char_height as i32 / 2).max(3)
+    }
+
+    /// Replaces the character at the given index, returning the previous one.
+    /// Every other character, and the atlas region it refers to, is left untouched.
+    pub fn set_char(&mut self, index: u8, character: Character) -> Option<Character> {
+        let slot = self.chars.get_mut(index.checked_sub(self.first)? as usize)?;
+        self.dirty = true;
+        Some(std::mem::replace(slot, character))
+    }
+
+    /// Replaces the pixel data of a single glyph without rebuilding the rest of the font.
+    /// If the font owns its graphics and the new glyph is the same size, its atlas region is overwritten in place,
+    /// unless `shared` says another glyph is drawn from it too, as it is for identical fonts and fallbacks.
+    /// Otherwise only this glyph is reallocated. Returns the old region if the font owned it and no longer draws
+    /// from it, to be given to `graphics_to_free`.
+    pub fn update_glyph(
+        &mut self,
+        index: u8,
+        offset: i32,
+        distance: i32,
+        width: i32,
+        height: i32,
+        data: Box<[u8]>,
+        shared: bool,
+        renderer: &mut Renderer,
+    ) -> Result<Option<AtlasRef>, String> {
+        let store = |target: Option<AtlasRef>, data: Box<[u8]>| -> Result<AtlasRef, String> {
+            match target {
+                Some(atlas_ref) => {
+                    // otherwise the glyph's old pixels would be uploaded over the new ones when it's first drawn
+                    renderer.upload_deferred(&atlas_ref)?;
+                    renderer.update_sprite(&atlas_ref, data)?;
+                    Ok(atlas_ref)
+                },
+                None => renderer.upload_sprite(data, width, height, 0, 0),
+            }
+        };
+        self.update_glyph_with(index, offset, distance, width, height, data, shared, store)
+    }
+
+    /// Does the same as update_glyph, storing the pixels with the given function, which is passed the region to
+    /// overwrite or None to allocate a new one, and returns where they went.
+    pub fn update_glyph_with(
+        &mut self,
+        index: u8,
+        offset: i32,
+        distance: i32,
+        width: i32,
+        height: i32,
+        data: Box<[u8]>,
+        shared: bool,
+        store: impl FnOnce(Option<AtlasRef>, Box<[u8]>) -> Result<AtlasRef, String>,
+    ) -> Result<Option<AtlasRef>, String> {
+        let old = self.own_char(index).ok_or_else(|| format!("Character {} is not in font", index))?;
+        let in_place =
+            self.own_graphics && !shared && old.atlas_ref.width() == width && old.atlas_ref.height() == height;
+        let atlas_ref = store(if in_place { Some(old.atlas_ref) } else { None }, data)?;
+        self.set_char(index, Character { offset, distance, atlas_ref, ..old });
+        Ok(if self.own_graphics && atlas_ref != old.atlas_ref { Some(old.atlas_ref) } else { None })
+    }
+
+    /// Finds pairs of characters whose glyphs overlap in their atlas, which means the packer went wrong.
+    /// Characters sharing the exact same region, as sprite fonts can, aren't counted.
+    pub fn overlapping_chars(&self) -> Vec<(u8, u8)> {
+        let refs = self.chars.iter().map(|c| c.atlas_ref).collect::<Vec<_>>();
+        let index = |i: usize| (usize::from(self.first) + i) as u8;
+        atlas::overlapping_regions(&refs).into_iter().map(|(a, b)| (index(a), index(b))).collect()
+    }
+
+    /// Gets the atlas regions this font and its fallbacks own, one per sprite, which are the ones that may need
+    /// freeing when it's deleted or replaced. Fonts which don't own their graphics share them with a sprite, so
+    /// nothing of theirs may be freed.
+    pub fn owned_graphics(&self) -> Vec<AtlasRef> {
+        let mut refs = Vec::<AtlasRef>::new();
+        for font in self.with_fallbacks().filter(|f| f.own_graphics) {
+            for c in font.chars.iter() {
+                if !refs.iter().any(|r| r.sprite_id == c.atlas_ref.sprite_id) {
+                    refs.push(c.atlas_ref);
+                }
+            }
+        }
+        refs
+    }
+
+    /// Gets this font followed by its fallback chain, in the order characters are looked for in them.
+    pub fn with_fallbacks(&self) -> impl Iterator<Item = &Font> {
+        std::iter::successors(Some(self), |font| font.fallback.as_deref())
+    }
+
+    /// Checks whether any glyph of this font or its fallbacks is drawn from the given sprite.
+    fn uses_sprite(&self, sprite_id: i32) -> bool {
+        self.with_fallbacks().any(|font| font.chars.iter().any(|c| c.atlas_ref.sprite_id == sprite_id))
+    }
+
+    /// Gets whether this font has the glyphs a system font with the given parameters would, so a font made with them
+    /// can share this one's glyphs rather than rasterizing the same ones again. Sprite fonts never match.
+    pub fn has_glyphs_for(&self, sys_name: &[u8], size: u32, bold: bool, italic: bool, range: (u8, u8)) -> bool {
+        self.own_graphics
+            && self.sys_name.as_ref() == sys_name
+            && (self.size, self.bold, self.italic, self.first, self.last) == (size, bold, italic, range.0, range.1)
+    }
+
+    /// Replaces everything about this font with another font, as font_replace does, so any handle to it now gets the
+    /// new glyphs. The name and fallback are kept. Returns the atlas regions the old font owned, to be given to
+    /// `graphics_to_free`.
+    pub fn replace(&mut self, new: Font) -> Vec<AtlasRef> {
+        let old_graphics = self.owned_graphics();
+        let (name, fallback) = (self.name.clone(), self.fallback.take());
+        *self = Font { name, fallback, dirty: true, ..new };
+        old_graphics
+    }
+
+    /// Gets the position of the centre of an underline below the top of a line, given where the baseline is, as from
+    /// `baseline`. It goes a third of the way into the space below the baseline, so fonts with longer descenders get
+    /// their underline further down, but always stays inside the line.
+    pub fn underline_position(&self, baseline: i32) -> i32 {
+        let descent = (self.tallest_char_height as i32 - baseline).max(0);
+        let bottom = (self.tallest_char_height as i32 - 1).max(baseline);
+        (baseline + (descent / 3).max(self.decoration_thickness())).min(bottom)
+    }
+
+    /// Gets the position of the centre of a strikethrough below the top of a line, given where the baseline is.
+    /// It's three tenths of the ascent above the baseline, which is about halfway up a lowercase letter.
+    pub fn strikethrough_position(&self, baseline: i32) -> i32 {
+        baseline - baseline * 3 / 10
+    }
+
+    /// Gets the thickness of an underline or strikethrough.
+    pub fn decoration_thickness(&self) -> i32 {
+        (self.tallest_char_height as i32 / 16).max(1)
+    }
+
+    /// Gets the metrics of every character this font can draw, along with its line metrics.
+    pub fn export_metrics(&self, renderer: &mut Renderer) -> FontMetrics {
+        self.upload_glyphs(renderer);
+        self.export_metrics_with(|refs| renderer.dump_sprites(refs))
+    }
+
+    /// Does the same as export_metrics, reading back the pixels of the glyphs used with the given function.
+    pub fn export_metrics_with(&self, dump: impl FnOnce(&[AtlasRef]) -> Vec<Box<[u8]>>) -> FontMetrics {
+        let line_height = self.tallest_char_height as i32;
+        let ascent = self.baseline_with(dump).min(line_height);
+        let glyphs = (0..=u8::MAX)
+            .filter_map(|index| {
+                let c = self.get_char(index)?;
+                let (width, height) = (c.atlas_ref.width(), c.atlas_ref.height());
+                Some(GlyphMetrics { index, advance: c.offset, distance: c.distance, yoffset: c.yoffset, width, height })
+            })
+            .collect();
+        FontMetrics {
+            first: self.first,
+            last: self.last,
+            line_height,
+            ascent,
+            descent: line_height - ascent,
+            missing_advance: self.advance(self.first),
+            glyphs,
+        }
+    }
+
+    /// Checks that the font's characters match its range and that every glyph points into a live atlas.
+    /// This catches fonts which don't belong to the current renderer, such as from a mismatched savestate.
+    pub fn validate(&self, renderer: &Renderer) -> Result<(), String> {
+        self.validate_with(|atlas_id| renderer.atlas_size(atlas_id))
+    }
+
+    /// Does the same as validate, getting the size of each atlas with the given function.
+    pub fn validate_with(&self, atlas_size: impl Fn(u32) -> Option<(i32, i32)>) -> Result<(), String> {
+        let expected = (usize::from(self.last) + 1).saturating_sub(usize::from(self.first));
+        if self.chars.len() != expected {
+            return Err(format!(
+                "Font {} has {} characters, but its range {}-{} needs {}",
+                self.name,
+                self.chars.len(),
+                self.first,
+                self.last,
+                expected
+            ))
+        }
+        for (i, c) in self.chars.iter().enumerate() {
+            let index = usize::from(self.first) + i;
+            match atlas_size(c.atlas_ref.atlas_id()) {
+                Some((width, height)) if c.atlas_ref.fits_in(width, height) => (),
+                Some(_) => return Err(format!("Character {} of font {} is outside its atlas", index, self.name)),
+                None => return Err(format!("Character {} of font {} is in a nonexistent atlas", index, self.name)),
+            }
         }
+        Ok(())
+    }
+
+    // Properties as returned by the font_get_* functions
+    pub fn gml_name(&self) -> gml::Value {
+        self.name.clone().into()
+    }
+
+    pub fn gml_fontname(&self) -> gml::Value {
+        self.sys_name.clone().into()
+    }
+
+    pub fn gml_size(&self) -> gml::Value {
+        self.size.into()
+    }
+
+    pub fn gml_bold(&self) -> gml::Value {
+        self.bold.into()
+    }
+
+    pub fn gml_italic(&self) -> gml::Value {
+        self.italic.into()
+    }
+
+    pub fn gml_first(&self) -> gml::Value {
+        self.first.into()
+    }
+
+    pub fn gml_last(&self) -> gml::Value {
+        self.last.into()
+    }
+
+    pub fn get_encoding(&self, default: &'static Encoding) -> &'static Encoding {
+        charset_encoding(self.charset, default)
+    }
+}
+
+/// Gets the text encoding for a Windows charset ID, as used by fonts.
+pub fn charset_encoding(charset: u32, default: &'static Encoding) -> &'static Encoding {
+    match charset {
+        0x00 => encoding_rs::WINDOWS_1252, // ANSI_CHARSET
+        0x80 => encoding_rs::SHIFT_JIS,    // SHIFTJIS_CHARSET
+        0x81 => encoding_rs::EUC_KR,       // HANGUL_CHARSET
+        0x82 => default,                   // JOHAB_CHARSET
+        0x86 => encoding_rs::GBK,          // GB2312_CHARSET
+        0x88 => encoding_rs::BIG5,         // CHINESEBIG5_CHARSET
+        0xA1 => encoding_rs::WINDOWS_1253, // GREEK_CHARSET
+        0xA2 => encoding_rs::WINDOWS_1254, // TURKISH_CHARSET
+        0xA3 => encoding_rs::WINDOWS_1258, // VIETNAMESE_CHARSET
+        0xB1 => encoding_rs::WINDOWS_1255, // HEBREW_CHARSET
+        0xB2 => encoding_rs::WINDOWS_1256, // ARABIC_CHARSET
+        0xBA => encoding_rs::WINDOWS_1257, // BALTIC_CHARSET
+        0xCC => encoding_rs::WINDOWS_1251, // RUSSIAN_CHARSET
+        0xDE => encoding_rs::WINDOWS_874,  // THAI_CHARSET
+        0xEE => encoding_rs::WINDOWS_1250, // EASTEUROPE_CHARSET
+        _ => default,
     }
 }
 
 pub fn load_default_font(atlases: &mut AtlasBuilder) -> Result<Font, String> {
-    // In GM8, the default font is Arial at size 12, but Arial is nonfree,
-    // so we instead went for a free alternative called Arimo, under Apache 2.0. https://fonts.google.com/specimen/Arimo
-    // arimo.dat was generated by importing Arimo into GM8 and exporting the resulting font data.
-    // The `offset` field was tweaked to be closer to Arial's.
-    let data = include_bytes!("../../data/arimo.dat");
     let mut chars = Vec::with_capacity(0x60);
     let mut tallest_char_height = 0;
-    let mut cursor = 0;
-    for _ in 0..0x60 {
-        let offset = data[cursor] as i8 as i32;
-        let distance = data[cursor + 1] as i8 as i32;
-        let width = data[cursor + 2] as u32;
-        let height = data[cursor + 3] as u32;
-        cursor += 4;
+    for (offset, distance, width, height, alpha) in default_glyphs() {
         if height > tallest_char_height {
             tallest_char_height = height;
         }
-        let size = (width * height) as usize;
-        let mut char = Vec::with_capacity(size * 4);
-        for i in 0..size {
-            char.extend_from_slice(&[0xFF, 0xFF, 0xFF]);
-            char.push(data[cursor + i]);
-        }
-        cursor += size;
-        let atlas_ref = atlases
-            .texture(width as _, height as _, 0, 0, char.into_boxed_slice())
-            .ok_or("Couldn't pack default font")?;
-        chars.push(Character { offset, distance, atlas_ref });
+        let atlas_ref =
+            atlases.texture(width as _, height as _, 0, 0, glyph_rgba(alpha)).ok_or("Couldn't pack default font")?;
+        chars.push(Character { offset, distance, yoffset: 0, atlas_ref });
     }
     Ok(Font {
         name: b"default_font".as_ref().into(),
@@ -100,32 +504,1135 @@ pub fn load_default_font(atlases: &mut AtlasBuilder) -> Result<Font, String> {
         tallest_char_height,
         chars: chars.into_boxed_slice(),
         own_graphics: true,
+        dirty: false,
+        fallback: None,
     })
 }
 
-pub fn create_chars_from_sprite(sprite: &Sprite, prop: bool, sep: i32, renderer: &Renderer) -> Box<[Character]> {
-    let mut chars = Vec::with_capacity(sprite.frames.len());
-    if prop {
-        // proportional font, get the left and right bounds of each character
-        for frame in &sprite.frames {
-            let data = renderer.dump_sprite(&frame.atlas_ref);
-            let column_empty =
-                |&x: &u32| (0..sprite.height).any(|y| data[(y * sprite.width + x) as usize * 4 + 3] != 0);
-            let left_edge = (0..sprite.width).find(column_empty).map(|x| x as i32).unwrap_or(sprite.width as i32 - 1);
-            let right_edge = (0..sprite.width).rfind(column_empty).unwrap_or(0) as i32;
-            chars.push(Character {
-                offset: right_edge + sep - left_edge,
-                distance: -left_edge,
-                atlas_ref: frame.atlas_ref.clone(),
-            });
+/// Reads the glyphs of the default font, characters 0x20 to 0x7f, as (offset, distance, width, height, alpha).
+fn default_glyphs() -> impl Iterator<Item = (i32, i32, u32, u32, &'static [u8])> {
+    // In GM8, the default font is Arial at size 12, but Arial is nonfree,
+    // so we instead went for a free alternative called Arimo, under Apache 2.0. https://fonts.google.com/specimen/Arimo
+    // arimo.dat was generated by importing Arimo into GM8 and exporting the resulting font data.
+    // The `offset` field was tweaked to be closer to Arial's.
+    let data: &'static [u8] = include_bytes!("../../data/arimo.dat");
+    let mut cursor = 0;
+    (0..0x60).map(move |_| {
+        let offset = data[cursor] as i8 as i32;
+        let distance = data[cursor + 1] as i8 as i32;
+        let width = data[cursor + 2] as u32;
+        let height = data[cursor + 3] as u32;
+        cursor += 4;
+        let alpha = &data[cursor..cursor + (width * height) as usize];
+        cursor += alpha.len();
+        (offset, distance, width, height, alpha)
+    })
+}
+
+/// Turns a glyph's alpha into white RGBA data.
+fn glyph_rgba(alpha: &[u8]) -> Box<[u8]> {
+    let mut data = Vec::with_capacity(alpha.len() * 4);
+    for &a in alpha {
+        data.extend_from_slice(&[0xFF, 0xFF, 0xFF, a]);
+    }
+    data.into_boxed_slice()
+}
+
+/// Makes a font for a system font, as font_add and font_replace ask for, by rasterizing the default font's glyphs at
+/// the requested size and style. Arimo is the only face there is to rasterize, so asking for any other than it or
+/// Arial, which it stands in for, is an error rather than a font that silently looks like neither.
+/// The default font's glyphs are GM8's own rendering at its size, so they're used as they are at that size. At any
+/// other size they're resampled, then hinted to make up for the edges resampling blurs. Bold glyphs are dilated by a
+/// pixel, as `weight_glyph` does, and italic ones are slanted. The range of characters is cut down to the ones the
+/// default font has. The glyphs are uploaded all at once, so they can go in a single texture rather than one each.
+pub fn substitute_font(
+    name: gml::String,
+    size: u32,
+    bold: bool,
+    italic: bool,
+    (first, last): (u8, u8),
+    default: &Font,
+    upload: impl FnOnce(Vec<(i32, i32, Box<[u8]>)>) -> Result<Vec<AtlasRef>, String>,
+) -> Result<Font, String> {
+    let face = name.as_ref();
+    if !face.eq_ignore_ascii_case(default.sys_name.as_ref()) && !face.eq_ignore_ascii_case(b"Arial") {
+        return Err(format!("Font {} isn't available, only {} is", name, default.sys_name))
+    }
+    if size == 0 {
+        return Err("Font size must be at least 1".into())
+    }
+    let (first, last) = (first.max(default.first), last.min(default.last));
+    if first > last {
+        return Err("No characters in range".into())
+    }
+    let scale = f64::from(size) / f64::from(default.size);
+    let glyphs = default_glyphs()
+        .skip(usize::from(first - default.first))
+        .take(usize::from(last - first) + 1)
+        .map(|glyph| rasterize_glyph(glyph, scale, bold, italic))
+        .collect::<Vec<_>>();
+    let refs = upload(glyphs.iter().map(|g| (g.width as i32, g.height as i32, glyph_rgba(&g.alpha))).collect())?;
+    let chars = glyphs
+        .iter()
+        .zip(refs)
+        .map(|(g, atlas_ref)| Character { offset: g.advance, distance: g.bearing, yoffset: g.yoffset, atlas_ref })
+        .collect::<Box<_>>();
+    Ok(Font {
+        name: "".into(),
+        sys_name: name,
+        charset: default.charset,
+        size,
+        bold,
+        italic,
+        first,
+        last,
+        tallest_char_height: glyphs.iter().map(|g| g.cell_height).max().unwrap_or(0),
+        chars,
+        own_graphics: true,
+        dirty: true,
+        fallback: None,
+    })
+}
+
+/// How far italic glyphs lean, in pixels across for every pixel up.
+const ITALIC_SLANT: f64 = 0.2;
+
+/// A glyph made by `rasterize_glyph`, with where to draw it relative to the cursor.
+struct RasterGlyph {
+    alpha: Vec<u8>,
+    width: u32,
+    height: u32,
+    advance: i32,
+    bearing: i32,
+    yoffset: i32,
+    /// The height of the glyph's cell before any padding for boldness, for the font's line height.
+    cell_height: u32,
+}
+
+/// Rasterizes one of the default font's glyphs at the given scale and style, as `substitute_font` describes.
+fn rasterize_glyph(
+    (advance, bearing, width, height, alpha): (i32, i32, u32, u32, &[u8]),
+    scale: f64,
+    bold: bool,
+    italic: bool,
+) -> RasterGlyph {
+    let mut glyph =
+        RasterGlyph { alpha: alpha.to_vec(), width, height, advance, bearing, yoffset: 0, cell_height: height };
+    if (scale - 1.0).abs() > f64::EPSILON {
+        glyph.width = (f64::from(width) * scale).round() as u32;
+        glyph.height = (f64::from(height) * scale).round() as u32;
+        glyph.cell_height = glyph.height;
+        glyph.alpha = resample(alpha, width, height, glyph.width, glyph.height);
+        let (advance, bearing) = hint_glyph(f64::from(advance) * scale, f64::from(bearing) * scale, &mut glyph.alpha);
+        glyph.advance = advance;
+        glyph.bearing = bearing;
+    }
+    if italic {
+        let (alpha, width) = shear(&glyph.alpha, glyph.width, glyph.height, ITALIC_SLANT);
+        glyph.alpha = alpha;
+        glyph.width = width;
+    }
+    if bold {
+        let weighted = weight_glyph(&glyph.alpha, glyph.width, glyph.height, 1);
+        glyph.alpha = weighted.alpha;
+        glyph.width = weighted.width;
+        glyph.height = weighted.height;
+        glyph.advance += 1;
+        glyph.bearing -= weighted.pad as i32;
+        glyph.yoffset -= weighted.pad as i32;
+    }
+    glyph
+}
+
+/// Resizes a glyph's alpha, averaging the area of the old pixels each new pixel covers.
+fn resample(alpha: &[u8], width: u32, height: u32, new_width: u32, new_height: u32) -> Vec<u8> {
+    if width == 0 || height == 0 || new_width == 0 || new_height == 0 {
+        return vec![0; (new_width * new_height) as usize]
+    }
+    // resamples `len` values, read with `get`, to `new_len` of them
+    fn line(get: impl Fn(usize) -> f64, len: u32, new_len: u32) -> Vec<f64> {
+        let step = f64::from(len) / f64::from(new_len);
+        (0..new_len)
+            .map(|i| {
+                let (start, end) = (f64::from(i) * step, f64::from(i + 1) * step);
+                let mut sum = 0.0;
+                let mut p = start.floor() as usize;
+                while (p as f64) < end && p < len as usize {
+                    sum += get(p) * (end.min(p as f64 + 1.0) - start.max(p as f64));
+                    p += 1;
+                }
+                sum / step
+            })
+            .collect()
+    }
+    let (w, nw) = (width as usize, new_width as usize);
+    let rows =
+        (0..height as usize).flat_map(|y| line(|x| f64::from(alpha[y * w + x]), width, new_width)).collect::<Vec<_>>();
+    let columns = (0..nw).map(|x| line(|y| rows[y * nw + x], height, new_height)).collect::<Vec<_>>();
+    (0..new_height as usize * nw).map(|i| columns[i % nw][i / nw].round().min(255.0) as u8).collect()
+}
+
+/// Slants a glyph's alpha to the right by the given number of pixels across for every pixel up, keeping the bottom
+/// row where it is. Returns the new alpha and width, which grows so none of the glyph is cut off.
+fn shear(alpha: &[u8], width: u32, height: u32, slant: f64) -> (Vec<u8>, u32) {
+    let new_width = width + (f64::from(height.saturating_sub(1)) * slant).ceil() as u32;
+    let (w, nw) = (width as usize, new_width as usize);
+    let mut out = vec![0.0f64; nw * height as usize];
+    for y in 0..height as usize {
+        let shift = (height as usize - 1 - y) as f64 * slant;
+        let (whole, frac) = (shift.floor() as usize, shift.fract());
+        for x in 0..w {
+            let a = f64::from(alpha[y * w + x]);
+            out[y * nw + x + whole] += a * (1.0 - frac);
+            if frac > 0.0 {
+                out[y * nw + x + whole + 1] += a * frac;
+            }
+        }
+    }
+    (out.into_iter().map(|a| a.round().min(255.0) as u8).collect(), new_width)
+}
+
+/// Checks whether more than one glyph of the given fonts and their fallbacks is drawn from the given region, in which
+/// case overwriting it would change all of them.
+pub fn region_shared(fonts: &[Option<Box<Font>>], atlas_ref: &AtlasRef) -> bool {
+    fonts
+        .iter()
+        .flatten()
+        .flat_map(|font| font.with_fallbacks())
+        .flat_map(|font| font.chars.iter())
+        .filter(|c| c.atlas_ref == *atlas_ref)
+        .nth(1)
+        .is_some()
+}
+
+/// Narrows down the `owned_graphics` of a font which has just been deleted or replaced, or the region a glyph was
+/// moved out of by `update_glyph`, to those no font uses any more, which are the ones to free. Fonts made with
+/// identical parameters share their glyphs, and fallbacks share theirs with the fonts they were copied from, so those
+/// are only freed along with the last font using them.
+pub fn graphics_to_free(fonts: &[Option<Box<Font>>], mut refs: Vec<AtlasRef>) -> Vec<AtlasRef> {
+    for font in fonts.iter().flatten() {
+        refs.retain(|r| !font.uses_sprite(r.sprite_id));
+    }
+    refs
+}
+
+/// Alpha level treated as the edge of a glyph when hinting.
+pub const HINT_THRESHOLD: u8 = 0x80;
+
+/// Light hinting for rasterized glyphs, to match the crispness of GDI output at small sizes.
+/// The advance and left bearing are snapped to whole pixels and returned as (offset, distance), and coverage
+/// near HINT_THRESHOLD is stretched so that edges land on fully opaque or fully transparent pixels.
+/// Sprite fonts must not be passed through this, as their pixels are exactly what the user drew.
+pub fn hint_glyph(advance: f64, bearing: f64, alpha: &mut [u8]) -> (i32, i32) {
+    const SOFTNESS: i32 = 0x30;
+    let (lo, hi) = (i32::from(HINT_THRESHOLD) - SOFTNESS, i32::from(HINT_THRESHOLD) + SOFTNESS);
+    for a in alpha.iter_mut() {
+        *a = match i32::from(*a) {
+            x if x <= lo => 0,
+            x if x >= hi => 0xFF,
+            x => ((x - lo) * 0xFF / (hi - lo)) as u8,
+        };
+    }
+    (advance.round() as i32, bearing.round() as i32)
+}
+
+/// A glyph's alpha after `weight_glyph`, with the padding it gained on each side.
+pub struct WeightedGlyph {
+    pub alpha: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+    pub pad: u32,
+}
+
+/// Makes a glyph bolder or lighter, for matching a game's intended weight when the exact font isn't available.
+/// Positive amounts dilate the alpha by that many pixels in every direction, padding the glyph by as much so none of
+/// it is cut off, and negative amounts erode it. 0 leaves the glyph as it is.
+pub fn weight_glyph(alpha: &[u8], width: u32, height: u32, amount: i32) -> WeightedGlyph {
+    let pad = amount.max(0) as u32;
+    let (w, h) = ((width + pad * 2) as usize, (height + pad * 2) as usize);
+    let mut out = vec![0u8; w * h];
+    for y in 0..height as usize {
+        let row = &alpha[y * width as usize..][..width as usize];
+        out[(y + pad as usize) * w + pad as usize..][..width as usize].copy_from_slice(row);
+    }
+    if amount != 0 {
+        let radius = amount.abs() as isize;
+        let pick = if amount > 0 { u8::max } else { u8::min };
+        // a square filter is a horizontal pass followed by a vertical one, with the outside of the glyph empty
+        let filter = |src: &[u8], step: usize, len: usize, at: usize, pos: usize| {
+            (-radius..=radius)
+                .map(|d| pos as isize + d)
+                .map(|p| if p < 0 || p >= len as isize { 0 } else { src[at + p as usize * step] })
+                .fold(if amount > 0 { 0 } else { 0xFF }, pick)
+        };
+        let rows = (0..w * h).map(|i| filter(&out, 1, w, i - i % w, i % w)).collect::<Vec<_>>();
+        out = (0..w * h).map(|i| filter(&rows, w, h, i % w, i / w)).collect();
+    }
+    WeightedGlyph { alpha: out, width: w as u32, height: h as u32, pad }
+}
+
+/// A closed outline, as the corners of a polygon in pixels. Outlines go clockwise around what's filled in, on screen,
+/// so those of holes, such as the inside of an 'O', go anticlockwise.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Contour(pub Vec<(i32, i32)>);
+
+impl Contour {
+    /// Checks whether this outlines a hole, from which way round it goes.
+    pub fn is_hole(&self) -> bool {
+        let points = &self.0;
+        let doubled_area = points
+            .iter()
+            .zip(points.iter().cycle().skip(1))
+            .map(|(&(x1, y1), &(x2, y2))| i64::from(x1) * i64::from(y2) - i64::from(x2) * i64::from(y1))
+            .sum::<i64>();
+        doubled_area < 0
+    }
+
+    /// Writes this outline as SVG path data, moved along by the given offset.
+    pub fn svg_path(&self, x: i32, y: i32) -> String {
+        let mut path = String::new();
+        for (i, &(px, py)) in self.0.iter().enumerate() {
+            path += &format!("{}{} {} ", if i == 0 { 'M' } else { 'L' }, px + x, py + y);
+        }
+        path + "Z"
+    }
+}
+
+/// Traces the outlines of the parts of an RGBA frame with at least HINT_THRESHOLD alpha, along the edges of its
+/// pixels. Pixels which only touch at a corner aren't joined. Outlines are merged into as few corners as possible.
+pub fn trace_outlines(data: &[u8], width: u32, height: u32) -> Vec<Contour> {
+    let (w, h) = (width as i32, height as i32);
+    let filled = |x: i32, y: i32| {
+        x >= 0 && y >= 0 && x < w && y < h && data[(y * w + x) as usize * 4 + 3] >= HINT_THRESHOLD
+    };
+    // every edge between a filled and an empty pixel, from one corner to the next going clockwise
+    let mut edges: HashMap<(i32, i32), Vec<(i32, i32)>> = HashMap::new();
+    let mut starts = Vec::new();
+    for (x, y) in (0..h).flat_map(|y| (0..w).map(move |x| (x, y))).filter(|&(x, y)| filled(x, y)) {
+        let sides = [
+            (!filled(x, y - 1), (x, y), (x + 1, y)),
+            (!filled(x + 1, y), (x + 1, y), (x + 1, y + 1)),
+            (!filled(x, y + 1), (x + 1, y + 1), (x, y + 1)),
+            (!filled(x - 1, y), (x, y + 1), (x, y)),
+        ];
+        for &(_, from, to) in sides.iter().filter(|(open, ..)| *open) {
+            edges.entry(from).or_default().push(to);
+            starts.push(from);
+        }
+    }
+
+    let mut contours = Vec::new();
+    for start in starts {
+        while let Some(mut to) = edges.get_mut(&start).and_then(Vec::pop) {
+            let mut points = vec![start];
+            let mut from = start;
+            while to != start {
+                points.push(to);
+                // where two filled pixels meet at a corner, turn right to keep them apart
+                let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+                let right = (to.0 - dy, to.1 + dx);
+                let next = edges.get_mut(&to).expect("outline edges always form loops");
+                let i = next.iter().position(|&p| p == right).unwrap_or(next.len() - 1);
+                from = to;
+                to = next.swap_remove(i);
+            }
+            // drop corners in the middle of straight lines
+            let n = points.len();
+            let corners = (0..n)
+                .filter(|&i| {
+                    let (a, b, c) = (points[(i + n - 1) % n], points[i], points[(i + 1) % n]);
+                    (b.0 - a.0) * (c.1 - b.1) != (b.1 - a.1) * (c.0 - b.0)
+                })
+                .map(|i| points[i])
+                .collect();
+            contours.push(Contour(corners));
+        }
+    }
+    contours
+}
+
+/// The outermost columns and rows of an RGBA frame with any visible pixels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ContentBounds {
+    pub left: u32,
+    pub right: u32,
+    pub top: u32,
+    pub bottom: u32,
+}
+
+/// Finds the visible part of an RGBA frame in a single pass over it, or None if it's blank.
+pub fn content_bounds(data: &[u8], width: u32, height: u32, colour_key: Option<[u8; 3]>) -> Option<ContentBounds> {
+    if width == 0 {
+        return None
+    }
+    let mut bounds: Option<ContentBounds> = None;
+    for (y, row) in data.chunks_exact(width as usize * 4).take(height as usize).enumerate() {
+        let visible = |pixel: &[u8]| pixel[3] != 0 && colour_key.map_or(true, |key| pixel[..3] != key);
+        let left = match row.chunks_exact(4).position(visible) {
+            Some(left) => left as u32,
+            None => continue,
+        };
+        let right = row.chunks_exact(4).rposition(visible).unwrap_or(0) as u32;
+        let y = y as u32;
+        match &mut bounds {
+            Some(b) => {
+                b.left = b.left.min(left);
+                b.right = b.right.max(right);
+                b.bottom = y;
+            },
+            None => bounds = Some(ContentBounds { left, right, top: y, bottom: y }),
         }
-    } else {
+    }
+    bounds
+}
+
+/// Gets the first and last rows of an RGBA frame with any visible pixels, or None if it's blank.
+pub fn content_rows(data: &[u8], width: u32, height: u32, colour_key: Option<[u8; 3]>) -> Option<(u32, u32)> {
+    content_bounds(data, width, height, colour_key).map(|b| (b.top, b.bottom))
+}
+
+/// Returns the vertical offset which centres the visible pixels of an RGBA frame within the frame's height.
+/// Frames with no visible pixels aren't moved.
+pub fn vertical_centre_offset(data: &[u8], width: u32, height: u32) -> i32 {
+    match content_rows(data, width, height, None) {
+        Some((top, bottom)) => (height - (bottom - top + 1)) as i32 / 2 - top as i32,
+        None => 0,
+    }
+}
+
+/// Options for creating fonts from sprites which GM8 doesn't have, as font_add_sprite_ext takes. The defaults match GM8.
+#[derive(Clone, Copy, Default)]
+pub struct SpriteFontOptions {
+    /// Centres each frame's content vertically in the line rather than keeping it where it was drawn,
+    /// which is useful for icon fonts.
+    pub vcentre: bool,
+    /// Shrinks the line to the rows which any frame draws on, so transparent padding shared by the whole sheet
+    /// doesn't push the text down or add to the line height. Sheets without such padding aren't affected.
+    pub trim_padding: bool,
+    /// Treats pixels of this colour as transparent when measuring frames, for old sheets which mark the
+    /// background with a colour (usually magenta) rather than with alpha. It doesn't change how the frames are drawn.
+    pub colour_key: Option<[u8; 3]>,
+}
+
+/// Options for loading a game's fonts, which are set on the command line. The defaults leave glyphs as GM8 has them.
+#[derive(Clone, Copy, Default)]
+pub struct LoadOptions {
+    /// Makes glyphs bolder by this many pixels, or lighter if it's negative, as `weight_glyph` does.
+    pub weight: i32,
+    /// Stores glyphs as alpha only, using a quarter of the texture memory.
+    pub alpha_only: bool,
+}
+
+/// The traditional colour key, magenta.
+pub const MAGENTA_KEY: [u8; 3] = [0xFF, 0x00, 0xFF];
+
+/// Gets the offset, distance and yoffset of each frame of a sprite font, and the height of a line.
+pub fn sprite_glyph_metrics(
+    frames: &[&[u8]],
+    width: u32,
+    height: u32,
+    prop: bool,
+    sep: i32,
+    options: SpriteFontOptions,
+) -> (Vec<(i32, i32, i32)>, u32) {
+    let bounds = frames.iter().map(|data| content_bounds(data, width, height, options.colour_key)).collect::<Vec<_>>();
+    let all = bounds.iter().flatten().map(|b| (b.top, b.bottom)).reduce(|(t1, b1), (t2, b2)| (t1.min(t2), b1.max(b2)));
+    let (top, line_height) = match all {
+        Some((top, bottom)) if options.trim_padding => (top, bottom - top + 1),
+        _ => (0, height),
+    };
+    let metrics = bounds
+        .iter()
+        .map(|bounds| {
+            let (offset, distance) =
+                if prop { proportional_from_bounds(*bounds, width, sep) } else { (width as i32 + sep, 0) };
+            let yoffset = match bounds {
+                Some(b) if options.vcentre => (line_height - (b.bottom - b.top + 1)) as i32 / 2 - b.top as i32,
+                _ if options.vcentre => 0,
+                _ => -(top as i32),
+            };
+            (offset, distance, yoffset)
+        })
+        .collect();
+    (metrics, line_height)
+}
+
+/// Gets the offset and distance of a proportional sprite font character from its RGBA frame.
+/// This mirrors GM8's scan, which starts with left at the last column and right at the first, and moves them
+/// to the outermost columns with any visible pixels. So a glyph's advance is the distance between those columns
+/// plus sep, and a blank frame keeps the starting values, giving it an advance of sep - (width - 1).
+pub fn proportional_metrics(data: &[u8], width: u32, height: u32, sep: i32, colour_key: Option<[u8; 3]>) -> (i32, i32) {
+    proportional_from_bounds(content_bounds(data, width, height, colour_key), width, sep)
+}
+
+fn proportional_from_bounds(bounds: Option<ContentBounds>, width: u32, sep: i32) -> (i32, i32) {
+    let (left_edge, right_edge) = bounds.map_or((width as i32 - 1, 0), |b| (b.left as i32, b.right as i32));
+    (right_edge + sep - left_edge, -left_edge)
+}
+
+/// Creates font characters from the frames of a sprite, along with the height of a line.
+pub fn create_chars_from_sprite(
+    sprite: &Sprite,
+    prop: bool,
+    sep: i32,
+    options: SpriteFontOptions,
+    renderer: &Renderer,
+) -> (Box<[Character]>, u32) {
+    if !prop && !options.vcentre && !options.trim_padding {
         // non-proportional font, just add them whole
-        chars.extend(sprite.frames.iter().map(|f| Character {
+        let chars = sprite.frames.iter().map(|f| Character {
             offset: f.width as i32 + sep,
             distance: 0,
+            yoffset: 0,
             atlas_ref: f.atlas_ref.clone(),
-        }));
+        });
+        return (chars.collect(), sprite.height)
+    }
+    // otherwise the pixels are needed, to get the bounds of each character
+    let atlas_refs = sprite.frames.iter().map(|f| f.atlas_ref).collect::<Vec<_>>();
+    let frames = renderer.dump_sprites(&atlas_refs);
+    let frames = frames.iter().map(|data| data.as_ref()).collect::<Vec<_>>();
+    let (metrics, line_height) = sprite_glyph_metrics(&frames, sprite.width, sprite.height, prop, sep, options);
+    let chars = sprite.frames.iter().zip(metrics).map(|(f, (offset, distance, yoffset))| Character {
+        offset,
+        distance,
+        yoffset,
+        atlas_ref: f.atlas_ref.clone(),
+    });
+    (chars.collect(), line_height)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testing::{default_font, default_font_with_glyphs};
+
+    /// Uploads glyphs into one texture of their own like font_replace does. The sheet is left blank, so this also
+    /// returns the data of each glyph.
+    fn upload_to_sheet(
+        atlases: &mut AtlasBuilder,
+        sprites: Vec<(i32, i32, Box<[u8]>)>,
+    ) -> Result<Vec<(AtlasRef, Box<[u8]>)>, String> {
+        let sizes = sprites.iter().map(|&(w, h, _)| (w, h)).collect::<Vec<_>>();
+        let ((w, h), positions) = atlas::sheet_layout(&sizes, 1024).ok_or("too many glyphs")?;
+        let sheet = atlases.texture(w, h, 0, 0, vec![0; (w * h * 4) as usize].into_boxed_slice()).ok_or("full")?;
+        let glyphs = sprites.into_iter().zip(positions);
+        Ok(glyphs
+            .map(|((w, h, data), (x, y))| (AtlasRef { x: sheet.x + x, y: sheet.y + y, w, h, ..sheet }, data))
+            .collect())
+    }
+
+    fn sharpness(alpha: &[u8]) -> usize {
+        alpha.iter().filter(|&&a| a == 0 || a == 0xFF).count() * 100 / alpha.len()
+    }
+
+    #[test]
+    fn hinted_glyphs_are_sharp() {
+        // arimo.dat holds GM8's size 12 rasterization, so use it as the unhinted input
+        let mut alpha = default_glyphs().flat_map(|(_, _, _, _, alpha)| alpha.iter().copied()).collect::<Vec<_>>();
+        assert!(sharpness(&alpha) < 80);
+        assert_eq!(hint_glyph(6.6, -0.4, &mut alpha), (7, 0));
+        assert!(sharpness(&alpha) >= 80);
+    }
+
+    #[test]
+    fn rasterized_sizes() {
+        let mut atlases = AtlasBuilder::new(1024);
+        let default = load_default_font(&mut atlases).unwrap();
+        let mut glyphs = Vec::new();
+        let font = substitute_font(b"arial".as_ref().into(), 24, false, false, (0, 255), &default, |sprites| {
+            glyphs = upload_to_sheet(&mut atlases, sprites)?;
+            Ok(glyphs.iter().map(|(r, _)| *r).collect())
+        })
+        .unwrap();
+        assert_eq!(font.tallest_char_height, default.tallest_char_height * 2);
+        let (a, big_a) = (default.get_char(b'A').unwrap(), font.get_char(b'A').unwrap());
+        assert_eq!(big_a.offset, a.offset * 2);
+        assert_eq!((big_a.atlas_ref.w, big_a.atlas_ref.h), (a.atlas_ref.w * 2, a.atlas_ref.h * 2));
+        // resampling is hinted, so the edges stay crisp
+        let alpha = glyphs.iter().flat_map(|(_, data)| data.iter().skip(3).step_by(4).copied()).collect::<Vec<_>>();
+        assert!(sharpness(&alpha) >= 80);
+
+        // the default size is GM8's own rendering, so it's left alone
+        let font = substitute_font(b"Arimo".as_ref().into(), 12, false, true, (0, 255), &default, |sprites| {
+            glyphs = upload_to_sheet(&mut atlases, sprites)?;
+            Ok(glyphs.iter().map(|(r, _)| *r).collect())
+        })
+        .unwrap();
+        assert_eq!(font.get_char(b'A').unwrap().offset, a.offset);
+        // italic glyphs lean over, so they're wider
+        assert!(font.get_char(b'l').unwrap().atlas_ref.w > default.get_char(b'l').unwrap().atlas_ref.w);
+
+        // any other face can't be drawn, so it isn't pretended to be
+        let missing = substitute_font(b"Comic Sans MS".as_ref().into(), 12, false, false, (0, 255), &default, |_| {
+            panic!("glyphs uploaded for a font that isn't there")
+        });
+        assert!(missing.is_err());
+    }
+
+    #[test]
+    fn update_glyph() {
+        let mut font = default_font();
+        let before = font.chars.clone();
+        let slot = usize::from(b'A' - font.first);
+        let old = before[slot];
+        let (w, h) = (old.atlas_ref.w, old.atlas_ref.h);
+
+        // the same size goes over the old glyph
+        let mut stored = None;
+        let pixels = vec![0x7F; (w * h * 4) as usize].into_boxed_slice();
+        let freed = font
+            .update_glyph_with(b'A', 9, 2, w, h, pixels, false, |target, data| {
+                stored = Some((target, data));
+                Ok(old.atlas_ref)
+            })
+            .unwrap();
+        let (target, data) = stored.unwrap();
+        assert_eq!(target, Some(old.atlas_ref));
+        assert!(data.len() == (w * h * 4) as usize && data.iter().all(|&p| p == 0x7F));
+        assert_eq!(freed, None);
+        let new = font.chars[slot];
+        assert_eq!((new.offset, new.distance, new.yoffset, new.atlas_ref), (9, 2, old.yoffset, old.atlas_ref));
+
+        // a different size gets a region of its own, and the old one is handed back to be freed
+        let mut atlases = AtlasBuilder::new(1024);
+        let mut stored = None;
+        let pixels = vec![0xFF; ((w + 2) * h * 4) as usize].into_boxed_slice();
+        let freed = font
+            .update_glyph_with(b'A', 5, -1, w + 2, h, pixels, false, |target, data| {
+                stored = Some(target);
+                atlases.texture(w + 2, h, 0, 0, data).ok_or_else(|| "full".into())
+            })
+            .unwrap();
+        assert_eq!(stored, Some(None));
+        let (atlas_ref, data) = atlases.into_inner().1.pop().unwrap();
+        assert!(data.len() == ((w + 2) * h * 4) as usize && data.iter().all(|&p| p == 0xFF));
+        assert_eq!(freed, Some(old.atlas_ref));
+        let new = font.chars[slot];
+        assert_eq!((new.offset, new.distance, new.yoffset, new.atlas_ref), (5, -1, old.yoffset, atlas_ref));
+
+        for (i, (old, new)) in before.iter().zip(font.chars.iter()).enumerate() {
+            if i != slot {
+                assert_eq!(old.atlas_ref, new.atlas_ref);
+            }
+        }
+        let pixels = vec![0; 4].into_boxed_slice();
+        assert!(font.update_glyph_with(0x10, 0, 0, 1, 1, pixels, false, |_, _| unreachable!()).is_err());
+    }
+
+    #[test]
+    fn update_shared_glyph() {
+        let mut atlases = AtlasBuilder::new(1024);
+        let first = load_default_font(&mut atlases).unwrap();
+        let second = Font { name: b"fnt_second".as_ref().into(), ..first.clone() };
+        let mut fonts = vec![Some(Box::new(first)), Some(Box::new(second))];
+        let old = fonts[0].as_ref().unwrap().get_char(b'A').unwrap().atlas_ref;
+        let (w, h) = (old.w, old.h);
+        assert!(region_shared(&fonts, &old));
+
+        // the region is the other font's glyph too, so the same size still gets a copy
+        let mut font = fonts[0].take().unwrap();
+        let pixels = vec![0x7F; (w * h * 4) as usize].into_boxed_slice();
+        let freed = font
+            .update_glyph_with(b'A', 9, 0, w, h, pixels, true, |target, data| {
+                assert_eq!(target, None);
+                atlases.texture(w, h, 0, 0, data).ok_or_else(|| "full".into())
+            })
+            .unwrap();
+        fonts[0] = Some(font);
+        assert_eq!(freed, Some(old));
+        let new = fonts[0].as_ref().unwrap().get_char(b'A').unwrap().atlas_ref;
+        assert_ne!(new, old);
+        assert_eq!(fonts[1].as_ref().unwrap().get_char(b'A').unwrap().atlas_ref, old);
+        assert!(!region_shared(&fonts, &old) && !region_shared(&fonts, &new));
+        // the second font still draws from the old region, so it isn't freed until that goes too
+        assert!(graphics_to_free(&fonts, vec![old]).is_empty());
+        fonts[1] = None;
+        assert_eq!(graphics_to_free(&fonts, vec![old]), vec![old]);
+    }
+
+    #[test]
+    fn set_char_leaves_others() {
+        let mut font = default_font();
+        let before = font.chars.clone();
+        let mut atlases = AtlasBuilder::new(1024);
+        let atlas_ref = atlases.texture(3, 5, 0, 0, vec![0xFF; 3 * 5 * 4].into_boxed_slice()).unwrap();
+        let old = font.set_char(b'A', Character { offset: 4, distance: 0, yoffset: 0, atlas_ref }).unwrap();
+        assert_eq!(old.atlas_ref, before[usize::from(b'A' - font.first)].atlas_ref);
+        for (i, (old, new)) in before.iter().zip(font.chars.iter()).enumerate() {
+            if i == usize::from(b'A' - font.first) {
+                assert_eq!(new.atlas_ref, atlas_ref);
+            } else {
+                assert_eq!(old.atlas_ref, new.atlas_ref);
+            }
+        }
+        assert!(font.set_char(0x10, Character { offset: 0, distance: 0, yoffset: 0, atlas_ref }).is_none());
+    }
+
+    #[test]
+    fn fallback_chain() {
+        let mut atlases = AtlasBuilder::new(1024);
+        let atlas_ref = atlases.texture(12, 16, 0, 0, vec![0xFF; 12 * 16 * 4].into_boxed_slice()).unwrap();
+        // half-width katakana, which Shift_JIS encodes as single bytes
+        let kana = Font {
+            name: b"kana".as_ref().into(),
+            charset: 0x80,
+            first: 0xA1,
+            last: 0xDF,
+            chars: vec![Character { offset: 13, distance: 1, yoffset: 0, atlas_ref }; 0x3F].into_boxed_slice(),
+            ..default_font()
+        };
+        let mut font = default_font();
+        assert!(font.get_char_wide('\u{FF71}').is_none());
+        font.fallback = Some(Box::new(kana));
+
+        let borrowed = font.get_char_wide('\u{FF71}').unwrap();
+        assert_eq!((borrowed.offset, borrowed.atlas_ref), (13, atlas_ref));
+        // characters this font has are still its own
+        let own = font.get_char_wide('A').unwrap();
+        assert_eq!(own.atlas_ref, font.chars[usize::from(b'A' - font.first)].atlas_ref);
+        assert!(font.get_char_wide('\u{65E5}').is_none());
+        // 0xB1 is a different character in this font's encoding, so the byte isn't borrowed
+        assert!(font.get_char(0xB1).is_none());
+        let mut latin = default_font();
+        latin.first = 0xA0;
+        font.fallback = Some(Box::new(latin));
+        assert!(font.get_char(0xB1).is_some());
+    }
+
+    #[test]
+    fn fallback_drawn() {
+        use crate::{game::draw, render::BlendType};
+
+        let mut atlases = AtlasBuilder::new(1024);
+        let default = load_default_font(&mut atlases).unwrap();
+        let wide = atlases.texture(20, 16, 0, 0, vec![0xFF; 20 * 16 * 4].into_boxed_slice()).unwrap();
+        let symbols = Font {
+            name: b"fnt_symbols".as_ref().into(),
+            first: 0x80,
+            last: 0x80,
+            chars: vec![Character { offset: 21, distance: 0, yoffset: 0, atlas_ref: wide }].into_boxed_slice(),
+            ..default.clone()
+        };
+        // the game's font has no glyph for 0x80, so it comes from the fallback with the fallback's advance
+        let font = Font { fallback: Some(Box::new(symbols.clone())), ..default.clone() };
+        assert!(default.get_char(0x80).is_none());
+        assert_eq!(font.measure(&[b'A', 0x80]), default.advance(b'A') + 21);
+        let options = Default::default();
+        let blend = (BlendType::SrcAlpha, BlendType::InvSrcAlpha);
+        let glyph_pixels = |r: &AtlasRef| {
+            let filled = if *r == wide { 0xFF } else { 0 };
+            vec![filled; (r.width() * r.height() * 4) as usize].into_boxed_slice()
+        };
+        let (w, _, pixels) = draw::render_text_to_buffer(&font, &[0x80], 0xFFFFFF, 1.0, options, blend, glyph_pixels);
+        assert_eq!(w, 21);
+        assert!(pixels.chunks_exact(4).any(|p| p[3] == 0xFF));
+
+        // the fallback's glyphs stay alive for as long as either font using them does
+        let mut fonts = vec![Some(Box::new(Font { own_graphics: true, ..symbols })), Some(Box::new(font))];
+        let owned = fonts[0].as_ref().unwrap().owned_graphics();
+        fonts[0] = None;
+        assert!(graphics_to_free(&fonts, owned.clone()).is_empty());
+        let owned = fonts[1].as_ref().unwrap().owned_graphics();
+        assert!(owned.contains(&wide));
+        fonts[1] = None;
+        assert!(graphics_to_free(&fonts, owned).contains(&wide));
+    }
+
+    #[test]
+    fn ink_bounds_descenders() {
+        let (font, glyphs) = default_font_with_glyphs();
+        let ink_bounds = |text: &[u8]| font.ink_bounds_with(text, |refs| glyphs.dump_sprites(refs));
+        // x sits on the baseline, so anything lower is a descender
+        let (_, x_top, _, baseline) = ink_bounds(b"x");
+        let (left, top, right, bottom) = ink_bounds(b"gjpqy");
+        assert!(bottom > baseline);
+        assert!(top < x_top);
+        assert!(right - left > ink_bounds(b"g").2);
+        assert_eq!(ink_bounds(b"   "), (0, 0, 0, 0));
+    }
+
+    #[test]
+    fn overlapping_chars() {
+        let mut font = default_font();
+        assert!(font.overlapping_chars().is_empty());
+        // sharing a glyph is fine
+        let a = font.get_char(b'a').unwrap();
+        font.set_char(b'b', a);
+        assert!(font.overlapping_chars().is_empty());
+        // a region from another builder lands on top of whatever this font's builder put in the corner
+        let mut atlases = AtlasBuilder::new(1024);
+        let atlas_ref = atlases.texture(20, 20, 0, 0, vec![0xFF; 20 * 20 * 4].into_boxed_slice()).unwrap();
+        font.set_char(b'c', Character { atlas_ref, ..a });
+        let overlaps = font.overlapping_chars();
+        assert!(!overlaps.is_empty());
+        assert!(overlaps.iter().all(|&(x, y)| x == b'c' || y == b'c'));
+    }
+
+    #[test]
+    fn trace_ring() {
+        // a 3x3 ring, and a pixel touching its corner which mustn't be joined to it
+        #[rustfmt::skip]
+        let mask = [
+            1, 1, 1, 0,
+            1, 0, 1, 0,
+            1, 1, 1, 0,
+            0, 0, 0, 1,
+        ];
+        let data = mask.iter().flat_map(|&m| vec![0xFF, 0xFF, 0xFF, m * 0xFF]).collect::<Vec<u8>>();
+        let contours = trace_outlines(&data, 4, 4);
+        assert_eq!(contours.len(), 3);
+        assert!(contours.contains(&Contour(vec![(0, 0), (3, 0), (3, 3), (0, 3)])));
+        assert!(contours.contains(&Contour(vec![(2, 1), (1, 1), (1, 2), (2, 2)])));
+        assert!(contours.contains(&Contour(vec![(3, 3), (4, 3), (4, 4), (3, 4)])));
+        assert_eq!(contours.iter().filter(|c| c.is_hole()).count(), 1);
+        assert!(trace_outlines(&[0; 16], 2, 2).is_empty());
+        assert_eq!(Contour(vec![(2, 1), (1, 1), (1, 2), (2, 2)]).svg_path(10, 0), "M12 1 L11 1 L11 2 L12 2 Z");
+    }
+
+    #[test]
+    fn glyph_outline_o() {
+        let (mut font, glyphs) = default_font_with_glyphs();
+        let dump = |r: &AtlasRef| glyphs.dump_sprite(r);
+
+        let outline = font.glyph_outline_with('O', dump).unwrap();
+        assert_eq!(outline.len(), 2);
+        assert_eq!(outline.iter().filter(|c| c.is_hole()).count(), 1);
+        assert_eq!(font.glyph_outline_with('l', dump).unwrap().len(), 1);
+        assert!(font.glyph_outline_with(' ', dump).unwrap().is_empty());
+        assert!(font.glyph_outline_with('\u{65E5}', dump).is_none());
+        font.own_graphics = false;
+        assert!(font.glyph_outline_with('O', dump).is_none());
+    }
+
+    #[test]
+    fn gml_properties() {
+        let font = default_font();
+        let real = |v: gml::Value| f64::from(v);
+        assert_eq!(<&[u8]>::from(&font.gml_name()), b"default_font");
+        assert_eq!(<&[u8]>::from(&font.gml_fontname()), b"Arimo");
+        assert_eq!(real(font.gml_size()), 12.0);
+        assert_eq!(real(font.gml_bold()), 0.0);
+        assert_eq!(real(font.gml_italic()), 0.0);
+        assert_eq!(real(font.gml_first()), 32.0);
+        assert_eq!(real(font.gml_last()), 127.0);
+    }
+
+    #[test]
+    fn validate() {
+        let mut font = default_font();
+        let atlas_size = |atlas_id| if atlas_id == 0 { Some((1024, 1024)) } else { None };
+        assert!(font.validate_with(atlas_size).is_ok());
+        // The atlas is too small, or doesn't exist
+        assert!(font.validate_with(|_| Some((1, 1))).is_err());
+        assert!(font.validate_with(|_| None).is_err());
+
+        let mut chars = font.chars.to_vec();
+        chars.truncate(10);
+        font.chars = chars.into_boxed_slice();
+        assert!(font.validate_with(atlas_size).is_err());
+    }
+
+    #[test]
+    fn sprite_font_keeps_frames() {
+        let mut atlases = AtlasBuilder::new(1024);
+        let frames = (0..3)
+            .map(|_| atlases.texture(8, 8, 0, 0, vec![0xFF; 8 * 8 * 4].into_boxed_slice()).unwrap())
+            .collect::<Vec<_>>();
+        let mut font = default_font();
+        font.chars =
+            frames.iter().map(|&atlas_ref| Character { offset: 8, distance: 0, yoffset: 0, atlas_ref }).collect();
+        font.last = font.first + 2;
+        font.own_graphics = false;
+        assert!(font.owned_graphics().is_empty());
+
+        // A font with its own graphics frees every glyph
+        let font = default_font();
+        let owned = font.owned_graphics();
+        assert!(font.chars.iter().all(|c| owned.contains(&c.atlas_ref)));
+    }
+
+    #[test]
+    fn glyph_weight() {
+        // a 3x3 blob with soft edges in a 7x7 glyph
+        let mut alpha = vec![0u8; 7 * 7];
+        for y in 2..5 {
+            for x in 2..5 {
+                alpha[y * 7 + x] = if (x, y) == (3, 3) { 0xFF } else { 0x80 };
+            }
+        }
+        let ink = |glyph: &WeightedGlyph| glyph.alpha.iter().map(|&a| u32::from(a)).sum::<u32>();
+        let plain = weight_glyph(&alpha, 7, 7, 0);
+        assert_eq!((plain.alpha.as_slice(), plain.width, plain.height, plain.pad), (alpha.as_slice(), 7, 7, 0));
+
+        let bold = weight_glyph(&alpha, 7, 7, 1);
+        assert_eq!((bold.width, bold.height, bold.pad), (9, 9, 1));
+        assert!(ink(&bold) > ink(&plain));
+        // the blob grew a pixel each way, and its middle spread to the ring around it
+        assert_eq!(bold.alpha.iter().filter(|&&a| a != 0).count(), 5 * 5);
+        assert_eq!(bold.alpha.iter().filter(|&&a| a == 0xFF).count(), 3 * 3);
+
+        let light = weight_glyph(&alpha, 7, 7, -1);
+        assert_eq!((light.width, light.height, light.pad), (7, 7, 0));
+        assert!(ink(&light) < ink(&plain));
+        assert_eq!(light.alpha.iter().filter(|&&a| a != 0).count(), 1);
+        assert_eq!(light.alpha[3 * 7 + 3], 0x80);
+    }
+
+    #[test]
+    fn vertical_centring() {
+        // 1x8 frames with content in rows top..bottom
+        let frame = |top: usize, bottom: usize| {
+            let mut data = vec![0u8; 8 * 4];
+            for y in top..=bottom {
+                data[y * 4 + 3] = 0xFF;
+            }
+            data
+        };
+        assert_eq!(vertical_centre_offset(&frame(0, 1), 1, 8), 3);
+        assert_eq!(vertical_centre_offset(&frame(2, 5), 1, 8), 0);
+        assert_eq!(vertical_centre_offset(&frame(5, 7), 1, 8), -3);
+        assert_eq!(vertical_centre_offset(&frame(0, 7), 1, 8), 0);
+        assert_eq!(vertical_centre_offset(&vec![0u8; 8 * 4], 1, 8), 0);
+    }
+
+    #[test]
+    fn padded_sprite_font() {
+        // 2x10 frames, the whole sheet drawing only on rows 3 to 6
+        let frame = |top: usize, bottom: usize| {
+            let mut data = vec![0u8; 2 * 10 * 4];
+            for y in top..=bottom {
+                data[y * 2 * 4 + 3] = 0xFF;
+            }
+            data
+        };
+        let (a, b) = (frame(3, 6), frame(4, 6));
+        let frames = [a.as_ref(), b.as_ref()];
+
+        // GM8 keeps the padding
+        let (metrics, height) = sprite_glyph_metrics(&frames, 2, 10, false, 1, Default::default());
+        assert_eq!((metrics, height), (vec![(3, 0, 0), (3, 0, 0)], 10));
+
+        // Trimmed, the tallest glyph starts at the top of the line and the last row with content ends it
+        let trim = SpriteFontOptions { trim_padding: true, ..Default::default() };
+        let (metrics, height) = sprite_glyph_metrics(&frames, 2, 10, false, 1, trim);
+        assert_eq!((metrics, height), (vec![(3, 0, -3), (3, 0, -3)], 4));
+        let mut font = default_font();
+        font.tallest_char_height = height;
+        assert!(font.underline_position(6 - 3) >= 6 - 3);
+        assert!(font.underline_position(6 - 3) < height as i32);
+
+        // Centring is within the trimmed line
+        let both = SpriteFontOptions { vcentre: true, trim_padding: true, ..Default::default() };
+        let (metrics, _) = sprite_glyph_metrics(&frames, 2, 10, false, 1, both);
+        assert_eq!(metrics, vec![(3, 0, -3), (3, 0, -4)]);
+
+        // A tightly cropped sheet isn't changed by trimming
+        let (c, d) = (frame(0, 9), frame(2, 5));
+        let frames = [c.as_ref(), d.as_ref()];
+        let untrimmed = sprite_glyph_metrics(&frames, 2, 10, true, 1, Default::default());
+        assert_eq!(sprite_glyph_metrics(&frames, 2, 10, true, 1, trim), untrimmed);
+        assert_eq!(untrimmed.1, 10);
+    }
+
+    #[test]
+    fn colour_keyed_sprite_font() {
+        // 6x4 opaque frames, magenta except for a black block at columns 2-3 and rows 1-2
+        let mut data = vec![0u8; 6 * 4 * 4];
+        for (i, px) in data.chunks_exact_mut(4).enumerate() {
+            let (x, y) = (i % 6, i / 6);
+            let colour = if (2..=3).contains(&x) && (1..=2).contains(&y) { [0, 0, 0] } else { MAGENTA_KEY };
+            px.copy_from_slice(&[colour[0], colour[1], colour[2], 0xFF]);
+        }
+        let frames = [data.as_ref()];
+
+        // by alpha, the whole frame is drawn on
+        let (metrics, height) = sprite_glyph_metrics(&frames, 6, 4, true, 1, Default::default());
+        assert_eq!((metrics, height), (vec![(6, 0, 0)], 4));
+
+        let keyed = SpriteFontOptions { colour_key: Some(MAGENTA_KEY), ..Default::default() };
+        let (metrics, _) = sprite_glyph_metrics(&frames, 6, 4, true, 1, keyed);
+        assert_eq!(metrics, vec![(2, -2, 0)]);
+        let keyed = SpriteFontOptions { trim_padding: true, ..keyed };
+        let (metrics, height) = sprite_glyph_metrics(&frames, 6, 4, true, 1, keyed);
+        assert_eq!((metrics, height), (vec![(2, -2, -1)], 2));
+    }
+
+    #[test]
+    fn prefix_widths() {
+        let font = default_font();
+        let text = b"Hello, world!\x01";
+        let widths = font.prefix_widths(text);
+        assert_eq!(widths.len(), text.len() + 1);
+        assert_eq!(widths[0], 0);
+        assert!(widths.windows(2).all(|w| w[0] <= w[1]));
+        assert_eq!(*widths.last().unwrap(), font.measure(text));
+        assert_eq!(widths[5] - widths[4], font.get_char(b'o').unwrap().offset);
+    }
+
+    #[test]
+    fn proportional_edge_cases() {
+        let frame = |columns: &[u32]| {
+            let mut data = vec![0u8; 8 * 4 * 4];
+            for &x in columns {
+                data[(2 * 8 + x) as usize * 4 + 3] = 0xFF;
+            }
+            data
+        };
+        // a normal glyph from column 2 to 5
+        assert_eq!(proportional_metrics(&frame(&[2, 3, 5]), 8, 4, 1, None), (4, -2));
+        // a one pixel wide glyph only advances by sep
+        assert_eq!(proportional_metrics(&frame(&[3]), 8, 4, 1, None), (1, -3));
+        assert_eq!(proportional_metrics(&frame(&[0]), 8, 4, 2, None), (2, 0));
+        // a blank space keeps the scan's starting edges
+        assert_eq!(proportional_metrics(&frame(&[]), 8, 4, 1, None), (-6, -7));
+    }
+
+    #[test]
+    fn content_bounds_single_pass() {
+        // 5x4 with visible pixels at (3, 1) and (1, 2)
+        let mut data = vec![0u8; 5 * 4 * 4];
+        data[(5 + 3) * 4 + 3] = 0xFF;
+        data[(2 * 5 + 1) * 4 + 3] = 0xFF;
+        let bounds = content_bounds(&data, 5, 4, None);
+        assert_eq!(bounds, Some(ContentBounds { left: 1, right: 3, top: 1, bottom: 2 }));
+        assert_eq!(content_bounds(&data, 5, 4, Some([0, 0, 0])), None);
+        assert_eq!(content_bounds(&[], 0, 0, None), None);
+    }
+
+    #[test]
+    fn replace_keeps_handle() {
+        use crate::{game::draw, render::BlendType};
+
+        let mut atlases = AtlasBuilder::new(1024);
+        let default = load_default_font(&mut atlases).unwrap();
+        let mut fonts = vec![Some(Box::new(Font { name: b"fnt_title".as_ref().into(), ..default.clone() }))];
+        let handle = 0;
+        let old_graphics = fonts[handle].as_ref().unwrap().owned_graphics();
+
+        let mut glyphs = Vec::new();
+        let new = substitute_font(b"Arial".as_ref().into(), 16, true, false, (0, 255), &default, |sprites| {
+            glyphs = upload_to_sheet(&mut atlases, sprites)?;
+            Ok(glyphs.iter().map(|(r, _)| *r).collect())
+        })
+        .unwrap();
+        let freed = fonts[handle].as_mut().unwrap().replace(new);
+        assert_eq!(freed, old_graphics);
+
+        let font = fonts[handle].as_ref().unwrap();
+        assert_eq!((font.name.as_ref(), font.sys_name.as_ref()), (b"fnt_title".as_ref(), b"Arial".as_ref()));
+        assert_eq!((font.first, font.last, font.size, font.bold), (0x20, 0x7f, 16, true));
+        assert!(font.own_graphics && font.dirty);
+        assert_eq!(font.get_char(b'A').unwrap().atlas_ref, glyphs[usize::from(b'A' - 0x20)].0);
+        // all the glyphs were uploaded into one texture, which is freed as one
+        assert!(glyphs.iter().all(|(r, _)| r.sprite_id == glyphs[0].0.sprite_id));
+        assert_eq!(font.owned_graphics().len(), 1);
+        assert!(font.overlapping_chars().is_empty());
+
+        // the handle draws the new glyphs, which are bigger than the old ones
+        let options = Default::default();
+        let blend = (BlendType::SrcAlpha, BlendType::InvSrcAlpha);
+        let glyph_pixels = |r: &AtlasRef| glyphs.iter().find(|(g, _)| g == r).unwrap().1.clone();
+        let (w, h, pixels) = draw::render_text_to_buffer(font, b"A", 0xFFFFFF, 1.0, options, blend, glyph_pixels);
+        assert!(pixels.chunks_exact(4).any(|p| p[3] == 0xFF));
+        let old_a = default.get_char(b'A').unwrap().atlas_ref;
+        assert!(w > old_a.w as u32 && h > old_a.h as u32);
+    }
+
+    #[test]
+    fn decorations_follow_baseline() {
+        let (mut font, glyphs) = default_font_with_glyphs();
+        let baseline = font.baseline_with(|refs| glyphs.dump_sprites(refs));
+        let height = font.tallest_char_height as i32;
+        assert!(font.underline_position(baseline) > baseline && font.underline_position(baseline) < height);
+        assert!(font.strikethrough_position(baseline) < baseline && font.strikethrough_position(baseline) > 0);
+
+        // the same letters with descenders three times as long put the underline lower, but leave the strikethrough
+        let (underline, strikethrough) = (font.underline_position(baseline), font.strikethrough_position(baseline));
+        font.tallest_char_height = (baseline + 3 * (height - baseline)) as u32;
+        assert!(font.underline_position(baseline) > underline);
+        assert!(font.underline_position(baseline) < font.tallest_char_height as i32);
+        assert_eq!(font.strikethrough_position(baseline), strikethrough);
+    }
+
+    #[test]
+    fn export_metrics() {
+        let (font, glyphs) = default_font_with_glyphs();
+        let dump = |refs: &[AtlasRef]| glyphs.dump_sprites(refs);
+        let metrics = font.export_metrics_with(dump);
+        assert_eq!((metrics.first, metrics.last), (0x20, 0x7f));
+        assert_eq!(metrics.line_height, font.tallest_char_height as i32);
+        assert_eq!(metrics.ascent + metrics.descent, metrics.line_height);
+        // the ascent is where x stops and g carries on down
+        let bottom = |text: &[u8]| font.ink_bounds_with(text, dump).3;
+        assert!(metrics.ascent >= bottom(b"x") && metrics.ascent < bottom(b"g"));
+        assert_eq!(metrics.glyphs.len(), 0x60);
+        let a = metrics.glyphs.iter().find(|g| g.index == b'A').unwrap();
+        assert_eq!((a.advance, a.width), (11, 11));
+        assert_eq!(metrics.missing_advance, font.advance(b' '));
+
+        // every advance matches what layout uses
+        let text = b"The quick brown fox";
+        let advance = |c: &u8| {
+            metrics.glyphs.iter().find(|g| g.index == *c).map_or(metrics.missing_advance, |g| g.advance)
+        };
+        assert_eq!(text.iter().map(advance).sum::<i32>(), font.measure(text));
+
+        let bytes = bincode::serialize(&metrics).unwrap();
+        assert_eq!(bincode::deserialize::<FontMetrics>(&bytes).unwrap(), metrics);
+    }
+
+    #[test]
+    fn identical_fonts_share_glyphs() {
+        let mut atlases = AtlasBuilder::new(1024);
+        let default = load_default_font(&mut atlases).unwrap();
+        let mut copies = 0;
+        let upload = |sprites| {
+            copies += 1;
+            Ok(upload_to_sheet(&mut atlases, sprites)?.into_iter().map(|(r, _)| r).collect())
+        };
+        let first = substitute_font(b"Arial".as_ref().into(), 12, false, false, (0, 255), &default, upload);
+        let mut fonts = vec![Some(Box::new(first.unwrap())), None];
+
+        // the second font finds the first one's glyphs instead of copying its own
+        let shared = fonts.iter().flatten().find(|f| f.has_glyphs_for(b"Arial", 12, false, false, (0x20, 0x7f)));
+        fonts[1] = Some(Box::new(Font { name: b"fnt_second".as_ref().into(), ..(**shared.unwrap()).clone() }));
+        assert_eq!(copies, 1);
+        assert_eq!(fonts[0].as_ref().unwrap().owned_graphics(), fonts[1].as_ref().unwrap().owned_graphics());
+        assert!(!fonts[0].as_ref().unwrap().has_glyphs_for(b"Arial", 12, true, false, (0x20, 0x7f)));
+
+        // freeing either one leaves the glyphs alone until the other is gone too
+        let owned = fonts[0].as_ref().unwrap().owned_graphics();
+        assert_eq!(owned.len(), 1);
+        fonts[0] = None;
+        assert!(graphics_to_free(&fonts, owned.clone()).is_empty());
+        fonts[1] = None;
+        assert_eq!(graphics_to_free(&fonts, owned.clone()), owned);
+    }
+}
+
+#[cfg(test)]
+mod bench {
+    use super::*;
+    use test::Bencher;
+
+    // an icon font, where every frame has a different width of content and some blank rows
+    #[bench]
+    fn proportional_256_frames(b: &mut Bencher) {
+        let (width, height) = (64, 64);
+        let frames = (0..256u32)
+            .map(|i| {
+                let (left, right) = (i % 20, 40 + i % 24);
+                (0..width * height)
+                    .flat_map(|p| {
+                        let visible = (left..right).contains(&(p % width)) && (p / width) % 7 != 0;
+                        vec![0xFF, 0xFF, 0xFF, if visible { 0xFF } else { 0 }]
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let frames = frames.iter().map(|f| f.as_slice()).collect::<Vec<_>>();
+        let options = SpriteFontOptions { vcentre: true, trim_padding: true, ..Default::default() };
+        b.iter(|| sprite_glyph_metrics(&frames, width, height, true, 1, options));
     }
-    chars.into_boxed_slice()
 }
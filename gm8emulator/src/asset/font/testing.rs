@@ -0,0 +1,34 @@
+//! Helpers for writing tests about fonts and text layout.
+
+use crate::{
+    asset::font::{self, Font},
+    render::atlas::{AtlasBuilder, AtlasRef},
+};
+
+/// The pixels of every texture an AtlasBuilder packed, for reading glyphs back without a renderer.
+pub struct Glyphs(Vec<(AtlasRef, Box<[u8]>)>);
+
+impl Glyphs {
+    /// Gets a glyph's pixels, as `Renderer::dump_sprite` does.
+    pub fn dump_sprite(&self, atlas_ref: &AtlasRef) -> Box<[u8]> {
+        self.0.iter().find(|(r, _)| r == atlas_ref).expect("glyph isn't in the atlas").1.clone()
+    }
+
+    /// Gets the pixels of several glyphs, as `Renderer::dump_sprites` does.
+    pub fn dump_sprites(&self, refs: &[AtlasRef]) -> Vec<Box<[u8]>> {
+        refs.iter().map(|r| self.dump_sprite(r)).collect()
+    }
+}
+
+/// Loads the default font into an atlas of its own, for tests which only lay text out.
+pub fn default_font() -> Font {
+    default_font_with_glyphs().0
+}
+
+/// Loads the default font into an atlas of its own, along with the pixels of its glyphs.
+pub fn default_font_with_glyphs() -> (Font, Glyphs) {
+    let mut atlases = AtlasBuilder::new(1024);
+    let font = font::load_default_font(&mut atlases).unwrap();
+    let (_, textures, _) = atlases.into_inner();
+    (font, Glyphs(textures))
+}
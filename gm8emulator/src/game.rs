@@ -1,9 +1,11 @@
 pub mod audio;
 pub mod background;
+pub mod clipboard;
 pub mod draw;
 pub mod events;
 pub mod external;
 pub mod gm_save;
+pub mod highscore;
 pub mod includedfile;
 pub mod model;
 pub mod movement;
@@ -12,6 +14,7 @@ pub mod pathfinding;
 pub mod recording;
 pub mod replay;
 pub mod savestate;
+pub mod statehash;
 pub mod surface;
 pub mod transition;
 pub mod view;
@@ -25,7 +28,7 @@ use crate::{
     action::Tree,
     asset::{
         self,
-        font::{Character, Font},
+        font::{self, Character, Font},
         path::{self, Path},
         room::{self, Room},
         sprite::{Collider, Frame, Sprite},
@@ -33,7 +36,14 @@ use crate::{
         Object, Script, Sound, Timeline,
     },
     game::gm_save::GMSave,
-    gml::{self, ds, ev, file, rand::Random, runtime::Instruction, Compiler, Context},
+    gml::{
+        self, ds, ev, file, json,
+        rand::Random,
+        runtime::{CallDepth, Instruction},
+        undo,
+        value::EqualityQuirks,
+        Compiler, Context,
+    },
     handleman::{HandleArray, HandleList},
     input::{self, Input},
     instance::{DummyFieldHolder, Instance, InstanceState},
@@ -77,6 +87,7 @@ pub struct Game {
     pub rand: Random,
     pub input: Input,
     pub assets: Assets,
+    pub stock_fonts: Rc<[Option<Box<Font>>]>, // Fonts as they were when the game was loaded
     pub event_holders: [IndexMap<u32, Rc<RefCell<Vec<ID>>>>; 12],
     pub custom_draw_objects: HashSet<ID>,
 
@@ -115,6 +126,8 @@ pub struct Game {
     pub grids: HandleList<ds::Grid>,
     pub mpgrids: HandleList<pathfinding::MpGrid>,
     pub ds_precision: Real,
+    pub ds_collation: ds::Collation,
+    pub json_nesting: json::Nesting,
 
     pub default_font: Font,
     pub draw_font_id: ID,
@@ -122,6 +135,10 @@ pub struct Game {
     pub draw_alpha: Real,
     pub draw_halign: draw::Halign,
     pub draw_valign: draw::Valign,
+    pub text_options: draw::TextOptions,
+    pub text_clip: Option<draw::ClipRect>,
+    pub text_hook: Option<draw::TextHook>,
+    pub highscores: highscore::Highscores,
     pub surfaces: Vec<Option<surface::Surface>>,
     pub surface_target: Option<i32>,
     pub models: Vec<Option<model::Model>>,
@@ -129,7 +146,12 @@ pub struct Game {
     pub auto_draw: bool,
     pub uninit_fields_are_zero: bool,
     pub uninit_args_are_zero: bool,
+    pub equality_quirks: EqualityQuirks,
     pub swap_creation_events: bool,
+    pub call_depth: CallDepth,
+    pub undo_log: Option<undo::UndoLog>, // writes to variables this frame, for the debugger to step back through
+    pub undefined_lookups: bool, // failed lookups return undefined rather than 0, as in newer GameMaker versions
+    pub grapheme_strings: bool,  // string_length and string_char_at count grapheme clusters rather than characters
 
     pub potential_step_settings: pathfinding::PotentialStepSettings,
 
@@ -285,6 +307,7 @@ impl Game {
         temp_dir: Option<PathBuf>,
         encoding: &'static Encoding,
         frame_limiter: bool,
+        font_options: font::LoadOptions,
         play_type: PlayType,
     ) -> Result<Self, Box<dyn std::error::Error>> {
         // Parse file path
@@ -571,6 +594,7 @@ impl Game {
         let mut renderer = Renderer::new((), &options, &window, settings.clear_colour.into())?;
 
         let mut atlases = AtlasBuilder::new(renderer.max_texture_size() as _);
+        atlases.set_alpha_glyphs(font_options.alpha_only);
 
         let scaling = match settings.scaling {
             0 => Scaling::Full,
@@ -846,44 +870,82 @@ impl Game {
             .collect::<Result<Vec<_>, ()>>()
             .expect("failed to pack backgrounds");
 
+        // font glyphs aren't uploaded until the font is first drawn, as many games never draw most of theirs
+        // fonts rasterized with identical parameters have identical glyphs, so later ones share the first one's
+        let same_glyphs = |a: &gm8exe::asset::Font, b: &gm8exe::asset::Font| {
+            a.sys_name.0 == b.sys_name.0
+                && (a.size, a.bold, a.italic, a.range_start, a.range_end, a.charset, a.aa_level)
+                    == (b.size, b.bold, b.italic, b.range_start, b.range_end, b.charset, b.aa_level)
+        };
+        let identical_to = fonts
+            .iter()
+            .enumerate()
+            .map(|(i, font)| {
+                let font = font.as_ref()?;
+                fonts[..i].iter().position(|other| other.as_ref().map_or(false, |other| same_glyphs(font, other)))
+            })
+            .collect::<Vec<_>>();
+        let mut shared_glyphs: Vec<Option<(Box<[Character]>, u32)>> = vec![None; fonts.len()];
         let fonts = fonts
             .into_iter()
-            .map(|o| {
+            .enumerate()
+            .map(|(i, o)| {
                 o.map(|b| {
-                    let mut tallest_char_height = 0;
                     let charset = match gm_version {
                         Version::GameMaker8_0 => 1, // DEFAULT_CHARSET
                         Version::GameMaker8_1 => b.charset,
                     };
-                    let chars = b
-                        .dmap
-                        .chunks_exact(6)
-                        .skip(b.range_start as usize)
-                        .take(((b.range_end - b.range_start) + 1) as usize)
-                        .map(|char_blob| {
-                            if tallest_char_height < char_blob[3] {
-                                tallest_char_height = char_blob[3];
-                            }
-                            let mut data: Vec<u8> = Vec::with_capacity((char_blob[2] * char_blob[3] * 4) as usize);
-                            for y in 0..char_blob[3] {
-                                for x in 0..char_blob[2] {
-                                    data.push(0xFF);
-                                    data.push(0xFF);
-                                    data.push(0xFF);
-                                    data.push(
-                                        b.pixel_map[((y + char_blob[1]) * b.map_width + x + char_blob[0]) as usize],
-                                    );
-                                }
-                            }
-                            Ok(Character {
-                                offset: char_blob[4] as _,
-                                distance: char_blob[5] as _,
-                                atlas_ref: atlases
-                                    .texture(char_blob[2] as _, char_blob[3] as _, 0, 0, data.into_boxed_slice())
-                                    .ok_or(())?,
-                            })
-                        })
-                        .collect::<Result<Box<_>, ()>>()?;
+                    let glyphs = match identical_to[i].and_then(|j| shared_glyphs[j].clone()) {
+                        Some(glyphs) => glyphs,
+                        None => {
+                            let mut tallest_char_height = 0;
+                            let char_blobs = b
+                                .dmap
+                                .chunks_exact(6)
+                                .skip(b.range_start as usize)
+                                .take(((b.range_end - b.range_start) + 1) as usize);
+                            let count = char_blobs.len().max(1) as u32;
+                            let (width, height) =
+                                char_blobs.clone().fold((0, 0), |(w, h), blob| (w + blob[2], h + blob[3]));
+                            atlases.reserve_glyphs(char_blobs.len(), ((width / count) as _, (height / count) as _));
+                            let chars = char_blobs
+                                .map(|char_blob| {
+                                    if tallest_char_height < char_blob[3] {
+                                        tallest_char_height = char_blob[3];
+                                    }
+                                    let mut alpha: Vec<u8> = Vec::with_capacity((char_blob[2] * char_blob[3]) as usize);
+                                    for y in 0..char_blob[3] {
+                                        for x in 0..char_blob[2] {
+                                            let index = (y + char_blob[1]) * b.map_width + x + char_blob[0];
+                                            alpha.push(b.pixel_map[index as usize]);
+                                        }
+                                    }
+                                    let glyph =
+                                        font::weight_glyph(&alpha, char_blob[2], char_blob[3], font_options.weight);
+                                    let mut data = Vec::with_capacity(glyph.alpha.len() * 4);
+                                    for &a in &glyph.alpha {
+                                        data.extend_from_slice(&[0xFF, 0xFF, 0xFF, a]);
+                                    }
+                                    Ok(Character {
+                                        offset: char_blob[4] as _,
+                                        distance: char_blob[5] as i32 - glyph.pad as i32,
+                                        yoffset: -(glyph.pad as i32),
+                                        atlas_ref: atlases
+                                            .deferred_glyph(
+                                                i as _,
+                                                glyph.width as _,
+                                                glyph.height as _,
+                                                data.into_boxed_slice(),
+                                            )
+                                            .ok_or(())?,
+                                    })
+                                })
+                                .collect::<Result<Box<_>, ()>>()?;
+                            (chars, tallest_char_height)
+                        },
+                    };
+                    shared_glyphs[i] = Some(glyphs.clone());
+                    let (chars, tallest_char_height) = glyphs;
                     Ok(Box::new(Font {
                         name: b.name.into(),
                         sys_name: b.sys_name.into(),
@@ -896,6 +958,8 @@ impl Game {
                         tallest_char_height,
                         chars,
                         own_graphics: true,
+                        dirty: false,
+                        fallback: None,
                     }))
                 })
                 .transpose()
@@ -903,6 +967,27 @@ impl Game {
             .collect::<Result<Vec<_>, ()>>()
             .expect("failed to pack fonts");
 
+        // a packer handing out the same space twice would only show up as subtly garbled text, so look for it
+        let overlaps = || {
+            let font_overlaps = |font: &Font| {
+                let overlaps = font.overlapping_chars().into_iter();
+                overlaps.map(move |(a, b)| format!("{} and {} of {}", a, b, font.name)).collect::<Vec<_>>()
+            };
+            fonts.iter().flatten().flat_map(|font| font_overlaps(&**font)).collect::<Vec<_>>()
+        };
+        debug_assert!(overlaps().is_empty(), "font characters overlap in their atlas: {}", overlaps().join(", "));
+
+        // exports every atlas to the working directory, with any overlaps tinted red
+        #[cfg(feature = "atlas-debug")]
+        for id in 0..atlases.atlas_count() {
+            if let Some((width, height, data)) = atlases.debug_image(id as _, true) {
+                let image = image::RgbaImage::from_raw(width as _, height as _, data).unwrap();
+                if let Err(e) = file::save_image(&PathBuf::from(format!("atlas{}.png", id)), image) {
+                    eprintln!("couldn't export atlas {}: {}", id, e);
+                }
+            }
+        }
+
         let paths = paths
             .into_iter()
             .map(|t| {
@@ -1197,6 +1282,7 @@ impl Game {
             externals,
             surface_fix: false,
             input: Input::new(),
+            stock_fonts: fonts.clone().into(),
             assets: Assets { backgrounds, fonts, objects, paths, rooms, scripts, sprites, sounds, timelines, triggers },
             event_holders,
             custom_draw_objects,
@@ -1233,12 +1319,18 @@ impl Game {
             grids: HandleList::new(),
             mpgrids: HandleList::new(),
             ds_precision: Real::from(0.00000001),
+            ds_collation: Default::default(),
+            json_nesting: Default::default(),
             default_font,
             draw_font_id: -1,
             draw_colour: Colour::new(0.0, 0.0, 0.0),
             draw_alpha: Real::from(1.0),
             draw_halign: draw::Halign::Left,
             draw_valign: draw::Valign::Top,
+            text_options: Default::default(),
+            text_clip: None,
+            text_hook: None,
+            highscores: Default::default(),
             surfaces: Vec::new(),
             surface_target: None,
             models: Vec::new(),
@@ -1248,6 +1340,11 @@ impl Game {
             last_tile_id,
             uninit_fields_are_zero: settings.zero_uninitialized_vars,
             uninit_args_are_zero: !settings.error_on_uninitialized_args,
+            equality_quirks: Default::default(),
+            call_depth: Default::default(),
+            undo_log: None,
+            undefined_lookups: false,
+            grapheme_strings: false,
             swap_creation_events: settings.swap_creation_events,
             potential_step_settings: Default::default(),
             transition_kind: 0,
@@ -1459,6 +1556,14 @@ impl Game {
         }
     }
 
+    /// Encodes text from the host, replacing any characters which the game's encoding can't represent.
+    pub fn encode_str_lossy(&self, utf8: &str) -> gml::String {
+        match self.gm_version {
+            Version::GameMaker8_0 => gml::String::encode_lossy(utf8, self.encoding),
+            Version::GameMaker8_1 => utf8.into(),
+        }
+    }
+
     pub fn load_room(&mut self, room_id: i32) -> Result<(), Box<dyn std::error::Error>> {
         let (room, room_state, is_stored) = if let Some(room) = self.assets.rooms.get_asset(room_id) {
             if let Some(p) = self.stored_rooms.iter().position(|x| x.id == room_id) {
@@ -1559,6 +1664,9 @@ impl Game {
         // Update some stored vars
         if self.room.persistent && !self.game_start {
             self.stored_rooms.push(self.room.clone());
+        } else {
+            // GM8 frees the old room's instances here, so let the next room's strings reuse their memory
+            self.room.instance_list.recycle_all();
         }
         self.room = room_state;
         // clearing input here breaks direct keyboard checks, so just step instead
@@ -1769,6 +1877,12 @@ impl Game {
 
     /// Runs a frame loop and draws the screen. Exits immediately, without waiting for any FPS limitation.
     pub fn frame(&mut self) -> gml::Result<()> {
+        #[cfg(feature = "value-trace")]
+        gml::trace::next_frame();
+        if let Some(log) = &mut self.undo_log {
+            log.clear();
+        }
+
         if self.esc_close_game && self.input.keyboard_lastkey() == input::Button::Escape as u8 {
             self.scene_change = Some(SceneChange::End);
             return Ok(())
@@ -2102,8 +2216,13 @@ impl Game {
         }
     }
 
-    // Replays some recorded inputs to the game
-    pub fn replay(mut self, replay: Replay, output_bin: Option<PathBuf>) -> Result<(), Box<dyn std::error::Error>> {
+    // Replays some recorded inputs to the game, logging the state hash after each frame if there's a log to add to
+    pub fn replay(
+        mut self,
+        replay: Replay,
+        output_bin: Option<PathBuf>,
+        mut hashes: Option<&mut statehash::StateHashLog>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
         let mut frame_count: usize = 0;
         self.rand.set_seed(replay.start_seed);
         self.spoofed_time_nanos = Some(replay.start_time);
@@ -2163,6 +2282,9 @@ impl Game {
 
             self.frame()?;
             handle_scene_change!(self);
+            if let Some(hashes) = hashes.as_deref_mut() {
+                hashes.push(self.state_hash());
+            }
 
             // exit if X pressed or game_end() invoked
             if self.close_requested {
@@ -0,0 +1,56 @@
+//! Reading text from the host's clipboard.
+
+/// Gets the text on the host's clipboard, or None if there isn't any or it couldn't be opened.
+#[cfg(target_os = "windows")]
+pub fn get_text() -> Option<String> {
+    use win32::*;
+    unsafe {
+        if OpenClipboard(std::ptr::null_mut()) == 0 {
+            return None
+        }
+        let handle = GetClipboardData(CF_UNICODETEXT);
+        let data = if handle.is_null() { std::ptr::null() } else { GlobalLock(handle) as *const u16 };
+        let text = if data.is_null() {
+            None
+        } else {
+            let len = (0..).take_while(|&i| *data.add(i) != 0).count();
+            let text = String::from_utf16_lossy(std::slice::from_raw_parts(data, len));
+            GlobalUnlock(handle);
+            Some(text)
+        };
+        CloseClipboard();
+        text
+    }
+}
+
+/// Gets the text on the host's clipboard. Only Windows' clipboard is supported so far, so this is always None.
+#[cfg(not(target_os = "windows"))]
+pub fn get_text() -> Option<String> {
+    None
+}
+
+#[cfg(target_os = "windows")]
+#[allow(bad_style)]
+mod win32 {
+    use std::ffi::c_void;
+
+    pub type BOOL = i32;
+    pub type HANDLE = *mut c_void;
+    pub type HWND = *mut c_void;
+    pub type UINT = u32;
+
+    pub const CF_UNICODETEXT: UINT = 13;
+
+    #[link(name = "user32")]
+    extern "system" {
+        pub fn OpenClipboard(hWndNewOwner: HWND) -> BOOL;
+        pub fn CloseClipboard() -> BOOL;
+        pub fn GetClipboardData(uFormat: UINT) -> HANDLE;
+    }
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        pub fn GlobalLock(hMem: HANDLE) -> *mut c_void;
+        pub fn GlobalUnlock(hMem: HANDLE) -> BOOL;
+    }
+}
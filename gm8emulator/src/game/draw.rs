@@ -3,23 +3,359 @@ use crate::{
     game::{Game, GetAsset, PlayType, Version},
     gml,
     math::Real,
+    render::{atlas::AtlasRef, BlendType},
+    types::Colour,
 };
 use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
 
-#[derive(Clone, Copy, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Halign {
     Left,
     Middle,
     Right,
 }
 
-#[derive(Clone, Copy, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Valign {
     Top,
     Middle,
     Bottom,
 }
 
+/// The global state draw_text uses, which GM8 never scopes to a script.
+#[derive(Clone, Copy)]
+pub struct TextState {
+    pub font_id: i32,
+    pub colour: Colour,
+    pub alpha: Real,
+    pub halign: Halign,
+    pub valign: Valign,
+    pub clip: Option<ClipRect>,
+    pub options: TextOptions,
+}
+
+impl Game {
+    pub fn text_state(&self) -> TextState {
+        TextState {
+            font_id: self.draw_font_id,
+            colour: self.draw_colour,
+            alpha: self.draw_alpha,
+            halign: self.draw_halign,
+            valign: self.draw_valign,
+            clip: self.text_clip,
+            options: self.text_options,
+        }
+    }
+
+    pub fn set_text_state(&mut self, state: TextState) {
+        self.draw_font_id = state.font_id;
+        self.draw_colour = state.colour;
+        self.draw_alpha = state.alpha;
+        self.draw_halign = state.halign;
+        self.draw_valign = state.valign;
+        self.text_clip = state.clip;
+        self.text_options = state.options;
+    }
+
+    /// Runs a closure which may change any of the text drawing state, then puts it all back as it was,
+    /// so overlays can draw text without the game noticing.
+    pub fn with_font_state<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> T {
+        let state = self.text_state();
+        let result = f(self);
+        self.set_text_state(state);
+        result
+    }
+}
+
+/// Text rendering options which GM8 doesn't have. The defaults match GM8.
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+pub struct TextOptions {
+    /// Draws a box for characters which aren't in the font, instead of leaving a space.
+    pub tofu: bool,
+    /// Blends glyph edges in linear light rather than directly on sRGB values, when compositing text in software.
+    pub gamma_correct: bool,
+    /// Draws a line under each line of text.
+    pub underline: bool,
+    /// Draws a line through each line of text.
+    pub strikethrough: bool,
+    /// Filters glyphs through mipmaps when text is drawn scaled down, so it doesn't shimmer. Text drawn at its
+    /// normal size or larger is still drawn unfiltered.
+    pub mipmaps: bool,
+    /// Aligns each line within the width of the widest line, with the block's left edge at the text origin, rather
+    /// than aligning every line to the origin itself.
+    pub block_align: bool,
+    /// Moves the cursor to the next multiple of this many pixels along the line for a tab, rather than drawing it as
+    /// a space like GM8 does.
+    pub tab_stops: Option<i32>,
+    /// Moves the first line of each string right by this many pixels, or left if it's negative, and wraps that line
+    /// to fit in what's left of the width, for indenting the start of a paragraph.
+    pub first_line_indent: i32,
+    /// Gives every character a cell this many pixels wide, with the glyph centred in it, as if the font were
+    /// monospaced. For lining up columns of values with a proportional font. Set with draw_set_text_monospace.
+    pub monospace: Option<i32>,
+    /// Moves each line so its glyphs start on a whole pixel vertically, rather than wherever the text's position and
+    /// scale put them, so small text isn't blurred across two rows of pixels. Meant for text which isn't rotated.
+    /// Set with draw_set_text_snap_baseline.
+    pub snap_baseline: bool,
+}
+
+/// Called with every string drawn, along with its position and the font ID, for tools such as translation overlays.
+/// If it returns a string, that's drawn instead.
+pub type TextHook = Box<dyn FnMut(&gml::String, Real, Real, i32) -> Option<gml::String>>;
+
+fn apply_text_hook(hook: &mut Option<TextHook>, string: gml::String, x: Real, y: Real, font_id: i32) -> gml::String {
+    match hook {
+        Some(hook) => hook(&string, x, y, font_id).unwrap_or(string),
+        None => string,
+    }
+}
+
+/// An underline or strikethrough, relative to the text origin like a GlyphQuad. y is the centre of the line.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Decoration {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+}
+
+/// A rectangle to clip text to, with the right and bottom edges exclusive. Glyphs are cut off at its edges.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClipRect {
+    pub left: i32,
+    pub top: i32,
+    pub right: i32,
+    pub bottom: i32,
+}
+
+impl ClipRect {
+    /// Converts a clip rectangle in room coordinates to one relative to the origin of text drawn at the given
+    /// position and scale, like a GlyphQuad. Text is clipped before it's rotated, so the rectangle turns with the text.
+    fn to_local(self, x: Real, y: Real, xscale: Real, yscale: Real) -> Self {
+        if xscale == Real::from(0) || yscale == Real::from(0) {
+            return Self { left: 0, top: 0, right: 0, bottom: 0 }
+        }
+        let local = |edge: i32, origin: Real, scale: Real| f64::from((Real::from(edge) - origin) / scale);
+        let (x1, x2) = (local(self.left, x, xscale), local(self.right, x, xscale));
+        let (y1, y2) = (local(self.top, y, yscale), local(self.bottom, y, yscale));
+        Self {
+            left: x1.min(x2).floor() as i32,
+            top: y1.min(y2).floor() as i32,
+            right: x1.max(x2).ceil() as i32,
+            bottom: y1.max(y2).ceil() as i32,
+        }
+    }
+
+    /// Gets the part of a glyph at the given position which is inside the rectangle, if any of it is, as
+    /// (x, y, width, height) within the glyph.
+    fn glyph_part(&self, left: i32, top: i32, width: i32, height: i32) -> Option<(i32, i32, i32, i32)> {
+        let (x1, y1) = (left.max(self.left), top.max(self.top));
+        let (x2, y2) = ((left + width).min(self.right), (top + height).min(self.bottom));
+        if x1 < x2 && y1 < y2 { Some((x1 - left, y1 - top, x2 - x1, y2 - y1)) } else { None }
+    }
+}
+
+/// A glyph positioned by text layout, relative to the text origin and before scaling and rotation.
+#[derive(Clone, Copy, Debug)]
+pub struct GlyphQuad {
+    /// Position of the glyph's cell. The character's distance and yoffset aren't included.
+    pub x: i32,
+    pub y: i32,
+    /// How far the cursor moves past this glyph.
+    pub advance: i32,
+    /// Position of the cell within its line and the width of that line, used for colour gradients.
+    pub line_x: i32,
+    pub line_width: i32,
+    /// The character to draw, or None for a missing-glyph box.
+    pub character: Option<font::Character>,
+}
+
+/// How a single glyph is moved and scaled on top of the text's own transform, for effects such as wobbling text.
+/// The offset is in unscaled pixels, and the glyph is scaled about its centre.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GlyphTransform {
+    pub x: Real,
+    pub y: Real,
+    pub xscale: Real,
+    pub yscale: Real,
+}
+
+impl Default for GlyphTransform {
+    fn default() -> Self {
+        Self { x: Real::from(0.0), y: Real::from(0.0), xscale: Real::from(1.0), yscale: Real::from(1.0) }
+    }
+}
+
+impl GlyphTransform {
+    /// Gets the transforms for text on a sine wave: each glyph is moved down by `amplitude * sin(phase + i * step)`,
+    /// where `i` is its index and the angles are in radians, so animating the phase makes the text wobble.
+    pub fn wave(amplitude: Real, step: Real, phase: Real) -> impl Fn(usize, &GlyphQuad) -> Self {
+        move |i, _| Self { y: amplitude * (phase + Real::from(i as f64) * step).sin(), ..Default::default() }
+    }
+}
+
+/// Gets the font to draw text with. Like GM8, this falls back to the default font if the current one was never set
+/// or has been deleted.
+fn current_font<'a>(fonts: &'a [Option<Box<Font>>], font_id: i32, default: &'a Font) -> &'a Font {
+    usize::try_from(font_id).ok().and_then(|i| fonts.get(i)?.as_deref()).unwrap_or(default)
+}
+
+/// Gets how far the cursor moves past a character, taking missing characters into account.
+fn glyph_advance(font: &font::Font, c: u8, options: &TextOptions) -> i32 {
+    match options.monospace {
+        Some(cell) => cell,
+        None if options.tofu && font.get_char(c).is_none() => font.tofu_width(),
+        None => font.advance(c),
+    }
+}
+
+/// Gets how far the cursor moves past a character which starts the given distance into its line, which only matters
+/// for tabs when there are tab stops.
+fn glyph_advance_at(font: &font::Font, c: u8, line_x: i32, options: &TextOptions) -> i32 {
+    match options.tab_stops {
+        Some(interval) if c == b'\t' && interval > 0 => interval - line_x.rem_euclid(interval),
+        _ => glyph_advance(font, c, options),
+    }
+}
+
+/// Gets how far the given line of a string is indented.
+fn line_indent(line: usize, options: &TextOptions) -> i32 {
+    if line == 0 { options.first_line_indent } else { 0 }
+}
+
+/// Gets the width of some text which starts the given distance into its line.
+fn text_advance_at(font: &font::Font, text: &[u8], line_x: i32, options: &TextOptions) -> i32 {
+    text.iter().fold(0, |width, &c| width + glyph_advance_at(font, c, line_x + width, options))
+}
+
+fn lerp_col(c1: i32, c2: i32, ratio: f64) -> i32 {
+    ((f64::from(c1 & 0xff) * (1.0 - ratio) + f64::from(c2 & 0xff) * ratio) as i32 & 0xff)
+        + ((f64::from(c1 & 0xff00) * (1.0 - ratio) + f64::from(c2 & 0xff00) * ratio) as i32 & 0xff00)
+        + ((f64::from(c1 & 0xff0000) * (1.0 - ratio) + f64::from(c2 & 0xff0000) * ratio) as i32 & 0xff0000)
+}
+
+/// Gets the vertex colours of a glyph, clockwise from the top left. Gradients run across each whole line.
+/// Alpha isn't included, as it's the same for every vertex: the renderer multiplies it into the glyph's own alpha.
+fn glyph_colours(
+    quad: &GlyphQuad,
+    character: &font::Character,
+    colours: Option<(i32, i32, i32, i32)>,
+    draw_colour: i32,
+) -> [i32; 4] {
+    match colours {
+        Some((c1, c2, c3, c4)) => {
+            let width = f64::from(quad.line_width);
+            let (left, right) = (f64::from(quad.line_x) / width, f64::from(quad.line_x + character.offset) / width);
+            [lerp_col(c1, c2, left), lerp_col(c1, c2, right), lerp_col(c4, c3, right), lerp_col(c4, c3, left)]
+        },
+        None => [draw_colour; 4],
+    }
+}
+
+/// Gets the top left of each of some already split lines, relative to the text origin.
+fn line_origins(
+    lines: &[(Vec<u8>, i32)],
+    line_height: i32,
+    halign: Halign,
+    valign: Valign,
+    options: &TextOptions,
+) -> Vec<(i32, i32)> {
+    let height = lines.len() as i32 * line_height;
+    let widths = lines.iter().enumerate().map(|(i, (_, width))| width + line_indent(i, options));
+    let block_width = widths.max().unwrap_or(0);
+    let top = match valign {
+        Valign::Top => 0,
+        Valign::Middle => -(height / 2),
+        Valign::Bottom => -height,
+    };
+    lines
+        .iter()
+        .enumerate()
+        .map(|(i, (_, width))| {
+            // an indented line is aligned along with its indent, then its text starts after it
+            let indent = line_indent(i, options);
+            let width = width + indent;
+            let left = match halign {
+                Halign::Left => 0,
+                Halign::Middle if options.block_align => (block_width - width) / 2,
+                Halign::Middle => -(width / 2),
+                Halign::Right if options.block_align => block_width - width,
+                Halign::Right => -width,
+            };
+            (left + indent, top + i as i32 * line_height)
+        })
+        .collect()
+}
+
+/// Gets the underlines and strikethroughs of some already split lines, spanning the width of each line.
+fn layout_decorations(
+    lines: &[(Vec<u8>, i32)],
+    font: &font::Font,
+    baseline: i32,
+    line_height: i32,
+    halign: Halign,
+    valign: Valign,
+    options: &TextOptions,
+) -> Vec<Decoration> {
+    let mut offsets = Vec::new();
+    if options.underline {
+        offsets.push(font.underline_position(baseline));
+    }
+    if options.strikethrough {
+        offsets.push(font.strikethrough_position(baseline));
+    }
+    let mut decorations = Vec::new();
+    for ((left, top), (_, width)) in line_origins(lines, line_height, halign, valign, options).into_iter().zip(lines) {
+        if *width > 0 {
+            decorations.extend(offsets.iter().map(|offset| Decoration { x: left, y: top + offset, width: *width }));
+        }
+    }
+    decorations
+}
+
+/// Positions every glyph of some already split lines.
+fn layout_lines(
+    lines: &[(Vec<u8>, i32)],
+    font: &font::Font,
+    line_height: i32,
+    halign: Halign,
+    valign: Valign,
+    options: &TextOptions,
+) -> Vec<GlyphQuad> {
+    let mut quads = Vec::new();
+    let origins = line_origins(lines, line_height, halign, valign, options);
+    for (i, ((line, width), (left_offset, cursor_y))) in lines.iter().zip(origins).enumerate() {
+        let width = *width;
+        let indent = line_indent(i, options);
+        let mut cursor_x = left_offset;
+
+        for c in line.iter().copied() {
+            let advance = glyph_advance_at(font, c, indent + cursor_x - left_offset, options);
+            let character = match options.monospace {
+                Some(cell) => font.get_char(c).map(|c| font::Character {
+                    offset: cell,
+                    distance: (cell - c.atlas_ref.width()) / 2,
+                    ..c
+                }),
+                None => font.get_char(c),
+            };
+            let tab = c == b'\t' && options.tab_stops.is_some();
+            if (character.is_some() || options.tofu) && !tab {
+                quads.push(GlyphQuad {
+                    x: cursor_x,
+                    y: cursor_y,
+                    advance,
+                    line_x: cursor_x - left_offset,
+                    line_width: width,
+                    character,
+                });
+            }
+            cursor_x += advance;
+        }
+    }
+    quads
+}
+
 struct LineIterator<'a> {
     text: Vec<u8>,
     pos: usize,
@@ -27,21 +363,52 @@ struct LineIterator<'a> {
     max_width: Option<i32>,
     word_buf: Vec<u8>,
     word_width: i32,
+    hash_newlines: bool,
+    options: TextOptions,
 }
 
 impl<'a> LineIterator<'a> {
-    fn next(&mut self) -> Option<(Vec<u8>, i32)> {
+    /// If hash_newlines is set, '#' starts a new line and '\#' draws a literal '#', as in GM8's draw_text.
+    /// Tabs and other control characters are never in a font, so they're drawn as spaces, unless there are tab stops.
+    fn new(text: Vec<u8>, font: &'a font::Font, max_width: Option<i32>, hash_newlines: bool) -> Self {
+        Self {
+            text,
+            pos: 0,
+            font,
+            max_width,
+            word_buf: Vec::new(),
+            word_width: 0,
+            hash_newlines,
+            options: Default::default(),
+        }
+    }
+
+    fn with_options(self, options: TextOptions) -> Self {
+        Self { options, ..self }
+    }
+}
+
+impl Iterator for LineIterator<'_> {
+    /// Each line's text and width.
+    type Item = (Vec<u8>, i32);
+
+    fn next(&mut self) -> Option<Self::Item> {
         if self.pos >= self.text.len() {
             return None
         }
         let mut line = Vec::new();
         let mut line_width = 0;
+        let indent = if self.pos == 0 { self.options.first_line_indent } else { 0 };
+        if self.options.tab_stops.is_some() {
+            // A word carried over from the last line may have a tab in it, which is narrower or wider here
+            self.word_width = text_advance_at(self.font, &self.word_buf, 0, &self.options);
+        }
 
         let mut iter = self.text[self.pos..].iter().copied().enumerate().peekable();
         while let Some((_, c)) = iter.next() {
             // First, process escape characters
             let c = match c {
-                b'#' | b'\r' | b'\n' => {
+                b'#' | b'\r' | b'\n' if c != b'#' || self.hash_newlines => {
                     // '#' is a newline character, don't process it but start a new line instead
                     // Likewise CR, LF, and CRLF
                     if c == b'\r' && iter.peek().map(|t| t.1) == Some(b'\n') {
@@ -50,13 +417,14 @@ impl<'a> LineIterator<'a> {
                     }
                     b'\n'
                 },
-                b'\\' if iter.peek().map(|t| t.1) == Some(b'#') => {
+                b'\\' if self.hash_newlines && iter.peek().map(|t| t.1) == Some(b'#') => {
                     // '\#' is an escaped newline character, treat it as '#'
                     iter.next();
                     b'#'
                 },
-                _ if self.font.get_char(c).is_some() => c, // Normal character
-                _ => b' ',                                 // Character is not in the font, replace with space
+                b'\t' if self.options.tab_stops.is_some() => c, // Tab, which moves to the next tab stop
+                _ if self.font.get_char(c).is_some() || self.options.tofu => c, // Normal character
+                _ => b' ', // Character is not in the font, replace with space
             };
             // Next, insert the character into the word buffer
             match c {
@@ -70,28 +438,21 @@ impl<'a> LineIterator<'a> {
                 },
                 _ => {
                     // Normal character
-                    if let Some(character) = self.font.get_char(c) {
-                        self.word_buf.push(c);
-                        self.word_width += character.offset;
-                    } else {
-                        // Space when it isn't in the font
-                        self.word_buf.push(b' ');
-                        if let Some(character) = self.font.get_char(self.font.first) {
-                            self.word_width += character.offset;
-                        }
-                    }
+                    self.word_buf.push(c);
+                    let line_x = indent + line_width + self.word_width;
+                    self.word_width += glyph_advance_at(self.font, c, line_x, &self.options);
                 },
             };
 
             // Check if we're going over the max width
             if let Some(max_width) = self.max_width {
-                if line_width + self.word_width > max_width && line_width != 0 {
+                if indent + line_width + self.word_width > max_width && line_width != 0 {
                     break
                 }
             }
 
             // Push new word if applicable
-            if c == b' ' {
+            if c == b' ' || c == b'\t' {
                 line.extend_from_slice(&self.word_buf);
                 line_width += self.word_width;
                 self.word_buf.clear();
@@ -112,6 +473,201 @@ impl<'a> LineIterator<'a> {
     }
 }
 
+/// Positions every glyph of some already split lines as draw_text_ext_transformed does. The lines were wrapped in
+/// unscaled units, then each glyph is scaled and rotated about the draw origin by the transform. Returns each glyph
+/// along with where its sprite should be drawn.
+fn layout_transformed(
+    lines: &[(Vec<u8>, i32)],
+    font: &font::Font,
+    line_height: i32,
+    halign: Halign,
+    valign: Valign,
+    options: &TextOptions,
+    transform: impl Fn(i32, i32) -> (Real, Real),
+) -> Vec<(GlyphQuad, Real, Real)> {
+    layout_lines(lines, font, line_height, halign, valign, options)
+        .into_iter()
+        .map(|quad| {
+            let (distance, yoffset) = quad.character.map(|c| (c.distance, c.yoffset)).unwrap_or((0, 0));
+            let (x, y) = transform(quad.x + distance, quad.y + yoffset);
+            if options.snap_baseline {
+                // the whole line moves by the same amount, so glyphs keep their places relative to each other
+                let (_, line_y) = transform(0, quad.y);
+                (quad, x, y + line_y.round() - line_y)
+            } else {
+                (quad, x, y)
+            }
+        })
+        .collect()
+}
+
+/// Applies a transform to each laid out glyph, given its index and quad. Missing-glyph boxes are left out.
+/// Returns each glyph along with where the top left of its sprite goes, relative to the text origin and before the
+/// text's own transform, and the scale it's drawn at.
+fn transform_glyphs(
+    quads: Vec<GlyphQuad>,
+    mut glyph_transform: impl FnMut(usize, &GlyphQuad) -> GlyphTransform,
+) -> Vec<(GlyphQuad, Real, Real, GlyphTransform)> {
+    let mut glyphs = Vec::with_capacity(quads.len());
+    for (i, quad) in quads.into_iter().enumerate() {
+        let transform = glyph_transform(i, &quad);
+        if let Some(character) = quad.character {
+            let half_width = Real::from(character.atlas_ref.width()) / Real::from(2.0);
+            let half_height = Real::from(character.atlas_ref.height()) / Real::from(2.0);
+            let centre_x = Real::from(quad.x + character.distance) + half_width;
+            let centre_y = Real::from(quad.y + character.yoffset) + half_height;
+            let left = centre_x - half_width * transform.xscale + transform.x;
+            let top = centre_y - half_height * transform.yscale + transform.y;
+            glyphs.push((quad, left, top, transform));
+        }
+    }
+    glyphs
+}
+
+/// Cuts laid out glyphs down to the first `count` of them, if there's a count, for revealing text a bit at a time.
+/// Returns whether every glyph is still there.
+fn reveal_glyphs<T>(glyphs: &mut Vec<T>, count: Option<usize>) -> bool {
+    match count {
+        Some(count) if count < glyphs.len() => {
+            glyphs.truncate(count);
+            false
+        },
+        _ => true,
+    }
+}
+
+/// Returns a function which scales and rotates a position relative to the text origin.
+/// Glyphs are drawn with the same scale, so a negative scale flips each glyph as well as the layout, mirroring the text
+/// around its origin.
+fn text_transform(x: Real, y: Real, xscale: Real, yscale: Real, angle: Real) -> impl Fn(i32, i32) -> (Real, Real) {
+    let transform = text_transform_real(x, y, xscale, yscale, angle);
+    move |xdiff, ydiff| transform(Real::from(xdiff), Real::from(ydiff))
+}
+
+/// Does the same as text_transform for positions which aren't whole pixels.
+fn text_transform_real(
+    x: Real,
+    y: Real,
+    xscale: Real,
+    yscale: Real,
+    angle: Real,
+) -> impl Fn(Real, Real) -> (Real, Real) {
+    let sin = angle.to_radians().sin();
+    let cos = angle.to_radians().cos();
+    move |xdiff, ydiff| {
+        (x + xdiff * xscale * cos + ydiff * yscale * sin, y + ydiff * yscale * cos - xdiff * xscale * sin)
+    }
+}
+
+fn srgb_to_linear(c: u8) -> f64 {
+    let c = f64::from(c) / 255.0;
+    if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+}
+
+fn linear_to_srgb(c: f64) -> u8 {
+    let c = if c <= 0.0031308 { c * 12.92 } else { 1.055 * c.powf(1.0 / 2.4) - 0.055 };
+    (c * 255.0).round().max(0.0).min(255.0) as u8
+}
+
+/// Alpha blends an RGBA pixel over another. GM8 blends sRGB values directly, which makes edges look too thin on dark
+/// backgrounds, so optionally the colours can be blended in linear light instead.
+fn blend_pixel(dst: &mut [u8], src: [u8; 4], gamma_correct: bool) {
+    let src_a = i32::from(src[3]);
+    let dst_a = i32::from(dst[3]) * (255 - src_a) / 255;
+    let out_a = src_a + dst_a;
+    if out_a == 0 {
+        return
+    }
+    for (dst_c, &src_c) in dst[..3].iter_mut().zip(&src[..3]) {
+        *dst_c = if gamma_correct {
+            let (src_l, dst_l) = (srgb_to_linear(src_c), srgb_to_linear(*dst_c));
+            linear_to_srgb((src_l * f64::from(src_a) + dst_l * f64::from(dst_a)) / f64::from(out_a))
+        } else {
+            ((i32::from(src_c) * src_a + i32::from(*dst_c) * dst_a) / out_a) as u8
+        };
+    }
+    dst[3] = out_a as u8;
+}
+
+/// Blends an RGBA pixel onto another with the given source and destination factors, as the renderer does for
+/// blend modes other than normal. Alpha is blended with the same factors as the colour channels.
+fn blend_pixel_factors(dst: &mut [u8], src: [u8; 4], (src_factor, dst_factor): (BlendType, BlendType)) {
+    let unit = |c: u8| f64::from(c) / 255.0;
+    let (src_a, dst_a) = (unit(src[3]), unit(dst[3]));
+    let factor = |blend, src_c: f64, dst_c: f64, alpha: bool| match blend {
+        BlendType::Zero => 0.0,
+        BlendType::One => 1.0,
+        BlendType::SrcColour => src_c,
+        BlendType::InvSrcColour => 1.0 - src_c,
+        BlendType::SrcAlpha => src_a,
+        BlendType::InvSrcAlpha => 1.0 - src_a,
+        BlendType::DestAlpha => dst_a,
+        BlendType::InvDestAlpha => 1.0 - dst_a,
+        BlendType::DestColour => dst_c,
+        BlendType::InvDestColour => 1.0 - dst_c,
+        BlendType::SrcAlphaSaturate if alpha => 1.0,
+        BlendType::SrcAlphaSaturate => src_a.min(1.0 - dst_a),
+    };
+    for (i, (dst_c, &src_c)) in dst.iter_mut().zip(&src).enumerate() {
+        let (src_c, dst_c_unit) = (unit(src_c), unit(*dst_c));
+        let out = src_c * factor(src_factor, src_c, dst_c_unit, i == 3)
+            + dst_c_unit * factor(dst_factor, src_c, dst_c_unit, i == 3);
+        *dst_c = (out * 255.0).round().max(0.0).min(255.0) as u8;
+    }
+}
+
+/// Draws text into a standalone RGBA buffer, without using the renderer, and returns its width and height with it.
+/// The text is laid out as draw_text would at the top left. glyph_pixels must give the RGBA data of a glyph.
+/// Each glyph's alpha is multiplied by the given alpha, as the vertex alpha is when text is drawn to the screen.
+/// Glyphs are stamped with the given blend mode, so additive text brightens where its glyphs overlap. The normal
+/// blend mode composites them over each other as the options say.
+pub fn render_text_to_buffer(
+    font: &Font,
+    text: &[u8],
+    colour: i32,
+    alpha: f64,
+    options: TextOptions,
+    blend: (BlendType, BlendType),
+    mut glyph_pixels: impl FnMut(&AtlasRef) -> Box<[u8]>,
+) -> (u32, u32, Vec<u8>) {
+    let line_height = font.tallest_char_height as i32;
+    let lines = LineIterator::new(text.to_vec(), font, None, true).with_options(options).collect::<Vec<_>>();
+    let width = lines.iter().enumerate().map(|(i, (_, w))| w + line_indent(i, &options)).max().unwrap_or(0).max(0);
+    let height = lines.len() as i32 * line_height;
+
+    let mut buffer = vec![0u8; (width * height * 4) as usize];
+    let rgb = [colour & 0xff, (colour >> 8) & 0xff, (colour >> 16) & 0xff];
+    for quad in layout_lines(&lines, font, line_height, Halign::Left, Valign::Top, &options) {
+        let character = match quad.character {
+            Some(character) => character,
+            None => continue,
+        };
+        let (glyph_w, glyph_h) = (character.atlas_ref.width(), character.atlas_ref.height());
+        let pixels = glyph_pixels(&character.atlas_ref);
+        for gy in 0..glyph_h {
+            for gx in 0..glyph_w {
+                let (x, y) = (quad.x + character.distance + gx, quad.y + character.yoffset + gy);
+                if x < 0 || y < 0 || x >= width || y >= height {
+                    continue
+                }
+                let src = &pixels[((gy * glyph_w + gx) * 4) as usize..][..4];
+                let tinted = [
+                    (i32::from(src[0]) * rgb[0] / 255) as u8,
+                    (i32::from(src[1]) * rgb[1] / 255) as u8,
+                    (i32::from(src[2]) * rgb[2] / 255) as u8,
+                    (f64::from(src[3]) * alpha).round() as u8,
+                ];
+                let dst = &mut buffer[((y * width + x) * 4) as usize..][..4];
+                match blend {
+                    (BlendType::SrcAlpha, BlendType::InvSrcAlpha) => blend_pixel(dst, tinted, options.gamma_correct),
+                    _ => blend_pixel_factors(dst, tinted, blend),
+                }
+            }
+        }
+    }
+    (width as u32, height as u32, buffer)
+}
+
 impl Game {
     /// Draws all instances, tiles and backgrounds to the screen, taking all active views into account.
     /// Note that this function runs GML code associated with object draw events, so its usage must match GameMaker 8.
@@ -452,14 +1008,14 @@ impl Game {
                 }
             },
         };
-        LineIterator { text: encoded_text, pos: 0, font, max_width, word_buf: Vec::new(), word_width: 0 }
+        LineIterator::new(encoded_text, font, max_width, true).with_options(self.text_options)
     }
 
     /// Gets width and height of a string using the current draw_font.
     /// If line_height is None, a line height will be inferred from the font.
     /// If max_width is None, the string will not be given a maximum width.
     pub fn get_string_size(&self, string: gml::String, line_height: Option<i32>, max_width: Option<i32>) -> (i32, i32) {
-        let font = self.assets.fonts.get_asset(self.draw_font_id).map(|x| x.as_ref()).unwrap_or(&self.default_font);
+        let font = current_font(&self.assets.fonts, self.draw_font_id, &self.default_font);
 
         // Figure out what the height of a line is if one wasn't specified
         let line_height = match line_height {
@@ -469,8 +1025,8 @@ impl Game {
 
         let mut width = 0;
         let mut line_count = 0;
-        let mut iter = self.split_string(string, max_width, font);
-        while let Some((_, current_w)) = iter.next() {
+        for (_, current_w) in self.split_string(string, max_width, font) {
+            let current_w = current_w + line_indent(line_count as usize, &self.text_options);
             if width < current_w {
                 width = current_w;
             }
@@ -480,6 +1036,43 @@ impl Game {
         (width, line_count * line_height)
     }
 
+    /// Gets the box around the pixels a string covers with the current font, relative to where it's drawn with left
+    /// and top alignment, as (left, top, right, bottom). Each line's box comes from `Font::ink_bounds`, so this
+    /// includes descenders and overhangs which `get_string_size` doesn't.
+    pub fn get_string_ink_bounds(&mut self, string: gml::String) -> (i32, i32, i32, i32) {
+        let font = current_font(&self.assets.fonts, self.draw_font_id, &self.default_font);
+        let line_height = font.tallest_char_height as i32;
+        let lines = self.split_string(string, None, font).map(|(line, _)| line).collect::<Vec<_>>();
+        let mut bounds: Option<(i32, i32, i32, i32)> = None;
+        for (i, line) in lines.iter().enumerate() {
+            let (x, y) = (line_indent(i, &self.text_options), i as i32 * line_height);
+            let (x0, y0, x1, y1) = match font.ink_bounds(line, &mut self.renderer) {
+                (0, 0, 0, 0) => continue,
+                (x0, y0, x1, y1) => (x + x0, y + y0, x + x1, y + y1),
+            };
+            bounds = Some(match bounds {
+                Some((bx0, by0, bx1, by1)) => (bx0.min(x0), by0.min(y0), bx1.max(x1), by1.max(y1)),
+                None => (x0, y0, x1, y1),
+            });
+        }
+        bounds.unwrap_or((0, 0, 0, 0))
+    }
+
+    /// Uploads the current draw_font's glyphs to the GPU if this is the first time it's being drawn.
+    fn upload_draw_font(&mut self) {
+        current_font(&self.assets.fonts, self.draw_font_id, &self.default_font).upload_glyphs(&mut self.renderer);
+    }
+
+    /// Draws a string with the current font and colour into a standalone RGBA buffer, for exporting text.
+    pub fn render_text_to_buffer(&mut self, string: gml::String) -> (u32, u32, Vec<u8>) {
+        self.upload_draw_font();
+        let font = current_font(&self.assets.fonts, self.draw_font_id, &self.default_font);
+        let (colour, alpha) = (u32::from(self.draw_colour) as i32, self.draw_alpha.into_inner());
+        let blend = self.renderer.get_blend_mode();
+        let dump = |r: &AtlasRef| self.renderer.dump_sprite(r);
+        render_text_to_buffer(font, string.as_ref(), colour, alpha, self.text_options, blend, dump)
+    }
+
     /// Draws a string to the screen at the given coordinates.
     /// If line_height is None, a line height will be inferred from the font.
     /// If max_width is None, the string will not be given a maximum width.
@@ -496,10 +1089,29 @@ impl Game {
         colours: Option<(i32, i32, i32, i32)>,
         alpha: Real,
     ) {
-        let font = self.assets.fonts.get_asset(self.draw_font_id).map(|x| x.as_ref()).unwrap_or(&self.default_font);
+        self.draw_string_revealed(x, y, string, line_height, max_width, xscale, yscale, angle, colours, alpha, None)
+    }
 
-        let sin = angle.to_radians().sin();
-        let cos = angle.to_radians().cos();
+    /// Draws a string as `draw_string` does, but if there's a reveal count, only the first that many glyphs.
+    /// The whole string is still laid out, so wrapped words don't jump between lines as more of them is revealed.
+    /// Underlines and strikethroughs are only drawn once every glyph is revealed.
+    pub fn draw_string_revealed(
+        &mut self,
+        x: Real,
+        y: Real,
+        string: gml::String,
+        line_height: Option<i32>,
+        max_width: Option<i32>,
+        xscale: Real,
+        yscale: Real,
+        angle: Real,
+        colours: Option<(i32, i32, i32, i32)>,
+        alpha: Real,
+        reveal: Option<usize>,
+    ) {
+        let string = apply_text_hook(&mut self.text_hook, string, x, y, self.draw_font_id);
+        self.upload_draw_font();
+        let font = current_font(&self.assets.fonts, self.draw_font_id, &self.default_font);
 
         // Figure out what the height of a line is if one wasn't specified
         let line_height = match line_height {
@@ -507,75 +1119,727 @@ impl Game {
             None => font.tallest_char_height as i32,
         };
 
-        let mut cursor_y = match self.draw_valign {
-            Valign::Top => 0,
-            Valign::Middle => -(self.get_string_size(string.clone(), Some(line_height), max_width).1 / 2),
-            Valign::Bottom => -self.get_string_size(string.clone(), Some(line_height), max_width).1,
-        };
+        let transform = text_transform(x, y, xscale, yscale, angle);
+        let clip = self.text_clip.map(|clip| clip.to_local(x, y, xscale, yscale));
 
-        fn lerp_col(c1: i32, c2: i32, ratio: f64) -> i32 {
-            ((f64::from(c1 & 0xff) * (1.0 - ratio) + f64::from(c2 & 0xff) * ratio) as i32 & 0xff)
-                + ((f64::from(c1 & 0xff00) * (1.0 - ratio) + f64::from(c2 & 0xff00) * ratio) as i32 & 0xff00)
-                + ((f64::from(c1 & 0xff0000) * (1.0 - ratio) + f64::from(c2 & 0xff0000) * ratio) as i32 & 0xff0000)
-        }
+        let lines = self.split_string(string, max_width, font).collect::<Vec<_>>();
 
-        let mut iter = self.split_string(string, max_width, font);
-        while let Some((line, width)) = iter.next() {
-            let left_offset = match self.draw_halign {
-                Halign::Left => 0,
-                Halign::Middle => -(width as i32 / 2),
-                Halign::Right => -width as i32,
+        let (halign, valign) = (self.draw_halign, self.draw_valign);
+        let mipmaps = self.renderer.get_mipmapping();
+        self.renderer.set_mipmapping(self.text_options.mipmaps);
+        let mut glyphs = layout_transformed(&lines, font, line_height, halign, valign, &self.text_options, &transform);
+        let revealed = reveal_glyphs(&mut glyphs, reveal);
+        for (quad, draw_x, draw_y) in glyphs {
+            let character = match quad.character {
+                Some(character) => character,
+                None => {
+                    // Missing glyph box
+                    let colour = match colours {
+                        Some((c1, ..)) => c1,
+                        None => u32::from(self.draw_colour) as i32,
+                    };
+                    let (left, right) = (quad.x + 1, quad.x + quad.advance - 1);
+                    let (top, bottom) = (quad.y + 1, quad.y + font.tallest_char_height as i32 - 1);
+                    // only whole boxes are drawn when clipping
+                    let (width, height) = (right - left + 1, bottom - top + 1);
+                    let whole = Some((0, 0, width, height));
+                    if clip.map_or(false, |clip| clip.glyph_part(left, top, width, height) != whole) {
+                        continue
+                    }
+                    let corners = [
+                        transform(left, top),
+                        transform(right, top),
+                        transform(right, bottom),
+                        transform(left, bottom),
+                    ];
+                    for i in 0..4 {
+                        let ((x1, y1), (x2, y2)) = (corners[i], corners[(i + 1) % 4]);
+                        let (x1, y1, x2, y2) = (f64::from(x1), f64::from(y1), f64::from(x2), f64::from(y2));
+                        self.renderer.draw_line(x1, y1, x2, y2, None, colour, colour, alpha.into());
+                    }
+                    continue
+                },
             };
-            let mut cursor_x = left_offset;
-
-            for c in line.iter().copied() {
-                let character = match font.get_char(c) {
-                    Some(character) => character,
-                    None => {
-                        // Space if it isn't in the font
-                        if let Some(character) = font.get_char(font.first) {
-                            cursor_x += character.offset as i32;
-                        }
+
+            let [c1, c2, c3, c4] = glyph_colours(&quad, &character, colours, u32::from(self.draw_colour) as i32);
+            if let Some(clip) = clip {
+                let (left, top) = (quad.x + character.distance, quad.y + character.yoffset);
+                let (width, height) = (character.atlas_ref.width(), character.atlas_ref.height());
+                match clip.glyph_part(left, top, width, height) {
+                    Some(part) if part == (0, 0, width, height) => (),
+                    Some((part_x, part_y, part_w, part_h)) => {
+                        let (draw_x, draw_y) = transform(left + part_x, top + part_y);
+                        self.renderer.draw_sprite_general(
+                            &character.atlas_ref,
+                            part_x.into(),
+                            part_y.into(),
+                            part_w.into(),
+                            part_h.into(),
+                            draw_x.into(),
+                            draw_y.into(),
+                            xscale.into(),
+                            yscale.into(),
+                            angle.into(),
+                            c1,
+                            c2,
+                            c3,
+                            c4,
+                            alpha.into(),
+                            false,
+                        );
                         continue
                     },
-                };
-
-                let xdiff = Real::from(character.distance as i32 + cursor_x);
-                let ydiff = Real::from(cursor_y);
-
-                let (xdiff, ydiff) =
-                    (xdiff * xscale * cos + ydiff * yscale * sin, ydiff * yscale * cos - xdiff * xscale * sin);
-
-                match colours {
-                    Some((c1, c2, c3, c4)) => self.renderer.draw_sprite_colour(
-                        &character.atlas_ref,
-                        (x + xdiff).into(),
-                        (y + ydiff).into(),
-                        xscale.into(),
-                        yscale.into(),
-                        angle.into(),
-                        lerp_col(c1, c2, f64::from(cursor_x - left_offset) / f64::from(width)),
-                        lerp_col(c1, c2, f64::from(cursor_x - left_offset + character.offset) / f64::from(width)),
-                        lerp_col(c4, c3, f64::from(cursor_x - left_offset + character.offset) / f64::from(width)),
-                        lerp_col(c4, c3, f64::from(cursor_x - left_offset) / f64::from(width)),
-                        alpha.into(),
-                    ),
-                    None => self.renderer.draw_sprite(
-                        &character.atlas_ref,
-                        (x + xdiff).into(),
-                        (y + ydiff).into(),
-                        xscale.into(),
-                        yscale.into(),
-                        angle.into(),
-                        u32::from(self.draw_colour) as i32,
-                        alpha.into(),
-                    ),
+                    None => continue,
+                }
+            }
+            self.renderer.draw_sprite_colour(
+                &character.atlas_ref,
+                draw_x.into(),
+                draw_y.into(),
+                xscale.into(),
+                yscale.into(),
+                angle.into(),
+                c1,
+                c2,
+                c3,
+                c4,
+                alpha.into(),
+            );
+        }
+        self.renderer.set_mipmapping(mipmaps);
+        if !revealed {
+            return
+        }
+
+        let decorations = if self.text_options.underline || self.text_options.strikethrough {
+            let (halign, valign) = (self.draw_halign, self.draw_valign);
+            let baseline = font.baseline(&mut self.renderer);
+            layout_decorations(&lines, font, baseline, line_height, halign, valign, &self.text_options)
+        } else {
+            Vec::new()
+        };
+        let thickness = Real::from(font.decoration_thickness()) * yscale.abs();
+        for decoration in decorations {
+            let (c1, c2) = match colours {
+                Some((c1, c2, ..)) => (c1, c2),
+                None => (u32::from(self.draw_colour) as i32, u32::from(self.draw_colour) as i32),
+            };
+            let (mut left, mut right) = (decoration.x, decoration.x + decoration.width);
+            if let Some(clip) = clip {
+                if decoration.y < clip.top || decoration.y >= clip.bottom {
+                    continue
                 }
+                left = left.max(clip.left);
+                right = right.min(clip.right);
+                if left >= right {
+                    continue
+                }
+            }
+            let (x1, y1) = transform(left, decoration.y);
+            let (x2, y2) = transform(right, decoration.y);
+            let (x1, y1, x2, y2) = (f64::from(x1), f64::from(y1), f64::from(x2), f64::from(y2));
+            self.renderer.draw_line(x1, y1, x2, y2, Some(thickness.into()), c1, c2, alpha.into());
+        }
+    }
+
+    /// Draws a string as `draw_string` does, but moves and scales each glyph by the transform given for it, along
+    /// with its index in the string's glyphs, for effects like wobbling text. It's all drawn in one pass, rather than
+    /// a draw call per character as GML would need. Missing-glyph boxes, clipping and decorations aren't drawn.
+    pub fn draw_string_per_glyph(
+        &mut self,
+        x: Real,
+        y: Real,
+        string: gml::String,
+        line_height: Option<i32>,
+        max_width: Option<i32>,
+        xscale: Real,
+        yscale: Real,
+        angle: Real,
+        colours: Option<(i32, i32, i32, i32)>,
+        alpha: Real,
+        glyph_transform: impl FnMut(usize, &GlyphQuad) -> GlyphTransform,
+    ) {
+        let string = apply_text_hook(&mut self.text_hook, string, x, y, self.draw_font_id);
+        self.upload_draw_font();
+        let font = current_font(&self.assets.fonts, self.draw_font_id, &self.default_font);
+        let line_height = line_height.unwrap_or(font.tallest_char_height as i32);
+
+        let lines = self.split_string(string, max_width, font).collect::<Vec<_>>();
+        let (halign, valign) = (self.draw_halign, self.draw_valign);
+        let quads = layout_lines(&lines, font, line_height, halign, valign, &self.text_options);
+
+        let transform = text_transform_real(x, y, xscale, yscale, angle);
+        let mipmaps = self.renderer.get_mipmapping();
+        self.renderer.set_mipmapping(self.text_options.mipmaps);
+        for (quad, left, top, glyph) in transform_glyphs(quads, glyph_transform) {
+            let character = match quad.character {
+                Some(character) => character,
+                None => continue,
+            };
+            let [c1, c2, c3, c4] = glyph_colours(&quad, &character, colours, u32::from(self.draw_colour) as i32);
+            let (draw_x, draw_y) = transform(left, top);
+            self.renderer.draw_sprite_colour(
+                &character.atlas_ref,
+                draw_x.into(),
+                draw_y.into(),
+                (xscale * glyph.xscale).into(),
+                (yscale * glyph.yscale).into(),
+                angle.into(),
+                c1,
+                c2,
+                c3,
+                c4,
+                alpha.into(),
+            );
+        }
+        self.renderer.set_mipmapping(mipmaps);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::atlas::AtlasBuilder;
+
+    fn lines(text: &[u8], hash_newlines: bool) -> Vec<Vec<u8>> {
+        let font = font::testing::default_font();
+        LineIterator::new(text.to_vec(), &font, None, hash_newlines).map(|(line, _)| line).collect()
+    }
+
+    #[test]
+    fn hash_newline() {
+        assert_eq!(lines(b"line1#line2", true), vec![b"line1".to_vec(), b"line2".to_vec()]);
+        assert_eq!(lines(b"line1\r\nline2", true), vec![b"line1".to_vec(), b"line2".to_vec()]);
+    }
+
+    #[test]
+    fn escaped_hash() {
+        assert_eq!(lines(b"a\\#b", true), vec![b"a#b".to_vec()]);
+    }
+
+    #[test]
+    fn literal_hash() {
+        assert_eq!(lines(b"line1#line2", false), vec![b"line1#line2".to_vec()]);
+        assert_eq!(lines(b"a\\#b", false), vec![b"a\\#b".to_vec()]);
+    }
+
+    #[test]
+    fn tab_as_space() {
+        assert_eq!(lines(b"a\tb", true), vec![b"a b".to_vec()]);
+    }
+
+    #[test]
+    fn tab_stops() {
+        let font = font::testing::default_font();
+        let options = TextOptions { tab_stops: Some(64), ..Default::default() };
+        let layout = |text: &[u8], max_width| {
+            let iter = LineIterator::new(text.to_vec(), &font, max_width, true).with_options(options);
+            let lines = iter.collect::<Vec<_>>();
+            let quads = layout_lines(&lines, &font, 20, Halign::Left, Valign::Top, &options);
+            (lines, quads)
+        };
+
+        // the word after a tab starts on the tab stop, and the tab itself isn't drawn
+        let (lines, quads) = layout(b"ab\tcd", None);
+        assert_eq!(lines[0].1, 64 + font.measure(b"cd"));
+        assert_eq!(quads.len(), 4);
+        assert_eq!((quads[2].x, quads[2].y), (64, 0));
+
+        // stops are counted from the start of each line, and a cursor already on one moves to the next
+        let (_, quads) = layout(b"x\t#\ty\t\tz", None);
+        assert_eq!((quads[1].x, quads[1].y), (64, 20));
+        assert_eq!(quads[2].x, 64 * 3);
+
+        // a word wrapped onto the next line keeps its tab, which now reaches the stop from the start of the line
+        let (lines, quads) = layout(b"a b\tc", Some(font.measure(b"a b")));
+        let lines = lines.iter().map(|(line, width)| (line.as_slice(), *width)).collect::<Vec<_>>();
+        assert_eq!(lines, vec![(b"a ".as_ref(), font.measure(b"a ")), (b"b\tc".as_ref(), 64 + font.measure(b"c"))]);
+        assert_eq!((quads[2].x, quads[2].y), (0, 20));
+        assert_eq!((quads[3].x, quads[3].y), (64, 20));
+
+        // without tab stops a tab is just a space, as in GM8
+        let mut iter = LineIterator::new(b"ab\tcd".to_vec(), &font, None, true);
+        assert_eq!(iter.next().unwrap().1, font.measure(b"ab cd"));
+    }
+
+    #[test]
+    fn monospace() {
+        let font = font::testing::default_font();
+        let options = TextOptions { monospace: Some(12), ..Default::default() };
+        let mut iter = LineIterator::new(b"iWi .".to_vec(), &font, None, true).with_options(options);
+        let line = iter.next().unwrap();
+        assert_eq!(line.1, 12 * 5);
+        let quads = layout_lines(&[line], &font, 20, Halign::Left, Valign::Top, &options);
+
+        // every cell is the same width however wide its glyph is, and each glyph is centred in its cell
+        let (i, w) = (font.get_char(b'i').unwrap(), font.get_char(b'W').unwrap());
+        assert_ne!(i.atlas_ref.width(), w.atlas_ref.width());
+        let cells = quads.iter().map(|quad| (quad.x, quad.advance)).collect::<Vec<_>>();
+        assert_eq!(cells, vec![(0, 12), (12, 12), (24, 12), (36, 12), (48, 12)]);
+        for quad in &quads {
+            let c = quad.character.unwrap();
+            assert!((2 * c.distance + c.atlas_ref.width() - 12).abs() <= 1);
+            assert_eq!(c.offset, 12);
+        }
+    }
+
+    #[test]
+    fn first_line_indent() {
+        let font = font::testing::default_font();
+        let text = b"The quick brown fox jumps over the lazy dog, then runs off into the trees.";
+        let max_width = font.measure(b"The quick brown fox ");
+        let layout = |indent| {
+            let options = TextOptions { first_line_indent: indent, ..Default::default() };
+            let iter = LineIterator::new(text.to_vec(), &font, Some(max_width), true).with_options(options);
+            let lines = iter.collect::<Vec<_>>();
+            let quads = layout_lines(&lines, &font, 20, Halign::Left, Valign::Top, &options);
+            (lines.into_iter().map(|(line, _)| line).collect::<Vec<_>>(), quads)
+        };
+        let indent = font.measure(b"fox ");
+        let (plain_lines, plain) = layout(0);
+        let (lines, indented) = layout(indent);
+
+        // the first line is shorter to leave room for the indent, and every line still fits
+        assert_eq!(plain_lines[0], b"The quick brown fox ");
+        assert_eq!(lines[0], b"The quick brown ");
+        assert!(lines[1].starts_with(b"fox "));
+        assert!(indent + font.measure(&lines[0]) <= max_width);
+        assert!(lines[1..].iter().all(|line| font.measure(line) <= max_width));
+
+        // only the first line is shifted
+        let starts = |quads: &[GlyphQuad]| {
+            let mut starts = Vec::new();
+            for quad in quads {
+                if starts.last().map_or(true, |&(_, y)| y != quad.y) {
+                    starts.push((quad.x, quad.y));
+                }
+            }
+            starts
+        };
+        let starts = starts(&indented);
+        assert_eq!(starts[0], (indent, 0));
+        assert!(starts.len() > 2 && starts[1..].iter().all(|&(x, _)| x == 0));
+        assert_eq!(indented[0].line_x, 0);
+        assert_eq!(plain[0].x, 0);
+    }
+
+    #[test]
+    fn wobble() {
+        let font = font::testing::default_font();
+        let mut iter = LineIterator::new(b"Wobbly text!".to_vec(), &font, None, true);
+        let line = iter.next().unwrap();
+        let quads = layout_lines(&[line], &font, 20, Halign::Left, Valign::Top, &Default::default());
+        let wave = |i: usize| Real::from((i as f64 * 0.8).sin() * 4.0);
+
+        let plain = transform_glyphs(quads.clone(), |_, _| Default::default());
+        let wobbled = transform_glyphs(quads.clone(), GlyphTransform::wave(4.0.into(), 0.8.into(), 0.0.into()));
+        // the space has a glyph too, so every character is there
+        assert_eq!(wobbled.len(), quads.len());
+        for (i, ((_, x, y, _), (_, plain_x, plain_y, _))) in wobbled.iter().zip(&plain).enumerate() {
+            assert_eq!(*x, *plain_x);
+            assert_eq!(*y, *plain_y + wave(i));
+        }
+        assert_eq!(plain[0].2, Real::from(quads[0].character.unwrap().yoffset));
+
+        // scaling is about the middle of the glyph
+        let scaled = transform_glyphs(quads.clone(), |_, _| GlyphTransform {
+            xscale: Real::from(2.0),
+            yscale: Real::from(0.5),
+            ..Default::default()
+        });
+        for ((_, x, y, glyph), (quad, plain_x, plain_y, _)) in scaled.iter().zip(&plain) {
+            let (w, h) = quad.character.map(|c| (c.atlas_ref.width(), c.atlas_ref.height())).unwrap();
+            assert_eq!(*x, *plain_x - Real::from(w) / Real::from(2.0));
+            assert_eq!(*y, *plain_y + Real::from(h) / Real::from(4.0));
+            assert_eq!((glyph.xscale, glyph.yscale), (Real::from(2.0), Real::from(0.5)));
+        }
+    }
+
+    #[test]
+    fn clip_rect() {
+        let font = font::testing::default_font();
+        let text = b"A long line of text in a narrow panel";
+        let mut iter = LineIterator::new(text.to_vec(), &font, None, true);
+        let lines = vec![iter.next().unwrap()];
+        let quads = layout_lines(&lines, &font, 20, Halign::Left, Valign::Top, &Default::default());
 
-                cursor_x += character.offset as i32;
+        // a panel at (100, 50) cutting through the middle of the 'o' in text drawn at (100, 50)
+        let o = quads[3].character.unwrap();
+        let cut = quads[3].x + o.distance + o.atlas_ref.width() / 2;
+        let panel = ClipRect { left: 100, top: 50, right: 100 + cut, bottom: 70 };
+        let clip = panel.to_local(Real::from(100), Real::from(50), Real::from(1), Real::from(1));
+        assert_eq!(clip, ClipRect { left: 0, top: 0, right: cut, bottom: 20 });
+        let parts = quads
+            .iter()
+            .map(|quad| {
+                let c = quad.character.unwrap();
+                let (left, width) = (quad.x + c.distance, c.atlas_ref.width());
+                (left, width, clip.glyph_part(left, quad.y + c.yoffset, width, c.atlas_ref.height()))
+            })
+            .collect::<Vec<_>>();
+        for &(left, width, part) in &parts {
+            match part {
+                // glyphs across the edge are cut off there
+                Some((x, _, w, _)) => assert_eq!(left + x + w, (left + width).min(cut)),
+                None => assert!(left >= cut || width == 0),
             }
+        }
+        assert_eq!(parts[3].2.map(|(_, _, w, _)| w), Some(o.atlas_ref.width() / 2));
+        assert!(parts[4..].iter().all(|(.., part)| part.is_none()));
+
+        // text drawn at double size shows half as much of itself
+        let panel = ClipRect { left: 100, top: 50, right: 140, bottom: 70 };
+        let clip = panel.to_local(Real::from(100), Real::from(50), Real::from(2), Real::from(-2));
+        assert_eq!(clip, ClipRect { left: 0, top: -10, right: 20, bottom: 0 });
+        assert_eq!(clip.glyph_part(18, -12, 4, 4), Some((0, 2, 2, 2)));
+        assert_eq!(clip.glyph_part(20, -5, 4, 4), None);
+    }
+
+    #[test]
+    fn missing_glyph() {
+        let font = font::testing::default_font();
+        let space = font.get_char(b' ').unwrap().offset;
+        let a = font.get_char(b'a').unwrap().offset;
+        let layout = |options: TextOptions| {
+            let mut iter = LineIterator::new(b"a\x01a".to_vec(), &font, None, true).with_options(options);
+            let line = iter.next().unwrap();
+            (line.1, layout_lines(&[line], &font, 16, Halign::Left, Valign::Top, &options))
+        };
+
+        // GM8 draws nothing, but still advances by a space
+        let (width, quads) = layout(Default::default());
+        assert_eq!(width, a * 2 + space);
+        assert_eq!(quads.iter().filter(|q| q.character.is_none()).count(), 0);
+
+        let (width, quads) = layout(TextOptions { tofu: true, ..Default::default() });
+        assert_eq!(width, a * 2 + font.tofu_width());
+        let tofu: Vec<_> = quads.iter().filter(|q| q.character.is_none()).collect();
+        assert_eq!(tofu.len(), 1);
+        assert_eq!(tofu[0].x, a);
+        assert_eq!(tofu[0].advance, font.tofu_width());
+        assert_eq!(quads.last().unwrap().x, a + font.tofu_width());
+    }
+
+    #[test]
+    fn gradient_colours() {
+        let font = font::testing::default_font();
+        let mut iter = LineIterator::new(b"ab".to_vec(), &font, None, true);
+        let line = iter.next().unwrap();
+        let quads = layout_lines(&[line], &font, 16, Halign::Left, Valign::Top, &Default::default());
+        let (a, b) = (quads[0].character.unwrap(), quads[1].character.unwrap());
+
+        assert_eq!(glyph_colours(&quads[0], &a, None, 0x123456), [0x123456; 4]);
+        let gradient = Some((0x000000, 0x0000ff, 0x00ff00, 0xff0000));
+        let left = glyph_colours(&quads[0], &a, gradient, 0);
+        let right = glyph_colours(&quads[1], &b, gradient, 0);
+        assert_eq!(left[0], 0x000000);
+        assert_eq!(left[3], 0xff0000);
+        assert_eq!(right[1], 0x0000ff);
+        assert_eq!(right[2], 0x00ff00);
+        assert_eq!(left[1], right[0]);
+    }
+
+    #[test]
+    fn diagonal_gradient() {
+        let font = font::testing::default_font();
+        let lines = LineIterator::new(b"ab\ncd".to_vec(), &font, None, true).collect::<Vec<_>>();
+        let quads = layout_lines(&lines, &font, 16, Halign::Left, Valign::Top, &Default::default());
+        // white in the top left fading to black in the bottom right, across each line
+        let gradient = Some((0xffffff, 0x808080, 0x000000, 0x808080));
+        let colours = |i: usize| glyph_colours(&quads[i], &quads[i].character.unwrap(), gradient, 0);
+        assert_eq!(quads.len(), 4);
+        assert_eq!(colours(0)[0], 0xffffff);
+        assert_eq!(colours(1)[1], 0x808080);
+        assert_eq!(colours(2)[3], 0x808080);
+        assert_eq!(colours(3)[2], 0x000000);
+        // neighbouring glyphs meet with the same colour, somewhere between the line's ends
+        assert_eq!(colours(0)[1], colours(1)[0]);
+        assert_eq!(colours(2)[2], colours(3)[3]);
+        assert!((0x80..0xff).contains(&(colours(0)[1] & 0xff)));
+    }
+
+    #[test]
+    fn text_to_buffer() {
+        let font = font::testing::default_font();
+
+        // Rebuild each glyph's pixels from the same data the font was loaded from
+        let data = include_bytes!("../../data/arimo.dat");
+        let mut glyphs = Vec::new();
+        let mut cursor = 0;
+        for _ in 0..0x60 {
+            let size = usize::from(data[cursor + 2]) * usize::from(data[cursor + 3]);
+            cursor += 4;
+            let pixels = data[cursor..cursor + size].iter().flat_map(|&a| vec![0xFF, 0xFF, 0xFF, a]);
+            glyphs.push(pixels.collect::<Vec<_>>());
+            cursor += size;
+        }
+        let glyph_pixels = |atlas_ref: &AtlasRef| {
+            let index = font.chars.iter().position(|c| c.atlas_ref == *atlas_ref).unwrap();
+            glyphs[index].clone().into_boxed_slice()
+        };
+
+        let normal = (BlendType::SrcAlpha, BlendType::InvSrcAlpha);
+        let (width, height, buffer) =
+            render_text_to_buffer(&font, b"Hi", 0x0000ff, 1.0, Default::default(), normal, glyph_pixels);
+        assert_eq!(width as i32, font.measure(b"Hi"));
+        assert_eq!(height, font.tallest_char_height);
+        assert_eq!(buffer.len(), (width * height * 4) as usize);
+        // Something was drawn, and it's red
+        let opaque = buffer.chunks(4).filter(|p| p[3] == 0xFF).collect::<Vec<_>>();
+        assert!(!opaque.is_empty());
+        assert!(opaque.iter().all(|p| p[0] == 0xFF && p[1] == 0 && p[2] == 0));
+
+        // draw_set_alpha fades the glyphs, halving every pixel's alpha at 0.5
+        let (_, _, full) = render_text_to_buffer(&font, b"H", 0xffffff, 1.0, Default::default(), normal, glyph_pixels);
+        let (_, _, half) = render_text_to_buffer(&font, b"H", 0xffffff, 0.5, Default::default(), normal, glyph_pixels);
+        assert!(full.chunks(4).any(|p| p[3] == 0xFF));
+        for (full, half) in full.chunks(4).zip(half.chunks(4)) {
+            assert_eq!(half[3], (f64::from(full[3]) * 0.5).round() as u8);
+        }
+    }
+
+    #[test]
+    fn additive_overlap() {
+        let mut font = font::testing::default_font();
+        // a narrow advance so the two glyphs overlap by half
+        let w = font.get_char(b'W').unwrap();
+        let half = w.atlas_ref.width() / 2;
+        font.set_char(b'W', font::Character { offset: half, distance: 0, ..w });
+        let glyph_pixels = |atlas_ref: &AtlasRef| {
+            vec![0x40; (atlas_ref.width() * atlas_ref.height() * 4) as usize].into_boxed_slice()
+        };
+        let render = |blend| {
+            let (width, _, buffer) =
+                render_text_to_buffer(&font, b"WW", 0xffffff, 1.0, Default::default(), blend, glyph_pixels);
+            let y = w.yoffset + w.atlas_ref.height() / 2;
+            let pixel = |x: i32| buffer[((y * width as i32 + x) * 4) as usize];
+            (pixel(half / 2), pixel(half + half / 2))
+        };
+
+        // additive glyphs brighten where they overlap, and normal ones don't
+        let (single, overlap) = render((BlendType::SrcAlpha, BlendType::One));
+        assert_eq!(single, 0x10);
+        assert_eq!(overlap, 0x20);
+        let (single, overlap) = render((BlendType::SrcAlpha, BlendType::InvSrcAlpha));
+        assert_eq!(single, overlap);
+    }
+
+    #[test]
+    fn mirrored_text() {
+        let font = font::testing::default_font();
+        let lines = LineIterator::new(b"Hi#there".to_vec(), &font, None, true).collect::<Vec<_>>();
+        let quads = layout_lines(&lines, &font, 16, Halign::Middle, Valign::Top, &Default::default());
+
+        // The horizontal span each glyph covers on screen, drawn the way draw_string does
+        let spans = |xscale: f64| {
+            let (x, xscale) = (Real::from(100.0), Real::from(xscale));
+            let transform = text_transform(x, Real::from(50.0), xscale, Real::from(1.0), Real::from(0.0));
+            quads
+                .iter()
+                .filter_map(|quad| quad.character.map(|c| (quad, c)))
+                .map(|(quad, c)| {
+                    let (draw_x, draw_y) = transform(c.distance + quad.x, c.yoffset + quad.y);
+                    let far_x = draw_x + Real::from(c.atlas_ref.width()) * xscale;
+                    (f64::from(draw_x - x), f64::from(far_x - x), f64::from(draw_y))
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let normal = spans(1.0);
+        let mirrored = spans(-1.0);
+        assert_eq!(normal.len(), mirrored.len());
+        for ((near, far, y), (m_near, m_far, m_y)) in normal.into_iter().zip(mirrored) {
+            assert_eq!((m_near, m_far, m_y), (-near, -far, y));
+            assert!(m_far < m_near);
+        }
+    }
+
+    #[test]
+    fn gamma_correct_blend() {
+        // A half-covered white glyph edge over a black background
+        let edge = [0xFF, 0xFF, 0xFF, 0x80];
+        let mut naive = [0, 0, 0, 0xFF];
+        blend_pixel(&mut naive, edge, false);
+        assert_eq!(naive, [0x80, 0x80, 0x80, 0xFF]);
+        let mut correct = [0, 0, 0, 0xFF];
+        blend_pixel(&mut correct, edge, true);
+        assert_eq!(correct, [188, 188, 188, 0xFF]);
+
+        // Fully covered and uncovered pixels are the same either way
+        for &gamma_correct in &[false, true] {
+            let mut dst = [10, 20, 30, 0xFF];
+            blend_pixel(&mut dst, [200, 100, 50, 0xFF], gamma_correct);
+            assert_eq!(dst, [200, 100, 50, 0xFF]);
+            blend_pixel(&mut dst, [0, 0, 0, 0], gamma_correct);
+            assert_eq!(dst, [200, 100, 50, 0xFF]);
+        }
+    }
+
+    #[test]
+    fn underline() {
+        let font = font::testing::default_font();
+        let lines = vec![(b"Hello".to_vec(), font.measure(b"Hello")), (b"Hi".to_vec(), font.measure(b"Hi"))];
+        let options = TextOptions { underline: true, ..Default::default() };
+
+        let baseline = 10;
+        let decorations = layout_decorations(&lines, &font, baseline, 20, Halign::Left, Valign::Top, &options);
+        assert_eq!(
+            decorations,
+            vec![
+                Decoration { x: 0, y: font.underline_position(baseline), width: font.measure(b"Hello") },
+                Decoration { x: 0, y: 20 + font.underline_position(baseline), width: font.measure(b"Hi") },
+            ]
+        );
+        assert!(font.underline_position(baseline) < font.tallest_char_height as i32);
+
+        let options = TextOptions { strikethrough: true, ..Default::default() };
+        let decorations = layout_decorations(&lines[1..], &font, baseline, 20, Halign::Right, Valign::Bottom, &options);
+        let width = font.measure(b"Hi");
+        assert_eq!(decorations, vec![Decoration { x: -width, y: -20 + font.strikethrough_position(baseline), width }]);
+
+        let no_options = Default::default();
+        assert!(layout_decorations(&lines, &font, baseline, 20, Halign::Left, Valign::Top, &no_options).is_empty());
+    }
+
+    #[test]
+    fn text_hook() {
+        let font = font::testing::default_font();
+        let mut seen = Vec::new();
+        let mut hook: Option<TextHook> = Some(Box::new(|s, _, _, font_id| {
+            assert_eq!(font_id, 3);
+            if s.as_ref() == b"HP" { Some("Vie".into()) } else { None }
+        }));
+        for text in &["HP", "MP"] {
+            let string = apply_text_hook(&mut hook, (*text).into(), Real::from(10.0), Real::from(20.0), 3);
+            seen.extend(LineIterator::new(string.as_ref().to_vec(), &font, None, true));
+        }
+        assert_eq!(seen, vec![(b"Vie".to_vec(), font.measure(b"Vie")), (b"MP".to_vec(), font.measure(b"MP"))]);
+
+        let string = apply_text_hook(&mut None, "HP".into(), Real::from(0.0), Real::from(0.0), 0);
+        assert_eq!(string.as_ref(), b"HP");
+    }
+
+    #[test]
+    fn block_align() {
+        let font = font::testing::default_font();
+        let max_width = Some(font.measure(b"Hello world"));
+        let lines = LineIterator::new(b"Hello world foo".to_vec(), &font, max_width, true).collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        let (wide, narrow) = (lines[0].1, lines[1].1);
+        assert!(wide > narrow);
+
+        let line_starts = |halign, options: &TextOptions| {
+            let quads = layout_lines(&lines, &font, 20, halign, Valign::Top, options);
+            let first_on = |y| quads.iter().find(|q| q.y == y).map(|q| q.x - q.line_x).unwrap();
+            (first_on(0), first_on(20))
+        };
+        // GM8 centres each line on the origin
+        assert_eq!(line_starts(Halign::Middle, &Default::default()), (-(wide / 2), -(narrow / 2)));
+        // in a block, each line is centred within the widest one, which starts at the origin
+        let block = TextOptions { block_align: true, ..Default::default() };
+        assert_eq!(line_starts(Halign::Middle, &block), (0, (wide - narrow) / 2));
+        assert_eq!(line_starts(Halign::Right, &block), (0, wide - narrow));
+        assert_eq!(line_starts(Halign::Left, &block), (0, 0));
+
+        let underline = TextOptions { underline: true, ..block };
+        let decorations = layout_decorations(&lines, &font, 10, 20, Halign::Middle, Valign::Top, &underline);
+        assert_eq!(decorations[1].x, (wide - narrow) / 2);
+    }
+
+    #[test]
+    fn wrapped_and_rotated() {
+        let font = font::testing::default_font();
+        // Wrapping is in unscaled units, so the scale doesn't change where lines break
+        let max_width = Some(font.measure(b"Hello world"));
+        let lines = LineIterator::new(b"Hello world foo".to_vec(), &font, max_width, true).collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[1].0.ends_with(b"foo"));
+
+        // Rotating by 90 degrees turns rightwards into upwards and downwards into rightwards, about the origin
+        let (x, y, scale) = (Real::from(100.0), Real::from(200.0), Real::from(2.0));
+        let transform = text_transform(x, y, scale, scale, Real::from(90.0));
+        let glyphs = layout_transformed(&lines, &font, 20, Halign::Left, Valign::Top, &Default::default(), transform);
+        let near = |a: Real, b: f64| (f64::from(a) - b).abs() < 1e-9;
+
+        let (first, first_x, first_y) = glyphs.first().unwrap();
+        let h = first.character.unwrap();
+        assert_eq!((first.x, first.y), (0, 0));
+        assert!(near(*first_x, 100.0 + f64::from(h.yoffset) * 2.0));
+        assert!(near(*first_y, 200.0 - f64::from(h.distance) * 2.0));
+
+        let (last, last_x, last_y) = glyphs.last().unwrap();
+        let o = last.character.unwrap();
+        assert_eq!(last.y, 20);
+        assert_eq!(last.x + last.advance, lines[1].1);
+        assert!(near(*last_x, 100.0 + f64::from(20 + o.yoffset) * 2.0));
+        assert!(near(*last_y, 200.0 - f64::from(last.x + o.distance) * 2.0));
+    }
+
+    #[test]
+    fn snap_baseline() {
+        let font = font::testing::default_font();
+        let mut iter = LineIterator::new(b"Ag\nyx".to_vec(), &font, None, true);
+        let lines = vec![iter.next().unwrap(), iter.next().unwrap()];
+        let layout = |snap_baseline| {
+            let options = TextOptions { snap_baseline, ..Default::default() };
+            let (one, zero) = (Real::from(1.0), Real::from(0.0));
+            let transform = text_transform(Real::from(10.3), Real::from(20.6), one, one, zero);
+            layout_transformed(&lines, &font, 15, Halign::Left, Valign::Top, &options, transform)
+        };
+        let near = |a: Real, b: f64| (f64::from(a) - b).abs() < 1e-9;
+        let is_whole = |y: Real| near(y, f64::from(y).round());
+        assert!(layout(false).iter().all(|(_, _, y)| !is_whole(*y)));
+
+        // every glyph's top is on a whole pixel and keeps its offset from the rest of its line, and x isn't moved
+        let snapped = layout(true);
+        assert!(snapped.iter().all(|(_, _, y)| is_whole(*y)));
+        for ((quad, x, y), (_, plain_x, plain_y)) in snapped.iter().zip(layout(false)) {
+            let yoffset = quad.character.unwrap().yoffset;
+            assert!(near(*y, f64::from(21 + quad.y + yoffset)));
+            assert!(near(*y - plain_y, 0.4));
+            assert_eq!(*x, plain_x);
+        }
+    }
+
+    #[test]
+    fn reveal_count() {
+        let font = font::testing::default_font();
+        let max_width = Some(font.measure(b"Hello world"));
+        let lines = LineIterator::new(b"Hello world foo".to_vec(), &font, max_width, true).collect::<Vec<_>>();
+        let full = layout_lines(&lines, &font, 20, Halign::Left, Valign::Top, &Default::default());
+        assert_eq!(lines.len(), 2);
+
+        for &n in &[0, 1, 7, 12] {
+            let mut glyphs = full.clone();
+            assert!(!reveal_glyphs(&mut glyphs, Some(n)));
+            assert_eq!(glyphs.len(), n);
+            // revealed glyphs sit where they would in the whole string, even on the wrapped line
+            assert!(glyphs.iter().zip(&full).all(|(a, b)| (a.x, a.y) == (b.x, b.y)));
+        }
+        let mut glyphs = full.clone();
+        assert!(reveal_glyphs(&mut glyphs, Some(full.len())));
+        assert!(reveal_glyphs(&mut glyphs, Some(100)));
+        assert!(reveal_glyphs(&mut glyphs, None));
+        assert_eq!(glyphs.len(), full.len());
+    }
+
+    #[test]
+    fn invalid_font_falls_back() {
+        let mut atlases = AtlasBuilder::new(1024);
+        let default = font::load_default_font(&mut atlases).unwrap();
+        let mut custom = font::load_default_font(&mut atlases).unwrap();
+        custom.name = b"custom".as_ref().into();
+        let fonts = vec![None, Some(Box::new(custom))];
 
-            cursor_y += line_height;
+        for &id in &[-1, 0, 2, i32::MAX] {
+            let font = current_font(&fonts, id, &default);
+            assert_eq!(font.name.as_ref(), b"default_font", "font {}", id);
+            let lines = vec![(b"Hi".to_vec(), font.measure(b"Hi"))];
+            let quads = layout_lines(&lines, font, 20, Halign::Left, Valign::Top, &Default::default());
+            let glyphs = quads.iter().map(|q| q.character.unwrap().atlas_ref).collect::<Vec<_>>();
+            let expected = [b'H', b'i'].iter().map(|&c| default.get_char(c).unwrap().atlas_ref).collect::<Vec<_>>();
+            assert_eq!(glyphs, expected);
         }
+        assert_eq!(current_font(&fonts, 1, &default).name.as_ref(), b"custom");
     }
 }
@@ -0,0 +1,180 @@
+use crate::{
+    game::{draw, Game},
+    gml::{self, Value},
+    math::Real,
+};
+use serde::{Deserialize, Serialize};
+
+/// How many entries the highscore table holds.
+pub const TABLE_SIZE: usize = 10;
+
+/// GM8's highscore table, kept sorted from the highest score down.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Highscores {
+    entries: Vec<(gml::String, Real)>,
+    /// The place of the most recently added entry, which is drawn in `new_colour`.
+    last_added: Option<usize>,
+    /// The colour filled in behind the table when it's drawn, which is nothing until highscore_set_colors sets one.
+    pub back_colour: Option<i32>,
+    pub new_colour: i32,
+    pub other_colour: i32,
+}
+
+impl Default for Highscores {
+    fn default() -> Self {
+        Self {
+            entries: vec![("<nobody>".into(), Real::from(0)); TABLE_SIZE],
+            last_added: None,
+            back_colour: None,
+            new_colour: 0x0000FF,
+            other_colour: 0x000000,
+        }
+    }
+}
+
+impl Highscores {
+    /// Resets every entry to nobody with a score of 0.
+    pub fn clear(&mut self) {
+        self.entries = Self::default().entries;
+        self.last_added = None;
+    }
+
+    /// Sets the colours of the background, the newest entry and every other entry, as highscore_set_colors does.
+    pub fn set_colours(&mut self, back: i32, new: i32, other: i32) {
+        self.back_colour = Some(back);
+        self.new_colour = new;
+        self.other_colour = other;
+    }
+
+    /// Adds an entry below any with the same score, returning its place from 0, or None if it didn't make the table.
+    pub fn add(&mut self, name: gml::String, score: Real) -> Option<usize> {
+        let place = self.entries.iter().position(|(_, s)| *s < score)?;
+        self.entries.insert(place, (name, score));
+        self.entries.truncate(TABLE_SIZE);
+        self.last_added = Some(place);
+        Some(place)
+    }
+
+    /// Gets the entry in the given place, counting from 0.
+    pub fn get(&self, place: usize) -> Option<&(gml::String, Real)> {
+        self.entries.get(place)
+    }
+
+    /// Gets every entry as values, ready for drawing as a score table.
+    pub fn rows(&self) -> Vec<(Value, Value)> {
+        self.entries.iter().map(|(name, score)| (Value::Str(name.clone()), Value::Real(*score))).collect()
+    }
+}
+
+/// Where one row of a score table is drawn, relative to the top left of its box.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RowLayout {
+    pub y: i32,
+    pub name_x: i32,
+    pub score_x: i32,
+}
+
+/// Lays out a score table in a box of the given size, given the width of each row's score.
+/// Like GM8, the rows are spread evenly down the box, with names against its left edge and scores against its right.
+pub fn layout_table(score_widths: &[i32], width: i32, height: i32) -> Vec<RowLayout> {
+    let rows = score_widths.len().max(1) as i32;
+    score_widths
+        .iter()
+        .enumerate()
+        .map(|(i, &score_width)| RowLayout { y: i as i32 * height / rows, name_x: 0, score_x: width - score_width })
+        .collect()
+}
+
+impl Game {
+    /// Draws rows of names and scores in the given box with the current font, in two aligned columns.
+    /// The row in the highlighted place is drawn in `colours.0`, and the rest in `colours.1`.
+    pub fn draw_score_table(
+        &mut self,
+        x1: Real,
+        y1: Real,
+        x2: Real,
+        y2: Real,
+        rows: &[(Value, Value)],
+        highlight: Option<usize>,
+        colours: (i32, i32),
+    ) {
+        self.with_font_state(|game| {
+            game.draw_halign = draw::Halign::Left;
+            game.draw_valign = draw::Valign::Top;
+
+            let scores = rows.iter().map(|(_, score)| score.repr()).collect::<Vec<_>>();
+            let widths =
+                scores.iter().map(|score| game.get_string_size(score.clone(), None, None).0).collect::<Vec<_>>();
+            let (width, height) = ((x2 - x1).round().to_i32(), (y2 - y1).round().to_i32());
+            for (i, (layout, ((name, _), score))) in
+                layout_table(&widths, width, height).into_iter().zip(rows.iter().zip(scores)).enumerate()
+            {
+                let colour = if highlight == Some(i) { colours.0 } else { colours.1 };
+                let colours = Some((colour, colour, colour, colour));
+                let (one, zero, alpha) = (Real::from(1), Real::from(0), game.draw_alpha);
+                let y = y1 + Real::from(layout.y);
+                let name_x = x1 + Real::from(layout.name_x);
+                game.draw_string(name_x, y, name.repr(), None, None, one, one, zero, colours, alpha);
+                let score_x = x1 + Real::from(layout.score_x);
+                game.draw_string(score_x, y, score, None, None, one, one, zero, colours, alpha);
+            }
+        })
+    }
+
+    /// Draws the highscore table in the given box with the current font, as `draw_highscore` does, over the
+    /// background colour if one has been set.
+    pub fn draw_highscores(&mut self, x1: Real, y1: Real, x2: Real, y2: Real) {
+        if let Some(back) = self.highscores.back_colour {
+            self.renderer.draw_rectangle(x1.into(), y1.into(), x2.into(), y2.into(), back, self.draw_alpha.into());
+        }
+        let rows = self.highscores.rows();
+        let colours = (self.highscores.new_colour, self.highscores.other_colour);
+        self.draw_score_table(x1, y1, x2, y2, &rows, self.highscores.last_added, colours);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asset::font;
+
+    #[test]
+    fn add_scores() {
+        let mut table = Highscores::default();
+        assert_eq!(table.add("first".into(), Real::from(100)), Some(0));
+        assert_eq!(table.add("second".into(), Real::from(50)), Some(1));
+        // ties go below the existing entry
+        assert_eq!(table.add("tied".into(), Real::from(100)), Some(1));
+        assert_eq!(table.add("zero".into(), Real::from(0)), None);
+        let names = (0..4).map(|i| table.get(i).unwrap().0.to_string()).collect::<Vec<_>>();
+        assert_eq!(names, ["first", "tied", "second", "<nobody>"]);
+        assert!(table.get(TABLE_SIZE).is_none());
+        table.clear();
+        assert_eq!(table.get(0).unwrap().0.to_string(), "<nobody>");
+    }
+
+    #[test]
+    fn colours() {
+        let mut table = Highscores::default();
+        assert_eq!((table.back_colour, table.new_colour, table.other_colour), (None, 0x0000FF, 0x000000));
+        table.set_colours(0xC0C0C0, 0x00FF00, 0xFFFFFF);
+        // clearing the entries leaves the colours alone
+        table.clear();
+        assert_eq!((table.back_colour, table.new_colour, table.other_colour), (Some(0xC0C0C0), 0x00FF00, 0xFFFFFF));
+    }
+
+    #[test]
+    fn column_alignment() {
+        let font = font::testing::default_font();
+        let scores: [&[u8]; 3] = [b"12500", b"800", b"7"];
+        let widths = scores.iter().map(|score| font.measure(score)).collect::<Vec<_>>();
+        let layout = layout_table(&widths, 300, 90);
+        assert_eq!(layout.iter().map(|row| row.y).collect::<Vec<_>>(), vec![0, 30, 60]);
+        for (row, width) in layout.iter().zip(&widths) {
+            // names line up on the left and scores end on the right edge
+            assert_eq!(row.name_x, 0);
+            assert_eq!(row.score_x + width, 300);
+        }
+        assert!(layout[0].score_x < layout[1].score_x && layout[1].score_x < layout[2].score_x);
+    }
+}
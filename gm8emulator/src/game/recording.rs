@@ -18,7 +18,10 @@ use serde::{Deserialize, Serialize};
 use std::{
     convert::TryFrom,
     fs::File,
+    io::{self, BufRead},
     path::PathBuf,
+    sync::mpsc,
+    thread,
     time::{Duration, Instant},
 };
 
@@ -305,10 +308,12 @@ impl Game {
 
         let mut game_running = true; // false indicates the game closed or crashed, and so advancing is not allowed
         let mut err_string: Option<String> = None;
+        self.start_undo_log();
 
         let mut frame_text = String::from("Frame: 0");
         let mut seed_text = format!("Seed: {}", self.rand.seed());
         let mut rerecord_text = format!("Re-record count: {}", config.rerecords);
+        let mut memory_text = self.memory_report().to_string();
         let save_text = (0..16).map(|i| format!("Save {}", i + 1)).collect::<Vec<_>>();
         let load_text = (0..16).map(|i| format!("Load {}", i + 1)).collect::<Vec<_>>();
         let select_text = (0..16).map(|i| format!("Select###Select{}", i + 1)).collect::<Vec<_>>();
@@ -382,6 +387,7 @@ impl Game {
 
                     frame_text = format!("Frame: {}", replay.frame_count());
                     seed_text = format!("Seed: {}", self.rand.seed());
+                    memory_text = self.memory_report().to_string();
                     self.renderer.resize_framebuffer(config.ui_width.into(), config.ui_height.into(), false);
                     self.renderer.set_state(&ui_renderer_state);
                     savestate = state;
@@ -416,6 +422,17 @@ impl Game {
         let mut new_rand: Option<Random> = None;
         let mut callback_data; // Putting this outside the loop makes sure it never goes out of scope
 
+        // Expressions typed into the terminal are evaluated against the game between frames
+        let (console_tx, console_rx) = mpsc::channel::<String>();
+        thread::spawn(move || {
+            for line in io::stdin().lock().lines() {
+                match line {
+                    Ok(line) if console_tx.send(line).is_ok() => (),
+                    _ => break,
+                }
+            }
+        });
+
         /* ----------------------
         Frame loop begins here
         ---------------------- */
@@ -469,6 +486,18 @@ impl Game {
                 }
             }
 
+            // evaluate console input on a snapshot, so that side-effects can't desync the replay
+            while let Ok(line) = console_rx.try_recv() {
+                if !game_running || line.trim().is_empty() {
+                    continue
+                }
+                let state = SaveState::from(self, replay.clone(), renderer_state.clone());
+                self.renderer.set_state(&renderer_state);
+                println!("{}", self.console_eval(&line, config.watched_ids.first().copied()));
+                let _ = state.load_into(self);
+                self.renderer.set_state(&ui_renderer_state);
+            }
+
             // present imgui
             let fps_text = format!("FPS: {}", io.framerate().round());
             let win_frame_height = context.frame_height();
@@ -730,6 +759,7 @@ impl Game {
 
                 frame_text = format!("Frame: {}", replay.frame_count());
                 seed_text = format!("Seed: {}", self.rand.seed());
+                memory_text = self.memory_report().to_string();
 
                 self.renderer.resize_framebuffer(config.ui_width.into(), config.ui_height.into(), true);
                 self.renderer.set_view(
@@ -754,6 +784,28 @@ impl Game {
                     config.watched_ids.iter().map(|id| (*id, InstanceReport::new(&*self, *id))).collect();
             }
 
+            // Only variables are put back, so the game can't carry on from here without desyncing the replay
+            if (frame.button("Step Back (B)", imgui::Vec2(165.0, 20.0), None)
+                || frame.key_pressed(input::ramen2vk(Key::B)))
+                && game_running
+                && err_string.is_none()
+                && replay.frame_count() > 0
+            {
+                self.rollback();
+                game_running = false;
+                err_string = Some(
+                    concat!(
+                        "Stepped back: variables hold what they did before the last frame.\n\n",
+                        "Load a savestate to carry on.",
+                    )
+                    .into(),
+                );
+                memory_text = self.memory_report().to_string();
+                instance_reports =
+                    config.watched_ids.iter().map(|id| (*id, InstanceReport::new(&*self, *id))).collect();
+                context_menu = None;
+            }
+
             if (frame.button("Quick Save (Q)", imgui::Vec2(165.0, 20.0), None)
                 || frame.key_pressed(input::ramen2vk(Key::Q)))
                 && game_running
@@ -794,6 +846,7 @@ impl Game {
 
                     frame_text = format!("Frame: {}", replay.frame_count());
                     seed_text = format!("Seed: {}", self.rand.seed());
+                    memory_text = self.memory_report().to_string();
                     context_menu = None;
                     new_rand = None;
                     new_mouse_pos = None;
@@ -830,6 +883,7 @@ impl Game {
             }
             frame.text(&rerecord_text);
             frame.text(&fps_text);
+            frame.text(&memory_text);
 
             let keyboard_label = if config.full_keyboard {
                 "Simple Keyboard###KeyboardLayout"
@@ -967,6 +1021,14 @@ impl Game {
                                     savestate::ReadError::DeserializeErr(err) => {
                                         format!("Error deserializing {}:\n\n{}", filename, err)
                                     },
+                                    savestate::ReadError::NewerFormat(version) => format!(
+                                        "{} was saved by a newer version of the emulator (format version {})",
+                                        filename, version
+                                    ),
+                                    savestate::ReadError::OlderFormat(version) => format!(
+                                        "{} was saved by an older version of the emulator (format version {})",
+                                        filename, version
+                                    ),
                                 });
                             },
                         }
@@ -1,10 +1,11 @@
 use crate::{
+    asset::Font,
     game::{
-        audio::AudioState, draw, external, includedfile::IncludedFile, model::Model, particle,
+        audio::AudioState, draw, external, highscore::Highscores, includedfile::IncludedFile, model::Model, particle,
         pathfinding::PotentialStepSettings, surface::Surface, transition::UserTransition, Assets, Game, Replay,
         RoomState, Version,
     },
-    gml::{self, ds, rand::Random, Compiler},
+    gml::{self, ds, json, rand::Random, value::EqualityQuirks, Compiler},
     handleman::HandleList,
     input::Input,
     instance::DummyFieldHolder,
@@ -25,6 +26,62 @@ use std::{
     rc::Rc,
 };
 
+/// Marks a savestate file which starts with a format version. Files from before that have no header.
+const HEADER_MAGIC: &[u8; 4] = b"OGMS";
+
+/// The version of the savestate format. Bump this whenever a change to anything in a SaveState means older versions
+/// of the emulator can't read what this one writes. Files with no header are version 0.
+/// Version 2 added font fallbacks.
+pub const FORMAT_VERSION: u32 = 2;
+
+/// Splits a savestate file into its format version and the rest of its contents.
+fn read_header(data: &[u8]) -> io::Result<(u32, &[u8])> {
+    match data.strip_prefix(HEADER_MAGIC) {
+        Some(mut rest) => rest.read_u32::<LE>().map(|version| (version, rest)),
+        None => Ok((0, data)),
+    }
+}
+
+/// Gets the contents of a savestate file after its header, if they're in the format this version reads.
+/// They aren't migrated from older versions, since everything after a change moves along with it.
+fn read_contents(data: &[u8]) -> Result<&[u8], ReadError> {
+    match read_header(data) {
+        Ok((version, _)) if version > FORMAT_VERSION => Err(ReadError::NewerFormat(version)),
+        Ok((version, _)) if version < FORMAT_VERSION => Err(ReadError::OlderFormat(version)),
+        Ok((_, contents)) => Ok(contents),
+        Err(err) => Err(ReadError::IOErr(err)),
+    }
+}
+
+/// A font in a savestate. Fonts which haven't changed since the game was loaded are stored by index only.
+#[derive(Clone, Serialize, Deserialize)]
+pub enum SavedFont {
+    Stock,
+    Dynamic(Box<Font>),
+}
+
+/// Converts the game's fonts into their savestate form.
+pub fn save_fonts(fonts: &[Option<Box<Font>>]) -> Vec<Option<SavedFont>> {
+    fonts
+        .iter()
+        .map(|font| {
+            font.as_ref().map(|font| if font.dirty { SavedFont::Dynamic(font.clone()) } else { SavedFont::Stock })
+        })
+        .collect()
+}
+
+/// Restores fonts from their savestate form, taking unchanged ones from the fonts the game was loaded with.
+pub fn load_fonts(fonts: Vec<Option<SavedFont>>, stock_fonts: &[Option<Box<Font>>]) -> Vec<Option<Box<Font>>> {
+    fonts
+        .into_iter()
+        .enumerate()
+        .map(|(i, font)| match font? {
+            SavedFont::Stock => stock_fonts.get(i).cloned().flatten(),
+            SavedFont::Dynamic(font) => Some(font),
+        })
+        .collect()
+}
+
 /// Represents a savestate. Very similar to the Game struct, but without things which aren't serialized.
 #[derive(Clone, Serialize, Deserialize)]
 pub struct SaveState {
@@ -32,6 +89,7 @@ pub struct SaveState {
     pub rand: Random,
     pub input: Input,
     pub assets: Assets,
+    pub fonts: Vec<Option<SavedFont>>,
     pub event_holders: [IndexMap<u32, Rc<RefCell<Vec<ID>>>>; 12],
     pub custom_draw_objects: HashSet<ID>,
 
@@ -64,12 +122,17 @@ pub struct SaveState {
     pub priority_queues: HandleList<ds::Priority>,
     pub grids: HandleList<ds::Grid>,
     pub ds_precision: Real,
+    pub ds_collation: ds::Collation,
+    pub json_nesting: json::Nesting,
 
     pub draw_font_id: ID,
     pub draw_colour: Colour,
     pub draw_alpha: Real,
     pub draw_halign: draw::Halign,
     pub draw_valign: draw::Valign,
+    pub text_options: draw::TextOptions,
+    pub text_clip: Option<draw::ClipRect>,
+    pub highscores: Highscores,
     pub surfaces: Vec<Option<Surface>>,
     pub surface_target: Option<i32>,
     pub models: Vec<Option<Model>>,
@@ -79,6 +142,7 @@ pub struct SaveState {
 
     pub uninit_fields_are_zero: bool,
     pub uninit_args_are_zero: bool,
+    pub equality_quirks: EqualityQuirks,
 
     pub potential_step_settings: PotentialStepSettings,
 
@@ -131,7 +195,8 @@ impl SaveState {
             compiler: game.compiler.clone(),
             rand: game.rand.clone(),
             input: game.input.clone(),
-            assets: game.assets.clone(),
+            assets: Assets { fonts: Vec::new(), ..game.assets.clone() },
+            fonts: save_fonts(&game.assets.fonts),
             event_holders: game.event_holders.clone(),
             custom_draw_objects: game.custom_draw_objects.clone(),
             background_colour: game.background_colour,
@@ -156,11 +221,16 @@ impl SaveState {
             priority_queues: game.priority_queues.clone(),
             grids: game.grids.clone(),
             ds_precision: game.ds_precision.clone(),
+            ds_collation: game.ds_collation,
+            json_nesting: game.json_nesting.clone(),
             draw_font_id: game.draw_font_id.clone(),
             draw_colour: game.draw_colour.clone(),
             draw_alpha: game.draw_alpha.clone(),
             draw_halign: game.draw_halign.clone(),
             draw_valign: game.draw_valign.clone(),
+            text_options: game.text_options,
+            text_clip: game.text_clip,
+            highscores: game.highscores.clone(),
             surfaces: game.surfaces.clone(),
             surface_target: game.surface_target,
             models: game.models.clone(),
@@ -169,6 +239,7 @@ impl SaveState {
             renderer_state,
             uninit_fields_are_zero: game.uninit_fields_are_zero.clone(),
             uninit_args_are_zero: game.uninit_args_are_zero.clone(),
+            equality_quirks: game.equality_quirks,
             potential_step_settings: game.potential_step_settings.clone(),
             fps: game.fps,
             frame_counter: game.frame_counter,
@@ -237,7 +308,7 @@ impl SaveState {
         game.compiler = self.compiler;
         game.rand = self.rand;
         game.input = self.input;
-        game.assets = self.assets;
+        game.assets = Assets { fonts: load_fonts(self.fonts, &game.stock_fonts), ..self.assets };
         game.event_holders = self.event_holders;
         game.custom_draw_objects = self.custom_draw_objects;
         game.background_colour = self.background_colour;
@@ -259,11 +330,16 @@ impl SaveState {
         game.priority_queues = self.priority_queues;
         game.grids = self.grids;
         game.ds_precision = self.ds_precision;
+        game.ds_collation = self.ds_collation;
+        game.json_nesting = self.json_nesting;
         game.draw_font_id = self.draw_font_id;
         game.draw_colour = self.draw_colour;
         game.draw_alpha = self.draw_alpha;
         game.draw_halign = self.draw_halign;
         game.draw_valign = self.draw_valign;
+        game.text_options = self.text_options;
+        game.text_clip = self.text_clip;
+        game.highscores = self.highscores;
         game.surfaces = surfaces;
         game.surface_target = self.surface_target;
         game.models = self.models;
@@ -271,6 +347,11 @@ impl SaveState {
         game.auto_draw = self.auto_draw;
         game.uninit_fields_are_zero = self.uninit_fields_are_zero;
         game.uninit_args_are_zero = self.uninit_args_are_zero;
+        game.equality_quirks = self.equality_quirks;
+        // anything recorded refers to what the game held before loading
+        if let Some(log) = &mut game.undo_log {
+            log.clear();
+        }
         game.potential_step_settings = self.potential_step_settings;
         game.fps = self.fps;
         game.frame_counter = self.frame_counter;
@@ -302,6 +383,11 @@ impl SaveState {
         (self.replay, self.renderer_state)
     }
 
+    /// Finds the ds_lists and ds_maps which differ between this SaveState and another, and where.
+    pub fn ds_changes(&self, other: &Self) -> Vec<ds::Change> {
+        ds::deep_changes((&self.lists, &self.maps), (&other.lists, &other.maps))
+    }
+
     /// Consumes this SaveState and returns just its Replay component.
     pub fn into_replay(self) -> Replay {
         self.replay
@@ -314,10 +400,9 @@ impl SaveState {
                 buffer.lz4_buf.clear();
                 buffer.lz4_buf.reserve(init_size);
                 match file.read_to_end(&mut buffer.lz4_buf) {
-                    Ok(_) => {
-                        match (buffer.lz4_buf.as_slice().read_u64::<LE>().map(|x| x as usize), buffer.lz4_buf.get(8..))
-                        {
-                            (Ok(len), Some(block)) => {
+                    Ok(_) => match read_contents(&buffer.lz4_buf) {
+                        Ok(mut block) => match block.read_u64::<LE>().map(|x| x as usize) {
+                            Ok(len) => {
                                 buffer.bin_buf.clear();
                                 buffer.bin_buf.reserve(len);
                                 unsafe { buffer.bin_buf.set_len(len) };
@@ -330,9 +415,9 @@ impl SaveState {
                                     Err(err) => Err(ReadError::DecompressErr(err)),
                                 }
                             },
-                            (Ok(_), None) => Err(ReadError::IOErr(io::Error::from(io::ErrorKind::UnexpectedEof))),
-                            (Err(err), _) => Err(ReadError::IOErr(err)),
-                        }
+                            Err(err) => Err(ReadError::IOErr(err)),
+                        },
+                        Err(err) => Err(err),
                     },
                     Err(err) => Err(ReadError::IOErr(err)),
                 }
@@ -351,7 +436,9 @@ impl SaveState {
                 match lz4::compress_to_vec(buffer.bin_buf.as_slice(), buffer.lz4_buf.as_mut(), lz4::ACC_LEVEL_DEFAULT) {
                     Ok(_length) => {
                         match OpenOptions::new().create(true).write(true).truncate(true).open(path).and_then(|mut f| {
-                            f.write_u64::<LE>(buffer.bin_buf.len() as u64)
+                            f.write_all(HEADER_MAGIC)
+                                .and_then(|_| f.write_u32::<LE>(FORMAT_VERSION))
+                                .and_then(|_| f.write_u64::<LE>(buffer.bin_buf.len() as u64))
                                 .and_then(|_| f.write_all(buffer.lz4_buf.as_slice()))
                         }) {
                             Ok(()) => Ok(()),
@@ -382,6 +469,10 @@ pub enum ReadError {
     IOErr(io::Error),
     DecompressErr(lzzzz::Error),
     DeserializeErr(Box<bincode::ErrorKind>),
+    /// The file was saved by a newer version of the emulator, in this format version.
+    NewerFormat(u32),
+    /// The file was saved by an older version of the emulator, in this format version.
+    OlderFormat(u32),
 }
 
 #[derive(Debug)]
@@ -390,3 +481,62 @@ pub enum WriteError {
     CompressErr(lzzzz::Error),
     SerializeErr(Box<bincode::ErrorKind>),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{asset::font, render::atlas::AtlasBuilder};
+
+    #[test]
+    fn stock_fonts_by_index() {
+        let mut atlases = AtlasBuilder::new(1024);
+        let stock = vec![Some(Box::new(font::load_default_font(&mut atlases).unwrap())), None];
+        let saved = save_fonts(&stock);
+        assert!(matches!(saved[0], Some(SavedFont::Stock)));
+        assert!(saved[1].is_none());
+        // Only the variant tags should be stored, not any glyph data
+        assert!(bincode::serialize(&saved).unwrap().len() < 32);
+
+        let loaded = load_fonts(saved, &stock);
+        assert_eq!(loaded[0].as_ref().unwrap().chars.len(), stock[0].as_ref().unwrap().chars.len());
+        assert!(loaded[1].is_none());
+    }
+
+    #[test]
+    fn dirty_fonts_saved() {
+        let mut atlases = AtlasBuilder::new(1024);
+        let stock = vec![Some(Box::new(font::load_default_font(&mut atlases).unwrap()))];
+        let mut fonts = stock.clone();
+        let mut character = fonts[0].as_ref().unwrap().get_char(b'a').unwrap();
+        character.offset += 1;
+        fonts[0].as_mut().unwrap().set_char(b'a', character);
+
+        let loaded = load_fonts(save_fonts(&fonts), &stock);
+        assert_eq!(loaded[0].as_ref().unwrap().get_char(b'a').unwrap().offset, character.offset);
+    }
+
+    #[test]
+    fn header_versions() {
+        let mut new = HEADER_MAGIC.to_vec();
+        new.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        new.extend_from_slice(&[1, 2, 3]);
+        assert_eq!(read_header(&new).unwrap(), (FORMAT_VERSION, &[1u8, 2, 3][..]));
+
+        // files from before the header start straight away with the length
+        let old = 100u64.to_le_bytes();
+        assert_eq!(read_header(&old).unwrap(), (0, &old[..]));
+
+        assert!(read_header(&new[..6]).is_err());
+    }
+
+    #[test]
+    fn older_formats_rejected() {
+        let file = |version: u32| [&HEADER_MAGIC[..], &version.to_le_bytes(), &[1, 2, 3]].concat();
+        assert_eq!(read_contents(&file(FORMAT_VERSION)).unwrap(), &[1, 2, 3]);
+        // a version 1 file has no font fallbacks, so its fonts can't be read as this version's
+        assert!(matches!(read_contents(&file(1)), Err(ReadError::OlderFormat(1))));
+        assert!(matches!(read_contents(&100u64.to_le_bytes()), Err(ReadError::OlderFormat(0))));
+        let newer = read_contents(&file(FORMAT_VERSION + 1));
+        assert!(matches!(newer, Err(ReadError::NewerFormat(v)) if v == FORMAT_VERSION + 1));
+    }
+}
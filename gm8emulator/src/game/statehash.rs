@@ -0,0 +1,209 @@
+//! Deterministic hashing of the GML-visible game state, used for finding the frame at which two runs desync.
+
+use crate::game::Game;
+use std::{
+    fs::{self, File},
+    hash::{Hash, Hasher},
+    io::{self, Write},
+    path::Path,
+};
+
+/// A 64-bit FNV-1a hasher. DefaultHasher's algorithm may change between Rust versions, but this always gives the same
+/// hash for the same state, so logs written by different builds of the emulator can be compared.
+#[derive(Clone, Copy, Debug)]
+pub struct StateHasher(u64);
+
+impl Default for StateHasher {
+    fn default() -> Self {
+        Self(0xCBF2_9CE4_8422_2325)
+    }
+}
+
+impl Hasher for StateHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 = (self.0 ^ u64::from(b)).wrapping_mul(0x0000_0100_0000_01B3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// A sequence of per-frame state hashes, as produced by `Game::state_hash()`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StateHashLog(Vec<u64>);
+
+impl StateHashLog {
+    pub fn new() -> Self {
+        Self(Vec::new())
+    }
+
+    pub fn push(&mut self, hash: u64) {
+        self.0.push(hash)
+    }
+
+    pub fn hashes(&self) -> &[u64] {
+        &self.0
+    }
+
+    /// Returns the first frame at which the two logs disagree, or None if one is a prefix of the other.
+    pub fn first_divergence(&self, other: &Self) -> Option<usize> {
+        self.0.iter().zip(other.0.iter()).position(|(a, b)| a != b)
+    }
+
+    /// Writes the log out as text, with each frame's hash on its own line in hex.
+    pub fn write_to(&self, mut w: impl Write) -> io::Result<()> {
+        for hash in self.0.iter() {
+            writeln!(w, "{:016x}", hash)?;
+        }
+        Ok(())
+    }
+
+    /// Reads a log written by `write_to`.
+    pub fn parse(text: &str) -> io::Result<Self> {
+        text.lines()
+            .map(|line| u64::from_str_radix(line.trim(), 16).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)))
+            .collect::<io::Result<_>>()
+            .map(Self)
+    }
+
+    pub fn to_file(&self, path: &Path) -> io::Result<()> {
+        let mut file = io::BufWriter::new(File::create(path)?);
+        self.write_to(&mut file)?;
+        file.flush()
+    }
+
+    pub fn from_file(path: &Path) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+}
+
+impl Game {
+    /// Computes a 64-bit hash over every Value reachable from GML: globals, instances (built-in and user
+    /// variables) and all data structures. Reals are hashed in canonical form, so the result is stable across runs.
+    pub fn state_hash(&self) -> u64 {
+        let mut state = StateHasher::default();
+
+        self.globals.hash(&mut state);
+
+        let list = &self.room.instance_list;
+        let mut iter = list.iter_by_insertion();
+        while let Some(handle) = iter.next(list) {
+            list.get(handle).hash(&mut state);
+        }
+        let mut iter = list.iter_inactive();
+        while let Some(handle) = iter.next(list) {
+            list.get(handle).hash(&mut state);
+        }
+
+        self.stacks.hash(&mut state);
+        self.queues.hash(&mut state);
+        self.lists.hash(&mut state);
+        self.maps.hash(&mut state);
+        self.priority_queues.hash(&mut state);
+        self.grids.hash(&mut state);
+
+        state.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        gml::Value,
+        instance::{DummyFieldHolder, Field, Instance},
+        math::Real,
+    };
+
+    fn new_instance() -> Instance {
+        Instance::new_ext(100001, 0.into(), 0.into(), 0, None, 1.into(), 1.into(), 0xFFFFFF, 1.into(), 0.into())
+    }
+
+    // Mirrors Game::state_hash for a single global and instance, whose speed is nudged at `diverge_at`
+    fn run(frames: usize, diverge_at: Option<usize>) -> StateHashLog {
+        let mut globals = DummyFieldHolder::new();
+        let instance = new_instance();
+        instance.speed.set(Real::from(2));
+        let mut log = StateHashLog::new();
+        for frame in 0..frames {
+            globals.fields.insert(frame % 3, Field::new(frame as u32 % 2, Value::from(frame as f64 * 0.5)));
+            if Some(frame) == diverge_at {
+                instance.speed.set(Real::from(2.0001));
+            }
+            instance.x.set(instance.x.get() + instance.speed.get());
+            let mut state = StateHasher::default();
+            globals.hash(&mut state);
+            instance.hash(&mut state);
+            log.push(state.finish());
+        }
+        log
+    }
+
+    #[test]
+    fn identical_runs() {
+        let a = run(10, None);
+        let b = run(10, None);
+        assert_eq!(a, b);
+        assert_eq!(a.first_divergence(&b), None);
+    }
+
+    #[test]
+    fn divergent_runs() {
+        let a = run(10, None);
+        let b = run(10, Some(6));
+        assert_eq!(a.first_divergence(&b), Some(6));
+    }
+
+    #[test]
+    fn builtin_variables() {
+        let hash = |instance: &Instance| {
+            let mut state = StateHasher::default();
+            instance.hash(&mut state);
+            state.finish()
+        };
+        let (a, b) = (new_instance(), new_instance());
+        assert_eq!(hash(&a), hash(&b));
+        b.direction.set(Real::from(90));
+        assert_ne!(hash(&a), hash(&b));
+        b.direction.set(Real::from(0));
+        b.alarms.borrow_mut().insert(0, 30);
+        assert_ne!(hash(&a), hash(&b));
+        a.alarms.borrow_mut().insert(0, 30);
+        a.image_angle.set(Real::from(-0.0));
+        assert_eq!(hash(&a), hash(&b));
+    }
+
+    #[test]
+    fn fixed_hasher() {
+        // the FNV-1a test vector, so the hashes in a log never change along with the standard library
+        let mut state = StateHasher::default();
+        state.write(b"a");
+        assert_eq!(state.finish(), 0xAF63_DC4C_8601_EC8C);
+    }
+
+    #[test]
+    fn log_files() {
+        let log = run(5, Some(2));
+        let mut text = Vec::new();
+        log.write_to(&mut text).unwrap();
+        assert_eq!(text.iter().filter(|&&b| b == b'\n').count(), 5);
+        let read = StateHashLog::parse(std::str::from_utf8(&text).unwrap()).unwrap();
+        assert_eq!(read, log);
+        assert_eq!(read.first_divergence(&run(5, None)), Some(2));
+        assert!(StateHashLog::parse("123\nnot a hash\n").is_err());
+    }
+
+    #[test]
+    fn canonical_zero() {
+        let hash = |v: Value| {
+            let mut state = StateHasher::default();
+            v.hash(&mut state);
+            state.finish()
+        };
+        assert_eq!(hash(Value::from(0.0)), hash(Value::from(-0.0)));
+        assert_eq!(hash(Value::from(f64::NAN)), hash(Value::from(-f64::NAN)));
+    }
+}
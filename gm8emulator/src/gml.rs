@@ -3,12 +3,25 @@ pub mod context;
 pub mod datetime;
 pub mod ds;
 pub mod file;
+pub mod json;
 pub mod kernel;
+#[cfg(feature = "lossy-warnings")]
+pub mod lossy;
 pub mod mappings;
+pub mod memory;
 pub mod network;
+#[cfg(feature = "packed-values")]
+pub mod packed;
+#[cfg(feature = "value-profiler")]
+pub mod profile;
 pub mod rand;
 pub mod runtime;
 pub mod string;
+#[cfg(test)]
+pub mod testing;
+#[cfg(feature = "value-trace")]
+pub mod trace;
+pub mod undo;
 pub mod value;
 
 pub use compiler::Compiler;
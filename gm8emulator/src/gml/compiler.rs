@@ -27,6 +27,39 @@ pub struct Compiler {
 
     /// Lookup table of unique field names
     fields: Vec<Box<[u8]>>,
+
+    /// String literals seen so far, so that every use of the same literal shares one string
+    #[serde(skip)]
+    strings: Interner,
+}
+
+/// How many string literals the compiler keeps for sharing. Any more, such as from a game calling execute_string with
+/// a different string every frame, are compiled without being shared.
+const MAX_INTERNED_STRINGS: usize = 4096;
+
+/// String literals shared between compiled code. It's only there to save memory, so it isn't saved in savestates and
+/// a copy of the compiler starts again without it.
+#[derive(Debug, Default)]
+struct Interner(HashMap<Box<[u8]>, gml::String>);
+
+impl Clone for Interner {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl Interner {
+    fn get(&mut self, string: &[u8]) -> gml::String {
+        match self.0.get(string) {
+            Some(interned) => interned.clone(),
+            None if self.0.len() < MAX_INTERNED_STRINGS => {
+                let interned = gml::String::from(string);
+                self.0.insert(string.into(), interned.clone());
+                interned
+            },
+            None => string.into(),
+        }
+    }
 }
 
 impl Compiler {
@@ -38,6 +71,7 @@ impl Compiler {
             script_names: HashMap::new(),
             extension_fn_names: HashMap::new(),
             fields: Vec::new(),
+            strings: Interner::default(),
         }
     }
 
@@ -271,7 +305,7 @@ impl Compiler {
         match expr {
             ast::Expr::LiteralReal(real) => Node::Literal { value: Value::Real(Real::from(*real)) },
 
-            ast::Expr::LiteralString(string) => Node::Literal { value: Value::Str((*string).into()) },
+            ast::Expr::LiteralString(string) => Node::Literal { value: Value::Str(self.strings.get(string)) },
 
             ast::Expr::LiteralIdentifier(string) => {
                 if let Some(entry) = self.constants.get(*string) {
@@ -645,3 +679,31 @@ impl Compiler {
         self.fields.get(id).map(|s| String::from_utf8_lossy(s).into())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_literals() {
+        let literal = |node: Node| match node {
+            Node::Literal { value: Value::Str(s) } => s,
+            _ => panic!("not a string literal"),
+        };
+        let mut compiler = Compiler::new();
+        let a = literal(compiler.compile_expression(b"\"abc\"").unwrap());
+        let b = literal(compiler.compile_expression(b"\"abc\"").unwrap());
+        assert!(a.ptr_eq(&b));
+
+        // savestates don't keep the shared literals, so loading one compiles them again
+        let mut copy = bincode::deserialize::<Compiler>(&bincode::serialize(&compiler).unwrap()).unwrap();
+        assert!(compiler.clone().strings.0.is_empty() && copy.strings.0.is_empty());
+        assert!(!literal(copy.compile_expression(b"\"abc\"").unwrap()).ptr_eq(&a));
+
+        let mut full = Compiler::new();
+        for i in 0..MAX_INTERNED_STRINGS + 10 {
+            full.strings.get(i.to_string().as_bytes());
+        }
+        assert_eq!(full.strings.0.len(), MAX_INTERNED_STRINGS);
+    }
+}
@@ -1,4 +1,8 @@
-use crate::{gml::Value, instance::DummyFieldHolder, types::ID};
+use crate::{
+    gml::{self, Value},
+    instance::DummyFieldHolder,
+    types::ID,
+};
 
 #[derive(Default)]
 pub struct Context {
@@ -58,4 +62,82 @@ impl Context {
             ..*context
         }
     }
+
+    /// Creates a new context as `copy_with_args` does, with the given arguments forwarded to it, as script_execute
+    /// does. Only the first 16 are kept and counted, as a script can't have more than that.
+    pub fn copy_with_arg_slice(context: &Self, args: &[Value]) -> Self {
+        let mut arguments: [Value; 16] = Default::default();
+        for (src, dest) in args.iter().zip(arguments.iter_mut()) {
+            *dest = src.clone();
+        }
+        Self::copy_with_args(context, arguments, args.len().min(16))
+    }
+
+    /// Reads an argument, as argument0-15 and argument[i] do. Any of the 16 arguments can be read whether it was
+    /// passed or not, and ones that weren't are 0 unless they've been written since, as in GM8. Anything past those
+    /// is an error, unless uninitialized arguments are treated as 0.
+    pub fn get_argument(&self, arg: usize, uninit_args_are_zero: bool) -> gml::Result<Value> {
+        match self.arguments.get(arg) {
+            Some(value) => Ok(value.clone()),
+            None if uninit_args_are_zero => Ok(Default::default()),
+            None => Err(gml::Error::UninitializedArgument(arg)),
+        }
+    }
+
+    /// Writes an argument. Only the ones which were passed can be written, unless uninitialized arguments are
+    /// treated as 0, in which case anything can be written but only the 16 arguments keep what's written to them.
+    pub fn set_argument(&mut self, arg: usize, value: Value, uninit_args_are_zero: bool) -> gml::Result<()> {
+        let arg_count = self.argument_count;
+        match self.arguments.get_mut(arg) {
+            Some(a) if arg < arg_count || uninit_args_are_zero => Ok(*a = value),
+            None if uninit_args_are_zero => Ok(()), // This corrupts stack in GM8...
+            _ => Err(gml::Error::UninitializedArgument(arg)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arguments_out_of_range() {
+        // a script called with two arguments
+        let mut arguments: [Value; 16] = Default::default();
+        arguments[0] = Value::from(1);
+        arguments[1] = Value::from("two");
+        let mut context = Context::copy_with_args(&Default::default(), arguments, 2);
+        let real = |value: gml::Result<Value>| f64::from(value.unwrap());
+
+        assert_eq!(real(context.get_argument(0, false)), 1.0);
+        assert_eq!(real(context.get_argument(5, false)), 0.0);
+        assert!(matches!(context.get_argument(16, false), Err(gml::Error::UninitializedArgument(16))));
+        assert_eq!(real(context.get_argument(16, true)), 0.0);
+
+        context.set_argument(1, Value::from(3), false).unwrap();
+        assert_eq!(real(context.get_argument(1, false)), 3.0);
+        assert!(context.set_argument(5, Value::from(3), false).is_err());
+        assert_eq!(real(context.get_argument(5, false)), 0.0);
+        context.set_argument(5, Value::from(4), true).unwrap();
+        assert_eq!(real(context.get_argument(5, false)), 4.0);
+        assert!(context.set_argument(16, Value::from(4), true).is_ok());
+    }
+
+    #[test]
+    fn forwarded_arguments() {
+        // script_execute(scr, 1, "two", 3.5)
+        let forwarded = [Value::from(1), Value::from("two"), Value::from(3.5)];
+        let context = Context::copy_with_arg_slice(&Default::default(), &forwarded);
+        assert_eq!(context.argument_count, 3);
+        assert_eq!(f64::from(context.get_argument(0, false).unwrap()), 1.0);
+        assert!(matches!(context.get_argument(1, false).unwrap(), Value::Str(s) if s.as_ref() == b"two"));
+        assert_eq!(f64::from(context.get_argument(2, false).unwrap()), 3.5);
+        assert_eq!(f64::from(context.get_argument(4, false).unwrap()), 0.0);
+
+        // anything past 16 is dropped
+        let many = (0..20_i32).map(Value::from).collect::<Vec<_>>();
+        let context = Context::copy_with_arg_slice(&Default::default(), &many);
+        assert_eq!(context.argument_count, 16);
+        assert_eq!(f64::from(context.get_argument(15, false).unwrap()), 15.0);
+    }
 }
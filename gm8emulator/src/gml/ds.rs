@@ -1,6 +1,7 @@
-use crate::{gml::Value, math::Real};
+use crate::{gml::Value, handleman::HandleList, math::Real};
+use encoding_rs::Encoding;
 use serde::{Deserialize, Serialize};
-use std::{cmp::Ordering, collections};
+use std::{cmp::Ordering, collections, io::Read};
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -8,24 +9,41 @@ pub type Stack = Vec<Value>;
 pub type Queue = collections::VecDeque<Value>;
 pub type List = Vec<Value>;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+/// A ds_map, kept as a sorted list rather than a HashMap. GM8 goes through keys in order with ds_map_find_next, keeps
+/// duplicate keys, and compares reals by ds_set_precision, which can change at any time. Equality within a precision
+/// isn't transitive, so no hash could put every pair of equal reals in the same bucket.
+#[derive(Clone, Debug, Hash, Serialize, Deserialize)]
 pub struct Map {
     pub keys: Vec<Value>, // should be pre-sorted
     pub values: Vec<Value>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Hash, Serialize, Deserialize)]
 pub struct Priority {
     pub priorities: Vec<Value>,
     pub values: Vec<Value>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Hash, Serialize, Deserialize)]
 pub struct Grid {
     grid: Vec<Vec<Value>>,
     height: usize, // if width is 0, this is inaccessible otherwise
 }
 
+/// How ds_list_sort orders strings. GM8 only has `Bytes`, the same order `<` uses,
+/// so `Locale` has to be opted into with ds_set_collation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Collation {
+    Bytes,
+    Locale,
+}
+
+impl Default for Collation {
+    fn default() -> Self {
+        Self::Bytes
+    }
+}
+
 #[derive(Debug)]
 pub enum Error {
     NonexistentStructure(i32),
@@ -40,6 +58,11 @@ impl From<Error> for String {
 }
 
 impl Map {
+    // Returns the value associated with the given key, or None if there is none.
+    pub fn find_value(&self, key: &Value, precision: Real) -> Option<Value> {
+        self.get_index(key, precision).map(|i| self.values[i].clone())
+    }
+
     // Returns the index associated with the given key, or None if there is none.
     pub fn get_index(&self, key: &Value, precision: Real) -> Option<usize> {
         match self.keys.binary_search_by(|x| cmp(x, key, precision)) {
@@ -140,6 +163,67 @@ impl Grid {
     }
 }
 
+// The first four bytes of each structure's ds_*_write string, which its reader checks for.
+const QUEUE_HEADER: u32 = 0xC9;
+const LIST_HEADER: u32 = 0x12D;
+const MAP_HEADER: u32 = 0x191;
+
+fn write_values<'a>(header: u32, size: usize, values: impl Iterator<Item = &'a Value>) -> String {
+    let mut output = hex::encode_upper(header.to_le_bytes());
+    output.push_str(&hex::encode_upper((size as u32).to_le_bytes()));
+    output.extend(values.map(|v| hex::encode_upper(v.as_bytes())));
+    output
+}
+
+fn read_size(reader: &mut &[u8], header: u32) -> Option<usize> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf).ok()?;
+    if u32::from_le_bytes(buf) != header {
+        return None
+    }
+    reader.read_exact(&mut buf).ok()?;
+    Some(u32::from_le_bytes(buf) as usize)
+}
+
+fn read_values(reader: &mut &[u8], size: usize) -> Option<Vec<Value>> {
+    (0..size).map(|_| Value::from_reader(reader)).collect()
+}
+
+/// Writes a list as ds_list_write does, in the same format as GM8.
+pub fn write_list(list: &List) -> String {
+    write_values(LIST_HEADER, list.len(), list.iter())
+}
+
+/// Reads a list from the decoded bytes of a ds_list_write string, or None if they aren't one.
+pub fn read_list(mut data: &[u8]) -> Option<List> {
+    let size = read_size(&mut data, LIST_HEADER)?;
+    read_values(&mut data, size)
+}
+
+/// Writes a queue as ds_queue_write does, from its head to its tail.
+pub fn write_queue(queue: &Queue) -> String {
+    write_values(QUEUE_HEADER, queue.len(), queue.iter())
+}
+
+/// Reads a queue from the decoded bytes of a ds_queue_write string, or None if they aren't one.
+pub fn read_queue(mut data: &[u8]) -> Option<Queue> {
+    let size = read_size(&mut data, QUEUE_HEADER)?;
+    read_values(&mut data, size).map(Queue::from)
+}
+
+/// Writes a map as ds_map_write does, with all of its keys in order and then all of its values.
+pub fn write_map(map: &Map) -> String {
+    write_values(MAP_HEADER, map.keys.len(), map.keys.iter().chain(&map.values))
+}
+
+/// Reads a map from the decoded bytes of a ds_map_write string, or None if they aren't one.
+pub fn read_map(mut data: &[u8]) -> Option<Map> {
+    let size = read_size(&mut data, MAP_HEADER)?;
+    let keys = read_values(&mut data, size)?;
+    let values = read_values(&mut data, size)?;
+    Some(Map { keys, values })
+}
+
 pub fn eq(v1: &Value, v2: &Value, precision: Real) -> bool {
     match (v1, v2) {
         (Value::Real(x), Value::Real(y)) => (*x - *y).abs() <= precision,
@@ -154,7 +238,8 @@ pub fn cmp(v1: &Value, v2: &Value, precision: Real) -> Ordering {
             if (*x - *y).abs() <= precision {
                 Ordering::Equal
             } else {
-                x.partial_cmp(&y).unwrap()
+                // NaN goes last rather than comparing as nothing, so sorts come out the same every time
+                x.cmp_nan_last(y)
             }
         },
         (Value::Str(x), Value::Str(y)) => x.cmp(y),
@@ -162,3 +247,199 @@ pub fn cmp(v1: &Value, v2: &Value, precision: Real) -> Ordering {
         (Value::Str(_), Value::Real(_)) => Ordering::Greater,
     }
 }
+
+/// Reads values as reals the way converting a Value into a Real does, with strings as 0, but by reference,
+/// so read-only passes over a list don't clone every value in it.
+pub fn reals(values: &[Value]) -> impl Iterator<Item = Real> + '_ {
+    values.iter().map(|value| match value {
+        Value::Real(r) => *r,
+        Value::Str(_) => Real::from(0.0),
+    })
+}
+
+/// Like `cmp`, but orders strings by the given collation, decoding them with the game's encoding if needed.
+pub fn cmp_collated(
+    v1: &Value,
+    v2: &Value,
+    precision: Real,
+    collation: Collation,
+    encoding: &'static Encoding,
+) -> Ordering {
+    match (v1, v2, collation) {
+        (Value::Str(x), Value::Str(y), Collation::Locale) => x.collate(y, encoding),
+        _ => cmp(v1, v2, precision),
+    }
+}
+
+/// A ds_list or ds_map whose contents differ between two saves of the game, by id. The index is the first entry
+/// (or key and value pair, for a map) which differs, or None if the structure only exists in one of them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Change {
+    List(i32, Option<usize>),
+    Map(i32, Option<usize>),
+}
+
+/// Gets the index of the first value which differs by `almost_equals`, or the end of the shorter list if one is
+/// longer, or None if they're the same.
+fn first_difference(old: &[Value], new: &[Value]) -> Option<usize> {
+    match old.iter().zip(new).position(|(a, b)| !a.almost_equals(b)) {
+        Some(index) => Some(index),
+        None if old.len() != new.len() => Some(old.len().min(new.len())),
+        None => None,
+    }
+}
+
+/// Compares every structure in two handle lists by id, giving the ids which differ and where.
+fn changed_ids<T>(
+    old: &HandleList<T>,
+    new: &HandleList<T>,
+    first_difference: impl Fn(&T, &T) -> Option<usize>,
+) -> Vec<(i32, Option<usize>)> {
+    let mut changes = old
+        .iter()
+        .filter_map(|(id, a)| match new.get(id) {
+            Some(b) => first_difference(a, b).map(|index| (id, Some(index))),
+            None => Some((id, None)),
+        })
+        .chain(new.iter().filter(|(id, _)| old.get(*id).is_none()).map(|(id, _)| (id, None)))
+        .collect::<Vec<_>>();
+    changes.sort_by_key(|(id, _)| *id);
+    changes
+}
+
+/// Finds every ds_list and ds_map which differs between two saves of the game.
+/// GM8 structures can't hold each other, only each other's ids, so a list "inside" a map is compared on its own:
+/// a change to it is reported against the list, and the map is unchanged as long as it holds the same id.
+pub fn deep_changes(
+    old: (&HandleList<List>, &HandleList<Map>),
+    new: (&HandleList<List>, &HandleList<Map>),
+) -> Vec<Change> {
+    let lists = changed_ids(old.0, new.0, |a, b| first_difference(a, b));
+    let maps = changed_ids(old.1, new.1, |a, b| {
+        let keys = first_difference(&a.keys, &b.keys);
+        let values = first_difference(&a.values, &b.values);
+        keys.into_iter().chain(values).min()
+    });
+    lists
+        .into_iter()
+        .map(|(id, index)| Change::List(id, index))
+        .chain(maps.into_iter().map(|(id, index)| Change::Map(id, index)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gml::testing::{assert_value_eq, values};
+
+    #[test]
+    fn undefined_missing_key() {
+        let map = Map { keys: vec!["a".into()], values: vec![1.into()] };
+        let find = |key: &str| map.find_value(&key.into(), Real::from(0.0)).unwrap_or_else(Value::undefined);
+        assert!(find("b").is_undefined());
+        assert!(!find("a").is_undefined());
+    }
+
+    #[test]
+    fn borrowed_reals() {
+        let list: List = values![1.5, 2, "3", -0.5];
+        let cloned = list.iter().cloned().map(Real::from).sum::<Real>();
+        assert_eq!(reals(&list).sum::<Real>(), cloned);
+        assert_eq!(reals(&list).sum::<Real>(), Real::from(3.0));
+        assert_eq!(reals(&list).collect::<Vec<_>>(), list.into_iter().map(Real::from).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn sort_nan() {
+        let nan = || Value::from(f64::NAN);
+        let sorted = |mut list: List| {
+            list.sort_by(|x, y| cmp(x, y, Real::from(0.0)));
+            list.into_iter().map(|x| Real::from(x).canonical_bits()).collect::<Vec<_>>()
+        };
+        let a = sorted(vec![3.into(), nan(), 1.into(), "s".into(), nan(), 2.into()]);
+        let b = sorted(vec![nan(), 2.into(), "s".into(), 3.into(), nan(), 1.into()]);
+        assert_eq!(a, b);
+        let reals = |xs: &[f64]| xs.iter().map(|&x| Real::from(x).canonical_bits()).collect::<Vec<_>>();
+        // the NaNs sort after every number but before strings, which are 0 here
+        assert_eq!(a, reals(&[1.0, 2.0, 3.0, f64::NAN, f64::NAN, 0.0]));
+    }
+
+    #[test]
+    fn write_format() {
+        // These are built by hand from the layout GM8 uses, not captured from GM8.1, so they only catch the emulator
+        // changing its own format. A string written by a real game belongs here once there's one to check against.
+        let header = |kind: &str, size: &str| format!("{}0000{}000000", kind, size);
+        let string = |len: &str, bytes: &str| format!("010000000000000000000000{}000000{}", len, bytes);
+        let real = |bits: &str| format!("00000000{}00000000", bits);
+
+        // {"name": "Kat\xe9", "score": 1500} and [0.5, "a"]
+        let map = [
+            header("9101", "02"),
+            string("04", "6E616D65"),
+            string("05", "73636F7265"),
+            string("04", "4B6174E9"),
+            real("0000000000709740"),
+        ]
+        .concat();
+        let list = [header("2D01", "02"), real("000000000000E03F"), string("01", "61")].concat();
+
+        let read = read_map(&hex::decode(&map).unwrap()).unwrap();
+        assert_eq!(<&[u8]>::from(&read.values[0]), b"Kat\xe9");
+        assert_value_eq!(read.values[1], Value::from(1500));
+        assert_eq!(write_map(&read), map);
+        let read = read_list(&hex::decode(&list).unwrap()).unwrap();
+        assert_value_eq!(read[0], Value::from(0.5));
+        assert_eq!(write_list(&read), list);
+        let queue = read.into_iter().collect::<Queue>();
+        assert_eq!(write_queue(&queue), list.replacen("2D01", "C900", 1));
+        assert_eq!(write_queue(&read_queue(&hex::decode(write_queue(&queue)).unwrap()).unwrap()), write_queue(&queue));
+
+        // the wrong header, or a string which ends early, is nothing
+        assert!(read_list(&hex::decode(&map).unwrap()).is_none());
+        assert!(read_map(&hex::decode(&map[..map.len() - 8]).unwrap()).is_none());
+    }
+
+    #[test]
+    fn sort_collation() {
+        // Windows-1252, where é is 0xE9 and so sorts after z byte by byte
+        let sorted = |collation| {
+            let mut list: List = vec!["z".into(), b"\xe9".as_ref().into(), "a".into(), "e".into()];
+            list.sort_by(|x, y| cmp_collated(x, y, Real::from(0.0), collation, encoding_rs::WINDOWS_1252));
+            list.iter()
+                .map(|x| match x {
+                    Value::Str(s) => s.as_ref().to_vec(),
+                    Value::Real(_) => unreachable!(),
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(sorted(Collation::Bytes), [b"a", b"e", b"z", b"\xe9"]);
+        assert_eq!(sorted(Collation::Locale), [b"a", b"e", b"\xe9", b"z"]);
+    }
+
+    #[test]
+    fn nested_changes() {
+        // a map holding the ids of two lists, the second of which has one value changed
+        let build = |leaf: f64| {
+            let mut lists = HandleList::new();
+            let inner = lists.put(values![1, "two", 3]);
+            let changed = lists.put(values!["a", leaf]);
+            let mut maps = HandleList::new();
+            maps.put(Map { keys: values!["first", "second"], values: values![inner, changed] });
+            (lists, maps)
+        };
+        let (old_lists, old_maps) = build(0.5);
+        let (new_lists, new_maps) = build(0.75);
+        assert!(deep_changes((&old_lists, &old_maps), (&old_lists, &old_maps)).is_empty());
+        assert_eq!(deep_changes((&old_lists, &old_maps), (&new_lists, &new_maps)), [Change::List(1, Some(1))]);
+
+        // a changed map value, a longer list and a destroyed list
+        let (mut new_lists, mut new_maps) = build(0.5);
+        new_maps.get_mut(0).unwrap().values[1] = Value::from(2);
+        new_lists.get_mut(0).unwrap().push(Value::from(4));
+        let (old, new) = ((&old_lists, &old_maps), (&new_lists, &new_maps));
+        assert_eq!(deep_changes(old, new), [Change::List(0, Some(3)), Change::Map(0, Some(1))]);
+        let mut fewer = HandleList::new();
+        fewer.put(values![1, "two", 3]);
+        assert_eq!(deep_changes((&old_lists, &old_maps), (&fewer, &old_maps)), [Change::List(1, None)]);
+    }
+}
@@ -63,6 +63,17 @@ impl std::fmt::Display for Error {
 
 // Helper functions
 
+/// Size of the pieces that strings are written in. Strings are written straight from the Value's own bytes a piece at
+/// a time, so a very long one doesn't turn into a single write call of its whole size.
+const WRITE_CHUNK_SIZE: usize = 0x10000;
+
+fn write_chunked(mut file: impl Write, text: &[u8]) -> io::Result<()> {
+    for chunk in text.chunks(WRITE_CHUNK_SIZE) {
+        file.write_all(chunk)?;
+    }
+    Ok(())
+}
+
 fn read_until<P>(file: impl Read, mut end_pred: P) -> io::Result<Vec<u8>>
 where
     P: FnMut(u8) -> bool,
@@ -155,7 +166,7 @@ impl TextHandle {
     }
 
     pub fn write_string(&mut self, text: &[u8]) -> Result<()> {
-        self.get_writer()?.write_all(text)?;
+        write_chunked(self.get_writer()?, text)?;
         Ok(())
     }
 
@@ -443,3 +454,40 @@ pub fn save_image<P: AsRef<Path>>(path: P, image: RgbaImage) -> Result<()> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct RecordingWriter {
+        data: Vec<u8>,
+        writes: Vec<usize>,
+    }
+
+    impl Write for RecordingWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.writes.push(buf.len());
+            self.data.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn chunk_boundaries() {
+        const N: usize = WRITE_CHUNK_SIZE;
+        let cases: &[(usize, &[usize])] =
+            &[(0, &[]), (1, &[1]), (N - 1, &[N - 1]), (N, &[N]), (N + 1, &[N, 1]), (N * 3 + 5, &[N, N, N, 5])];
+        for &(len, writes) in cases {
+            let text = (0..len as u32).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+            let mut writer = RecordingWriter::default();
+            write_chunked(&mut writer, &text).unwrap();
+            assert!(writer.data == text, "{} bytes came out wrong", len);
+            assert_eq!(writer.writes, writes);
+        }
+    }
+}
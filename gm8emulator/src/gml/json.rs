@@ -0,0 +1,488 @@
+//! JSON parsing and writing for json_decode and json_encode.
+//! Strings are kept as bytes, so non-ASCII text passes through in whatever encoding the game uses.
+
+use crate::{
+    gml::{ds, Value},
+    handleman::HandleList,
+    math::Real,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    Str(Vec<u8>),
+    Array(Vec<Json>),
+    Object(Vec<(Vec<u8>, Json)>),
+}
+
+/// The type of data structure a nested JSON object or array was decoded to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Nested {
+    Map,
+    List,
+}
+
+/// An entry in a map or list: a map key, which json_decode only creates as strings, or a list index.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Slot {
+    Map(i32, Vec<u8>),
+    List(i32, usize),
+}
+
+/// Records which entries json_decode filled with the id of a nested structure, so that json_encode can write them
+/// as objects and arrays rather than as numbers. Entries aren't tracked once the structures are edited by other
+/// functions, so deleting or moving a nested id with ds_* functions can leave a stale mark behind.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Nesting(HashMap<Slot, Nested>);
+
+struct Parser<'a> {
+    text: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn error<T>(&self, msg: &str) -> Result<T, String> {
+        Err(format!("{} at position {}", msg, self.pos))
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.text.get(self.pos).map_or(false, |c| matches!(c, b' ' | b'\t' | b'\n' | b'\r')) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, c: u8) -> Result<(), String> {
+        self.skip_whitespace();
+        if self.text.get(self.pos) == Some(&c) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            self.error(&format!("expected '{}'", c as char))
+        }
+    }
+
+    fn literal(&mut self, word: &[u8], value: Json) -> Result<Json, String> {
+        if self.text[self.pos..].starts_with(word) {
+            self.pos += word.len();
+            Ok(value)
+        } else {
+            self.error("invalid literal")
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.text.get(self.pos) {
+            Some(b'{') => self.object(),
+            Some(b'[') => self.array(),
+            Some(b'"') => self.string().map(Json::Str),
+            Some(b't') => self.literal(b"true", Json::Bool(true)),
+            Some(b'f') => self.literal(b"false", Json::Bool(false)),
+            Some(b'n') => self.literal(b"null", Json::Null),
+            Some(b'-') | Some(b'0'..=b'9') => self.number(),
+            Some(_) => self.error("unexpected character"),
+            None => self.error("unexpected end of input"),
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, String> {
+        self.expect(b'{')?;
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.text.get(self.pos) == Some(&b'}') {
+            self.pos += 1;
+            return Ok(Json::Object(members))
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            self.expect(b':')?;
+            members.push((key, self.value()?));
+            self.skip_whitespace();
+            match self.text.get(self.pos) {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(Json::Object(members))
+                },
+                _ => return self.error("expected ',' or '}'"),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Json, String> {
+        self.expect(b'[')?;
+        let mut elements = Vec::new();
+        self.skip_whitespace();
+        if self.text.get(self.pos) == Some(&b']') {
+            self.pos += 1;
+            return Ok(Json::Array(elements))
+        }
+        loop {
+            elements.push(self.value()?);
+            self.skip_whitespace();
+            match self.text.get(self.pos) {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(Json::Array(elements))
+                },
+                _ => return self.error("expected ',' or ']'"),
+            }
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let digits = self.text.get(self.pos..self.pos + 4).and_then(|d| std::str::from_utf8(d).ok());
+        match digits.and_then(|d| u32::from_str_radix(d, 16).ok()) {
+            Some(n) => {
+                self.pos += 4;
+                Ok(n)
+            },
+            None => self.error("invalid unicode escape"),
+        }
+    }
+
+    fn string(&mut self) -> Result<Vec<u8>, String> {
+        if self.text.get(self.pos) != Some(&b'"') {
+            return self.error("expected string")
+        }
+        self.pos += 1;
+        let mut out = Vec::new();
+        loop {
+            let c = match self.text.get(self.pos) {
+                Some(&c) => c,
+                None => return self.error("unterminated string"),
+            };
+            self.pos += 1;
+            match c {
+                b'"' => return Ok(out),
+                b'\\' => {
+                    let escape = self.text.get(self.pos).copied();
+                    self.pos += 1;
+                    match escape {
+                        Some(b'"') => out.push(b'"'),
+                        Some(b'\\') => out.push(b'\\'),
+                        Some(b'/') => out.push(b'/'),
+                        Some(b'b') => out.push(0x08),
+                        Some(b'f') => out.push(0x0C),
+                        Some(b'n') => out.push(b'\n'),
+                        Some(b'r') => out.push(b'\r'),
+                        Some(b't') => out.push(b'\t'),
+                        Some(b'u') => {
+                            let mut code = self.hex4()?;
+                            if (0xD800..0xDC00).contains(&code) && self.text[self.pos..].starts_with(b"\\u") {
+                                // a high surrogate without a low one after it is U+FFFD, and whatever's after it
+                                // is read on its own
+                                let high_end = self.pos;
+                                self.pos += 2;
+                                match self.hex4()? {
+                                    low @ 0xDC00..=0xDFFF => code = 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00),
+                                    _ => self.pos = high_end,
+                                }
+                            }
+                            let ch = std::char::from_u32(code).unwrap_or(std::char::REPLACEMENT_CHARACTER);
+                            out.extend_from_slice(ch.encode_utf8(&mut [0; 4]).as_bytes());
+                        },
+                        _ => return self.error("invalid escape"),
+                    }
+                },
+                c => out.push(c),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.pos;
+        while self.text.get(self.pos).map_or(false, |c| matches!(c, b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9')) {
+            self.pos += 1;
+        }
+        match std::str::from_utf8(&self.text[start..self.pos]).ok().and_then(|n| n.parse::<f64>().ok()) {
+            Some(n) => Ok(Json::Number(n)),
+            None => {
+                self.pos = start;
+                self.error("invalid number")
+            },
+        }
+    }
+}
+
+/// Parses a JSON document. Anything but whitespace after the value is an error.
+pub fn parse(text: &[u8]) -> Result<Json, String> {
+    let mut parser = Parser { text, pos: 0 };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    if parser.pos == text.len() { Ok(value) } else { parser.error("trailing characters") }
+}
+
+fn write_string(s: &[u8], out: &mut Vec<u8>) {
+    out.push(b'"');
+    for &c in s {
+        match c {
+            b'"' => out.extend_from_slice(b"\\\""),
+            b'\\' => out.extend_from_slice(b"\\\\"),
+            b'\n' => out.extend_from_slice(b"\\n"),
+            b'\r' => out.extend_from_slice(b"\\r"),
+            b'\t' => out.extend_from_slice(b"\\t"),
+            c if c < 0x20 => out.extend_from_slice(format!("\\u{:04x}", c).as_bytes()),
+            c => out.push(c),
+        }
+    }
+    out.push(b'"');
+}
+
+/// Writes a JSON document without any whitespace. Numbers are written with as many digits as it takes to read them
+/// back exactly, and non-finite numbers, which JSON can't represent, are written as null.
+pub fn write(json: &Json, out: &mut Vec<u8>) {
+    match json {
+        Json::Null => out.extend_from_slice(b"null"),
+        Json::Bool(b) => out.extend_from_slice(if *b { b"true" } else { b"false" }),
+        Json::Number(n) if n.is_finite() => out.extend_from_slice(n.to_string().as_bytes()),
+        Json::Number(_) => out.extend_from_slice(b"null"),
+        Json::Str(s) => write_string(s, out),
+        Json::Array(elements) => {
+            out.push(b'[');
+            for (i, element) in elements.iter().enumerate() {
+                if i > 0 {
+                    out.push(b',');
+                }
+                write(element, out);
+            }
+            out.push(b']');
+        },
+        Json::Object(members) => {
+            out.push(b'{');
+            for (i, (key, value)) in members.iter().enumerate() {
+                if i > 0 {
+                    out.push(b',');
+                }
+                write_string(key, out);
+                out.push(b':');
+                write(value, out);
+            }
+            out.push(b'}');
+        },
+    }
+}
+
+struct Decoder<'a> {
+    lists: &'a mut HandleList<ds::List>,
+    maps: &'a mut HandleList<ds::Map>,
+    nesting: &'a mut Nesting,
+    precision: Real,
+}
+
+impl Decoder<'_> {
+    fn value(&mut self, json: Json, slot: Option<Slot>) -> Value {
+        let (kind, id) = match json {
+            Json::Null => return self.scalar(Value::from(0), slot),
+            Json::Bool(b) => return self.scalar(Value::from(b), slot),
+            Json::Number(n) => return self.scalar(Value::from(n), slot),
+            Json::Str(s) => return self.scalar(Value::Str(s.into()), slot),
+            Json::Array(elements) => {
+                let id = self.lists.put(ds::List::with_capacity(elements.len()));
+                for (i, element) in elements.into_iter().enumerate() {
+                    let value = self.value(element, Some(Slot::List(id, i)));
+                    self.lists.get_mut(id).unwrap().push(value);
+                }
+                (Nested::List, id)
+            },
+            Json::Object(members) => {
+                let id = self.maps.put(ds::Map { keys: Vec::new(), values: Vec::new() });
+                for (key, member) in members {
+                    let value = self.value(member, Some(Slot::Map(id, key.clone())));
+                    let key = Value::Str(key.into());
+                    let map = self.maps.get_mut(id).unwrap();
+                    // later duplicates win, as in most JSON readers
+                    match map.get_index(&key, self.precision) {
+                        Some(index) => map.values[index] = value,
+                        None => {
+                            let index = map.get_next_index(&key, self.precision);
+                            map.keys.insert(index, key);
+                            map.values.insert(index, value);
+                        },
+                    }
+                }
+                (Nested::Map, id)
+            },
+        };
+        if let Some(slot) = slot {
+            self.nesting.0.insert(slot, kind);
+        }
+        id.into()
+    }
+
+    fn scalar(&mut self, value: Value, slot: Option<Slot>) -> Value {
+        // the slot may have been used by a destroyed structure with the same id
+        if let Some(slot) = slot {
+            self.nesting.0.remove(&slot);
+        }
+        value
+    }
+}
+
+/// Builds ds_maps and ds_lists out of a JSON document and returns the id of the outermost map.
+/// Numbers become reals, booleans become 1 or 0 and null becomes 0. If the document isn't an object, its value is
+/// put in a map under the key "default".
+pub fn decode(
+    json: Json,
+    lists: &mut HandleList<ds::List>,
+    maps: &mut HandleList<ds::Map>,
+    nesting: &mut Nesting,
+    precision: Real,
+) -> i32 {
+    let json = match json {
+        Json::Object(_) => json,
+        other => Json::Object(vec![(b"default".to_vec(), other)]),
+    };
+    let mut decoder = Decoder { lists, maps, nesting, precision };
+    decoder.value(json, None).round()
+}
+
+struct Encoder<'a> {
+    lists: &'a HandleList<ds::List>,
+    maps: &'a HandleList<ds::Map>,
+    nesting: &'a Nesting,
+    visiting: Vec<(Nested, i32)>,
+}
+
+impl Encoder<'_> {
+    fn structure(&mut self, kind: Nested, id: i32) -> Json {
+        // a structure that contains itself can't be written out, so cut the cycle short
+        if self.visiting.contains(&(kind, id)) {
+            return Json::Null
+        }
+        self.visiting.push((kind, id));
+        let (lists, maps) = (self.lists, self.maps);
+        let json = match kind {
+            Nested::List => match lists.get(id) {
+                Some(list) => Json::Array(
+                    list.iter().enumerate().map(|(i, value)| self.entry(value, Some(Slot::List(id, i)))).collect(),
+                ),
+                None => Json::Null,
+            },
+            Nested::Map => match maps.get(id) {
+                Some(map) => Json::Object(
+                    map.keys
+                        .iter()
+                        .zip(&map.values)
+                        .map(|(key, value)| match key {
+                            Value::Str(key) => {
+                                let key = key.as_ref().to_vec();
+                                (key.clone(), self.entry(value, Some(Slot::Map(id, key))))
+                            },
+                            Value::Real(_) => (key.repr().as_ref().to_vec(), self.entry(value, None)),
+                        })
+                        .collect(),
+                ),
+                None => Json::Null,
+            },
+        };
+        self.visiting.pop();
+        json
+    }
+
+    fn entry(&mut self, value: &Value, slot: Option<Slot>) -> Json {
+        match (value, slot.and_then(|slot| self.nesting.0.get(&slot))) {
+            (Value::Real(id), Some(&kind)) => self.structure(kind, id.round().to_i32()),
+            (Value::Real(r), None) => Json::Number(r.into_inner()),
+            (Value::Str(s), _) => Json::Str(s.as_ref().to_vec()),
+        }
+    }
+}
+
+/// Turns a ds_map back into a JSON document, following the entries json_decode marked as nested.
+/// Returns None if the map doesn't exist.
+pub fn encode(id: i32, lists: &HandleList<ds::List>, maps: &HandleList<ds::Map>, nesting: &Nesting) -> Option<Json> {
+    maps.get(id)?;
+    Some(Encoder { lists, maps, nesting, visiting: Vec::new() }.structure(Nested::Map, id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(json: &Json) -> Json {
+        let mut out = Vec::new();
+        write(json, &mut out);
+        parse(&out).unwrap()
+    }
+
+    #[test]
+    fn nested() {
+        let text = r#" {"name": "café \"bar\"", "list": [1, [2, 3], {"a": null}], "ok": true, "empty": {}} "#;
+        let json = parse(text.as_bytes()).unwrap();
+        assert_eq!(
+            json,
+            Json::Object(vec![
+                (b"name".to_vec(), Json::Str("café \"bar\"".as_bytes().to_vec())),
+                (
+                    b"list".to_vec(),
+                    Json::Array(vec![
+                        Json::Number(1.0),
+                        Json::Array(vec![Json::Number(2.0), Json::Number(3.0)]),
+                        Json::Object(vec![(b"a".to_vec(), Json::Null)]),
+                    ])
+                ),
+                (b"ok".to_vec(), Json::Bool(true)),
+                (b"empty".to_vec(), Json::Object(vec![])),
+            ])
+        );
+        assert_eq!(round_trip(&json), json);
+        assert_eq!(parse(br#""\ud83d\ude00""#).unwrap(), Json::Str("😀".as_bytes().to_vec()));
+        assert_eq!(parse(br#""\ud800\u0041""#).unwrap(), Json::Str("\u{FFFD}A".as_bytes().to_vec()));
+        assert_eq!(parse(br#""\ude00x""#).unwrap(), Json::Str("\u{FFFD}x".as_bytes().to_vec()));
+
+        assert!(parse(b"{\"a\": 1,}").is_err());
+        assert!(parse(b"[1 2]").is_err());
+        assert!(parse(b"\"unterminated").is_err());
+        assert!(parse(b"1 1").is_err());
+    }
+
+    #[test]
+    fn number_precision() {
+        for &n in &[0.1, 1.0 / 3.0, -0.0, 1e300, 5e-324, 9007199254740993.0, 123456789.125, f64::MAX] {
+            match round_trip(&Json::Number(n)) {
+                Json::Number(m) => assert_eq!(m.to_bits(), n.to_bits()),
+                other => panic!("{} came back as {:?}", n, other),
+            }
+        }
+        assert_eq!(parse(b"1e2").unwrap(), Json::Number(100.0));
+        assert_eq!(parse(b"-2.5E-1").unwrap(), Json::Number(-0.25));
+        assert_eq!(round_trip(&Json::Number(f64::NAN)), Json::Null);
+    }
+
+    #[test]
+    fn structures() {
+        let mut lists = HandleList::new();
+        let mut maps = HandleList::new();
+        let mut nesting = Nesting::default();
+        let precision = Real::from(0.00000001);
+
+        let text = br#"{"list": [1, {"b": "x"}, []], "c": 2.5, "a": null, "c": 3}"#;
+        let id = decode(parse(text).unwrap(), &mut lists, &mut maps, &mut nesting, precision);
+        let map = maps.get(id).unwrap();
+        assert_eq!(map.keys.len(), 3);
+        let list_id = map.values[map.get_index(&Value::from("list"), precision).unwrap()].round();
+        assert_eq!(lists.get(list_id).unwrap().len(), 3);
+
+        let mut out = Vec::new();
+        write(&encode(id, &lists, &maps, &nesting).unwrap(), &mut out);
+        assert_eq!(out, br#"{"a":0,"c":3,"list":[1,{"b":"x"},[]]}"#.to_vec());
+
+        let id = decode(parse(b"[0.1, true]").unwrap(), &mut lists, &mut maps, &mut nesting, precision);
+        let mut out = Vec::new();
+        write(&encode(id, &lists, &maps, &nesting).unwrap(), &mut out);
+        assert_eq!(out, br#"{"default":[0.1,1]}"#.to_vec());
+
+        // a map that contains itself
+        let id = decode(parse(br#"{"self": {}}"#).unwrap(), &mut lists, &mut maps, &mut nesting, precision);
+        maps.get_mut(id).unwrap().values[0] = Value::from(id);
+        assert_eq!(encode(id, &lists, &maps, &nesting), Some(Json::Object(vec![(b"self".to_vec(), Json::Null)])));
+        assert_eq!(encode(-1, &lists, &maps, &nesting), None);
+    }
+}
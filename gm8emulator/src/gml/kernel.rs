@@ -5,19 +5,19 @@
 use crate::{
     action, asset,
     game::{
-        draw, external, gm_save::GMSave, model, particle, pathfinding, replay, surface::Surface,
+        clipboard, draw, external, gm_save::GMSave, model, particle, pathfinding, replay, surface::Surface,
         transition::UserTransition, view::View, Game, GetAsset, PlayType, SceneChange, Version,
     },
     gml::{
         self,
         datetime::{self, DateTime},
-        ds, file,
+        ds, file, json,
         mappings::{self, constants as gml_consts},
-        network, Context, Value,
+        network, value::Coercion, Context, Value,
     },
     handleman::HandleManager,
     input::MouseButton,
-    instance::{Field, Instance, InstanceState},
+    instance::{Field, Instance, InstanceState, ARRAY_ROW},
     math::Real,
     render::{BlendType, Fog, Light, Renderer, Scaling},
     tile::Tile,
@@ -35,8 +35,14 @@ macro_rules! _arg_into {
     (bool, $v: expr) => {{ Ok($v.is_truthy()) }};
     (int, $v: expr) => {{ Ok(<Value as Into<i32>>::into($v.clone())) }};
     (real, $v: expr) => {{ Ok(<Value as Into<Real>>::into($v.clone())) }};
+    (colour, $v: expr) => {{ Ok($v.as_colour() as i32) }};
     (string, $v: expr) => {{ Ok(String::from_utf8_lossy(<&Value as Into<&[u8]>>::into($v))) }};
     (bytes, $v: expr) => {{ Ok(<Value as Into<gml::String>>::into($v.clone())) }};
+    (strict_int, $v: expr) => {{ $v.try_as::<i32>() }};
+    (strict_real, $v: expr) => {{ $v.try_as::<Real>() }};
+    (coerce_int, $v: expr) => {{ $v.coerce::<i32>(Coercion::CoerceReal) }};
+    (coerce_real, $v: expr) => {{ $v.coerce::<Real>(Coercion::CoerceReal) }};
+    (coerce_string, $v: expr) => {{ $v.coerce::<gml::String>(Coercion::CoerceString) }};
 }
 
 macro_rules! _count_rep {
@@ -303,7 +309,7 @@ impl Game {
     }
 
     pub fn window_set_color(&mut self, args: &[Value]) -> gml::Result<Value> {
-        let col = expect_args!(args, [int])?;
+        let col = expect_args!(args, [colour])?;
         self.background_colour = (col as u32).into();
         Ok(Default::default())
     }
@@ -536,7 +542,7 @@ impl Game {
     }
 
     pub fn draw_set_color(&mut self, args: &[Value]) -> gml::Result<Value> {
-        let col = expect_args!(args, [int])?;
+        let col = expect_args!(args, [colour])?;
         self.draw_colour = (col as u32).into();
         Ok(Default::default())
     }
@@ -617,7 +623,7 @@ impl Game {
     }
 
     pub fn merge_color(args: &[Value]) -> gml::Result<Value> {
-        let (c1, c2, amount) = expect_args!(args, [int, int, real])?;
+        let (c1, c2, amount) = expect_args!(args, [colour, colour, real])?;
         let r = Real::from(c1 & 255) * (Real::from(1) - amount) + Real::from(c2 & 255) * amount;
         let g = Real::from((c1 >> 8) & 255) * (Real::from(1) - amount) + Real::from((c2 >> 8) & 255) * amount;
         let b = Real::from((c1 >> 16) & 255) * (Real::from(1) - amount) + Real::from((c2 >> 16) & 255) * amount;
@@ -660,7 +666,7 @@ impl Game {
     }
 
     pub fn draw_clear(&mut self, args: &[Value]) -> gml::Result<Value> {
-        let col = expect_args!(args, [int])?;
+        let col = expect_args!(args, [colour])?;
         if self.gm_version == Version::GameMaker8_0 && !self.surface_fix {
             self.renderer.clear_view_no_zbuf((col as u32).into(), 1.0);
         } else {
@@ -670,7 +676,7 @@ impl Game {
     }
 
     pub fn draw_clear_alpha(&mut self, args: &[Value]) -> gml::Result<Value> {
-        let (col, alpha) = expect_args!(args, [int, real])?;
+        let (col, alpha) = expect_args!(args, [colour, real])?;
         if self.gm_version == Version::GameMaker8_0 && !self.surface_fix {
             self.renderer.clear_view_no_zbuf((col as u32).into(), alpha.into());
         } else {
@@ -932,19 +938,19 @@ impl Game {
     }
 
     pub fn draw_point_color(&mut self, args: &[Value]) -> gml::Result<Value> {
-        let (x, y, col) = expect_args!(args, [real, real, int])?;
+        let (x, y, col) = expect_args!(args, [real, real, colour])?;
         self.renderer.draw_point(x.into(), y.into(), col, self.draw_alpha.into());
         Ok(Default::default())
     }
 
     pub fn draw_line_color(&mut self, args: &[Value]) -> gml::Result<Value> {
-        let (x1, y1, x2, y2, c1, c2) = expect_args!(args, [real, real, real, real, int, int])?;
+        let (x1, y1, x2, y2, c1, c2) = expect_args!(args, [real, real, real, real, colour, colour])?;
         self.renderer.draw_line(x1.into(), y1.into(), x2.into(), y2.into(), None, c1, c2, self.draw_alpha.into());
         Ok(Default::default())
     }
 
     pub fn draw_line_width_color(&mut self, args: &[Value]) -> gml::Result<Value> {
-        let (x1, y1, x2, y2, w, c1, c2) = expect_args!(args, [real, real, real, real, real, int, int])?;
+        let (x1, y1, x2, y2, w, c1, c2) = expect_args!(args, [real, real, real, real, real, colour, colour])?;
         self.renderer.draw_line(
             x1.into(),
             y1.into(),
@@ -960,7 +966,7 @@ impl Game {
 
     pub fn draw_rectangle_color(&mut self, args: &[Value]) -> gml::Result<Value> {
         let (x1, y1, x2, y2, c1, c2, c3, c4, outline) =
-            expect_args!(args, [real, real, real, real, int, int, int, int, bool])?;
+            expect_args!(args, [real, real, real, real, colour, colour, colour, colour, bool])?;
         self.renderer.draw_rectangle_gradient(
             x1.into(),
             y1.into(),
@@ -977,7 +983,7 @@ impl Game {
     }
 
     pub fn draw_roundrect_color(&mut self, args: &[Value]) -> gml::Result<Value> {
-        let (x1, y1, x2, y2, col1, col2, outline) = expect_args!(args, [real, real, real, real, int, int, bool])?;
+        let (x1, y1, x2, y2, col1, col2, outline) = expect_args!(args, [real, real, real, real, colour, colour, bool])?;
         self.renderer.draw_roundrect(
             x1.into(),
             y1.into(),
@@ -993,7 +999,7 @@ impl Game {
 
     pub fn draw_triangle_color(&mut self, args: &[Value]) -> gml::Result<Value> {
         let (x1, y1, x2, y2, x3, y3, c1, c2, c3, outline) =
-            expect_args!(args, [real, real, real, real, real, real, int, int, int, bool])?;
+            expect_args!(args, [real, real, real, real, real, real, colour, colour, colour, bool])?;
         self.renderer.draw_triangle(
             x1.into(),
             y1.into(),
@@ -1011,7 +1017,7 @@ impl Game {
     }
 
     pub fn draw_circle_color(&mut self, args: &[Value]) -> gml::Result<Value> {
-        let (x, y, radius, col1, col2, outline) = expect_args!(args, [real, real, real, int, int, bool])?;
+        let (x, y, radius, col1, col2, outline) = expect_args!(args, [real, real, real, colour, colour, bool])?;
         self.renderer.draw_ellipse(
             x.into(),
             y.into(),
@@ -1026,7 +1032,7 @@ impl Game {
     }
 
     pub fn draw_ellipse_color(&mut self, args: &[Value]) -> gml::Result<Value> {
-        let (x1, y1, x2, y2, col1, col2, outline) = expect_args!(args, [real, real, real, real, int, int, bool])?;
+        let (x1, y1, x2, y2, col1, col2, outline) = expect_args!(args, [real, real, real, real, colour, colour, bool])?;
         let xcenter = (x1 + x2) / 2.into();
         let ycenter = (y1 + y2) / 2.into();
         let rad_x = (xcenter - x1).abs();
@@ -1075,7 +1081,7 @@ impl Game {
     }
 
     pub fn draw_vertex_color(&mut self, args: &[Value]) -> gml::Result<Value> {
-        let (x, y, col, alpha) = expect_args!(args, [real, real, int, real])?;
+        let (x, y, col, alpha) = expect_args!(args, [real, real, colour, real])?;
         self.renderer.vertex_2d(x.into(), y.into(), 0.0, 0.0, col, alpha.into());
         Ok(Default::default())
     }
@@ -1094,7 +1100,7 @@ impl Game {
     }
 
     pub fn draw_vertex_texture_color(&mut self, args: &[Value]) -> gml::Result<Value> {
-        let (x, y, xtex, ytex, col, alpha) = expect_args!(args, [real, real, real, real, int, real])?;
+        let (x, y, xtex, ytex, col, alpha) = expect_args!(args, [real, real, real, real, colour, real])?;
         self.renderer.vertex_2d(x.into(), y.into(), xtex.into(), ytex.into(), col, alpha.into());
         Ok(Default::default())
     }
@@ -1194,6 +1200,29 @@ impl Game {
         Ok(Default::default())
     }
 
+    pub fn draw_set_text_clip(&mut self, args: &[Value]) -> gml::Result<Value> {
+        let (x, y, width, height) = expect_args!(args, [int, int, int, int])?;
+        self.text_clip = Some(draw::ClipRect { left: x, top: y, right: x + width, bottom: y + height });
+        Ok(Default::default())
+    }
+
+    pub fn draw_reset_text_clip(&mut self, args: &[Value]) -> gml::Result<Value> {
+        expect_args!(args, [])?;
+        self.text_clip = None;
+        Ok(Default::default())
+    }
+
+    pub fn draw_set_text_monospace(&mut self, args: &[Value]) -> gml::Result<Value> {
+        let cell_width = expect_args!(args, [int])?;
+        self.text_options.monospace = if cell_width > 0 { Some(cell_width) } else { None };
+        Ok(Default::default())
+    }
+
+    pub fn draw_set_text_snap_baseline(&mut self, args: &[Value]) -> gml::Result<Value> {
+        self.text_options.snap_baseline = expect_args!(args, [bool])?;
+        Ok(Default::default())
+    }
+
     pub fn string_width(&self, args: &[Value]) -> gml::Result<Value> {
         let string = expect_args!(args, [bytes])?;
         let (width, _) = self.get_string_size(string, None, None);
@@ -1206,6 +1235,18 @@ impl Game {
         Ok(height.into())
     }
 
+    pub fn string_ink_width(&mut self, args: &[Value]) -> gml::Result<Value> {
+        let string = expect_args!(args, [bytes])?;
+        let (left, _, right, _) = self.get_string_ink_bounds(string);
+        Ok((right - left).into())
+    }
+
+    pub fn string_ink_height(&mut self, args: &[Value]) -> gml::Result<Value> {
+        let string = expect_args!(args, [bytes])?;
+        let (_, top, _, bottom) = self.get_string_ink_bounds(string);
+        Ok((bottom - top).into())
+    }
+
     pub fn string_width_ext(&self, args: &[Value]) -> gml::Result<Value> {
         let (string, line_height, max_width) = expect_args!(args, [bytes, int, int])?;
         let (width, _) = self.get_string_size(
@@ -1252,6 +1293,32 @@ impl Game {
         Ok(Default::default())
     }
 
+    pub fn draw_text_reveal(&mut self, args: &[Value]) -> gml::Result<Value> {
+        let (x, y, text, count) = expect_args!(args, [real, real, any, int])?;
+        self.draw_text_ext_reveal(&[x.into(), y.into(), text, (-1).into(), (-1).into(), count.into()])
+    }
+
+    pub fn draw_text_ext_reveal(&mut self, args: &[Value]) -> gml::Result<Value> {
+        let (x, y, text, line_height, max_width, count) = expect_args!(args, [real, real, any, int, int, int])?;
+        let line_height = if line_height < 0 { None } else { Some(line_height as _) };
+        let max_width = if max_width < 0 { None } else { Some(max_width as _) };
+
+        self.draw_string_revealed(
+            x,
+            y,
+            text.repr(),
+            line_height,
+            max_width,
+            1.into(),
+            1.into(),
+            0.into(),
+            None,
+            self.draw_alpha.into(),
+            Some(count.max(0) as usize),
+        );
+        Ok(Default::default())
+    }
+
     pub fn draw_text_transformed(&mut self, args: &[Value]) -> gml::Result<Value> {
         let (x, y, text, xscale, yscale, angle) = expect_args!(args, [real, real, any, real, real, real])?;
         self.draw_string(x, y, text.repr(), None, None, xscale, yscale, angle, None, self.draw_alpha.into());
@@ -1281,7 +1348,8 @@ impl Game {
 
     pub fn draw_text_color(&mut self, args: &[Value]) -> gml::Result<Value> {
         let (x, y, text, col1, col2, col3, col4, alpha) =
-            expect_args!(args, [real, real, any, int, int, int, int, real])?;
+            expect_args!(args, [real, real, any, colour, colour, colour, colour, real])?;
+        // like GM8, the given alpha is used instead of draw_alpha rather than multiplied by it
         self.draw_string(
             x,
             y,
@@ -1299,14 +1367,14 @@ impl Game {
 
     pub fn draw_text_transformed_color(&mut self, args: &[Value]) -> gml::Result<Value> {
         let (x, y, text, xscale, yscale, angle, col1, col2, col3, col4, alpha) =
-            expect_args!(args, [real, real, any, real, real, real, int, int, int, int, real])?;
+            expect_args!(args, [real, real, any, real, real, real, colour, colour, colour, colour, real])?;
         self.draw_string(x, y, text.repr(), None, None, xscale, yscale, angle, Some((col1, col2, col3, col4)), alpha);
         Ok(Default::default())
     }
 
     pub fn draw_text_ext_color(&mut self, args: &[Value]) -> gml::Result<Value> {
         let (x, y, text, line_height, max_width, col1, col2, col3, col4, alpha) =
-            expect_args!(args, [real, real, any, int, int, int, int, int, int, real])?;
+            expect_args!(args, [real, real, any, int, int, colour, colour, colour, colour, real])?;
         let line_height = if line_height < 0 { None } else { Some(line_height as _) };
         let max_width = if max_width < 0 { None } else { Some(max_width as _) };
 
@@ -1327,7 +1395,7 @@ impl Game {
 
     pub fn draw_text_ext_transformed_color(&mut self, args: &[Value]) -> gml::Result<Value> {
         let (x, y, text, line_height, max_width, xscale, yscale, angle, col1, col2, col3, col4, alpha) =
-            expect_args!(args, [real, real, any, int, int, real, real, real, int, int, int, int, real])?;
+            expect_args!(args, [real, real, any, int, int, real, real, real, colour, colour, colour, colour, real])?;
         let line_height = if line_height < 0 { None } else { Some(line_height as _) };
         let max_width = if max_width < 0 { None } else { Some(max_width as _) };
 
@@ -1346,6 +1414,24 @@ impl Game {
         Ok(Default::default())
     }
 
+    pub fn draw_text_wave(&mut self, args: &[Value]) -> gml::Result<Value> {
+        let (x, y, text, amplitude, step, phase) = expect_args!(args, [real, real, any, real, real, real])?;
+        self.draw_string_per_glyph(
+            x,
+            y,
+            text.repr(),
+            None,
+            None,
+            1.into(),
+            1.into(),
+            0.into(),
+            None,
+            self.draw_alpha.into(),
+            draw::GlyphTransform::wave(amplitude, step, phase),
+        );
+        Ok(Default::default())
+    }
+
     pub fn draw_self(&mut self, context: &mut Context, args: &[Value]) -> gml::Result<Value> {
         expect_args!(args, [])?;
         self.draw_instance_default(context.this)?;
@@ -1353,7 +1439,7 @@ impl Game {
     }
 
     pub fn draw_sprite(&mut self, context: &mut Context, args: &[Value]) -> gml::Result<Value> {
-        let (sprite_index, image_index, x, y) = expect_args!(args, [int, int, real, real])?;
+        let (sprite_index, image_index, x, y) = expect_args!(args, [int, int, strict_real, strict_real])?;
         if let Some(sprite) = self.assets.sprites.get_asset(sprite_index) {
             let image_index = if image_index < 0 {
                 self.room.instance_list.get(context.this).image_index.get().floor().to_i32()
@@ -1376,7 +1462,7 @@ impl Game {
 
     pub fn draw_sprite_ext(&mut self, context: &mut Context, args: &[Value]) -> gml::Result<Value> {
         let (sprite_index, image_index, x, y, xscale, yscale, angle, colour, alpha) =
-            expect_args!(args, [int, int, real, real, real, real, real, int, real])?;
+            expect_args!(args, [int, int, real, real, real, real, real, colour, real])?;
         if let Some(sprite) = self.assets.sprites.get_asset(sprite_index) {
             let image_index = if image_index < 0 {
                 self.room.instance_list.get(context.this).image_index.get().floor().to_i32()
@@ -1419,7 +1505,7 @@ impl Game {
 
     pub fn draw_sprite_stretched_ext(&mut self, context: &mut Context, args: &[Value]) -> gml::Result<Value> {
         let (sprite_index, image_index, x, y, w, h, colour, alpha) =
-            expect_args!(args, [int, int, real, real, real, real, int, real])?;
+            expect_args!(args, [int, int, real, real, real, real, colour, real])?;
         if let Some(sprite) = self.assets.sprites.get_asset(sprite_index) {
             let image_index = if image_index < 0 {
                 self.room.instance_list.get(context.this).image_index.get().floor().to_i32()
@@ -1465,7 +1551,7 @@ impl Game {
 
     pub fn draw_sprite_part_ext(&mut self, context: &mut Context, args: &[Value]) -> gml::Result<Value> {
         let (sprite_index, image_index, left, top, width, height, x, y, xscale, yscale, colour, alpha) =
-            expect_args!(args, [int, int, real, real, real, real, real, real, real, real, int, real])?;
+            expect_args!(args, [int, int, real, real, real, real, real, real, real, real, colour, real])?;
         if let Some(sprite) = self.assets.sprites.get_asset(sprite_index) {
             let image_index = if image_index < 0 {
                 self.room.instance_list.get(context.this).image_index.get().floor().to_i32()
@@ -1513,7 +1599,7 @@ impl Game {
             col4,
             alpha,
         ) = expect_args!(args, [
-            int, int, real, real, real, real, real, real, real, real, real, int, int, int, int, real
+            int, int, real, real, real, real, real, real, real, real, real, colour, colour, colour, colour, real
         ])?;
         if let Some(sprite) = self.assets.sprites.get_asset(sprite_index) {
             let image_index = if image_index < 0 {
@@ -1563,7 +1649,7 @@ impl Game {
 
     pub fn draw_sprite_tiled_ext(&mut self, context: &mut Context, args: &[Value]) -> gml::Result<Value> {
         let (sprite_index, image_index, x, y, xscale, yscale, colour, alpha) =
-            expect_args!(args, [int, int, real, real, real, real, int, real])?;
+            expect_args!(args, [int, int, real, real, real, real, colour, real])?;
         if let Some(sprite) = self.assets.sprites.get_asset(sprite_index) {
             let image_index = if image_index < 0 {
                 self.room.instance_list.get(context.this).image_index.get().floor().to_i32()
@@ -1596,7 +1682,7 @@ impl Game {
 
     pub fn draw_background_ext(&mut self, args: &[Value]) -> gml::Result<Value> {
         let (bg_index, x, y, xscale, yscale, angle, colour, alpha) =
-            expect_args!(args, [int, real, real, real, real, real, int, real])?;
+            expect_args!(args, [int, real, real, real, real, real, colour, real])?;
         if let Some(background) = self.assets.backgrounds.get_asset(bg_index) {
             if let Some(atlas_ref) = &background.atlas_ref {
                 self.renderer.draw_sprite(
@@ -1622,7 +1708,7 @@ impl Game {
     }
 
     pub fn draw_background_stretched_ext(&mut self, args: &[Value]) -> gml::Result<Value> {
-        let (bg_index, x, y, w, h, colour, alpha) = expect_args!(args, [int, real, real, real, real, int, real])?;
+        let (bg_index, x, y, w, h, colour, alpha) = expect_args!(args, [int, real, real, real, real, colour, real])?;
         if let Some(background) = self.assets.backgrounds.get_asset(bg_index) {
             if let Some(atlas_ref) = &background.atlas_ref {
                 self.renderer.draw_sprite(
@@ -1662,7 +1748,7 @@ impl Game {
 
     pub fn draw_background_part_ext(&mut self, args: &[Value]) -> gml::Result<Value> {
         let (bg_index, left, top, width, height, x, y, xscale, yscale, colour, alpha) =
-            expect_args!(args, [int, real, real, real, real, real, real, real, real, int, real])?;
+            expect_args!(args, [int, real, real, real, real, real, real, real, real, colour, real])?;
         if let Some(background) = self.assets.backgrounds.get_asset(bg_index) {
             if let Some(atlas_ref) = &background.atlas_ref {
                 self.renderer.draw_sprite_partial(
@@ -1688,7 +1774,9 @@ impl Game {
 
     pub fn draw_background_general(&mut self, args: &[Value]) -> gml::Result<Value> {
         let (bg_index, left, top, width, height, x, y, xscale, yscale, angle, col1, col2, col3, col4, alpha) =
-            expect_args!(args, [int, real, real, real, real, real, real, real, real, real, int, int, int, int, real])?;
+            expect_args!(args, [
+                int, real, real, real, real, real, real, real, real, real, colour, colour, colour, colour, real
+            ])?;
         if let Some(background) = self.assets.backgrounds.get_asset(bg_index) {
             if let Some(atlas_ref) = &background.atlas_ref {
                 self.renderer.draw_sprite_general(
@@ -1723,7 +1811,7 @@ impl Game {
 
     pub fn draw_background_tiled_ext(&mut self, args: &[Value]) -> gml::Result<Value> {
         let (bg_index, x, y, xscale, yscale, colour, alpha) =
-            expect_args!(args, [int, real, real, real, real, int, real])?;
+            expect_args!(args, [int, real, real, real, real, colour, real])?;
         if let Some(background) = self.assets.backgrounds.get_asset(bg_index) {
             if let Some(atlas_ref) = &background.atlas_ref {
                 self.renderer.draw_sprite_tiled(
@@ -1957,7 +2045,7 @@ impl Game {
     }
 
     pub fn tile_set_blend(&mut self, args: &[Value]) -> gml::Result<Value> {
-        let (tile_id, blend) = expect_args!(args, [int, int])?;
+        let (tile_id, blend) = expect_args!(args, [int, colour])?;
         if let Some(handle) = self.room.tile_list.get_by_tileid(tile_id) {
             self.room.tile_list.get(handle).blend.set(blend);
             Ok(Default::default())
@@ -2234,7 +2322,7 @@ impl Game {
 
     pub fn draw_surface_ext(&mut self, args: &[Value]) -> gml::Result<Value> {
         let (surf_id, x, y, xscale, yscale, rot, colour, alpha) =
-            expect_args!(args, [int, real, real, real, real, real, int, real])?;
+            expect_args!(args, [int, real, real, real, real, real, colour, real])?;
         if let Some(surf) = self.surfaces.get_asset(surf_id) {
             self.renderer.draw_sprite(
                 &surf.atlas_ref,
@@ -2288,7 +2376,7 @@ impl Game {
 
     pub fn draw_surface_part_ext(&mut self, args: &[Value]) -> gml::Result<Value> {
         let (surf_id, l, t, w, h, x, y, xscale, yscale, colour, alpha) =
-            expect_args!(args, [int, real, real, real, real, real, real, real, real, int, real])?;
+            expect_args!(args, [int, real, real, real, real, real, real, real, real, colour, real])?;
         if let Some(surf) = self.surfaces.get_asset(surf_id) {
             self.renderer.draw_sprite_partial(
                 &surf.atlas_ref,
@@ -2310,7 +2398,9 @@ impl Game {
 
     pub fn draw_surface_general(&mut self, args: &[Value]) -> gml::Result<Value> {
         let (surf_id, l, t, w, h, x, y, xscale, yscale, angle, col1, col2, col3, col4, alpha) =
-            expect_args!(args, [int, real, real, real, real, real, real, real, real, real, int, int, int, int, real])?;
+            expect_args!(args, [
+                int, real, real, real, real, real, real, real, real, real, colour, colour, colour, colour, real
+            ])?;
         if let Some(surf) = self.surfaces.get_asset(surf_id) {
             self.renderer.draw_sprite_general(
                 &surf.atlas_ref,
@@ -2341,7 +2431,7 @@ impl Game {
 
     pub fn draw_surface_tiled_ext(&mut self, args: &[Value]) -> gml::Result<Value> {
         let (surf_id, x, y, xscale, yscale, colour, alpha) =
-            expect_args!(args, [int, real, real, real, real, int, real])?;
+            expect_args!(args, [int, real, real, real, real, colour, real])?;
         if let Some(surf) = self.surfaces.get_asset(surf_id) {
             self.renderer.draw_sprite_tiled(
                 &surf.atlas_ref,
@@ -2842,7 +2932,7 @@ impl Game {
     }
 
     pub fn action_sprite_color(&mut self, context: &mut Context, args: &[Value]) -> gml::Result<Value> {
-        let (col, alpha) = expect_args!(args, [int, real])?;
+        let (col, alpha) = expect_args!(args, [colour, real])?;
         let instance = self.room.instance_list.get(context.this);
         instance.image_blend.set(col);
         instance.image_alpha.set(alpha);
@@ -3273,7 +3363,8 @@ impl Game {
     }
 
     pub fn action_draw_health(&mut self, context: &mut Context, args: &[Value]) -> gml::Result<Value> {
-        let (mut x1, mut y1, mut x2, mut y2, back_col, col) = expect_args!(args, [real, real, real, real, int, int])?;
+        let (mut x1, mut y1, mut x2, mut y2, back_col, col) =
+            expect_args!(args, [real, real, real, real, colour, colour])?;
 
         if context.relative {
             let instance = self.room.instance_list.get(context.this);
@@ -3388,7 +3479,7 @@ impl Game {
     }
 
     pub fn action_parttype_create_old(&mut self, args: &[Value]) -> gml::Result<Value> {
-        let (id, shape, size_min, size_max, col1, col2) = expect_args!(args, [int, int, real, real, int, int])?;
+        let (id, shape, size_min, size_max, col1, col2) = expect_args!(args, [int, int, real, real, colour, colour])?;
         let pt = self.particles.get_dnd_type_mut(id as usize);
         pt.graphic = particle::ParticleGraphic::Shape(shape);
         pt.size_min = size_min;
@@ -3415,7 +3506,8 @@ impl Game {
     }
 
     pub fn action_parttype_color(&mut self, args: &[Value]) -> gml::Result<Value> {
-        let (id, changing, col1, col2, start_alpha, end_alpha) = expect_args!(args, [int, bool, int, int, real, real])?;
+        let (id, changing, col1, col2, start_alpha, end_alpha) =
+            expect_args!(args, [int, bool, colour, colour, real, real])?;
         let pt = self.particles.get_dnd_type_mut(id as usize);
         pt.colour = if changing {
             particle::ParticleColour::Two(col1, col2)
@@ -3717,11 +3809,15 @@ impl Game {
 
     pub fn is_string(args: &[Value]) -> gml::Result<Value> {
         match expect_args!(args, [any])? {
-            Value::Str(_) => Ok(gml::TRUE.into()),
+            Value::Str(s) if !s.is_undefined() => Ok(gml::TRUE.into()),
             _ => Ok(gml::FALSE.into()),
         }
     }
 
+    pub fn is_undefined(args: &[Value]) -> gml::Result<Value> {
+        Ok(expect_args!(args, [any])?.is_undefined().into())
+    }
+
     pub fn random(&mut self, args: &[Value]) -> gml::Result<Value> {
         let bound = expect_args!(args, [real])?;
         Ok(self.rand.next(bound.into()).into())
@@ -3862,6 +3958,38 @@ impl Game {
         expect_args!(args, [real, real]).map(|(y, x)| Value::Real(y.arctan2(x)))
     }
 
+    pub fn dsin(args: &[Value]) -> gml::Result<Value> {
+        expect_args!(args, [any])?.dsin()
+    }
+
+    pub fn dcos(args: &[Value]) -> gml::Result<Value> {
+        expect_args!(args, [any])?.dcos()
+    }
+
+    pub fn dtan(args: &[Value]) -> gml::Result<Value> {
+        expect_args!(args, [any])?.dtan()
+    }
+
+    pub fn darcsin(args: &[Value]) -> gml::Result<Value> {
+        expect_args!(args, [any])?.darcsin()
+    }
+
+    pub fn darccos(args: &[Value]) -> gml::Result<Value> {
+        expect_args!(args, [any])?.darccos()
+    }
+
+    pub fn darctan(args: &[Value]) -> gml::Result<Value> {
+        expect_args!(args, [any])?.darctan()
+    }
+
+    pub fn darctan2(args: &[Value]) -> gml::Result<Value> {
+        expect_args!(args, [any, any]).and_then(|(y, x)| y.darctan2(&x))
+    }
+
+    pub fn angle_difference(args: &[Value]) -> gml::Result<Value> {
+        expect_args!(args, [any, any]).and_then(|(a, b)| a.angle_difference(&b))
+    }
+
     pub fn degtorad(args: &[Value]) -> gml::Result<Value> {
         expect_args!(args, [real]).map(|x| Value::Real(x.to_radians()))
     }
@@ -3898,7 +4026,7 @@ impl Game {
 
     pub fn mean(args: &[Value]) -> gml::Result<Value> {
         if !args.is_empty() {
-            Ok(Value::from(args.iter().cloned().map(Real::from).sum::<Real>() / Real::from(args.len() as f64)))
+            Ok(Value::from(ds::reals(args).sum::<Real>() / Real::from(args.len() as f64)))
         } else {
             Ok(Default::default())
         }
@@ -3907,13 +4035,11 @@ impl Game {
     pub fn median(args: &[Value]) -> gml::Result<Value> {
         Ok(args
             .iter()
-            .cloned()
-            .find(|v| {
-                let v = Real::from(v.clone());
+            .zip(ds::reals(args))
+            .find(|&(_, v)| {
                 let mut less = 0.0;
                 let mut less_eq = 0.0;
-                for arg in args.iter().cloned() {
-                    let arg = Real::from(arg);
+                for arg in ds::reals(args) {
                     if arg <= v {
                         less_eq += 1.0;
                         if arg != v {
@@ -3923,20 +4049,23 @@ impl Game {
                 }
                 less < args.len() as f64 / 2.0 && less_eq >= args.len() as f64 / 2.0
             })
+            .map(|(v, _)| v.clone())
             .unwrap_or_default())
     }
 
     pub fn choose(&mut self, args: &[Value]) -> gml::Result<Value> {
-        match args.len().checked_sub(1) {
-            Some(i) => Ok(args[self.rand.next_int(i as _) as usize].clone()),
-            None => Ok(Default::default()),
-        }
+        Ok(self.rand.choose(args).cloned().unwrap_or_default())
     }
 
     pub fn clamp(args: &[Value]) -> gml::Result<Value> {
         expect_args!(args, [real, real, real]).map(|(n, lo, hi)| Value::Real(n.clamp(lo, hi)))
     }
 
+    pub fn in_range(args: &[Value]) -> gml::Result<Value> {
+        let (n, lo, hi) = expect_args!(args, [any, any, any])?;
+        n.in_range(lo, hi)
+    }
+
     pub fn lerp(args: &[Value]) -> gml::Result<Value> {
         let (low, high, amount) = expect_args!(args, [real, real, real])?;
         Ok(Value::from(((high - low) * amount) + low))
@@ -3957,7 +4086,7 @@ impl Game {
     }
 
     pub fn string(args: &[Value]) -> gml::Result<Value> {
-        expect_args!(args, [any]).map(|v| v.repr().into())
+        expect_args!(args, [coerce_string]).map(Value::Str)
     }
 
     pub fn string_format(args: &[Value]) -> gml::Result<Value> {
@@ -3993,6 +4122,9 @@ impl Game {
 
     pub fn string_length(&self, args: &[Value]) -> gml::Result<Value> {
         let string = expect_args!(args, [bytes])?;
+        if self.grapheme_strings {
+            return Ok(gml::string::graphemes(&self.decode_str(string.as_ref())).len().into())
+        }
         match self.gm_version {
             Version::GameMaker8_0 => Ok(Value::Real((string.as_ref().len() as f64).into())),
             Version::GameMaker8_1 => Ok(Value::Real((self.decode_str(string.as_ref()).chars().count() as f64).into())),
@@ -4012,26 +4144,34 @@ impl Game {
     }
 
     pub fn string_pos(&self, args: &[Value]) -> gml::Result<Value> {
-        expect_args!(args, [bytes, bytes]).map(|(query, main_string)| match self.gm_version {
-            Version::GameMaker8_0 => {
-                let query = query.as_ref();
-                Value::Real(Real::from(
-                    main_string
-                        .as_ref()
-                        .windows(query.len())
-                        .position(|x| x == query)
-                        .map(|p| p + 1)
-                        .unwrap_or_default() as f64,
-                ))
+        expect_args!(args, [bytes, bytes]).map(|(query, main_string)| {
+            Value::Real(Real::from(self.string_position(query.as_ref(), main_string.as_ref(), false) as f64))
+        })
+    }
+
+    pub fn string_pos_nocase(&self, args: &[Value]) -> gml::Result<Value> {
+        expect_args!(args, [bytes, bytes]).map(|(query, main_string)| {
+            Value::Real(Real::from(self.string_position(query.as_ref(), main_string.as_ref(), true) as f64))
+        })
+    }
+
+    /// Implementation of string_pos, optionally ignoring case. Returns 0 if query is not found.
+    pub fn string_position(&self, query: &[u8], main_string: &[u8], ignore_case: bool) -> usize {
+        match self.gm_version {
+            Version::GameMaker8_0 if !ignore_case => {
+                main_string.windows(query.len()).position(|x| x == query).map(|p| p + 1).unwrap_or_default()
             },
+            Version::GameMaker8_0 => gml::string::find_ignore_case_encoded(main_string, query, self.encoding)
+                .map(|p| p + 1)
+                .unwrap_or_default(),
             Version::GameMaker8_1 => {
-                let query = self.decode_str(query.as_ref());
-                let main_string = self.decode_str(main_string.as_ref());
-                Value::Real(Real::from(
-                    main_string.as_ref().find(query.as_ref()).map(|p| p + 1).unwrap_or_default() as f64
-                ))
+                let query = self.decode_str(query);
+                let main_string = self.decode_str(main_string);
+                gml::string::find(main_string.as_ref(), query.as_ref(), ignore_case)
+                    .map(|p| p + 1)
+                    .unwrap_or_default()
             },
-        })
+        }
     }
 
     pub fn string_copy(&self, args: &[Value]) -> gml::Result<Value> {
@@ -4047,6 +4187,12 @@ impl Game {
 
     pub fn string_char_at(&self, args: &[Value]) -> gml::Result<Value> {
         let (string, pos) = expect_args!(args, [bytes, int])?;
+        if self.grapheme_strings {
+            let string = self.decode_str(string.as_ref());
+            let index = (pos as isize - 1).max(0) as usize;
+            let cluster = gml::string::graphemes(&string).get(index).and_then(|cluster| self.encode_str_maybe(cluster));
+            return Ok(cluster.map_or("".into(), |bytes| Value::Str(bytes.as_ref().into())))
+        }
         match self.gm_version {
             Version::GameMaker8_0 => {
                 Ok(string.as_ref().get((pos as isize - 1).max(0) as usize).map_or("".into(), |ch| vec![*ch].into()))
@@ -4108,19 +4254,24 @@ impl Game {
         expect_args!(args, [bytes, real]).map(|(s, n)| Value::Str(s.as_ref().repeat(n.into_inner() as usize).into()))
     }
 
-    pub fn string_letters(args: &[Value]) -> gml::Result<Value> {
-        expect_args!(args, [string])
-            .map(|s| Value::Str(s.as_ref().chars().filter(|ch| ch.is_ascii_alphabetic()).collect::<String>().into()))
+    fn string_filter(&self, args: &[Value], letters: bool, digits: bool) -> gml::Result<Value> {
+        let encoding = match self.gm_version {
+            Version::GameMaker8_0 => self.encoding,
+            Version::GameMaker8_1 => encoding_rs::UTF_8,
+        };
+        expect_args!(args, [bytes]).map(|s| Value::Str(s.filter_alphanumeric(encoding, letters, digits)))
+    }
+
+    pub fn string_letters(&self, args: &[Value]) -> gml::Result<Value> {
+        self.string_filter(args, true, false)
     }
 
-    pub fn string_digits(args: &[Value]) -> gml::Result<Value> {
-        expect_args!(args, [string])
-            .map(|s| Value::Str(s.as_ref().chars().filter(|ch| ch.is_ascii_digit()).collect::<String>().into()))
+    pub fn string_digits(&self, args: &[Value]) -> gml::Result<Value> {
+        self.string_filter(args, false, true)
     }
 
-    pub fn string_lettersdigits(args: &[Value]) -> gml::Result<Value> {
-        expect_args!(args, [string])
-            .map(|s| Value::Str(s.as_ref().chars().filter(|ch| ch.is_ascii_alphanumeric()).collect::<String>().into()))
+    pub fn string_lettersdigits(&self, args: &[Value]) -> gml::Result<Value> {
+        self.string_filter(args, true, true)
     }
 
     pub fn string_replace(args: &[Value]) -> gml::Result<Value> {
@@ -4164,9 +4315,15 @@ impl Game {
         }
     }
 
-    pub fn string_count(args: &[Value]) -> gml::Result<Value> {
-        expect_args!(args, [string, string])
-            .map(|(ss, s)| Value::Real(Real::from(s.as_ref().matches(ss.as_ref()).count() as f64)))
+    pub fn string_count(&self, args: &[Value]) -> gml::Result<Value> {
+        let (substr, s) = expect_args!(args, [bytes, bytes])?;
+        let count = match self.gm_version {
+            // a double-byte character can end in the same byte as '\\', which mustn't count as one
+            Version::GameMaker8_0 => gml::string::count_encoded(s.as_ref(), substr.as_ref(), self.encoding),
+            // GM8.1's strings are already UTF-8, so the bytes can be counted as they are
+            Version::GameMaker8_1 => gml::string::count(s.as_ref(), substr.as_ref()),
+        };
+        Ok(count.into())
     }
 
     pub fn dot_product(args: &[Value]) -> gml::Result<Value> {
@@ -6713,9 +6870,10 @@ impl Game {
         unimplemented!("Called unimplemented kernel function highscore_set_strings")
     }
 
-    pub fn highscore_set_colors(&mut self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 3
-        unimplemented!("Called unimplemented kernel function highscore_set_colors")
+    pub fn highscore_set_colors(&mut self, args: &[Value]) -> gml::Result<Value> {
+        let (back, new, other) = expect_args!(args, [colour, colour, colour])?;
+        self.highscores.set_colours(back, new, other);
+        Ok(Default::default())
     }
 
     pub fn highscore_show_ext(&mut self, _args: &[Value]) -> gml::Result<Value> {
@@ -6723,14 +6881,16 @@ impl Game {
         unimplemented!("Called unimplemented kernel function highscore_show_ext")
     }
 
-    pub fn highscore_clear(&mut self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 0
-        unimplemented!("Called unimplemented kernel function highscore_clear")
+    pub fn highscore_clear(&mut self, args: &[Value]) -> gml::Result<Value> {
+        expect_args!(args, [])?;
+        self.highscores.clear();
+        Ok(Default::default())
     }
 
-    pub fn highscore_add(&mut self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 2
-        unimplemented!("Called unimplemented kernel function highscore_add")
+    pub fn highscore_add(&mut self, args: &[Value]) -> gml::Result<Value> {
+        let (name, score) = expect_args!(args, [bytes, real])?;
+        self.highscores.add(name, score);
+        Ok(Default::default())
     }
 
     pub fn highscore_add_current(&mut self, _args: &[Value]) -> gml::Result<Value> {
@@ -6738,19 +6898,22 @@ impl Game {
         unimplemented!("Called unimplemented kernel function highscore_add_current")
     }
 
-    pub fn highscore_value(&self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 1
-        unimplemented!("Called unimplemented kernel function highscore_value")
+    pub fn highscore_value(&self, args: &[Value]) -> gml::Result<Value> {
+        let place = expect_args!(args, [int])?;
+        let entry = usize::try_from(place - 1).ok().and_then(|place| self.highscores.get(place));
+        Ok(entry.map(|(_, score)| Value::Real(*score)).unwrap_or_default())
     }
 
-    pub fn highscore_name(&self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 1
-        unimplemented!("Called unimplemented kernel function highscore_name")
+    pub fn highscore_name(&self, args: &[Value]) -> gml::Result<Value> {
+        let place = expect_args!(args, [int])?;
+        let entry = usize::try_from(place - 1).ok().and_then(|place| self.highscores.get(place));
+        Ok(entry.map(|(name, _)| Value::Str(name.clone())).unwrap_or_else(|| "".into()))
     }
 
-    pub fn draw_highscore(&mut self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 4
-        unimplemented!("Called unimplemented kernel function draw_highscore")
+    pub fn draw_highscore(&mut self, args: &[Value]) -> gml::Result<Value> {
+        let (x1, y1, x2, y2) = expect_args!(args, [real, real, real, real])?;
+        self.draw_highscores(x1, y1, x2, y2);
+        Ok(Default::default())
     }
 
     pub fn show_message_ext(&mut self, _args: &[Value]) -> gml::Result<Value> {
@@ -7024,7 +7187,7 @@ impl Game {
         // Expected arg count: 1
         //unimplemented!("Called unimplemented kernel function joystick_name")
         // TODO
-        Ok("".into())
+        Ok(Value::from_static(""))
     }
 
     pub fn joystick_axes(&mut self, _args: &[Value]) -> gml::Result<Value> {
@@ -7587,15 +7750,7 @@ impl Game {
 
     pub fn variable_global_exists(&self, args: &[Value]) -> gml::Result<Value> {
         let identifier = expect_args!(args, [bytes])?;
-        if let Some(var) = mappings::get_instance_variable_by_name(identifier.as_ref()) {
-            Ok(self.globals.vars.contains_key(var).into())
-        } else {
-            Ok(self
-                .compiler
-                .find_field_id(identifier.as_ref())
-                .map_or(false, |i| self.globals.fields.contains_key(&i))
-                .into())
-        }
+        Ok(self.globals.contains_name(&self.compiler, identifier.as_ref()).into())
     }
 
     pub fn variable_global_get(&self, args: &[Value]) -> gml::Result<Value> {
@@ -7605,22 +7760,12 @@ impl Game {
 
     pub fn variable_global_array_get(&self, args: &[Value]) -> gml::Result<Value> {
         let (identifier, index) = expect_args!(args, [bytes, int])?;
-        let index = index as u32;
-        if let Some(var) = mappings::get_instance_variable_by_name(identifier.as_ref()) {
-            Ok(self.globals.vars.get(var).and_then(|x| x.get(index)).unwrap_or_default())
-        } else {
-            Ok(self
-                .compiler
-                .find_field_id(identifier.as_ref())
-                .and_then(|i| self.globals.fields.get(&i))
-                .and_then(|x| x.get(index))
-                .unwrap_or_default())
-        }
+        Ok(self.globals.get_by_name(&self.compiler, identifier.as_ref(), index as u32).unwrap_or_default())
     }
 
     pub fn variable_global_array2_get(&self, args: &[Value]) -> gml::Result<Value> {
         let (identifier, index1, index2) = expect_args!(args, [any, int, int])?;
-        self.variable_global_array_get(&[identifier, ((index1 * 32000) + index2).into()])
+        self.variable_global_array_get(&[identifier, (index1 * ARRAY_ROW as i32 + index2).into()])
     }
 
     pub fn variable_global_set(&mut self, args: &[Value]) -> gml::Result<Value> {
@@ -7630,27 +7775,13 @@ impl Game {
 
     pub fn variable_global_array_set(&mut self, args: &[Value]) -> gml::Result<Value> {
         let (identifier, index, value) = expect_args!(args, [bytes, int, any])?;
-        let index = index as u32;
-        if let Some(var) = mappings::get_instance_variable_by_name(identifier.as_ref()) {
-            if let Some(field) = self.globals.vars.get_mut(var) {
-                field.set(index, value);
-            } else {
-                self.globals.vars.insert(*var, Field::new(index, value));
-            }
-        } else {
-            let field_id = self.compiler.get_field_id(identifier.as_ref());
-            if let Some(field) = self.globals.fields.get_mut(&field_id) {
-                field.set(index, value);
-            } else {
-                self.globals.fields.insert(field_id, Field::new(index, value));
-            }
-        }
+        self.globals.set_by_name(&mut self.compiler, identifier.as_ref(), index as u32, value);
         Ok(Default::default())
     }
 
     pub fn variable_global_array2_set(&mut self, args: &[Value]) -> gml::Result<Value> {
         let (identifier, index1, index2, value) = expect_args!(args, [any, int, int, any])?;
-        self.variable_global_array_set(&[identifier, ((index1 * 32000) + index2).into(), value])
+        self.variable_global_array_set(&[identifier, (index1 * ARRAY_ROW as i32 + index2).into(), value])
     }
 
     pub fn variable_local_exists(&mut self, context: &mut Context, args: &[Value]) -> gml::Result<Value> {
@@ -7689,7 +7820,7 @@ impl Game {
 
     pub fn variable_local_array2_get(&mut self, context: &mut Context, args: &[Value]) -> gml::Result<Value> {
         let (identifier, index1, index2) = expect_args!(args, [any, int, int])?;
-        self.variable_local_array_get(context, &[identifier, ((index1 * 32000) + index2).into()])
+        self.variable_local_array_get(context, &[identifier, (index1 * ARRAY_ROW as i32 + index2).into()])
     }
 
     pub fn variable_local_set(&mut self, context: &mut Context, args: &[Value]) -> gml::Result<Value> {
@@ -7716,7 +7847,27 @@ impl Game {
 
     pub fn variable_local_array2_set(&mut self, context: &mut Context, args: &[Value]) -> gml::Result<Value> {
         let (identifier, index1, index2, value) = expect_args!(args, [any, int, int, any])?;
-        self.variable_local_array_set(context, &[identifier, ((index1 * 32000) + index2).into(), value])
+        self.variable_local_array_set(context, &[identifier, (index1 * ARRAY_ROW as i32 + index2).into(), value])
+    }
+
+    pub fn array_length_1d(&mut self, context: &mut Context, args: &[Value]) -> gml::Result<Value> {
+        let identifier = expect_args!(args, [any])?;
+        self.array_length_2d(context, &[identifier, 0.into()])
+    }
+
+    /// Gets the length of a row of one of the calling instance's arrays, by name, as GM8.1 counts it.
+    pub fn array_length_2d(&mut self, context: &mut Context, args: &[Value]) -> gml::Result<Value> {
+        let (identifier, row) = expect_args!(args, [bytes, int])?;
+        if row < 0 {
+            return Ok(0.into())
+        }
+        let fields = self.room.instance_list.get(context.this).fields.borrow();
+        Ok(self
+            .compiler
+            .find_field_id(identifier.as_ref())
+            .and_then(|i| fields.get(&i))
+            .map_or(0, |f| f.row_length(row as u32))
+            .into())
     }
 
     pub fn clipboard_has_text(&self, _args: &[Value]) -> gml::Result<Value> {
@@ -7729,9 +7880,10 @@ impl Game {
         unimplemented!("Called unimplemented kernel function clipboard_set_text")
     }
 
-    pub fn clipboard_get_text(&self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 0
-        unimplemented!("Called unimplemented kernel function clipboard_get_text")
+    pub fn clipboard_get_text(&self, args: &[Value]) -> gml::Result<Value> {
+        expect_args!(args, [])?;
+        // GM8 only reads the clipboard's ANSI text, which Windows converts from Unicode like encode_str_lossy does
+        Ok(self.encode_str_lossy(&clipboard::get_text().unwrap_or_default()).into())
     }
 
     pub fn date_current_datetime(&self, args: &[Value]) -> gml::Result<Value> {
@@ -8712,7 +8864,7 @@ impl Game {
     }
 
     pub fn background_create_color(&mut self, args: &[Value]) -> gml::Result<Value> {
-        let (w, h, col) = expect_args!(args, [int, int, int])?;
+        let (w, h, col) = expect_args!(args, [int, int, colour])?;
         let background_id = self.assets.backgrounds.len();
         self.assets.backgrounds.push(Some(Box::new(asset::Background {
             name: format!("__newbackground{}", background_id).into(),
@@ -8988,37 +9140,37 @@ impl Game {
 
     pub fn font_get_name(&self, args: &[Value]) -> gml::Result<Value> {
         let asset_id = expect_args!(args, [int])?;
-        Ok(self.assets.fonts.get_asset(asset_id).map(|x| x.name.clone().into()).unwrap_or("<undefined>".into()))
+        Ok(self.assets.fonts.get_asset(asset_id).map(|x| x.gml_name()).unwrap_or("<undefined>".into()))
     }
 
     pub fn font_get_fontname(&self, args: &[Value]) -> gml::Result<Value> {
         let id = expect_args!(args, [int])?;
-        Ok(self.assets.fonts.get_asset(id).map(|x| x.sys_name.clone().into()).unwrap_or("".into()))
+        Ok(self.assets.fonts.get_asset(id).map(|x| x.gml_fontname()).unwrap_or("".into()))
     }
 
     pub fn font_get_size(&self, args: &[Value]) -> gml::Result<Value> {
         let id = expect_args!(args, [int])?;
-        Ok(self.assets.fonts.get_asset(id).map(|x| x.size.into()).unwrap_or((-1).into()))
+        Ok(self.assets.fonts.get_asset(id).map(|x| x.gml_size()).unwrap_or((-1).into()))
     }
 
     pub fn font_get_bold(&self, args: &[Value]) -> gml::Result<Value> {
         let id = expect_args!(args, [int])?;
-        Ok(self.assets.fonts.get_asset(id).map(|x| x.bold.into()).unwrap_or((-1).into()))
+        Ok(self.assets.fonts.get_asset(id).map(|x| x.gml_bold()).unwrap_or((-1).into()))
     }
 
     pub fn font_get_italic(&self, args: &[Value]) -> gml::Result<Value> {
         let id = expect_args!(args, [int])?;
-        Ok(self.assets.fonts.get_asset(id).map(|x| x.italic.into()).unwrap_or((-1).into()))
+        Ok(self.assets.fonts.get_asset(id).map(|x| x.gml_italic()).unwrap_or((-1).into()))
     }
 
     pub fn font_get_first(&self, args: &[Value]) -> gml::Result<Value> {
         let id = expect_args!(args, [int])?;
-        Ok(self.assets.fonts.get_asset(id).map(|x| x.first.into()).unwrap_or((-1).into()))
+        Ok(self.assets.fonts.get_asset(id).map(|x| x.gml_first()).unwrap_or((-1).into()))
     }
 
     pub fn font_get_last(&self, args: &[Value]) -> gml::Result<Value> {
         let id = expect_args!(args, [int])?;
-        Ok(self.assets.fonts.get_asset(id).map(|x| x.last.into()).unwrap_or((-1).into()))
+        Ok(self.assets.fonts.get_asset(id).map(|x| x.gml_last()).unwrap_or((-1).into()))
     }
 
     pub fn font_add(&mut self, _args: &[Value]) -> gml::Result<Value> {
@@ -9026,15 +9178,64 @@ impl Game {
         unimplemented!("Called unimplemented kernel function font_add")
     }
 
-    pub fn font_replace(&mut self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 7
-        unimplemented!("Called unimplemented kernel function font_replace")
+    pub fn font_replace(&mut self, args: &[Value]) -> gml::Result<Value> {
+        let (font_id, name, size, bold, italic, first, last) =
+            expect_args!(args, [int, bytes, int, bool, bool, int, int])?;
+        if self.assets.fonts.get_asset(font_id).is_none() {
+            return Err(gml::Error::NonexistentAsset(asset::Type::Font, font_id))
+        }
+        let (size, range) = (size.max(0) as u32, (first.clamp(0, 255) as u8, last.clamp(0, 255) as u8));
+        // substituted fonts only keep the characters the default font has, so look for those too
+        let default = &self.default_font;
+        let substituted = (range.0.max(default.first), range.1.min(default.last));
+        let identical = self.assets.fonts.iter().enumerate().find_map(|(i, font)| match font {
+            Some(font) if i != font_id as usize => [range, substituted]
+                .iter()
+                .any(|&range| font.has_glyphs_for(name.as_ref(), size, bold, italic, range))
+                .then(|| font.as_ref().clone()),
+            _ => None,
+        });
+        let new = match identical {
+            Some(font) => font,
+            None => {
+                let renderer = &mut self.renderer;
+                asset::font::substitute_font(name, size, bold, italic, range, default, |s| renderer.upload_sprites(s))
+                    .map_err(|e| gml::Error::FunctionError("font_replace".into(), e))?
+            },
+        };
+        // the handle stays the same, so anything holding it draws with the new glyphs from now on
+        let old_graphics = self.assets.fonts.get_asset_mut(font_id).unwrap().replace(new);
+        for atlas_ref in asset::font::graphics_to_free(&self.assets.fonts, old_graphics) {
+            self.renderer.delete_sprite(atlas_ref);
+        }
+        Ok(Default::default())
     }
 
     pub fn font_add_sprite(&mut self, args: &[Value]) -> gml::Result<Value> {
         let (sprite_id, first, prop, sep) = expect_args!(args, [int, int, bool, int])?;
+        self.add_sprite_font(sprite_id, first, prop, sep, Default::default())
+    }
+
+    pub fn font_add_sprite_ext(&mut self, args: &[Value]) -> gml::Result<Value> {
+        let (sprite_id, first, prop, sep, vcentre, trim_padding, key) =
+            expect_args!(args, [int, int, bool, int, bool, bool, int])?;
+        // a negative colour key means the frames use alpha, as in font_add_sprite
+        let colour_key = if key >= 0 { Some([key as u8, (key >> 8) as u8, (key >> 16) as u8]) } else { None };
+        let options = asset::font::SpriteFontOptions { vcentre, trim_padding, colour_key };
+        self.add_sprite_font(sprite_id, first, prop, sep, options)
+    }
+
+    fn add_sprite_font(
+        &mut self,
+        sprite_id: i32,
+        first: i32,
+        prop: bool,
+        sep: i32,
+        options: asset::font::SpriteFontOptions,
+    ) -> gml::Result<Value> {
         if let Some(sprite) = self.assets.sprites.get_asset(sprite_id) {
-            let chars = asset::font::create_chars_from_sprite(sprite, prop, sep, &self.renderer);
+            let (chars, line_height) =
+                asset::font::create_chars_from_sprite(sprite, prop, sep, options, &self.renderer);
             let font_id = self.assets.fonts.len();
             let first = first.clamp(0, 255) as _;
             let last = (first as usize + chars.len() - 1).min(255) as _;
@@ -9047,9 +9248,11 @@ impl Game {
                 italic: false,
                 first,
                 last,
-                tallest_char_height: sprite.height,
+                tallest_char_height: line_height,
                 chars,
                 own_graphics: false,
+                dirty: true,
+                fallback: None,
             })));
             Ok(font_id.into())
         } else {
@@ -9061,21 +9264,22 @@ impl Game {
         let (font_id, sprite_id, first, prop, sep) = expect_args!(args, [int, int, int, bool, int])?;
         if let Some(font) = self.assets.fonts.get_asset_mut(font_id) {
             if let Some(sprite) = self.assets.sprites.get_asset(sprite_id) {
-                if font.own_graphics {
-                    // font_add isn't in yet but atm for ttfs all characters are on the same texture
-                    if let Some(c) = font.get_char(font.first) {
-                        self.renderer.delete_sprite(c.atlas_ref);
-                    }
-                }
-                let chars = asset::font::create_chars_from_sprite(sprite, prop, sep, &self.renderer);
+                let old_graphics = font.owned_graphics();
+                let (chars, line_height) =
+                    asset::font::create_chars_from_sprite(sprite, prop, sep, Default::default(), &self.renderer);
                 font.sys_name = "".into();
                 font.size = 12;
                 font.bold = false;
                 font.italic = false;
                 font.first = first.clamp(0, 255) as _;
                 font.last = (first as usize + chars.len() - 1).min(255) as _;
+                font.tallest_char_height = line_height;
                 font.chars = chars;
                 font.own_graphics = false;
+                font.dirty = true;
+                for atlas_ref in asset::font::graphics_to_free(&self.assets.fonts, old_graphics) {
+                    self.renderer.delete_sprite(atlas_ref);
+                }
                 Ok(Default::default())
             } else {
                 Err(gml::Error::NonexistentAsset(asset::Type::Sprite, sprite_id))
@@ -9085,9 +9289,114 @@ impl Game {
         }
     }
 
-    pub fn font_delete(&mut self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 1
-        unimplemented!("Called unimplemented kernel function font_delete")
+    pub fn font_delete(&mut self, args: &[Value]) -> gml::Result<Value> {
+        let font_id = expect_args!(args, [int])?;
+        let old_graphics = match self.assets.fonts.get_asset(font_id) {
+            Some(font) => font.owned_graphics(),
+            None => {
+                return Err(gml::Error::FunctionError("font_delete".into(), "Trying to delete non-existing font".into()))
+            },
+        };
+        self.assets.fonts[font_id as usize] = None;
+        for atlas_ref in asset::font::graphics_to_free(&self.assets.fonts, old_graphics) {
+            self.renderer.delete_sprite(atlas_ref);
+        }
+        Ok(Default::default())
+    }
+
+    pub fn font_set_fallback(&mut self, args: &[Value]) -> gml::Result<Value> {
+        let (font_id, fallback_id) = expect_args!(args, [int, int])?;
+        // the fallback is a copy, so it keeps working if the font it came from is deleted or replaced
+        let fallback = if fallback_id < 0 {
+            None
+        } else {
+            match self.assets.fonts.get_asset(fallback_id) {
+                Some(fallback) => Some(Box::new(fallback.as_ref().clone())),
+                None => return Err(gml::Error::NonexistentAsset(asset::Type::Font, fallback_id)),
+            }
+        };
+        let old_graphics = match self.assets.fonts.get_asset_mut(font_id) {
+            Some(font) => {
+                let old = std::mem::replace(&mut font.fallback, fallback);
+                font.dirty = true;
+                old.map_or_else(Vec::new, |old| old.owned_graphics())
+            },
+            None => return Err(gml::Error::NonexistentAsset(asset::Type::Font, font_id)),
+        };
+        for atlas_ref in asset::font::graphics_to_free(&self.assets.fonts, old_graphics) {
+            self.renderer.delete_sprite(atlas_ref);
+        }
+        Ok(Default::default())
+    }
+
+    pub fn font_replace_glyph(&mut self, args: &[Value]) -> gml::Result<Value> {
+        let (font_id, index, sprite_id, image_index) = expect_args!(args, [int, int, int, int])?;
+        let (width, height, data) = match self.assets.sprites.get_asset(sprite_id) {
+            Some(sprite) => match sprite.get_frame(image_index) {
+                Some(frame) => (frame.width as i32, frame.height as i32, self.renderer.dump_sprite(&frame.atlas_ref)),
+                None => return Ok(Default::default()),
+            },
+            None => return Err(gml::Error::NonexistentAsset(asset::Type::Sprite, sprite_id)),
+        };
+        let error = |e| gml::Error::FunctionError("font_replace_glyph".into(), e);
+        let index = u8::try_from(index).map_err(|_| error(format!("Character {} is not in font", index)))?;
+        let shared = match self.assets.fonts.get_asset(font_id) {
+            Some(font) => {
+                font.get_char(index).map_or(false, |c| asset::font::region_shared(&self.assets.fonts, &c.atlas_ref))
+            },
+            None => return Err(gml::Error::NonexistentAsset(asset::Type::Font, font_id)),
+        };
+        let font = self.assets.fonts.get_asset_mut(font_id).unwrap();
+        let old = font.update_glyph(index, width, 0, width, height, data, shared, &mut self.renderer).map_err(error)?;
+        for atlas_ref in asset::font::graphics_to_free(&self.assets.fonts, old.into_iter().collect()) {
+            self.renderer.delete_sprite(atlas_ref);
+        }
+        Ok(Default::default())
+    }
+
+    pub fn font_export_metrics(&mut self, args: &[Value]) -> gml::Result<Value> {
+        let (font_id, fname) = expect_args!(args, [int, string])?;
+        let metrics = match self.assets.fonts.get_asset(font_id) {
+            Some(font) => font.export_metrics(&mut self.renderer),
+            None => return Err(gml::Error::NonexistentAsset(asset::Type::Font, font_id)),
+        };
+        let error = |e: String| gml::Error::FunctionError("font_export_metrics".into(), e);
+        let mut file =
+            std::fs::File::create(fname.as_ref()).map(std::io::BufWriter::new).map_err(|e| error(e.to_string()))?;
+        bincode::serialize_into(&mut file, &metrics).map_err(|e| error(e.to_string()))?;
+        file.flush().map_err(|e| error(e.to_string()))?;
+        Ok(Default::default())
+    }
+
+    pub fn font_get_occupancy(&self, args: &[Value]) -> gml::Result<Value> {
+        let font_id = expect_args!(args, [int])?;
+        // how much of the atlas page holding the font's glyphs is taken up by anything at all
+        match self.assets.fonts.get_asset(font_id) {
+            Some(font) => Ok(font
+                .chars
+                .first()
+                .map_or(0.0, |c| f64::from(self.renderer.atlas_occupancy(c.atlas_ref.atlas_id)))
+                .into()),
+            None => Err(gml::Error::NonexistentAsset(asset::Type::Font, font_id)),
+        }
+    }
+
+    pub fn font_get_outline(&mut self, args: &[Value]) -> gml::Result<Value> {
+        let (font_id, text) = expect_args!(args, [int, string])?;
+        let font = match self.assets.fonts.get_asset(font_id) {
+            Some(font) => font,
+            None => return Err(gml::Error::NonexistentAsset(asset::Type::Font, font_id)),
+        };
+        // SVG path data for the line of text, traced from the glyphs' pixels: see Font::glyph_outline
+        font.upload_glyphs(&mut self.renderer);
+        let mut contours = Vec::new();
+        let mut x = 0;
+        for c in text.chars() {
+            let outline = font.glyph_outline_with(c, |atlas_ref| self.renderer.dump_sprite(atlas_ref));
+            contours.extend(outline.into_iter().flatten().map(|contour| contour.svg_path(x, 0)));
+            x += font.get_char_wide(c).map_or_else(|| font.advance(font.first), |c| c.offset);
+        }
+        Ok(contours.join(" ").into())
     }
 
     pub fn script_exists(&self, args: &[Value]) -> gml::Result<Value> {
@@ -9110,11 +9419,7 @@ impl Game {
             let script_id = script_id.round();
             if let Some(script) = self.assets.scripts.get_asset(script_id) {
                 let instructions = script.compiled.clone();
-                let mut new_args: [Value; 16] = Default::default();
-                for (src, dest) in args[1..].iter().zip(new_args.iter_mut()) {
-                    *dest = src.clone();
-                }
-                let mut new_context = Context::copy_with_args(context, new_args, args.len() - 1);
+                let mut new_context = Context::copy_with_arg_slice(context, &args[1..]);
                 self.execute(&instructions, &mut new_context)?;
                 Ok(new_context.return_value)
             } else {
@@ -9762,7 +10067,7 @@ impl Game {
     }
 
     pub fn room_set_background_color(&mut self, args: &[Value]) -> gml::Result<Value> {
-        let (room_id, colour, show) = expect_args!(args, [int, int, bool])?;
+        let (room_id, colour, show) = expect_args!(args, [int, colour, bool])?;
         if let Some(room) = self.assets.rooms.get_asset_mut(room_id) {
             room.bg_colour = (colour as u32).into();
             room.clear_screen = show;
@@ -10037,7 +10342,7 @@ impl Game {
     }
 
     pub fn part_type_color_mix(&mut self, args: &[Value]) -> gml::Result<Value> {
-        let (id, c1, c2) = expect_args!(args, [int, int, int])?;
+        let (id, c1, c2) = expect_args!(args, [int, colour, colour])?;
         if let Some(pt) = self.particles.get_type_mut(id) {
             pt.colour = particle::ParticleColour::Mix(c1, c2);
         }
@@ -10061,7 +10366,7 @@ impl Game {
     }
 
     pub fn part_type_color1(&mut self, args: &[Value]) -> gml::Result<Value> {
-        let (id, col) = expect_args!(args, [int, int])?;
+        let (id, col) = expect_args!(args, [int, colour])?;
         if let Some(pt) = self.particles.get_type_mut(id) {
             pt.colour = particle::ParticleColour::One(col);
         }
@@ -10069,7 +10374,7 @@ impl Game {
     }
 
     pub fn part_type_color2(&mut self, args: &[Value]) -> gml::Result<Value> {
-        let (id, c1, c2) = expect_args!(args, [int, int, int])?;
+        let (id, c1, c2) = expect_args!(args, [int, colour, colour])?;
         if let Some(pt) = self.particles.get_type_mut(id) {
             pt.colour = particle::ParticleColour::Two(c1, c2);
         }
@@ -10077,7 +10382,7 @@ impl Game {
     }
 
     pub fn part_type_color3(&mut self, args: &[Value]) -> gml::Result<Value> {
-        let (id, c1, c2, c3) = expect_args!(args, [int, int, int, int])?;
+        let (id, c1, c2, c3) = expect_args!(args, [int, colour, colour, colour])?;
         if let Some(pt) = self.particles.get_type_mut(id) {
             pt.colour = particle::ParticleColour::Three(c1, c2, c3);
         }
@@ -10206,7 +10511,7 @@ impl Game {
     }
 
     pub fn part_particles_create_color(&mut self, args: &[Value]) -> gml::Result<Value> {
-        let (id, x, y, parttype, colour, number) = expect_args!(args, [int, real, real, int, int, int])?;
+        let (id, x, y, parttype, colour, number) = expect_args!(args, [int, real, real, int, colour, int])?;
         self.particles.system_create_particles(id, x, y, parttype, Some(colour), number, &mut self.rand);
         Ok(Default::default())
     }
@@ -10665,7 +10970,7 @@ impl Game {
     }
 
     pub fn effect_create_below(&mut self, args: &[Value]) -> gml::Result<Value> {
-        let (kind, x, y, size, colour) = expect_args!(args, [int, real, real, int, int])?;
+        let (kind, x, y, size, colour) = expect_args!(args, [int, real, real, int, colour])?;
         let kind = match kind {
             0 => particle::EffectType::Explosion,
             1 => particle::EffectType::Ring,
@@ -10702,7 +11007,7 @@ impl Game {
     }
 
     pub fn effect_create_above(&mut self, args: &[Value]) -> gml::Result<Value> {
-        let (kind, x, y, size, colour) = expect_args!(args, [int, real, real, int, int])?;
+        let (kind, x, y, size, colour) = expect_args!(args, [int, real, real, int, colour])?;
         let kind = match kind {
             0 => particle::EffectType::Explosion,
             1 => particle::EffectType::Ring,
@@ -10749,6 +11054,12 @@ impl Game {
         Ok(Default::default())
     }
 
+    pub fn ds_set_collation(&mut self, args: &[Value]) -> gml::Result<Value> {
+        let locale = expect_args!(args, [bool])?;
+        self.ds_collation = if locale { ds::Collation::Locale } else { ds::Collation::Bytes };
+        Ok(Default::default())
+    }
+
     pub fn ds_stack_create(&mut self, args: &[Value]) -> gml::Result<Value> {
         expect_args!(args, [])?;
         Ok(self.stacks.put(ds::Stack::new()).into())
@@ -10984,14 +11295,30 @@ impl Game {
         }
     }
 
-    pub fn ds_queue_write(&self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 1
-        unimplemented!("Called unimplemented kernel function ds_queue_write")
+    pub fn ds_queue_write(&self, args: &[Value]) -> gml::Result<Value> {
+        let id = expect_args!(args, [int])?;
+        match self.queues.get(id) {
+            Some(queue) => Ok(ds::write_queue(queue).into()),
+            None => Err(gml::Error::FunctionError("ds_queue_write".into(), ds::Error::NonexistentStructure(id).into())),
+        }
     }
 
-    pub fn ds_queue_read(&mut self, _args: &[Value]) -> gml::Result<Value> {
-        // Expected arg count: 2
-        unimplemented!("Called unimplemented kernel function ds_queue_read")
+    pub fn ds_queue_read(&mut self, args: &[Value]) -> gml::Result<Value> {
+        let (id, hex_data) = expect_args!(args, [int, string])?;
+        match self.queues.get_mut(id) {
+            Some(old_queue) => {
+                match hex::decode(hex_data.as_ref()) {
+                    Ok(data) => {
+                        if let Some(queue) = ds::read_queue(&data) {
+                            *old_queue = queue;
+                        }
+                    },
+                    Err(e) => eprintln!("Warning (ds_queue_read): {}", e),
+                }
+                Ok(Default::default())
+            },
+            None => Err(gml::Error::FunctionError("ds_queue_read".into(), ds::Error::NonexistentStructure(id).into())),
+        }
     }
 
     pub fn ds_list_create(&mut self, args: &[Value]) -> gml::Result<Value> {
@@ -11143,11 +11470,11 @@ impl Game {
         let (id, asc) = expect_args!(args, [int, bool])?;
         match self.lists.get_mut(id) {
             Some(list) => {
-                let precision = self.ds_precision; // otherwise we get borrowing issues
+                let (precision, collation, encoding) = (self.ds_precision, self.ds_collation, self.encoding);
                 if asc {
-                    list.sort_by(|x, y| ds::cmp(x, y, precision));
+                    list.sort_by(|x, y| ds::cmp_collated(x, y, precision, collation, encoding));
                 } else {
-                    list.sort_by(|x, y| ds::cmp(y, x, precision));
+                    list.sort_by(|x, y| ds::cmp_collated(y, x, precision, collation, encoding));
                 }
                 Ok(Default::default())
             },
@@ -11175,37 +11502,18 @@ impl Game {
     pub fn ds_list_write(&self, args: &[Value]) -> gml::Result<Value> {
         let id = expect_args!(args, [int])?;
         match self.lists.get(id) {
-            Some(list) => {
-                let mut output = "2D010000".to_string();
-                output.push_str(&hex::encode_upper((list.len() as u32).to_le_bytes()));
-                output.extend(list.iter().map(|v| hex::encode_upper(v.as_bytes())));
-                Ok(output.into())
-            },
+            Some(list) => Ok(ds::write_list(list).into()),
             None => Err(gml::Error::FunctionError("ds_list_write".into(), ds::Error::NonexistentStructure(id).into())),
         }
     }
 
     pub fn ds_list_read(&mut self, args: &[Value]) -> gml::Result<Value> {
         let (id, hex_data) = expect_args!(args, [int, string])?;
-        fn read_list(mut reader: &[u8]) -> Option<ds::List> {
-            let mut buf = [0u8; 4];
-            reader.read_exact(&mut buf).ok()?;
-            if u32::from_le_bytes(buf) != 0x12d {
-                return None
-            }
-            reader.read_exact(&mut buf).ok()?;
-            let size = u32::from_le_bytes(buf) as usize;
-            let mut list = ds::List::with_capacity(size);
-            for _ in 0..size {
-                list.push(Value::from_reader(&mut reader)?);
-            }
-            Some(list)
-        }
         match self.lists.get_mut(id) {
             Some(old_list) => {
                 match hex::decode(hex_data.as_ref()) {
                     Ok(data) => {
-                        if let Some(list) = read_list(data.as_slice()) {
+                        if let Some(list) = ds::read_list(&data) {
                             *old_list = list;
                         }
                     },
@@ -11327,10 +11635,15 @@ impl Game {
         }
     }
 
+    /// What a lookup returns when there's nothing to find: 0 as in GM8, or undefined if that's been turned on.
+    fn missing_value(&self) -> Value {
+        if self.undefined_lookups { Value::undefined() } else { 0.into() }
+    }
+
     pub fn ds_map_find_value(&self, args: &[Value]) -> gml::Result<Value> {
         let (id, key) = expect_args!(args, [int, any])?;
         match self.maps.get(id) {
-            Some(map) => Ok(map.get_index(&key, self.ds_precision).map_or(0.into(), |i| map.values[i].clone())),
+            Some(map) => Ok(map.find_value(&key, self.ds_precision).unwrap_or_else(|| self.missing_value())),
             None => {
                 Err(gml::Error::FunctionError("ds_map_find_value".into(), ds::Error::NonexistentStructure(id).into()))
             },
@@ -11387,42 +11700,18 @@ impl Game {
     pub fn ds_map_write(&self, args: &[Value]) -> gml::Result<Value> {
         let id = expect_args!(args, [int])?;
         match self.maps.get(id) {
-            Some(map) => {
-                let mut output = "91010000".to_string();
-                output.push_str(&hex::encode_upper((map.keys.len() as u32).to_le_bytes()));
-                output.extend(map.keys.iter().map(|v| hex::encode_upper(v.as_bytes())));
-                output.extend(map.values.iter().map(|v| hex::encode_upper(v.as_bytes())));
-                Ok(output.into())
-            },
+            Some(map) => Ok(ds::write_map(map).into()),
             None => Err(gml::Error::FunctionError("ds_map_write".into(), ds::Error::NonexistentStructure(id).into())),
         }
     }
 
     pub fn ds_map_read(&mut self, args: &[Value]) -> gml::Result<Value> {
         let (id, hex_data) = expect_args!(args, [int, string])?;
-        fn read_map(mut reader: &[u8]) -> Option<ds::Map> {
-            let mut buf = [0u8; 4];
-            reader.read_exact(&mut buf).ok()?;
-            if u32::from_le_bytes(buf) != 0x191 {
-                return None
-            }
-            reader.read_exact(&mut buf).ok()?;
-            let size = u32::from_le_bytes(buf) as usize;
-            let mut keys = Vec::with_capacity(size);
-            let mut values = Vec::with_capacity(size);
-            for _ in 0..size {
-                keys.push(Value::from_reader(&mut reader)?);
-            }
-            for _ in 0..size {
-                values.push(Value::from_reader(&mut reader)?);
-            }
-            Some(ds::Map { keys, values })
-        }
         match self.maps.get_mut(id) {
             Some(old_map) => {
                 match hex::decode(hex_data.as_ref()) {
                     Ok(data) => {
-                        if let Some(map) = read_map(data.as_slice()) {
+                        if let Some(map) = ds::read_map(&data) {
                             *old_map = map;
                         }
                     },
@@ -11434,6 +11723,32 @@ impl Game {
         }
     }
 
+    pub fn json_decode(&mut self, args: &[Value]) -> gml::Result<Value> {
+        let text = expect_args!(args, [bytes])?;
+        match json::parse(text.as_ref()) {
+            Ok(doc) => {
+                let id = json::decode(doc, &mut self.lists, &mut self.maps, &mut self.json_nesting, self.ds_precision);
+                Ok(id.into())
+            },
+            Err(e) => {
+                eprintln!("Warning (json_decode): {}", e);
+                Ok((-1).into())
+            },
+        }
+    }
+
+    pub fn json_encode(&self, args: &[Value]) -> gml::Result<Value> {
+        let id = expect_args!(args, [int])?;
+        match json::encode(id, &self.lists, &self.maps, &self.json_nesting) {
+            Some(doc) => {
+                let mut out = Vec::new();
+                json::write(&doc, &mut out);
+                Ok(Value::Str(out.into()))
+            },
+            None => Err(gml::Error::FunctionError("json_encode".into(), ds::Error::NonexistentStructure(id).into())),
+        }
+    }
+
     pub fn ds_priority_create(&mut self, args: &[Value]) -> gml::Result<Value> {
         expect_args!(args, [])?;
         Ok(self.priority_queues.put(ds::Priority { priorities: Vec::new(), values: Vec::new() }).into())
@@ -12291,7 +12606,7 @@ impl Game {
     }
 
     pub fn d3d_set_fog(&mut self, args: &[Value]) -> gml::Result<Value> {
-        let (enabled, colour, begin, end) = expect_args!(args, [bool, int, real, real])?;
+        let (enabled, colour, begin, end) = expect_args!(args, [bool, colour, real, real])?;
         let fog = if enabled {
             Some(Fog { colour, begin: begin.into_inner() as f32, end: end.into_inner() as f32 })
         } else {
@@ -12335,7 +12650,7 @@ impl Game {
     }
 
     pub fn d3d_vertex_color(&mut self, args: &[Value]) -> gml::Result<Value> {
-        let (x, y, z, col, alpha) = expect_args!(args, [real, real, real, int, real])?;
+        let (x, y, z, col, alpha) = expect_args!(args, [real, real, real, colour, real])?;
         let col = col | 0x010000;
         self.renderer.vertex_3d(x.into(), y.into(), z.into(), 0.0, 0.0, 0.0, 0.0, 0.0, col, alpha.into());
         Ok(Default::default())
@@ -12360,7 +12675,7 @@ impl Game {
     }
 
     pub fn d3d_vertex_texture_color(&mut self, args: &[Value]) -> gml::Result<Value> {
-        let (x, y, z, xtex, ytex, col, alpha) = expect_args!(args, [real, real, real, real, real, int, real])?;
+        let (x, y, z, xtex, ytex, col, alpha) = expect_args!(args, [real, real, real, real, real, colour, real])?;
         let col = col | 0x010000;
         self.renderer.vertex_3d(
             x.into(),
@@ -12396,7 +12711,7 @@ impl Game {
     }
 
     pub fn d3d_vertex_normal_color(&mut self, args: &[Value]) -> gml::Result<Value> {
-        let (x, y, z, nx, ny, nz, col, alpha) = expect_args!(args, [real, real, real, real, real, real, int, real])?;
+        let (x, y, z, nx, ny, nz, col, alpha) = expect_args!(args, [real, real, real, real, real, real, colour, real])?;
         let col = col | 0x010000;
         self.renderer.vertex_3d(
             x.into(),
@@ -12433,7 +12748,7 @@ impl Game {
 
     pub fn d3d_vertex_normal_texture_color(&mut self, args: &[Value]) -> gml::Result<Value> {
         let (x, y, z, nx, ny, nz, xtex, ytex, col, alpha) =
-            expect_args!(args, [real, real, real, real, real, real, real, real, int, real])?;
+            expect_args!(args, [real, real, real, real, real, real, real, real, colour, real])?;
         let col = col | 0x010000;
         self.renderer.vertex_3d(
             x.into(),
@@ -12937,13 +13252,13 @@ impl Game {
     }
 
     pub fn d3d_light_define_ambient(&mut self, args: &[Value]) -> gml::Result<Value> {
-        let colour = expect_args!(args, [int])?;
+        let colour = expect_args!(args, [colour])?;
         self.renderer.set_ambient_colour(colour);
         Ok(Default::default())
     }
 
     pub fn d3d_light_define_direction(&mut self, args: &[Value]) -> gml::Result<Value> {
-        let (id, dx, dy, dz, colour) = expect_args!(args, [int, real, real, real, int])?;
+        let (id, dx, dy, dz, colour) = expect_args!(args, [int, real, real, real, colour])?;
         if (0..8).contains(&id) {
             self.renderer.set_light(id as usize, Light::Directional {
                 direction: [dx.into_inner() as f32, dy.into_inner() as f32, dz.into_inner() as f32],
@@ -12954,7 +13269,7 @@ impl Game {
     }
 
     pub fn d3d_light_define_point(&mut self, args: &[Value]) -> gml::Result<Value> {
-        let (id, x, y, z, range, colour) = expect_args!(args, [int, real, real, real, real, int])?;
+        let (id, x, y, z, range, colour) = expect_args!(args, [int, real, real, real, real, colour])?;
         if (0..8).contains(&id) {
             self.renderer.set_light(id as usize, Light::Point {
                 position: [x.into_inner() as f32, y.into_inner() as f32, z.into_inner() as f32],
@@ -13391,7 +13706,7 @@ impl Game {
     }
 
     pub fn d3d_model_vertex_color(&mut self, args: &[Value]) -> gml::Result<Value> {
-        let (model_id, x, y, z, col, alpha) = expect_args!(args, [int, real, real, real, int, real])?;
+        let (model_id, x, y, z, col, alpha) = expect_args!(args, [int, real, real, real, colour, real])?;
         if let Some(model) = self.models.get_asset_mut(model_id) {
             model.commands.push(model::Command::VertexColour {
                 pos: [x, y, z],
@@ -13417,7 +13732,7 @@ impl Game {
 
     pub fn d3d_model_vertex_texture_color(&mut self, args: &[Value]) -> gml::Result<Value> {
         let (model_id, x, y, z, xtex, ytex, col, alpha) =
-            expect_args!(args, [int, real, real, real, real, real, int, real])?;
+            expect_args!(args, [int, real, real, real, real, real, colour, real])?;
         if let Some(model) = self.models.get_asset_mut(model_id) {
             model.commands.push(model::Command::VertexColour {
                 pos: [x, y, z],
@@ -13443,7 +13758,7 @@ impl Game {
 
     pub fn d3d_model_vertex_normal_color(&mut self, args: &[Value]) -> gml::Result<Value> {
         let (model_id, x, y, z, nx, ny, nz, col, alpha) =
-            expect_args!(args, [int, real, real, real, real, real, real, int, real])?;
+            expect_args!(args, [int, real, real, real, real, real, real, colour, real])?;
         if let Some(model) = self.models.get_asset_mut(model_id) {
             model.commands.push(model::Command::VertexColour {
                 pos: [x, y, z],
@@ -13470,7 +13785,7 @@ impl Game {
 
     pub fn d3d_model_vertex_normal_texture_color(&mut self, args: &[Value]) -> gml::Result<Value> {
         let (model_id, x, y, z, nx, ny, nz, xtex, ytex, col, alpha) =
-            expect_args!(args, [int, real, real, real, real, real, real, real, real, int, real])?;
+            expect_args!(args, [int, real, real, real, real, real, real, real, real, colour, real])?;
         if let Some(model) = self.models.get_asset_mut(model_id) {
             model.commands.push(model::Command::VertexColour {
                 pos: [x, y, z],
@@ -0,0 +1,53 @@
+//! Warnings for when a string is converted to a number by one of Value's lossy `From` impls, which turn any string
+//! into 0. That's what GM8 does when a string is passed where a number is expected, but it's also what happens when
+//! a builtin is wired up to take the wrong type, so seeing where it happens helps tell the two apart.
+//! Only built with the `lossy-warnings` feature, so it costs nothing otherwise.
+
+use crate::gml;
+use std::cell::RefCell;
+
+/// How many warnings are kept for `take`. Every one is still printed.
+pub const MAX_KEPT: usize = 100;
+
+thread_local! {
+    static WARNINGS: RefCell<Vec<String>> = RefCell::new(Vec::new());
+}
+
+/// Warns that a string was converted to the given type as 0.
+pub fn warn(target: &str, string: &gml::String) {
+    let warning = format!("Warning: string \"{}\" converted to {} as 0, is it meant to be a number?", string, target);
+    eprintln!("{}", warning);
+    WARNINGS.with(|warnings| {
+        let mut warnings = warnings.borrow_mut();
+        if warnings.len() < MAX_KEPT {
+            warnings.push(warning);
+        }
+    });
+}
+
+/// Takes the warnings given so far on this thread, up to `MAX_KEPT` of them.
+pub fn take() -> Vec<String> {
+    WARNINGS.with(|warnings| warnings.take())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{gml::Value, math::Real};
+
+    #[test]
+    fn string_to_number() {
+        take();
+        assert_eq!(i32::from(Value::from(3)), 3);
+        assert_eq!(f64::from(Value::from(1.5)), 1.5);
+        assert!(take().is_empty());
+
+        assert_eq!(i32::from(Value::from("12")), 0);
+        assert_eq!(Real::from(Value::from("abc")), Real::from(0.0));
+        let warnings = take();
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].contains("\"12\" converted to i32"), "{}", warnings[0]);
+        assert!(warnings[1].contains("\"abc\" converted to real"), "{}", warnings[1]);
+        assert!(take().is_empty());
+    }
+}
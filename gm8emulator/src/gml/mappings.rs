@@ -974,18 +974,27 @@ pub const FUNCTIONS: phf::OrderedMap<&'static str, Function> = phf_ordered_map!
     "draw_set_font" => Function::Engine(Game::draw_set_font),
     "draw_set_halign" => Function::Engine(Game::draw_set_halign),
     "draw_set_valign" => Function::Engine(Game::draw_set_valign),
+    "draw_set_text_clip" => Function::Engine(Game::draw_set_text_clip),
+    "draw_reset_text_clip" => Function::Engine(Game::draw_reset_text_clip),
+    "draw_set_text_monospace" => Function::Engine(Game::draw_set_text_monospace),
+    "draw_set_text_snap_baseline" => Function::Engine(Game::draw_set_text_snap_baseline),
     "string_width" => Function::Constant(Game::string_width),
     "string_height" => Function::Constant(Game::string_height),
+    "string_ink_width" => Function::Engine(Game::string_ink_width),
+    "string_ink_height" => Function::Engine(Game::string_ink_height),
     "string_width_ext" => Function::Constant(Game::string_width_ext),
     "string_height_ext" => Function::Constant(Game::string_height_ext),
     "draw_text" => Function::Engine(Game::draw_text),
     "draw_text_ext" => Function::Engine(Game::draw_text_ext),
+    "draw_text_reveal" => Function::Engine(Game::draw_text_reveal),
+    "draw_text_ext_reveal" => Function::Engine(Game::draw_text_ext_reveal),
     "draw_text_transformed" => Function::Engine(Game::draw_text_transformed),
     "draw_text_ext_transformed" => Function::Engine(Game::draw_text_ext_transformed),
     "draw_text_color" => Function::Engine(Game::draw_text_color),
     "draw_text_transformed_color" => Function::Engine(Game::draw_text_transformed_color),
     "draw_text_ext_color" => Function::Engine(Game::draw_text_ext_color),
     "draw_text_ext_transformed_color" => Function::Engine(Game::draw_text_ext_transformed_color),
+    "draw_text_wave" => Function::Engine(Game::draw_text_wave),
     "draw_self" => Function::Runtime(Game::draw_self),
     "draw_sprite" => Function::Runtime(Game::draw_sprite),
     "draw_sprite_pos" => Function::Runtime(Game::draw_sprite_pos),
@@ -1199,6 +1208,7 @@ pub const FUNCTIONS: phf::OrderedMap<&'static str, Function> = phf_ordered_map!
     "action_effect" => Function::Runtime(Game::action_effect),
     "is_real" => Function::Pure(Game::is_real),
     "is_string" => Function::Pure(Game::is_string),
+    "is_undefined" => Function::Pure(Game::is_undefined),
     "random" => Function::Engine(Game::random),
     "random_range" => Function::Engine(Game::random_range),
     "irandom" => Function::Engine(Game::irandom),
@@ -1225,6 +1235,14 @@ pub const FUNCTIONS: phf::OrderedMap<&'static str, Function> = phf_ordered_map!
     "arccos" => Function::Pure(Game::arccos),
     "arctan" => Function::Pure(Game::arctan),
     "arctan2" => Function::Pure(Game::arctan2),
+    "dsin" => Function::Pure(Game::dsin),
+    "dcos" => Function::Pure(Game::dcos),
+    "dtan" => Function::Pure(Game::dtan),
+    "darcsin" => Function::Pure(Game::darcsin),
+    "darccos" => Function::Pure(Game::darccos),
+    "darctan" => Function::Pure(Game::darctan),
+    "darctan2" => Function::Pure(Game::darctan2),
+    "angle_difference" => Function::Pure(Game::angle_difference),
     "degtorad" => Function::Pure(Game::degtorad),
     "radtodeg" => Function::Pure(Game::radtodeg),
     "power" => Function::Pure(Game::power),
@@ -1237,6 +1255,7 @@ pub const FUNCTIONS: phf::OrderedMap<&'static str, Function> = phf_ordered_map!
     "median" => Function::Pure(Game::median),
     "choose" => Function::Engine(Game::choose),
     "clamp" => Function::Pure(Game::clamp),
+    "in_range" => Function::Pure(Game::in_range),
     "lerp" => Function::Pure(Game::lerp),
     "real" => Function::Constant(Game::real),
     "string" => Function::Pure(Game::string),
@@ -1248,6 +1267,7 @@ pub const FUNCTIONS: phf::OrderedMap<&'static str, Function> = phf_ordered_map!
     "string_byte_length" => Function::Pure(Game::string_byte_length),
     "string_byte_at" => Function::Pure(Game::string_byte_at),
     "string_pos" => Function::Constant(Game::string_pos),
+    "string_pos_nocase" => Function::Constant(Game::string_pos_nocase),
     "string_copy" => Function::Constant(Game::string_copy),
     "string_char_at" => Function::Constant(Game::string_char_at),
     "string_delete" => Function::Constant(Game::string_delete),
@@ -1255,12 +1275,12 @@ pub const FUNCTIONS: phf::OrderedMap<&'static str, Function> = phf_ordered_map!
     "string_lower" => Function::Constant(Game::string_lower),
     "string_upper" => Function::Constant(Game::string_upper),
     "string_repeat" => Function::Pure(Game::string_repeat),
-    "string_letters" => Function::Pure(Game::string_letters),
-    "string_digits" => Function::Pure(Game::string_digits),
-    "string_lettersdigits" => Function::Pure(Game::string_lettersdigits),
+    "string_letters" => Function::Constant(Game::string_letters),
+    "string_digits" => Function::Constant(Game::string_digits),
+    "string_lettersdigits" => Function::Constant(Game::string_lettersdigits),
     "string_replace" => Function::Pure(Game::string_replace),
     "string_replace_all" => Function::Pure(Game::string_replace_all),
-    "string_count" => Function::Pure(Game::string_count),
+    "string_count" => Function::Constant(Game::string_count),
     "dot_product" => Function::Pure(Game::dot_product),
     "dot_product_3d" => Function::Pure(Game::dot_product_3d),
     "point_distance_3d" => Function::Pure(Game::point_distance_3d),
@@ -1613,6 +1633,8 @@ pub const FUNCTIONS: phf::OrderedMap<&'static str, Function> = phf_ordered_map!
     "variable_local_set" => Function::Runtime(Game::variable_local_set),
     "variable_local_array_set" => Function::Runtime(Game::variable_local_array_set),
     "variable_local_array2_set" => Function::Runtime(Game::variable_local_array2_set),
+    "array_length_1d" => Function::Runtime(Game::array_length_1d),
+    "array_length_2d" => Function::Runtime(Game::array_length_2d),
     "clipboard_has_text" => Function::Volatile(Game::clipboard_has_text),
     "clipboard_set_text" => Function::Engine(Game::clipboard_set_text),
     "clipboard_get_text" => Function::Volatile(Game::clipboard_get_text),
@@ -1734,8 +1756,14 @@ pub const FUNCTIONS: phf::OrderedMap<&'static str, Function> = phf_ordered_map!
     "font_add" => Function::Engine(Game::font_add),
     "font_replace" => Function::Engine(Game::font_replace),
     "font_add_sprite" => Function::Engine(Game::font_add_sprite),
+    "font_add_sprite_ext" => Function::Engine(Game::font_add_sprite_ext),
     "font_replace_sprite" => Function::Engine(Game::font_replace_sprite),
     "font_delete" => Function::Engine(Game::font_delete),
+    "font_set_fallback" => Function::Engine(Game::font_set_fallback),
+    "font_replace_glyph" => Function::Engine(Game::font_replace_glyph),
+    "font_export_metrics" => Function::Engine(Game::font_export_metrics),
+    "font_get_outline" => Function::Engine(Game::font_get_outline),
+    "font_get_occupancy" => Function::Constant(Game::font_get_occupancy),
     "script_name" => Function::Constant(Game::script_get_name),
     "script_exists" => Function::Constant(Game::script_exists),
     "script_get_name" => Function::Constant(Game::script_get_name),
@@ -1907,6 +1935,7 @@ pub const FUNCTIONS: phf::OrderedMap<&'static str, Function> = phf_ordered_map!
     "effect_create_above" => Function::Engine(Game::effect_create_above),
     "effect_clear" => Function::Engine(Game::effect_clear),
     "ds_set_precision" => Function::Engine(Game::ds_set_precision),
+    "ds_set_collation" => Function::Engine(Game::ds_set_collation),
     "ds_stack_create" => Function::Engine(Game::ds_stack_create),
     "ds_stack_destroy" => Function::Engine(Game::ds_stack_destroy),
     "ds_stack_clear" => Function::Engine(Game::ds_stack_clear),
@@ -1963,6 +1992,8 @@ pub const FUNCTIONS: phf::OrderedMap<&'static str, Function> = phf_ordered_map!
     "ds_map_find_last" => Function::Constant(Game::ds_map_find_last),
     "ds_map_write" => Function::Constant(Game::ds_map_write),
     "ds_map_read" => Function::Engine(Game::ds_map_read),
+    "json_decode" => Function::Engine(Game::json_decode),
+    "json_encode" => Function::Constant(Game::json_encode),
     "ds_priority_create" => Function::Engine(Game::ds_priority_create),
     "ds_priority_destroy" => Function::Engine(Game::ds_priority_destroy),
     "ds_priority_clear" => Function::Engine(Game::ds_priority_clear),
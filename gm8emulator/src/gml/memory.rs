@@ -0,0 +1,139 @@
+//! Totals of what a game's variables and data structures hold, to show where its memory goes in the debug UI.
+//! Strings are shared between Values, so each one only counts towards the byte totals once, however many Values
+//! hold it.
+
+use crate::{game::Game, gml::Value, instance::Field};
+use std::{collections::HashSet, fmt};
+
+/// Which part of the game a Value was found in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Store {
+    /// Instance, global and built-in variables.
+    Variables,
+    /// ds_stack, ds_queue, ds_list, ds_map, ds_priority and ds_grid contents.
+    DataStructures,
+}
+
+/// What the Values in one store hold.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub reals: usize,
+    pub strings: usize,
+    /// Bytes of string data, leaving out strings which were already counted, here or in another store.
+    pub string_bytes: usize,
+}
+
+impl std::ops::Add for Usage {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        Self {
+            reals: self.reals + rhs.reals,
+            strings: self.strings + rhs.strings,
+            string_bytes: self.string_bytes + rhs.string_bytes,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MemoryReport {
+    pub variables: Usage,
+    pub data_structures: Usage,
+    seen: HashSet<*const u8>,
+}
+
+impl MemoryReport {
+    pub fn add(&mut self, store: Store, value: &Value) {
+        let usage = match store {
+            Store::Variables => &mut self.variables,
+            Store::DataStructures => &mut self.data_structures,
+        };
+        match value {
+            Value::Real(_) => usage.reals += 1,
+            Value::Str(s) => {
+                usage.strings += 1;
+                if self.seen.insert(s.as_ptr()) {
+                    usage.string_bytes += s.as_ref().len();
+                }
+            },
+        }
+    }
+
+    pub fn add_field(&mut self, store: Store, field: &Field) {
+        match field {
+            Field::Single(value) => self.add(store, value),
+            Field::Array(array) => array.values().for_each(|value| self.add(store, value)),
+        }
+    }
+
+    pub fn total(&self) -> Usage {
+        self.variables + self.data_structures
+    }
+
+    /// How many separate strings were found.
+    pub fn unique_strings(&self) -> usize {
+        self.seen.len()
+    }
+}
+
+impl fmt::Display for MemoryReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let total = self.total();
+        write!(f, "Strings: {} KiB, reals: {}", (total.string_bytes + 1023) / 1024, total.reals)
+    }
+}
+
+impl Game {
+    /// Goes through every variable and data structure in the game, counting what they hold.
+    pub fn memory_report(&self) -> MemoryReport {
+        let mut report = MemoryReport::default();
+        for field in self.globals.fields.values().chain(self.globals.vars.values()) {
+            report.add_field(Store::Variables, field);
+        }
+        let list = &self.room.instance_list;
+        let (mut active, mut inactive) = (list.iter_by_insertion(), list.iter_inactive());
+        while let Some(handle) = active.next(list).or_else(|| inactive.next(list)) {
+            for field in list.get(handle).fields.borrow().values() {
+                report.add_field(Store::Variables, field);
+            }
+        }
+
+        let mut add_all = |values: &mut dyn Iterator<Item = &Value>| {
+            values.for_each(|value| report.add(Store::DataStructures, value));
+        };
+        self.stacks.iter().for_each(|(_, stack)| add_all(&mut stack.iter()));
+        self.queues.iter().for_each(|(_, queue)| add_all(&mut queue.iter()));
+        self.lists.iter().for_each(|(_, list)| add_all(&mut list.iter()));
+        self.maps.iter().for_each(|(_, map)| add_all(&mut map.keys.iter().chain(&map.values)));
+        self.priority_queues.iter().for_each(|(_, pq)| add_all(&mut pq.priorities.iter().chain(&pq.values)));
+        for (_, grid) in self.grids.iter() {
+            add_all(&mut (0..grid.width()).flat_map(|x| (0..grid.height()).map(move |y| grid.get(x, y))));
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn string_bytes() {
+        let shared = Value::from("shared");
+        let mut report = MemoryReport::default();
+        report.add(Store::Variables, &shared);
+        report.add(Store::Variables, &Value::from("four"));
+        report.add(Store::Variables, &Value::from(1));
+        report.add_field(Store::Variables, &Field::new(3, Value::from("abc")));
+        // the same string again, and another string with the same contents
+        report.add(Store::DataStructures, &shared.clone());
+        report.add(Store::DataStructures, &Value::from("shared"));
+        report.add(Store::DataStructures, &Value::from(2));
+        report.add(Store::DataStructures, &Value::from(""));
+
+        assert_eq!(report.variables, Usage { reals: 1, strings: 3, string_bytes: 6 + 4 + 3 });
+        assert_eq!(report.data_structures, Usage { reals: 1, strings: 3, string_bytes: 6 });
+        assert_eq!(report.total().string_bytes, 19);
+        assert_eq!(report.unique_strings(), 5);
+    }
+}
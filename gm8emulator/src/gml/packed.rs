@@ -0,0 +1,174 @@
+//! A NaN-boxed array of `Value`s in 8 bytes each, for storing large numbers of values compactly.
+//!
+//! Reals are stored as they are, except that every NaN is folded into one canonical NaN, which GML can't tell apart
+//! anyway. A string can't fit in 8 bytes, since `gml::String` is a pointer and a length, so it's moved into a side
+//! table and the index of its slot is stored in the payload of a NaN with the sign bit set, which no real can have.
+//! The string itself isn't copied or reallocated, and freed slots are reused, so an array which is mostly reals
+//! takes about half the memory of a `Vec<Value>`.
+
+use crate::gml::{self, Value};
+
+const STR_TAG: u64 = 0xFFFF_0000_0000_0000;
+const INDEX_MASK: u64 = 0x0000_FFFF_FFFF_FFFF;
+
+#[derive(Clone, Debug, Default)]
+pub struct PackedValues {
+    words: Vec<u64>,
+    strings: Vec<Option<gml::String>>,
+    free: Vec<usize>,
+}
+
+impl PackedValues {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.words.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    // This will panic on OOB, so make sure you check bounds before calling
+    pub fn get(&self, index: usize) -> Value {
+        let word = self.words[index];
+        match Self::string_slot(word) {
+            Some(slot) => Value::Str(self.strings[slot].clone().unwrap()),
+            None => Value::Real(f64::from_bits(word).into()),
+        }
+    }
+
+    // This will panic on OOB, so make sure you check bounds before calling
+    pub fn set(&mut self, index: usize, value: Value) {
+        self.release(self.words[index]);
+        self.words[index] = self.pack(value);
+    }
+
+    pub fn push(&mut self, value: Value) {
+        let word = self.pack(value);
+        self.words.push(word);
+    }
+
+    pub fn resize(&mut self, len: usize, value: Value) {
+        while self.len() > len {
+            let word = self.words.pop().unwrap();
+            self.release(word);
+        }
+        while self.len() < len {
+            self.push(value.clone());
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Value> + '_ {
+        (0..self.len()).map(move |i| self.get(i))
+    }
+
+    fn string_slot(word: u64) -> Option<usize> {
+        if word & STR_TAG == STR_TAG { Some((word & INDEX_MASK) as usize) } else { None }
+    }
+
+    fn pack(&mut self, value: Value) -> u64 {
+        match value {
+            Value::Real(r) if r.into_inner().is_nan() => f64::NAN.to_bits(),
+            Value::Real(r) => r.into_inner().to_bits(),
+            Value::Str(s) => {
+                let slot = match self.free.pop() {
+                    Some(slot) => {
+                        self.strings[slot] = Some(s);
+                        slot
+                    },
+                    None => {
+                        self.strings.push(Some(s));
+                        self.strings.len() - 1
+                    },
+                };
+                assert!(slot as u64 <= INDEX_MASK, "too many strings for a NaN payload");
+                STR_TAG | slot as u64
+            },
+        }
+    }
+
+    fn release(&mut self, word: u64) {
+        if let Some(slot) = Self::string_slot(word) {
+            self.strings[slot] = None;
+            self.free.push(slot);
+        }
+    }
+}
+
+impl std::iter::FromIterator<Value> for PackedValues {
+    fn from_iter<I: IntoIterator<Item = Value>>(iter: I) -> Self {
+        let mut values = Self::new();
+        for value in iter {
+            values.push(value);
+        }
+        values
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let reals = [0.0, -0.0, -1.5, 1e300, f64::INFINITY, f64::NEG_INFINITY, f64::MIN_POSITIVE, f64::NAN, -f64::NAN];
+        let values = reals.iter().map(|&r| Value::from(r)).collect::<PackedValues>();
+        assert_eq!(values.len(), reals.len());
+        for (packed, &r) in values.iter().zip(reals.iter()) {
+            match packed {
+                Value::Real(x) if r.is_nan() => assert!(x.into_inner().is_nan()),
+                Value::Real(x) => assert_eq!(x.into_inner().to_bits(), r.to_bits()),
+                Value::Str(_) => panic!("{} came back as a string", r),
+            }
+        }
+
+        let s = gml::String::from("hello");
+        let mut values = PackedValues::new();
+        values.push(Value::from(1.0));
+        values.push(Value::Str(s.clone()));
+        match values.get(1) {
+            Value::Str(x) => assert!(x.ptr_eq(&s), "string was copied"),
+            Value::Real(_) => panic!("string came back as a real"),
+        }
+        assert_eq!(f64::from(values.get(0)), 1.0);
+    }
+
+    #[test]
+    fn slots_reused() {
+        let mut values = PackedValues::new();
+        values.resize(4, Value::from("a"));
+        assert_eq!(values.strings.len(), 4);
+        values.set(1, Value::from(2.0));
+        values.set(2, Value::from(3.0));
+        values.set(3, Value::from("b"));
+        values.push(Value::from("c"));
+        assert_eq!(values.strings.len(), 4);
+        let expected = [Value::from("a"), Value::from(2.0), Value::from(3.0), Value::from("b"), Value::from("c")];
+        assert_eq!(format!("{:?}", values.iter().collect::<Vec<_>>()), format!("{:?}", expected));
+        values.resize(1, Value::from(0.0));
+        assert_eq!(values.free.len(), 3);
+    }
+}
+
+#[cfg(test)]
+mod bench {
+    use super::*;
+    use test::Bencher;
+
+    const COUNT: usize = 1 << 20;
+
+    #[bench]
+    fn sum_vec(b: &mut Bencher) {
+        let values = (0..COUNT).map(|i| Value::from(i as f64)).collect::<Vec<_>>();
+        b.iter(|| values.iter().map(|v| f64::from(v.clone())).sum::<f64>());
+    }
+
+    #[bench]
+    fn sum_packed(b: &mut Bencher) {
+        let values = (0..COUNT).map(|i| Value::from(i as f64)).collect::<PackedValues>();
+        b.iter(|| values.iter().map(f64::from).sum::<f64>());
+    }
+}
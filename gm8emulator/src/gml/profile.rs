@@ -0,0 +1,66 @@
+//! Counts of every Value operation run by the interpreter, grouped by operator and operand types.
+//! This shows whether a game spends its time on arithmetic or on string handling.
+//! Only built with the `value-profiler` feature, so it costs nothing otherwise.
+
+use crate::gml::Value;
+use std::{cell::RefCell, collections::HashMap, fmt};
+
+/// Operator name, then type of each operand. Unary operators have an empty right-hand type.
+pub type Key = (String, &'static str, &'static str);
+
+thread_local! {
+    static COUNTS: RefCell<HashMap<Key, u64>> = RefCell::new(HashMap::new());
+}
+
+pub fn record(op: impl fmt::Debug, lhs: &Value, rhs: Option<&Value>) {
+    let key = (format!("{:?}", op), lhs.ty_str(), rhs.map(Value::ty_str).unwrap_or(""));
+    COUNTS.with(|counts| *counts.borrow_mut().entry(key).or_insert(0) += 1);
+}
+
+/// Gets all counts recorded so far, most frequent first.
+pub fn counts() -> Vec<(Key, u64)> {
+    let mut counts = COUNTS.with(|counts| counts.borrow().iter().map(|(k, v)| (k.clone(), *v)).collect::<Vec<_>>());
+    counts.sort_by(|(k1, v1), (k2, v2)| v2.cmp(v1).then_with(|| k1.cmp(k2)));
+    counts
+}
+
+pub fn reset() {
+    COUNTS.with(|counts| counts.borrow_mut().clear());
+}
+
+/// Formats the counts as a table, one operation per line.
+pub fn dump() -> String {
+    let mut out = String::new();
+    for ((op, lhs, rhs), count) in counts() {
+        if rhs.is_empty() {
+            out += &format!("{:>10} {} {}\n", count, op, lhs);
+        } else {
+            out += &format!("{:>10} {} {} {}\n", count, lhs, op, rhs);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gml::runtime::{BinaryOperator, UnaryOperator};
+
+    #[test]
+    fn count_operations() {
+        reset();
+        for i in 0..5 {
+            BinaryOperator::Add.call(Value::from(i), Value::from(1)).unwrap();
+        }
+        BinaryOperator::Add.call(Value::from("a"), Value::from("b")).unwrap();
+        BinaryOperator::Add.call(Value::from("a"), Value::from("b")).unwrap();
+        BinaryOperator::LessThan.call(Value::from(1), Value::from(2)).unwrap();
+        UnaryOperator::Neg.call(Value::from(1)).unwrap();
+        assert_eq!(counts(), vec![
+            (("Add".into(), "real", "real"), 5),
+            (("Add".into(), "string", "string"), 2),
+            (("LessThan".into(), "real", "real"), 1),
+            (("Neg".into(), "real", ""), 1),
+        ]);
+    }
+}
@@ -82,6 +82,15 @@ impl Random {
         let lb = u64::from(bound.wrapping_add(1));
         ((ls.wrapping_mul(lb)) >> 32) as _
     }
+
+    /// Equivalent to GML choose(...).
+    ///
+    /// Picks one of the given items, cycling the seed once. Returns None without cycling it if there are none.
+    #[inline]
+    pub fn choose<'a, T>(&mut self, items: &'a [T]) -> Option<&'a T> {
+        let last = items.len().checked_sub(1)?;
+        Some(&items[self.next_int(last as _) as usize])
+    }
 }
 
 // Makes a pseudorandom integer. Only used for seeding, such as in randomize().
@@ -90,3 +99,24 @@ fn rand_int() -> i32 {
     let _ = getrandom::getrandom(&mut bytes);
     i32::from_le_bytes(bytes)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gml::{testing::values, Value};
+
+    #[test]
+    fn choose_sequence() {
+        // the same sequence as Delphi's Random(4), which GM8's choose uses, from the seed 12345
+        let mut rand = Random::with_seed(12345);
+        let args = values!["a", "b", "c", 3];
+        let picks = (0..8).map(|_| rand.choose(&args).unwrap().repr().to_string()).collect::<Vec<_>>();
+        assert_eq!(picks, ["b", "b", "a", "c", "c", "b", "b", "a"]);
+
+        let mut rand = Random::with_seed(12345);
+        rand.choose(&args);
+        assert_eq!(rand.seed(), 1655067934);
+        assert!(rand.choose::<Value>(&[]).is_none());
+        assert_eq!(rand.seed(), 1655067934);
+    }
+}
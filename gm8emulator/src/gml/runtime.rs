@@ -5,10 +5,13 @@ use crate::{
         self,
         datetime::DateTime,
         mappings::{self, constants as gml_constants},
+        undo,
+        value::EqualityQuirks,
         Context, InstanceVariable, Value,
     },
-    instance::Field,
+    instance::{Field, Instance, ARRAY_ROW},
     math::Real,
+    types::ID,
 };
 use gml_parser::token::Operator;
 use serde::{Deserialize, Serialize};
@@ -20,6 +23,41 @@ use std::{
 
 const DEFAULT_ALARM: i32 = -1;
 
+/// How deeply code and events can nest before the interpreter gives up. Each level uses several native stack frames,
+/// so this keeps runaway recursion well clear of the thread's stack limit while leaving room for legitimate games.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 4096;
+
+/// Counts how many pieces of code and events are currently running, so that runaway recursion raises a GML error
+/// instead of overflowing the native stack and aborting the process.
+#[derive(Clone, Copy, Debug)]
+pub struct CallDepth {
+    depth: usize,
+    pub limit: usize,
+}
+
+impl Default for CallDepth {
+    fn default() -> Self {
+        Self { depth: 0, limit: DEFAULT_MAX_CALL_DEPTH }
+    }
+}
+
+impl CallDepth {
+    /// Enters a call, failing if that would nest deeper than the limit.
+    pub fn enter(&mut self) -> gml::Result<()> {
+        if self.depth >= self.limit {
+            Err(Error::StackOverflow(self.limit))
+        } else {
+            self.depth += 1;
+            Ok(())
+        }
+    }
+
+    /// Leaves a call previously entered with `enter`.
+    pub fn leave(&mut self) {
+        self.depth = self.depth.saturating_sub(1);
+    }
+}
+
 /// A compiled runtime instruction. Generally represents a line of code.
 #[derive(Serialize, Deserialize)]
 pub enum Instruction {
@@ -159,8 +197,10 @@ pub enum Error {
     UninitializedArgument(usize),
     TooManyArrayDimensions(usize),
     WrongArgumentCount(usize, usize),
+    WrongArgumentType(String, Value),
     FunctionError(String, String),
     ReplayError(String),
+    StackOverflow(usize),
     BadDirectoryError(String),
     ExternalFunction(String, String),
 }
@@ -174,6 +214,8 @@ impl Display for Error {
             Self::InvalidOperandsUnary(op, x) => {
                 write!(f, "invalid operands {} to {} operator ({1}{})", x.ty_str(), op, x)
             },
+            // GM8's own wording, since error handlers in games sometimes show or check for it
+            Self::InvalidOperandsBinary(Operator::Add, _, _) => write!(f, "Wrong type of arguments to +."),
             Self::InvalidOperandsBinary(op, x, y) => {
                 write!(f, "invalid operands {} and {} to {} operator ({} {2} {})", x.ty_str(), y.ty_str(), op, x, y)
             },
@@ -205,8 +247,12 @@ impl Display for Error {
             Self::UninitializedArgument(n) => write!(f, "uninitialized argument #{}", n),
             Self::TooManyArrayDimensions(n) => write!(f, "too many array dimensions ({})", n),
             Self::WrongArgumentCount(exp, got) => write!(f, "wrong argument count (expected: {}, got: {})", exp, got),
+            Self::WrongArgumentType(exp, got) => {
+                write!(f, "wrong type of argument (expected: {}, got: {} {})", exp, got.ty_str(), got)
+            },
             Self::FunctionError(fname, s) => write!(f, "{}: {}", fname, s),
             Self::ReplayError(s) => write!(f, "{}", s),
+            Self::StackOverflow(limit) => write!(f, "stack overflow (calls nested more than {} deep)", limit),
             Self::BadDirectoryError(s) => write!(f, "cannot encode working directory {} with current encoding", s),
             Self::ExternalFunction(s, e) => write!(f, "failed to call external function \"{}\": {}", s, e),
         }
@@ -271,7 +317,27 @@ impl fmt::Debug for Node {
 
 impl BinaryOperator {
     pub fn call(&self, lhs: Value, rhs: Value) -> gml::Result<Value> {
+        self.call_with(lhs, rhs, &Default::default())
+    }
+
+    /// Like `call`, but with the given equality quirks applied to `==` and `!=`.
+    pub fn call_with(&self, lhs: Value, rhs: Value, quirks: &EqualityQuirks) -> gml::Result<Value> {
+        #[cfg(feature = "value-profiler")]
+        gml::profile::record(self, &lhs, Some(&rhs));
+        #[cfg(feature = "value-trace")]
+        let operands = if gml::trace::enabled() { Some((lhs.clone(), rhs.clone())) } else { None };
+        let result = self.apply(lhs, rhs, quirks);
+        #[cfg(feature = "value-trace")]
+        if let Some((lhs, rhs)) = operands {
+            gml::trace::record(self, &lhs, Some(&rhs), &result);
+        }
+        result
+    }
+
+    fn apply(&self, lhs: Value, rhs: Value, quirks: &EqualityQuirks) -> gml::Result<Value> {
         let f = match self {
+            Self::Equal => return lhs.gml_eq_with(rhs, quirks),
+            Self::NotEqual => return lhs.gml_ne_with(rhs, quirks),
             Self::Add => Value::add,
             Self::And => Value::bool_and,
             Self::BitwiseAnd => Value::bitand,
@@ -280,7 +346,6 @@ impl BinaryOperator {
             Self::BinaryShiftRight => Value::shr,
             Self::BitwiseXor => Value::bitxor,
             Self::Divide => Value::div,
-            Self::Equal => Value::gml_eq,
             Self::GreaterThan => Value::gml_gt,
             Self::GreaterThanOrEqual => Value::gml_gte,
             Self::IntDivide => Value::intdiv,
@@ -288,7 +353,6 @@ impl BinaryOperator {
             Self::LessThanOrEqual => Value::gml_lte,
             Self::Multiply => Value::mul,
             Self::Modulo => Value::modulo,
-            Self::NotEqual => Value::gml_ne,
             Self::Or => Value::bool_or,
             Self::Subtract => Value::sub,
             Self::Xor => Value::bool_xor,
@@ -299,12 +363,21 @@ impl BinaryOperator {
 
 impl UnaryOperator {
     pub fn call(&self, value: Value) -> gml::Result<Value> {
+        #[cfg(feature = "value-profiler")]
+        gml::profile::record(self, &value, None);
+        #[cfg(feature = "value-trace")]
+        let operand = if gml::trace::enabled() { Some(value.clone()) } else { None };
         let f = match self {
             Self::Neg => Value::neg,
             Self::Not => Value::not,
             Self::Complement => Value::complement,
         };
-        f(value)
+        let result = f(value);
+        #[cfg(feature = "value-trace")]
+        if let Some(value) = operand {
+            gml::trace::record(self, &value, None, &result);
+        }
+        result
     }
 }
 
@@ -313,7 +386,15 @@ impl Game {
         mappings::FUNCTIONS.index(function_id).unwrap().1.invoke(self, context, args)
     }
 
+    /// Runs a script, code action or other piece of code from the start, counting it towards the call depth limit.
     pub fn execute(&mut self, instructions: &[Instruction], context: &mut Context) -> gml::Result<ReturnType> {
+        self.call_depth.enter()?;
+        let result = self.execute_block(instructions, context);
+        self.call_depth.leave();
+        result
+    }
+
+    fn execute_block(&mut self, instructions: &[Instruction], context: &mut Context) -> gml::Result<ReturnType> {
         for instruction in instructions.iter() {
             match self.exec_instruction(instruction, context)? {
                 ReturnType::Normal => (),
@@ -348,6 +429,10 @@ impl Game {
                         }
                     },
                     Target::Global => {
+                        if let Some(log) = &mut self.undo_log {
+                            let old = self.globals.fields.get(&accessor.index);
+                            log.record(undo::Location::GlobalField(accessor.index), old);
+                        }
                         if let Some(field) = self.globals.fields.get_mut(&accessor.index) {
                             field.set(array_index, value)
                         } else {
@@ -386,6 +471,9 @@ impl Game {
                         }
                     },
                     Target::Global => {
+                        if let Some(log) = &mut self.undo_log {
+                            log.record(undo::Location::GlobalVar(accessor.var), self.globals.vars.get(&accessor.var));
+                        }
                         if let Some(field) = self.globals.vars.get_mut(&accessor.var) {
                             field.set(array_index, value)
                         } else {
@@ -406,16 +494,16 @@ impl Game {
             },
             Instruction::IfElse { cond, if_body, else_body } => {
                 let return_type = if self.eval(cond, context)?.is_truthy() {
-                    self.execute(if_body, context)
+                    self.execute_block(if_body, context)
                 } else {
-                    self.execute(else_body, context)
+                    self.execute_block(else_body, context)
                 }?;
                 if return_type != ReturnType::Normal {
                     return Ok(return_type)
                 }
             },
             Instruction::LoopUntil { cond, body } => loop {
-                match self.execute(body, context)? {
+                match self.execute_block(body, context)? {
                     ReturnType::Normal => (),
                     ReturnType::Continue => continue,
                     ReturnType::Break => break,
@@ -427,7 +515,7 @@ impl Game {
             },
             Instruction::LoopWhile { cond, body } => {
                 while self.eval(cond, context)?.is_truthy() {
-                    match self.execute(body, context)? {
+                    match self.execute_block(body, context)? {
                         ReturnType::Normal => (),
                         ReturnType::Continue => continue,
                         ReturnType::Break => break,
@@ -437,12 +525,12 @@ impl Game {
             },
             Instruction::LoopFor { cond, body, step } => {
                 while self.eval(cond, context)?.is_truthy() {
-                    match self.execute(body, context)? {
+                    match self.execute_block(body, context)? {
                         ReturnType::Normal => {
-                            self.execute(step, context)?;
+                            self.execute_block(step, context)?;
                         },
                         ReturnType::Continue => {
-                            self.execute(step, context)?;
+                            self.execute_block(step, context)?;
                             continue
                         },
                         ReturnType::Break => break,
@@ -454,7 +542,7 @@ impl Game {
             Instruction::Repeat { count, body } => {
                 let mut count = self.eval(count, context)?.round();
                 while count > 0 {
-                    match self.execute(body, context)? {
+                    match self.execute_block(body, context)? {
                         ReturnType::Normal => (),
                         ReturnType::Continue => continue,
                         ReturnType::Break => break,
@@ -470,14 +558,14 @@ impl Game {
                 let input = self.eval(input, context)?;
                 for (cond, start) in cases.iter() {
                     if self.eval(cond, context)?.almost_equals(&input) {
-                        return Ok(match self.execute(&body[*start..], context)? {
+                        return Ok(match self.execute_block(&body[*start..], context)? {
                             ReturnType::Break => ReturnType::Normal,
                             x => x,
                         })
                     }
                 }
                 if let Some(start) = default {
-                    return Ok(match self.execute(&body[*start..], context)? {
+                    return Ok(match self.execute_block(&body[*start..], context)? {
                         ReturnType::Break => ReturnType::Normal,
                         x => x,
                     })
@@ -492,14 +580,14 @@ impl Game {
 
                 match target_id {
                     gml::SELF | gml::UNSPECIFIED => {
-                        if self.execute(body, context)? == ReturnType::Exit {
+                        if self.execute_block(body, context)? == ReturnType::Exit {
                             context.other = old_other;
                             return Ok(ReturnType::Exit)
                         }
                     },
                     gml::OTHER => {
                         context.this = old_other;
-                        if self.execute(body, context)? == ReturnType::Exit {
+                        if self.execute_block(body, context)? == ReturnType::Exit {
                             context.this = old_this;
                             context.other = old_other;
                             return Ok(ReturnType::Exit)
@@ -509,7 +597,7 @@ impl Game {
                         let mut iter = self.room.instance_list.iter_by_insertion();
                         while let Some(instance) = iter.next(&self.room.instance_list) {
                             context.this = instance;
-                            match self.execute(body, context)? {
+                            match self.execute_block(body, context)? {
                                 ReturnType::Normal => (),
                                 ReturnType::Continue => continue,
                                 ReturnType::Break => break,
@@ -526,7 +614,7 @@ impl Game {
                         let mut iter = self.room.instance_list.iter_by_identity(i);
                         while let Some(instance) = iter.next(&self.room.instance_list) {
                             context.this = instance;
-                            match self.execute(body, context)? {
+                            match self.execute_block(body, context)? {
                                 ReturnType::Normal => (),
                                 ReturnType::Continue => continue,
                                 ReturnType::Break => break,
@@ -541,7 +629,7 @@ impl Game {
                     i => {
                         if let Some(instance) = self.room.instance_list.get_by_instid(i) {
                             context.this = instance;
-                            match self.execute(body, context)? {
+                            match self.execute_block(body, context)? {
                                 ReturnType::Exit => {
                                     context.this = old_this;
                                     context.other = old_other;
@@ -778,30 +866,64 @@ impl Game {
                 }
             },
             Node::Binary { left, right, operator } => {
-                operator.call(self.eval(left, context)?, self.eval(right, context)?)
+                operator.call_with(self.eval(left, context)?, self.eval(right, context)?, &self.equality_quirks)
             },
             Node::Unary { child, operator } => operator.call(self.eval(child, context)?),
             Node::RuntimeError { error } => Err(error.clone()),
         }
     }
 
+    /// Compiles and evaluates a single GML expression in the given context, for use by debug consoles.
+    /// The result can be displayed with `Value::log_fmt`.
+    pub fn evaluate_expression(&mut self, source: &str, context: &mut Context) -> gml::Result<Value> {
+        let node = compile_console_expression(&mut self.compiler, source)?;
+        self.eval(&node, context)
+    }
+
+    /// Evaluates an expression typed into the debug console as the instance with the given ID, or a dummy instance
+    /// if there's no such instance, and formats the result or error for printing.
+    pub fn console_eval(&mut self, source: &str, instance_id: Option<ID>) -> String {
+        let handle = instance_id.and_then(|id| self.room.instance_list.get_by_instid(id));
+        let dummy = match handle {
+            Some(_) => None,
+            None => Some(
+                self.room
+                    .instance_list
+                    .insert_dummy(Instance::new_dummy(self.assets.objects.get_asset(0).map(|x| x.as_ref()))),
+            ),
+        };
+        let mut context = Context::with_single_instance(handle.or(dummy).unwrap());
+        let result = self.evaluate_expression(source, &mut context);
+        if let Some(dummy) = dummy {
+            self.room.instance_list.remove_dummy(dummy);
+        }
+        match result {
+            Ok(value) => value.log_fmt(),
+            Err(e) => format!("Error: {}", e),
+        }
+    }
+
     // Resolves an ArrayAccessor to an index (u32)
     fn get_array_index(&mut self, accessor: &ArrayAccessor, context: &mut Context) -> gml::Result<u32> {
         match accessor {
             ArrayAccessor::None => Ok(0),
             ArrayAccessor::Single(node) => {
                 let index = self.eval(node, context)?.round();
-                if index < 0 || index >= 32000 { Err(Error::InvalidArrayIndex(index)) } else { Ok(index as u32) }
+                if index < 0 || index >= ARRAY_ROW as i32 {
+                    Err(Error::InvalidArrayIndex(index))
+                } else {
+                    Ok(index as u32)
+                }
             },
             ArrayAccessor::Double(node1, node2) => {
                 let index1 = self.eval(node1, context)?.round();
                 let index2 = self.eval(node2, context)?.round();
-                if index1 < 0 || index1 >= 32000 {
+                if index1 < 0 || index1 >= ARRAY_ROW as i32 {
                     Err(Error::InvalidArrayIndex(index1))
-                } else if index2 < 0 || index2 >= 32000 {
+                } else if index2 < 0 || index2 >= ARRAY_ROW as i32 {
                     Err(Error::InvalidArrayIndex(index2))
                 } else {
-                    Ok(((index1 * 32000) + index2) as u32)
+                    Ok(Field::index_2d(index1 as u32, index2 as u32))
                 }
             },
         }
@@ -823,8 +945,12 @@ impl Game {
     }
 
     // Set a field on an instance
-    fn set_instance_field(&self, instance: usize, field_id: usize, array_index: u32, value: Value) {
-        let mut fields = self.room.instance_list.get(instance).fields.borrow_mut();
+    fn set_instance_field(&mut self, instance: usize, field_id: usize, array_index: u32, value: Value) {
+        let instance = self.room.instance_list.get(instance);
+        let mut fields = instance.fields.borrow_mut();
+        if let Some(log) = &mut self.undo_log {
+            log.record(undo::Location::InstanceField(instance.id.get(), field_id), fields.get(&field_id));
+        }
         if let Some(field) = fields.get_mut(&field_id) {
             field.set(array_index, value)
         } else {
@@ -1196,6 +1322,13 @@ impl Game {
         value: Value,
         context: &mut Context,
     ) -> gml::Result<()> {
+        if self.undo_log.is_some() {
+            let old = self.get_instance_var(instance_handle, var, array_index, context);
+            let id = self.room.instance_list.get(instance_handle).id.get();
+            if let (Some(log), Ok(old)) = (&mut self.undo_log, old) {
+                log.record(undo::Location::InstanceVar(id, *var, array_index), Some(&Field::Single(old)));
+            }
+        }
         let instance = self.room.instance_list.get(instance_handle);
 
         match var {
@@ -1535,29 +1668,14 @@ impl Game {
         }
     }
 
-    // Gets an argument from the context. If the argument is out-of-bounds, then it will either
-    // return an error or return 0.0, depending on the uninit_args_are_zero setting.
+    // Gets an argument from the context, as Context::get_argument does with the uninit_args_are_zero setting.
     fn get_argument(&self, context: &Context, arg: usize) -> gml::Result<Value> {
-        if let Some(value) = context.arguments.get(arg) {
-            Ok(value.clone())
-        } else {
-            if self.uninit_args_are_zero {
-                Ok(Value::Real(Real::from(0.0)))
-            } else {
-                Err(Error::UninitializedArgument(arg))
-            }
-        }
+        context.get_argument(arg, self.uninit_args_are_zero)
     }
 
-    // Sets an argument from the context. If the argument is out-of-bounds, then it will either
-    // return an error or return 0.0, depending on the uninit_args_are_zero setting.
+    // Sets an argument in the context, as Context::set_argument does with the uninit_args_are_zero setting.
     fn set_argument(&self, context: &mut Context, arg: usize, value: Value) -> gml::Result<()> {
-        let arg_count = context.argument_count;
-        match context.arguments.get_mut(arg) {
-            Some(a) if arg < arg_count || self.uninit_args_are_zero => Ok(*a = value),
-            None if self.uninit_args_are_zero => Ok(()), // This corrupts stack in GM8...
-            _ => Err(Error::UninitializedArgument(arg)),
-        }
+        context.set_argument(arg, value, self.uninit_args_are_zero)
     }
 
     // Resolves an InstanceIdentifier to a Target
@@ -1595,3 +1713,72 @@ impl Game {
         }
     }
 }
+
+/// Compiles an expression for `Game::evaluate_expression`, as a FunctionError if it doesn't parse.
+fn compile_console_expression(compiler: &mut gml::Compiler, source: &str) -> gml::Result<Node> {
+    compiler
+        .compile_expression(source.as_bytes())
+        .map_err(|e| Error::FunctionError("evaluate_expression".into(), e.message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gml::testing::assert_value_eq;
+
+    // Constant expressions are folded by the compiler, so evaluate_expression can return them without a game
+    fn evaluate(source: &str) -> Value {
+        match gml::Compiler::new().compile_expression(source.as_bytes()).unwrap() {
+            Node::Literal { value } => value,
+            node => panic!("{} wasn't folded: {:?}", source, node),
+        }
+    }
+
+    fn evaluate_err(source: &str) -> Error {
+        match gml::Compiler::new().compile_expression(source.as_bytes()).unwrap() {
+            Node::RuntimeError { error } => error,
+            node => panic!("{} didn't fold to an error: {:?}", source, node),
+        }
+    }
+
+    #[test]
+    fn pure_expressions() {
+        assert_value_eq!(evaluate("3 + 4 * 2"), Value::from(11));
+        assert_value_eq!(evaluate("1 > 2"), Value::from(0));
+        assert_value_eq!(evaluate("-(2 - 5)"), Value::from(3));
+        assert_value_eq!(evaluate("\"a\" + \"b\""), Value::from("ab"));
+    }
+
+    #[test]
+    fn impure_expressions() {
+        let mut compiler = gml::Compiler::new();
+        assert!(matches!(compiler.compile_expression(b"x + 1").unwrap(), Node::Binary { .. }));
+    }
+
+    #[test]
+    fn console_expressions() {
+        let mut compiler = gml::Compiler::new();
+        // variables are looked up on the instance the console runs as
+        match compile_console_expression(&mut compiler, "x * 2").unwrap() {
+            Node::Binary { left, .. } => match *left {
+                Node::Variable { accessor } => {
+                    assert!(matches!(accessor.var, InstanceVariable::X));
+                    assert!(matches!(accessor.owner, InstanceIdentifier::Own));
+                },
+                node => panic!("x compiled to {:?}", node),
+            },
+            node => panic!("x * 2 compiled to {:?}", node),
+        }
+        let error = compile_console_expression(&mut compiler, "3 +").unwrap_err();
+        assert!(matches!(error, Error::FunctionError(name, _) if name == "evaluate_expression"));
+    }
+
+    #[test]
+    fn add_error_message() {
+        let error = evaluate_err("1 + \"x\"");
+        assert_eq!(error.to_string(), "Wrong type of arguments to +.");
+        // the other operators keep the more detailed message
+        let error = evaluate_err("\"x\" - 1");
+        assert_eq!(error.to_string(), "invalid operands string and real to - operator (\"x\" - 1)");
+    }
+}
@@ -1,12 +1,92 @@
 use encoding_rs::Encoding;
 use serde::{de::Visitor, Deserialize, Deserializer, Serialize, Serializer};
-use std::{borrow::Cow, fmt, rc::Rc};
+use std::{
+    borrow::Cow,
+    cell::{Cell, RefCell},
+    cmp::Ordering,
+    collections::HashMap,
+    fmt,
+    rc::Rc,
+};
 
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 #[repr(transparent)]
 pub struct String(Rc<[u8]>);
 
+thread_local! {
+    static STATIC_STRINGS: RefCell<HashMap<&'static str, String>> = RefCell::new(HashMap::new());
+    static UNDEFINED: String = String(Rc::from(&[][..]));
+    // free buffers by length, and how many there are in all so making a string can skip the pool when it's empty
+    static POOL: RefCell<Vec<Vec<Rc<[u8]>>>> = RefCell::new(Vec::new());
+    static POOLED: Cell<usize> = Cell::new(0);
+}
+
+/// Strings longer than this aren't worth keeping around for reuse.
+const POOL_MAX_LEN: usize = 256;
+/// How many free buffers of each length the pool holds on to.
+const POOL_DEPTH: usize = 64;
+
+/// Makes a buffer holding a copy of the given bytes, reusing one from the pool if there's one of the right length.
+fn alloc(bytes: &[u8]) -> Rc<[u8]> {
+    if bytes.is_empty() || bytes.len() > POOL_MAX_LEN || POOLED.with(Cell::get) == 0 {
+        return bytes.into()
+    }
+    match POOL.with(|pool| pool.borrow_mut().get_mut(bytes.len()).and_then(Vec::pop)) {
+        Some(mut buf) => {
+            POOLED.with(|count| count.set(count.get() - 1));
+            // Only unshared buffers go in the pool, so this can't fail
+            Rc::get_mut(&mut buf).expect("shared buffer in string pool").copy_from_slice(bytes);
+            buf
+        },
+        None => bytes.into(),
+    }
+}
+
 impl String {
+    /// Gets a String for a constant, allocating it only the first time it's used on this thread.
+    pub fn from_static(value: &'static str) -> Self {
+        STATIC_STRINGS.with(|strings| strings.borrow_mut().entry(value).or_insert_with(|| value.into()).clone())
+    }
+
+    /// Gets the string which stands in for `undefined`. It's empty, but is_undefined tells it apart from any other
+    /// empty string, so it still acts like GM8 would if a game uses it as a string or a real.
+    pub fn undefined() -> Self {
+        UNDEFINED.with(String::clone)
+    }
+
+    pub fn is_undefined(&self) -> bool {
+        UNDEFINED.with(|undefined| self.ptr_eq(undefined))
+    }
+
+    /// Checks whether two strings are the same one, such as two uses of the same literal, rather than just equal.
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+
+    /// Gives this string's buffer back to the pool if nothing else holds it, so the next string of the same length
+    /// can reuse it instead of allocating. This is for when a lot of Values are freed at once, like on room change.
+    pub fn recycle(self) {
+        let mut buf = self.0;
+        if !buf.is_empty() && buf.len() <= POOL_MAX_LEN && Rc::get_mut(&mut buf).is_some() {
+            POOL.with(|pool| {
+                let mut pool = pool.borrow_mut();
+                if pool.len() <= buf.len() {
+                    pool.resize_with(buf.len() + 1, Vec::new);
+                }
+                let free = &mut pool[buf.len()];
+                if free.len() < POOL_DEPTH {
+                    free.push(buf);
+                    POOLED.with(|count| count.set(count.get() + 1));
+                }
+            });
+        }
+    }
+
+    /// Gets where this string's bytes are, which is the same for every copy of it.
+    pub fn as_ptr(&self) -> *const u8 {
+        self.0.as_ptr()
+    }
+
     pub fn decode(&self, encoding: &'static Encoding) -> Cow<str> {
         encoding.decode_without_bom_handling(&self.0).0
     }
@@ -15,6 +95,35 @@ impl String {
         std::string::String::from_utf8_lossy(&self.0)
     }
 
+    /// Encodes text from the host, such as the clipboard's, the way Windows hands Unicode text to a program which
+    /// only reads its code page: any character the encoding can't represent becomes a question mark.
+    /// Windows sometimes picks a similar character instead, like "a" for "ā", which isn't copied here.
+    pub fn encode_lossy(utf8: &str, encoding: &'static Encoding) -> Self {
+        let mut bytes = Vec::with_capacity(utf8.len());
+        let mut buf = [0u8; 4];
+        for ch in utf8.chars() {
+            let (encoded, _, unmappable) = encoding.encode(ch.encode_utf8(&mut buf));
+            if unmappable {
+                bytes.push(b'?');
+            } else {
+                bytes.extend_from_slice(&encoded);
+            }
+        }
+        bytes.into()
+    }
+
+    /// Keeps only the letters and/or digits, as string_letters, string_digits and string_lettersdigits do.
+    /// Letters are anything the encoding decodes to an alphabetic character, so accented letters are included,
+    /// but only ASCII digits count as digits, as in GM8.
+    pub fn filter_alphanumeric(&self, encoding: &'static Encoding, letters: bool, digits: bool) -> Self {
+        let filtered = self
+            .decode(encoding)
+            .chars()
+            .filter(|ch| (letters && ch.is_alphabetic()) || (digits && ch.is_ascii_digit()))
+            .collect::<std::string::String>();
+        encoding.encode(&filtered).0.as_ref().into()
+    }
+
     pub fn eq_ignore_ascii_case(&self, other: &[u8]) -> bool {
         self.0.len() == other.len()
             && self.0.iter().copied().zip(other.iter().copied()).all(|(x, y)| {
@@ -25,6 +134,127 @@ impl String {
                 }
             })
     }
+
+    /// Compares two strings the way a person would sort them, rather than byte by byte like `<`.
+    /// Letters are compared first, ignoring case and accents, so "é" sorts with "e" and "É" with "é".
+    /// Ties are broken by accents, then case, then the raw bytes, so this is still a total order.
+    pub fn collate(&self, other: &Self, encoding: &'static Encoding) -> Ordering {
+        let (a, b) = (self.decode(encoding), other.decode(encoding));
+        let primary = |s: &str| {
+            let mut letters = Vec::with_capacity(s.len());
+            s.chars().for_each(|ch| collation_letters(ch, &mut letters));
+            letters
+        };
+        let secondary = |s: &str| s.chars().flat_map(char::to_lowercase).collect::<Vec<_>>();
+        primary(&a)
+            .cmp(&primary(&b))
+            .then_with(|| secondary(&a).cmp(&secondary(&b)))
+            .then_with(|| self.0.cmp(&other.0))
+    }
+}
+
+/// Pushes the lowercase letters a character sorts as, with any accent removed.
+/// This covers Latin-1, Latin Extended-A and fullwidth ASCII, which is what GM8's encodings can represent.
+fn collation_letters(ch: char, out: &mut Vec<char>) {
+    const LATIN_1: &str = "aaaaaa\0ceeeeiiiidnooooo\0ouuuuy\0\0aaaaaa\0ceeeeiiiidnooooo\0ouuuuy\0y";
+    const LATIN_EXTENDED_A: &str = concat!(
+        "aaaaaaccccccccddddeeeeeeeeeegggggggghhhhiiiiiiiiii\0\0jjkkkllllllllllnnnnnnnnnoooooo\0\0",
+        "rrrrrrsssssssstttttt",
+        "uuuuuuuuuuuuwwyyyzzzzzzs",
+    );
+    let base = match ch as u32 {
+        0xC0..=0xFF => LATIN_1.chars().nth(ch as usize - 0xC0),
+        0x100..=0x17F => LATIN_EXTENDED_A.chars().nth(ch as usize - 0x100),
+        0xFF01..=0xFF5E => std::char::from_u32(ch as u32 - 0xFEE0),
+        _ => None,
+    };
+    match (base, ch) {
+        (Some(base), _) if base != '\0' => out.extend(base.to_lowercase()),
+        (_, 'Æ') | (_, 'æ') => out.extend(&['a', 'e']),
+        (_, 'Œ') | (_, 'œ') => out.extend(&['o', 'e']),
+        (_, 'Ĳ') | (_, 'ĳ') => out.extend(&['i', 'j']),
+        (_, 'ß') => out.extend(&['s', 's']),
+        _ => out.extend(ch.to_lowercase()),
+    }
+}
+
+/// Finds the byte position of needle in haystack.
+/// If ignore_case is set, characters are compared by their lowercase forms, so this works outside of ASCII too.
+pub fn find(haystack: &str, needle: &str, ignore_case: bool) -> Option<usize> {
+    if !ignore_case {
+        return haystack.find(needle)
+    }
+    let fold = |s: &str| s.chars().flat_map(char::to_lowercase).collect::<Vec<_>>();
+    let needle = fold(needle);
+    haystack.char_indices().map(|(i, _)| i).chain(std::iter::once(haystack.len())).find(|&i| {
+        let mut rest = haystack[i..].chars().flat_map(char::to_lowercase);
+        needle.iter().all(|c| rest.next() == Some(*c))
+    })
+}
+
+/// Finds the byte position of needle in haystack ignoring case, where both are in the given encoding.
+/// Case folding needs the real characters, but the position is still in bytes of that encoding.
+pub fn find_ignore_case_encoded(haystack: &[u8], needle: &[u8], encoding: &'static Encoding) -> Option<usize> {
+    let haystack = encoding.decode_without_bom_handling(haystack).0;
+    let needle = encoding.decode_without_bom_handling(needle).0;
+    find(&haystack, &needle, true).map(|p| encoding.encode(&haystack[..p]).0.len())
+}
+
+/// Splits a string into what a reader would count as single characters, such as a letter with its combining accents
+/// or an emoji made of several joined together. This covers combining marks, variation selectors, emoji modifiers and
+/// tags, zero-width joiner sequences, flags and CRLF, but not every rule Unicode has, such as for Hangul syllables.
+pub fn graphemes(s: &str) -> Vec<&str> {
+    const ZWJ: char = '\u{200D}';
+    let is_regional_indicator = |c: char| matches!(c, '\u{1F1E6}'..='\u{1F1FF}');
+    let mut clusters: Vec<&str> = Vec::new();
+    let mut start = 0;
+    let mut prev: Option<char> = None;
+    let mut indicators = 0;
+    for (i, c) in s.char_indices() {
+        let joins = match prev {
+            None => false,
+            Some('\r') => c == '\n',
+            Some(ZWJ) => true,
+            Some(p) if is_regional_indicator(p) && is_regional_indicator(c) => indicators % 2 == 1,
+            Some(_) => extends_grapheme(c),
+        };
+        if !joins && prev.is_some() {
+            clusters.push(&s[start..i]);
+            start = i;
+            indicators = 0;
+        }
+        if is_regional_indicator(c) {
+            indicators += 1;
+        }
+        prev = Some(c);
+    }
+    if start < s.len() {
+        clusters.push(&s[start..]);
+    }
+    clusters
+}
+
+fn extends_grapheme(c: char) -> bool {
+    matches!(
+        c,
+        '\u{0300}'..='\u{036F}' // combining diacritical marks
+            | '\u{0483}'..='\u{0489}'
+            | '\u{0591}'..='\u{05BD}'
+            | '\u{0610}'..='\u{061A}'
+            | '\u{064B}'..='\u{065F}'
+            | '\u{0900}'..='\u{0903}'
+            | '\u{093A}'..='\u{094F}'
+            | '\u{1AB0}'..='\u{1AFF}'
+            | '\u{1DC0}'..='\u{1DFF}'
+            | '\u{200C}'..='\u{200D}' // zero-width non-joiner and joiner
+            | '\u{20D0}'..='\u{20FF}'
+            | '\u{3099}'..='\u{309A}' // kana voicing marks
+            | '\u{FE00}'..='\u{FE0F}' // variation selectors
+            | '\u{FE20}'..='\u{FE2F}'
+            | '\u{1F3FB}'..='\u{1F3FF}' // skin tone modifiers
+            | '\u{E0020}'..='\u{E007F}' // tags, as in subdivision flags
+            | '\u{E0100}'..='\u{E01EF}'
+    )
 }
 
 impl AsRef<[u8]> for String {
@@ -41,25 +271,25 @@ impl fmt::Display for String {
 
 impl From<std::string::String> for String {
     fn from(value: std::string::String) -> Self {
-        Self(value.into_bytes().into())
+        Self(alloc(value.as_bytes()))
     }
 }
 
 impl From<&str> for String {
     fn from(value: &str) -> Self {
-        Self(value.as_bytes().to_vec().into())
+        Self(alloc(value.as_bytes()))
     }
 }
 
 impl From<Vec<u8>> for String {
     fn from(value: Vec<u8>) -> Self {
-        Self(value.into())
+        Self(alloc(&value))
     }
 }
 
 impl From<&[u8]> for String {
     fn from(value: &[u8]) -> Self {
-        Self(value.to_vec().into())
+        Self(alloc(value))
     }
 }
 
@@ -104,3 +334,178 @@ impl<'de> Deserialize<'de> for String {
         deserializer.deserialize_bytes(SerdeVisitor)
     }
 }
+
+/// Counts the non-overlapping occurrences of needle in haystack, scanning from the left, as string_count does.
+/// An empty needle never occurs, as in GM8.
+pub fn count(haystack: &[u8], needle: &[u8]) -> usize {
+    if needle.is_empty() {
+        return 0
+    }
+    let mut count = 0;
+    let mut rest = haystack;
+    while let Some(pos) = rest.windows(needle.len()).position(|x| x == needle) {
+        count += 1;
+        rest = &rest[pos + needle.len()..];
+    }
+    count
+}
+
+/// Counts occurrences as `count` does, in text in a code page such as Shift_JIS, where the trail byte of a double-byte
+/// character can be the same as a whole ASCII character. Only matches on whole characters are counted, which is done
+/// by searching the text as UTF-8, since a UTF-8 character can't match in the middle of another.
+pub fn count_encoded(haystack: &[u8], needle: &[u8], encoding: &'static Encoding) -> usize {
+    let haystack = encoding.decode_without_bom_handling(haystack).0;
+    let needle = encoding.decode_without_bom_handling(needle).0;
+    count(haystack.as_bytes(), needle.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_static() {
+        let a = String::from_static("hello");
+        let b = String::from_static("hello");
+        assert_eq!(a, String::from("hello".to_string()));
+        assert!(Rc::ptr_eq(&a.0, &b.0));
+    }
+
+    #[test]
+    fn recycle() {
+        let freed = String::from("recycled");
+        let ptr = freed.as_ptr();
+        freed.recycle();
+        let reused = String::from("reusable");
+        assert_eq!(reused.as_ptr(), ptr);
+        assert_eq!(reused.as_ref(), b"reusable");
+        // still in use elsewhere, so it can't be handed out again
+        let shared = String::from("shared");
+        shared.clone().recycle();
+        assert_ne!(String::from("xxxxxx").as_ptr(), shared.as_ptr());
+    }
+
+    #[test]
+    fn find_case() {
+        assert_eq!(find("Hello World", "world", false), None);
+        assert_eq!(find("Hello World", "World", false), Some(6));
+        assert_eq!(find("Hello World", "wORLD", true), Some(6));
+        assert_eq!(find("Café ÉCLAIR", "éclair", true), Some(6));
+        assert_eq!(find("abc", "", true), Some(0));
+        assert_eq!(find("abc", "abcd", true), None);
+        assert_eq!(find("ΑΒΓ ΔΈΛΤΑ", "δέλτα", false), None);
+        assert_eq!(find("ΑΒΓ ΔΈΛΤΑ", "δέλτα", true), Some(7));
+    }
+
+    #[test]
+    fn find_case_encoded() {
+        let latin1 = encoding_rs::WINDOWS_1252;
+        assert_eq!(find_ignore_case_encoded(b"Caf\xE9 \xC9CLAIR", b"\xE9clair", latin1), Some(5));
+        assert_eq!(find_ignore_case_encoded(b"Caf\xE9 \xC9CLAIR", b"eclair", latin1), None);
+        let greek = encoding_rs::WINDOWS_1253;
+        assert_eq!(find_ignore_case_encoded(b"\xC1\xC2\xC3 \xC4\xE5", b"\xE4\xC5", greek), Some(4));
+    }
+
+    #[test]
+    fn grapheme_clusters() {
+        // "é" as an e followed by a combining acute accent
+        let accented = "caf\u{65}\u{301}!";
+        assert_eq!(accented.len(), 7);
+        assert_eq!(accented.chars().count(), 6);
+        assert_eq!(graphemes(accented), ["c", "a", "f", "e\u{301}", "!"]);
+
+        let family = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}";
+        let thumbs = "\u{1F44D}\u{1F3FD}";
+        let flags = "\u{1F1EF}\u{1F1F5}\u{1F1EB}\u{1F1F7}";
+        assert_eq!(graphemes(family), [family]);
+        assert_eq!(graphemes(thumbs), [thumbs]);
+        assert_eq!(graphemes(flags), ["\u{1F1EF}\u{1F1F5}", "\u{1F1EB}\u{1F1F7}"]);
+        assert_eq!(graphemes("a\r\nb\n\r"), ["a", "\r\n", "b", "\n", "\r"]);
+        assert!(graphemes("").is_empty());
+    }
+
+    #[test]
+    fn count_occurrences() {
+        assert_eq!(count(b"aaaa", b"aa"), 2);
+        assert_eq!(count(b"ababa", b"aba"), 1);
+        assert_eq!(count(b"hello", b""), 0);
+        assert_eq!(count(b"", b""), 0);
+        assert_eq!(count(b"ab", b"abc"), 0);
+        assert_eq!(count("日本日本語".as_bytes(), "日本".as_bytes()), 2);
+        // Shift_JIS "ソ" ends in the byte for '\\', which a byte search matches on its own
+        let sjis = encoding_rs::SHIFT_JIS.encode("ソ\\").0;
+        assert_eq!(count(&sjis, b"\\"), 2);
+        let decoded = String::from(sjis.as_ref()).decode(encoding_rs::SHIFT_JIS).into_owned();
+        assert_eq!(count(decoded.as_bytes(), b"\\"), 1);
+        assert_eq!(count_encoded(&sjis, b"\\", encoding_rs::SHIFT_JIS), 1);
+        assert_eq!(count_encoded(&sjis, &sjis[..2], encoding_rs::SHIFT_JIS), 1);
+        // in a single-byte code page, that's the same as counting bytes
+        assert_eq!(count_encoded(b"\xe9a\xe9a", b"\xe9a", encoding_rs::WINDOWS_1252), 2);
+        assert_eq!(count_encoded(b"hello", b"", encoding_rs::SHIFT_JIS), 0);
+    }
+
+    #[test]
+    fn filter_alphanumeric() {
+        // "Café #42, ½!" in Windows-1252
+        let s = String::from(b"Caf\xe9 #42, \xbd!".as_ref());
+        let filter = |letters, digits| s.filter_alphanumeric(encoding_rs::WINDOWS_1252, letters, digits);
+        assert_eq!(filter(true, false).as_ref(), b"Caf\xe9");
+        assert_eq!(filter(false, true).as_ref(), b"42");
+        assert_eq!(filter(true, true).as_ref(), b"Caf\xe942");
+
+        let s = String::from("Ünïcode 3² ٣");
+        assert_eq!(s.filter_alphanumeric(encoding_rs::UTF_8, true, true).as_ref(), "Ünïcode3".as_bytes());
+    }
+
+    #[test]
+    fn collate() {
+        let collate = |a: &str, b: &str| String::from(a).collate(&String::from(b), encoding_rs::UTF_8);
+        assert_eq!(collate("e", "é"), Ordering::Less);
+        assert_eq!(collate("é", "f"), Ordering::Less);
+        assert_eq!(collate("É", "é"), Ordering::Less);
+        assert_eq!(collate("Apple", "apple"), Ordering::Less);
+        assert_eq!(collate("apple", "Banana"), Ordering::Less);
+        assert_eq!(collate("Ærø", "aerz"), Ordering::Less);
+        assert_eq!(collate("straße", "strasse"), Ordering::Greater);
+        assert_eq!(collate("Ｚ", "y"), Ordering::Greater);
+        assert_eq!(collate("Ž", "Ž"), Ordering::Equal);
+    }
+
+    #[test]
+    fn encode_lossy() {
+        let encode = |s, encoding| String::encode_lossy(s, encoding).as_ref().to_vec();
+        assert_eq!(encode("café", encoding_rs::WINDOWS_1252), b"caf\xe9");
+        // Windows-1252 has no Japanese or emoji, so each of those is one question mark
+        assert_eq!(encode("ソ1😀", encoding_rs::WINDOWS_1252), b"?1?");
+        assert_eq!(encode("ソ1😀", encoding_rs::SHIFT_JIS), b"\x83\x5c1?");
+    }
+}
+
+#[cfg(test)]
+mod bench {
+    use super::*;
+    use test::Bencher;
+
+    // the lengths instance variables' strings tend to have, like names and states
+    fn words() -> Vec<Vec<u8>> {
+        (0..64).map(|i| vec![b'a'; 8 + i % 24]).collect()
+    }
+
+    #[bench]
+    fn alloc_plain(b: &mut Bencher) {
+        let words = words();
+        b.iter(|| test::black_box(words.iter().map(|w| Rc::<[u8]>::from(&w[..])).collect::<Vec<_>>()));
+    }
+
+    #[bench]
+    fn alloc_empty_pool(b: &mut Bencher) {
+        let words = words();
+        b.iter(|| test::black_box(words.iter().map(|w| String::from(&w[..])).collect::<Vec<_>>()));
+    }
+
+    #[bench]
+    fn alloc_recycled(b: &mut Bencher) {
+        let words = words();
+        b.iter(|| words.iter().map(|w| String::from(&w[..])).collect::<Vec<_>>().into_iter().for_each(String::recycle));
+    }
+}
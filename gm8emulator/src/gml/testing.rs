@@ -0,0 +1,81 @@
+//! Helpers for writing tests about Values.
+
+use crate::gml::Value;
+use gml_parser::token::Operator;
+
+/// Checks whether two values are equal by GML's `==`, so reals within `Real::CMP_EPSILON` of each other match.
+/// Values of different types never match. If they aren't equal, returns a description of how they differ.
+pub fn check_value_eq(left: &Value, right: &Value) -> Result<(), String> {
+    let mismatch = |reason: String| {
+        Err(format!("values are not equal\n  left: {}\n right: {}\n{}", left.log_fmt(), right.log_fmt(), reason))
+    };
+    match (left, right) {
+        (Value::Real(_), Value::Real(_)) | (Value::Str(_), Value::Str(_)) => {
+            if left.clone().compare(right.clone(), Operator::Equal).map_or(false, |eq| eq.is_truthy()) {
+                Ok(())
+            } else {
+                mismatch(left.explain_cmp(right, Operator::Equal))
+            }
+        },
+        _ => mismatch(format!("one is a {} and the other is a {}", left.ty_str(), right.ty_str())),
+    }
+}
+
+/// Asserts that two values are equal by GML's `==`, printing both and how they differ if they aren't.
+macro_rules! assert_value_eq {
+    ($left: expr, $right: expr $(,)?) => {
+        if let Err(msg) = $crate::gml::testing::check_value_eq(&$left, &$right) {
+            panic!("{}", msg)
+        }
+    };
+    ($left: expr, $right: expr, $($arg: tt)+) => {
+        if let Err(msg) = $crate::gml::testing::check_value_eq(&$left, &$right) {
+            panic!("{}: {}", format_args!($($arg)+), msg)
+        }
+    };
+}
+
+/// Makes a Vec of Values from anything they convert from, such as `values![1, "two", 3.5]`.
+macro_rules! values {
+    ($($value: expr),* $(,)?) => {
+        vec![$($crate::gml::Value::from($value)),*]
+    };
+}
+
+pub(crate) use assert_value_eq;
+pub(crate) use values;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::Real;
+
+    #[test]
+    fn matching_values() {
+        assert_value_eq!(Value::from(3), Value::from(3.0));
+        // close enough by GML's epsilon, though not bit for bit
+        assert_value_eq!(Value::from(0.1).add(Value::from(0.2)).unwrap(), Value::from(0.3));
+        assert_value_eq!(Value::from("abc"), Value::from("abc".to_string()), "strings with the same contents");
+        let built = values![1, "two", 3.5];
+        assert_eq!(built.len(), 3);
+        assert_value_eq!(built[1], Value::from("two"));
+        assert_value_eq!(built[2], Value::Real(Real::from(3.5)));
+    }
+
+    #[test]
+    fn mismatch_messages() {
+        let msg = check_value_eq(&Value::from(1), &Value::from(2)).unwrap_err();
+        assert!(msg.contains("left: 1\n right: 2\n"), "{}", msg);
+        assert!(msg.contains("less than 2"), "{}", msg);
+        let msg = check_value_eq(&Value::from("abc"), &Value::from("abd")).unwrap_err();
+        assert!(msg.contains("left: \"abc\"") && msg.contains("character 3"), "{}", msg);
+        let msg = check_value_eq(&Value::from("1"), &Value::from(1)).unwrap_err();
+        assert!(msg.contains("one is a string and the other is a real"), "{}", msg);
+    }
+
+    #[test]
+    #[should_panic(expected = "adding: values are not equal")]
+    fn mismatch_panics() {
+        assert_value_eq!(Value::from(1).add(Value::from(1)).unwrap(), Value::from(3), "adding");
+    }
+}
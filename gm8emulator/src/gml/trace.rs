@@ -0,0 +1,110 @@
+//! A log of every Value operation run by the interpreter during a range of frames, with its operands and result.
+//! Two runs of the same game can be traced and compared with `first_difference` to find the exact operation where
+//! they diverge. This is much heavier than `gml::profile`, so it's meant for short windows of frames.
+//! Only built with the `value-trace` feature, so it costs nothing otherwise.
+
+use crate::gml::{self, Value};
+use std::{cell::RefCell, fmt, fs, io, ops::Range, path::Path};
+
+struct Trace {
+    frames: Range<u64>,
+    frame: u64,
+    lines: Vec<String>,
+}
+
+thread_local! {
+    static TRACE: RefCell<Option<Trace>> = RefCell::new(None);
+}
+
+/// Starts tracing, logging operations from frames in the given range.
+/// Frames are counted from here, so anything run before the next frame starts is on frame 0.
+pub fn start(frames: Range<u64>) {
+    TRACE.with(|trace| *trace.borrow_mut() = Some(Trace { frames, frame: 0, lines: Vec::new() }));
+}
+
+/// Stops tracing and returns the log.
+pub fn stop() -> Vec<String> {
+    TRACE.with(|trace| trace.borrow_mut().take().map(|t| t.lines).unwrap_or_default())
+}
+
+/// Moves on to the next frame. Game::frame calls this whenever a frame starts.
+pub fn next_frame() {
+    TRACE.with(|trace| {
+        if let Some(trace) = trace.borrow_mut().as_mut() {
+            trace.frame += 1;
+        }
+    })
+}
+
+/// Checks whether operations on the current frame are being logged.
+pub fn enabled() -> bool {
+    TRACE.with(|trace| trace.borrow().as_ref().map_or(false, |t| t.frames.contains(&t.frame)))
+}
+
+pub fn record(op: impl fmt::Debug, lhs: &Value, rhs: Option<&Value>, result: &gml::Result<Value>) {
+    TRACE.with(|trace| {
+        if let Some(trace) = trace.borrow_mut().as_mut() {
+            let operands = match rhs {
+                Some(rhs) => format!("{} {:?} {}", lhs.log_fmt(), op, rhs.log_fmt()),
+                None => format!("{:?} {}", op, lhs.log_fmt()),
+            };
+            let result = match result {
+                Ok(value) => value.log_fmt(),
+                Err(e) => format!("error: {}", e),
+            };
+            let line = format!("{} #{}: {} = {}", trace.frame, trace.lines.len(), operands, result);
+            trace.lines.push(line);
+        }
+    })
+}
+
+/// Writes a log to a file, one operation per line.
+pub fn write(lines: &[String], path: impl AsRef<Path>) -> io::Result<()> {
+    let mut out = lines.join("\n");
+    out.push('\n');
+    fs::write(path, out)
+}
+
+/// Finds the index of the first operation where two logs differ, if they do.
+pub fn first_difference(a: &[String], b: &[String]) -> Option<usize> {
+    match a.iter().zip(b).position(|(a, b)| a != b) {
+        Some(i) => Some(i),
+        None if a.len() != b.len() => Some(a.len().min(b.len())),
+        None => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gml::runtime::{BinaryOperator, UnaryOperator};
+
+    fn run(x: i32) -> Vec<String> {
+        start(1..2);
+        for frame in 0..3 {
+            if frame > 0 {
+                next_frame();
+            }
+            BinaryOperator::Add.call(Value::from(x), Value::from(frame)).unwrap();
+            BinaryOperator::Add.call(Value::from("a"), Value::from("b")).unwrap();
+            UnaryOperator::Neg.call(Value::from(frame)).unwrap();
+            let _ = BinaryOperator::Subtract.call(Value::from("a"), Value::from(1));
+        }
+        stop()
+    }
+
+    #[test]
+    fn trace_frames() {
+        let trace = run(5);
+        assert_eq!(trace, vec![
+            "1 #0: 5 Add 1 = 6",
+            "1 #1: \"a\" Add \"b\" = \"ab\"",
+            "1 #2: Neg 1 = -1",
+            "1 #3: \"a\" Subtract 1 = error: invalid operands string and real to - operator (\"a\" - 1)",
+        ]);
+        assert_eq!(first_difference(&trace, &run(5)), None);
+        assert_eq!(first_difference(&trace, &run(6)), Some(0));
+        assert_eq!(first_difference(&trace, &trace[..2]), Some(2));
+        assert!(!enabled());
+    }
+}
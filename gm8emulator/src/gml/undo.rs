@@ -0,0 +1,175 @@
+//! A log of what variables held before they were written to, so the debugger can step back through a frame without
+//! loading a whole savestate. Only the first write to each variable is recorded, since that holds the value from
+//! before the frame, and the log is cleared whenever a frame starts.
+//! Instances created or destroyed during the frame aren't brought back or removed, so rolling back is only exact
+//! for frames which don't do either. Data structures and other game state aren't covered either.
+
+use crate::{
+    game::Game,
+    gml::{Context, InstanceVariable},
+    instance::{DummyFieldHolder, Field},
+    types::ID,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    hash::Hash,
+};
+
+/// A variable which has been written to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Location {
+    GlobalField(usize),
+    GlobalVar(InstanceVariable),
+    InstanceField(ID, usize),
+    /// A built-in instance variable, such as x, of the instance with the given id.
+    InstanceVar(ID, InstanceVariable, u32),
+}
+
+#[derive(Debug, Default)]
+pub struct UndoLog {
+    entries: Vec<(Location, Option<Field>)>,
+    seen: HashSet<Location>,
+}
+
+impl UndoLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records what a variable held before being written to, or None if it didn't exist.
+    /// Anything after the first write to the same variable is ignored.
+    pub fn record(&mut self, location: Location, old: Option<&Field>) {
+        if self.seen.insert(location) {
+            self.entries.push((location, old.cloned()));
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.seen.clear();
+    }
+
+    /// Takes every recorded variable along with what it held, most recently written first, leaving the log empty.
+    pub fn drain(&mut self) -> impl Iterator<Item = (Location, Option<Field>)> {
+        self.seen.clear();
+        std::mem::take(&mut self.entries).into_iter().rev()
+    }
+
+    /// Puts back the recorded global variables and fields, leaving the rest in the log.
+    pub fn restore_globals(&mut self, globals: &mut DummyFieldHolder) {
+        let mut rest = Vec::new();
+        for (location, old) in self.drain() {
+            match location {
+                Location::GlobalField(field_id) => restore(&mut globals.fields, field_id, old),
+                Location::GlobalVar(var) => restore(&mut globals.vars, var, old),
+                _ => rest.push((location, old)),
+            }
+        }
+        for (location, old) in rest.into_iter().rev() {
+            self.record(location, old.as_ref());
+        }
+    }
+}
+
+/// Puts an old field back into a map of fields, removing it if it didn't exist before.
+pub fn restore<K: Eq + Hash>(fields: &mut HashMap<K, Field>, key: K, old: Option<Field>) {
+    match old {
+        Some(field) => {
+            fields.insert(key, field);
+        },
+        None => {
+            fields.remove(&key);
+        },
+    }
+}
+
+impl Game {
+    /// Starts recording writes to variables, so they can be undone with `rollback`.
+    pub fn start_undo_log(&mut self) {
+        self.undo_log = Some(UndoLog::new());
+    }
+
+    /// Undoes every write recorded since the current frame started, most recent first.
+    /// Variables of instances which no longer exist are skipped.
+    pub fn rollback(&mut self) {
+        // taken out so the writes made here aren't recorded themselves
+        let mut log = match self.undo_log.take() {
+            Some(log) => log,
+            None => return,
+        };
+        log.restore_globals(&mut self.globals);
+        for (location, old) in log.drain() {
+            match location {
+                Location::InstanceField(id, field_id) => {
+                    if let Some(handle) = self.room.instance_list.get_by_instid(id) {
+                        restore(&mut self.room.instance_list.get(handle).fields.borrow_mut(), field_id, old);
+                    }
+                },
+                Location::InstanceVar(id, var, index) => {
+                    let handle = self.room.instance_list.get_by_instid(id);
+                    if let (Some(handle), Some(Field::Single(value))) = (handle, old) {
+                        let mut context = Context::with_single_instance(handle);
+                        // read-only variables were never written, so they can't fail to be put back
+                        let _ = self.set_instance_var(handle, &var, index, value, &mut context);
+                    }
+                },
+                Location::GlobalField(_) | Location::GlobalVar(_) => (),
+            }
+        }
+        self.undo_log = Some(log);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gml::{testing::assert_value_eq, Value};
+
+    // does what a SetField instruction targeting global does
+    fn set_global(log: &mut UndoLog, globals: &mut DummyFieldHolder, field_id: usize, index: u32, value: Value) {
+        log.record(Location::GlobalField(field_id), globals.fields.get(&field_id));
+        match globals.fields.get_mut(&field_id) {
+            Some(field) => field.set(index, value),
+            None => {
+                globals.fields.insert(field_id, Field::new(index, value));
+            },
+        }
+    }
+
+    #[test]
+    fn rollback_globals() {
+        let mut globals = DummyFieldHolder::new();
+        globals.fields.insert(0, Field::new(0, Value::from(10)));
+        globals.fields.insert(1, Field::new(0, Value::from("name")));
+        let mut log = UndoLog::new();
+
+        // score += 5; score += 5; name[2] = "x"; lives = 3;
+        for _ in 0..2 {
+            let score = globals.fields[&0].get(0).unwrap().add(Value::from(5)).unwrap();
+            set_global(&mut log, &mut globals, 0, 0, score);
+        }
+        set_global(&mut log, &mut globals, 1, 2, Value::from("x"));
+        set_global(&mut log, &mut globals, 2, 0, Value::from(3));
+        log.record(Location::InstanceField(100001, 0), None);
+        assert_value_eq!(globals.fields[&0].get(0).unwrap(), Value::from(20));
+        assert_eq!(log.len(), 4);
+
+        log.restore_globals(&mut globals);
+        assert_value_eq!(globals.fields[&0].get(0).unwrap(), Value::from(10));
+        assert!(matches!(&globals.fields[&1], Field::Single(_)));
+        assert_value_eq!(globals.fields[&1].get(0).unwrap(), Value::from("name"));
+        assert!(!globals.fields.contains_key(&2));
+        // the instance's field is left for the game to put back
+        let rest = log.drain().map(|(location, _)| location).collect::<Vec<_>>();
+        assert_eq!(rest, vec![Location::InstanceField(100001, 0)]);
+        assert!(log.is_empty());
+    }
+}
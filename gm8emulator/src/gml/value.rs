@@ -1,16 +1,68 @@
-use crate::{game::external::dll, gml, math::Real};
+use crate::{
+    asset::font,
+    game::{external::dll, Version},
+    gml,
+    math::Real,
+};
+use gml_parser::token::Operator;
 use serde::{Deserialize, Serialize};
 use std::{
     convert::TryInto,
     fmt::{self, Display},
+    hash::{Hash, Hasher},
 };
 
+/// Equality rules which GM8 doesn't have, for games which depend on them. The defaults match GM8.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct EqualityQuirks {
+    /// Compares a real and a numeric string as reals, so that `5 == "5"`.
+    pub loose_numeric: bool,
+    /// Ignores leading and trailing whitespace when comparing two strings, so that `" hi " == "hi"`.
+    pub trim_strings: bool,
+}
+
+fn trim_whitespace(s: &[u8]) -> &[u8] {
+    let start = s.iter().position(|c| !c.is_ascii_whitespace()).unwrap_or(s.len());
+    let end = s.iter().rposition(|c| !c.is_ascii_whitespace()).map_or(start, |i| i + 1);
+    &s[start..end]
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(into = "SavedValue", from = "SavedValue")]
 pub enum Value {
     Real(Real),
     Str(gml::String),
 }
 
+/// How a Value is serialized. The first two variants are the ones Value always had, so older savestates still load.
+#[derive(Serialize, Deserialize)]
+enum SavedValue {
+    Real(Real),
+    Str(gml::String),
+    /// Undefined is a particular empty string, which wouldn't survive being saved as one.
+    Undefined,
+}
+
+impl From<Value> for SavedValue {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::Real(r) => Self::Real(r),
+            Value::Str(s) if s.is_undefined() => Self::Undefined,
+            Value::Str(s) => Self::Str(s),
+        }
+    }
+}
+
+impl From<SavedValue> for Value {
+    fn from(value: SavedValue) -> Self {
+        match value {
+            SavedValue::Real(r) => Self::Real(r),
+            SavedValue::Str(s) => Self::Str(s),
+            SavedValue::Undefined => Self::undefined(),
+        }
+    }
+}
+
 impl Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -20,21 +72,41 @@ impl Display for Value {
     }
 }
 
+impl Hash for Value {
+    /// Hashes the canonical form of the value, so `-0.0` hashes the same as `0.0` and every NaN hashes alike.
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self {
+            Self::Real(r) => {
+                state.write_u8(0);
+                state.write_u64(r.canonical_bits());
+            },
+            Self::Str(s) => {
+                state.write_u8(1);
+                s.as_ref().hash(state);
+            },
+        }
+    }
+}
+
 macro_rules! gml_cmp_impl {
-    ($($v: vis $fname: ident aka $op_variant: ident: real: $r_cond: expr, string: $s_cond: expr)*) => {
+    ($(
+        $v: vis $fname: ident aka $op_variant: ident: real: $r_cond: expr, string: $s_cond: expr, same: $same: expr
+    )*) => {
         $(
             $v fn $fname(self, rhs: Self) -> gml::Result<Self> {
                 let freal: fn(Real) -> bool = $r_cond;
                 let fstr: fn(&[u8], &[u8]) -> bool = $s_cond;
                 Ok(if match (self, rhs) {
                     (Self::Real(a), Self::Real(b)) => freal((a - b)),
+                    // Two uses of the same literal share a string, so there's no need to compare the bytes
+                    (Self::Str(a), Self::Str(b)) if a.ptr_eq(&b) => $same,
                     (Self::Str(a), Self::Str(b)) => fstr(a.as_ref(), b.as_ref()),
                     (a, b) => return invalid_op!($op_variant, a, b),
                 } {
-                    super::TRUE
+                    Self::TRUE
                 } else {
-                    super::FALSE
-                }.into())
+                    Self::FALSE
+                })
             }
         )*
     };
@@ -50,32 +122,170 @@ macro_rules! invalid_op {
 }
 
 impl Value {
+    /// The value GML uses for true, which every comparison returns.
+    pub const TRUE: Self = Self::Real(Real::new(gml::TRUE));
+    /// The value GML uses for false, which every comparison returns.
+    pub const FALSE: Self = Self::Real(Real::new(gml::FALSE));
+
+    /// Gets `undefined`, which newer games get back from failed lookups and check for with is_undefined.
+    /// GM8 has nothing like it, so it's an empty string: using it as a real is therefore an error, or 0 in
+    /// functions which accept strings as reals, and it compares equal to "".
+    pub fn undefined() -> Self {
+        Self::Str(gml::String::undefined())
+    }
+
+    pub fn is_undefined(&self) -> bool {
+        matches!(self, Self::Str(s) if s.is_undefined())
+    }
+
+    /// Frees this value, giving its string to the pool to be reused if nothing else holds it.
+    pub fn recycle(self) {
+        if let Self::Str(s) = self {
+            s.recycle();
+        }
+    }
+
     // All the GML comparison operators (which return Value not bool).
+    // Strings are ordered byte by byte like GM8 does, never by what number they look like, so "10" < "9" because
+    // '1' is before '9', and "abc" < "abd" < "abda". The bytes are unsigned, so characters above 0x7F sort after
+    // ASCII. One known difference: GM8.1 compares UTF-16, and we compare the UTF-8 it's stored as here, so a character
+    // outside the BMP sorts after U+E000 to U+FFFF rather than before them.
     #[rustfmt::skip]
     gml_cmp_impl! {
         pub gml_eq aka Equal:
             real: |diff| diff.abs() < Real::CMP_EPSILON,
-            string: |s1, s2| s1 == s2
+            string: |s1, s2| s1 == s2,
+            same: true
 
         pub gml_ne aka NotEqual:
             real: |diff| diff.abs() >= Real::CMP_EPSILON,
-            string: |s1, s2| s1 != s2
+            string: |s1, s2| s1 != s2,
+            same: false
 
         pub gml_lt aka LessThan:
             real: |diff| diff <= -Real::CMP_EPSILON,
-            string: |s1, s2| s1 < s2
+            string: |s1, s2| s1 < s2,
+            same: false
 
         pub gml_lte aka LessThanOrEqual:
             real: |diff| diff < Real::CMP_EPSILON,
-            string: |s1, s2| s1 <= s2
+            string: |s1, s2| s1 <= s2,
+            same: true
 
         pub gml_gt aka GreaterThan:
             real: |diff| diff >= Real::CMP_EPSILON,
-            string: |s1, s2| s1 > s2
+            string: |s1, s2| s1 > s2,
+            same: false
 
         pub gml_gte aka GreaterThanOrEqual:
             real: |diff| diff > -Real::CMP_EPSILON,
-            string: |s1, s2| s1 >= s2
+            string: |s1, s2| s1 >= s2,
+            same: true
+    }
+
+    /// Applies any of the GML comparison operators, or fails if the operator isn't one.
+    pub fn compare(self, rhs: Self, op: Operator) -> gml::Result<Self> {
+        match op {
+            Operator::Equal => self.gml_eq(rhs),
+            Operator::NotEqual => self.gml_ne(rhs),
+            Operator::LessThan => self.gml_lt(rhs),
+            Operator::LessThanOrEqual => self.gml_lte(rhs),
+            Operator::GreaterThan => self.gml_gt(rhs),
+            Operator::GreaterThanOrEqual => self.gml_gte(rhs),
+            op => Err(gml::Error::InvalidBinaryOperator(op)),
+        }
+    }
+
+    /// Checks whether the value is between two others, inclusive, as `self >= lo && self <= hi` would. Both bounds are
+    /// compared with the same epsilon as the comparison operators, and comparing a real with a string is an error
+    /// whichever bound it's against.
+    pub fn in_range(self, lo: Self, hi: Self) -> gml::Result<Self> {
+        let above = self.clone().gml_gte(lo)?.is_truthy();
+        let below = self.gml_lte(hi)?.is_truthy();
+        Ok((above && below).into())
+    }
+
+    /// Describes how a comparison comes out the way it does, such as when two reals count as equal because they're
+    /// within `Real::CMP_EPSILON` of each other. Meant for showing in a debugger.
+    pub fn explain_cmp(&self, rhs: &Self, op: Operator) -> String {
+        let comparison = format!("{} {} {}", self.log_fmt(), op, rhs.log_fmt());
+        let result = match self.clone().compare(rhs.clone(), op) {
+            Ok(result) => result.is_truthy(),
+            Err(e) => return format!("{} is an error: {}", comparison, e),
+        };
+        let reason = match (self, rhs) {
+            (Self::Real(a), Self::Real(b)) => {
+                let (diff, epsilon) = ((*a - *b).into_inner(), Real::CMP_EPSILON.into_inner());
+                if diff == 0.0 {
+                    format!("{} and {} are exactly equal", a, b)
+                } else if diff.abs() < epsilon {
+                    let diff = diff.abs();
+                    format!("{} \u{2248} {}, as they differ by {:e}, within the epsilon of {:e}", a, b, diff, epsilon)
+                } else {
+                    let (side, diff) = if diff < 0.0 { ("less", -diff) } else { ("greater", diff) };
+                    format!("{} is {} than {} by {:e}, more than the epsilon of {:e}", a, side, b, diff, epsilon)
+                }
+            },
+            (Self::Str(a), Self::Str(b)) => {
+                let (a, b) = (a.as_ref(), b.as_ref());
+                match a.iter().zip(b).position(|(x, y)| x != y) {
+                    Some(i) => {
+                        format!("the strings first differ at character {} ({:#04x} vs {:#04x})", i + 1, a[i], b[i])
+                    },
+                    None if a.len() == b.len() => "the strings are identical".into(),
+                    None => {
+                        let len = a.len().min(b.len());
+                        format!("the strings match up to character {}, where one of them ends", len)
+                    },
+                }
+            },
+            _ => unreachable!("comparing a real with a string is always an error"),
+        };
+        format!("{} is {}: {}", comparison, result, reason)
+    }
+
+    /// GML `==` with the given quirks applied.
+    pub fn gml_eq_with(self, rhs: Self, quirks: &EqualityQuirks) -> gml::Result<Self> {
+        match (self, rhs) {
+            (a @ Self::Real(_), Self::Str(b)) | (Self::Str(b), a @ Self::Real(_)) if quirks.loose_numeric => {
+                Ok(match Self::Str(b).parse_real() {
+                    Some(b) => a.gml_eq(b.into())?,
+                    None => Self::FALSE,
+                })
+            },
+            (Self::Str(a), Self::Str(b)) if quirks.trim_strings => {
+                Ok((trim_whitespace(a.as_ref()) == trim_whitespace(b.as_ref())).into())
+            },
+            (a, b) => a.gml_eq(b),
+        }
+    }
+
+    /// GML `!=` with the given quirks applied.
+    pub fn gml_ne_with(self, rhs: Self, quirks: &EqualityQuirks) -> gml::Result<Self> {
+        match (self, rhs) {
+            (a @ Self::Real(_), Self::Str(b)) | (Self::Str(b), a @ Self::Real(_)) if quirks.loose_numeric => {
+                Ok(match Self::Str(b).parse_real() {
+                    Some(b) => a.gml_ne(b.into())?,
+                    None => Self::TRUE,
+                })
+            },
+            (Self::Str(a), Self::Str(b)) if quirks.trim_strings => {
+                Ok((trim_whitespace(a.as_ref()) != trim_whitespace(b.as_ref())).into())
+            },
+            (a, b) => a.gml_ne(b),
+        }
+    }
+
+    /// Parses a string the way `real()` does, returning None if it isn't a number.
+    /// Reals are returned as they are.
+    pub fn parse_real(&self) -> Option<Real> {
+        match self {
+            Self::Real(r) => Some(*r),
+            Self::Str(s) => match std::str::from_utf8(s.as_ref()).ok()?.trim() {
+                "" => Some(Real::from(0.0)),
+                x => x.parse::<f64>().ok().map(Real::from),
+            },
+        }
     }
 
     pub fn max<'a>(&'a self, other: &'a Self) -> &'a Self {
@@ -134,6 +344,42 @@ impl Value {
         }
     }
 
+    /// Creates a string from bytes in the given Windows charset. GM8.0 strings are kept in the charset's own bytes, so
+    /// they're copied as they are, but GM8.1 strings are UTF-8, so they're decoded. Charsets with no fixed encoding,
+    /// such as DEFAULT_CHARSET, are read as Windows-1252.
+    pub fn from_encoded_bytes(bytes: &[u8], charset: u32, gm_version: Version) -> Self {
+        match gm_version {
+            Version::GameMaker8_0 => bytes.into(),
+            Version::GameMaker8_1 => {
+                let encoding = font::charset_encoding(charset, encoding_rs::WINDOWS_1252);
+                encoding.decode_without_bom_handling(bytes).0.into_owned().into()
+            },
+        }
+    }
+
+    /// Creates a string from a constant without allocating it again every time.
+    pub fn from_static(value: &'static str) -> Self {
+        Self::Str(gml::String::from_static(value))
+    }
+
+    /// Converts the value to the given type, failing if it holds the wrong type.
+    pub fn try_as<T: TryFromValue>(&self) -> gml::Result<T> {
+        T::try_from_value(self)
+    }
+
+    /// Converts the value to the given type under a coercion policy, for builtin arguments GM8 converted itself.
+    /// Values that are already the right type are never converted.
+    pub fn coerce<T: TryFromValue>(&self, policy: Coercion) -> gml::Result<T> {
+        T::try_from_value(self).or_else(|e| match (policy, self) {
+            (Coercion::CoerceReal, Self::Str(_)) => match self.parse_real() {
+                Some(r) => T::try_from_value(&Self::Real(r)),
+                None => Err(e),
+            },
+            (Coercion::CoerceString, Self::Real(_)) => T::try_from_value(&Self::Str(self.repr())),
+            _ => Err(e),
+        })
+    }
+
     pub fn ty_str(&self) -> &'static str {
         match self {
             Self::Real(_) => "real",
@@ -150,6 +396,17 @@ impl Value {
         }
     }
 
+    /// Exact equality, NOT GML semantics: reals are equal only if they have the same bit pattern, so there's no
+    /// epsilon, `-0.0` and `0.0` differ and a NaN equals an identical NaN. Strings must match exactly, and values of
+    /// different types are never equal. For savestate and replay checks, where any difference matters.
+    pub fn bit_eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Real(a), Self::Real(b)) => a.into_inner().to_bits() == b.into_inner().to_bits(),
+            (Self::Str(a), Self::Str(b)) => a.as_ref() == b.as_ref(),
+            _ => false,
+        }
+    }
+
     /// Rounds the value to an i32. This is done very commonly by the GM8 runner.
     pub fn round(&self) -> i32 {
         match &self {
@@ -158,9 +415,15 @@ impl Value {
         }
     }
 
+    /// Converts the value to a 0xBBGGRR colour the way GM8 does, by rounding it to an integer and keeping the low
+    /// 24 bits, so values above 0xFFFFFF wrap around and negative values wrap from the top.
+    pub fn as_colour(&self) -> u32 {
+        self.round() as u32 & 0xFFFFFF
+    }
+
     /// Formats the value as a number or a string with quotes around it so you can see that it is.
     /// Used in generating error messages.
-    fn log_fmt(&self) -> String {
+    pub fn log_fmt(&self) -> String {
         match self {
             Self::Real(real) => real.to_string(),
             Self::Str(string) => format!("\"{}\"", string),
@@ -218,6 +481,23 @@ impl Value {
         }
     }
 
+    /// Concatenates all the given strings into one buffer, which is much cheaper than adding them one at a time.
+    /// Any non-string fails the same way `add` would, with everything before it as the left-hand operand.
+    pub fn concat_many(values: &[Self]) -> gml::Result<Self> {
+        let mut len = 0;
+        for (i, value) in values.iter().enumerate() {
+            match value {
+                Self::Str(s) => len += s.as_ref().len(),
+                x => return invalid_op!(Add, Self::concat_many(&values[..i])?, x.clone()),
+            }
+        }
+        let mut buf = Vec::with_capacity(len);
+        for value in values {
+            buf.extend_from_slice(value.into());
+        }
+        Ok(buf.into())
+    }
+
     pub fn add_assign(&mut self, rhs: Self) -> gml::Result<()> {
         match (self, rhs) {
             (Self::Real(lhs), Self::Real(rhs)) => Ok(*lhs += rhs),
@@ -321,6 +601,58 @@ impl Value {
         }
     }
 
+    fn real_fn(&self, f: impl FnOnce(Real) -> Real) -> gml::Result<Self> {
+        match self {
+            Self::Real(r) => Ok(Self::Real(f(*r))),
+            Self::Str(_) => Err(gml::Error::WrongArgumentType("real".into(), self.clone())),
+        }
+    }
+
+    /// Sine of an angle in degrees, exact at multiples of 30 and 90 degrees. See `Real::sin_cos_degrees`.
+    pub fn dsin(&self) -> gml::Result<Self> {
+        self.real_fn(|r| r.sin_cos_degrees().0)
+    }
+
+    /// Cosine of an angle in degrees, exact at multiples of 60 and 90 degrees. See `Real::sin_cos_degrees`.
+    pub fn dcos(&self) -> gml::Result<Self> {
+        self.real_fn(|r| r.sin_cos_degrees().1)
+    }
+
+    /// Tangent of an angle in degrees, exactly 0 at multiples of 180 degrees and infinite at odd multiples of 90.
+    pub fn dtan(&self) -> gml::Result<Self> {
+        self.real_fn(|r| {
+            let (sin, cos) = r.sin_cos_degrees();
+            sin / cos
+        })
+    }
+
+    /// Arcsine in degrees.
+    pub fn darcsin(&self) -> gml::Result<Self> {
+        self.real_fn(|r| r.arcsin().to_degrees())
+    }
+
+    /// Arccosine in degrees.
+    pub fn darccos(&self) -> gml::Result<Self> {
+        self.real_fn(|r| r.arccos().to_degrees())
+    }
+
+    /// Arctangent in degrees.
+    pub fn darctan(&self) -> gml::Result<Self> {
+        self.real_fn(|r| r.arctan().to_degrees())
+    }
+
+    /// Angle of the vector (x, y) in degrees, where self is y, as with arctan2.
+    pub fn darctan2(&self, x: &Self) -> gml::Result<Self> {
+        let x = x.try_as::<Real>()?;
+        self.real_fn(|y| y.arctan2(x).to_degrees())
+    }
+
+    /// Signed difference from another angle to this one in degrees, in the range (-180, 180].
+    pub fn angle_difference(&self, other: &Self) -> gml::Result<Self> {
+        let other = other.try_as::<Real>()?;
+        self.real_fn(|r| r.angle_difference(other))
+    }
+
     pub fn not(self) -> gml::Result<Self> {
         match self {
             Self::Real(_) => Ok((!self.is_truthy()).into()),
@@ -400,6 +732,77 @@ impl Value {
             None
         }
     }
+
+    /// Gets this value as raw bytes of the given buffer type, little-endian like GM8.1's buffers.
+    /// Numbers are converted the same lazy way as anywhere else, so a string written as a number is 0, and a real
+    /// written as a string is written as `string()` would format it. Strings are null-terminated like GM8.1's C
+    /// strings, so a string with a null byte in it is only written up to there, the same as it would be read back.
+    pub fn to_bytes(&self, kind: BufferType) -> Vec<u8> {
+        match kind {
+            BufferType::U8 => vec![i32::from(self.clone()) as u8],
+            BufferType::S16 => (i32::from(self.clone()) as i16).to_le_bytes().to_vec(),
+            BufferType::F64 => f64::from(self.clone()).to_le_bytes().to_vec(),
+            BufferType::String => {
+                let repr = self.repr();
+                let text = repr.as_ref();
+                let mut bytes = text[..text.iter().position(|&b| b == 0).unwrap_or(text.len())].to_vec();
+                bytes.push(0);
+                bytes
+            },
+        }
+    }
+
+    /// Reads a value of the given buffer type from the start of some bytes, returning it with how many bytes it took
+    /// up, or None if they end before it does. A string runs up to the first null byte, which it takes up too.
+    pub fn from_bytes(kind: BufferType, bytes: &[u8]) -> Option<(Self, usize)> {
+        match kind {
+            BufferType::U8 => bytes.first().map(|&b| (b.into(), 1)),
+            BufferType::S16 => Some((i32::from(i16::from_le_bytes(bytes.get(..2)?.try_into().unwrap())).into(), 2)),
+            BufferType::F64 => Some((f64::from_le_bytes(bytes.get(..8)?.try_into().unwrap()).into(), 8)),
+            BufferType::String => {
+                let len = bytes.iter().position(|&b| b == 0)?;
+                Some((bytes[..len].into(), len + 1))
+            },
+        }
+    }
+
+    /// Gets the change from this value to the next one, for syncing variables without resending them in full.
+    /// Reals are compared by their canonical bits, so any two reals GML can't tell apart are unchanged.
+    pub fn diff(&self, next: &Self) -> ValueDelta {
+        match (self, next) {
+            (Self::Real(a), Self::Real(b)) if a.canonical_bits() == b.canonical_bits() => ValueDelta::Unchanged,
+            (Self::Str(a), Self::Str(b)) if a.as_ref() == b.as_ref() => ValueDelta::Unchanged,
+            (_, Self::Real(b)) => ValueDelta::Real(b.canonical_bits()),
+            (_, Self::Str(b)) => ValueDelta::Str(b.clone()),
+        }
+    }
+
+    /// Applies a change made by `diff` to this value, giving the next value.
+    pub fn patch(&self, delta: &ValueDelta) -> Self {
+        match delta {
+            ValueDelta::Unchanged => self.clone(),
+            ValueDelta::Real(bits) => Self::Real(f64::from_bits(*bits).into()),
+            ValueDelta::Str(s) => Self::Str(s.clone()),
+        }
+    }
+}
+
+/// The types GM8.1 buffers read and write values as, for `Value::to_bytes` and `Value::from_bytes`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BufferType {
+    U8,
+    S16,
+    F64,
+    String,
+}
+
+/// A change from one value to another, as given by `Value::diff`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum ValueDelta {
+    Unchanged,
+    /// The canonical bits of the new real.
+    Real(u64),
+    Str(gml::String),
 }
 
 impl From<f64> for Value {
@@ -440,7 +843,7 @@ impl From<u8> for Value {
 
 impl From<bool> for Value {
     fn from(value: bool) -> Self {
-        Self::Real(if value { gml::TRUE } else { gml::FALSE }.into())
+        if value { Self::TRUE } else { Self::FALSE }
     }
 }
 
@@ -479,7 +882,11 @@ impl From<Value> for i32 {
     fn from(value: Value) -> Self {
         match value {
             Value::Real(r) => r.round().to_i32(),
-            Value::Str(_) => 0,
+            Value::Str(_s) => {
+                #[cfg(feature = "lossy-warnings")]
+                gml::lossy::warn("i32", &_s);
+                0
+            },
         }
     }
 }
@@ -489,7 +896,11 @@ impl From<Value> for u32 {
     fn from(value: Value) -> Self {
         match value {
             Value::Real(r) => r.round().to_u32(),
-            Value::Str(_) => 0,
+            Value::Str(_s) => {
+                #[cfg(feature = "lossy-warnings")]
+                gml::lossy::warn("u32", &_s);
+                0
+            },
         }
     }
 }
@@ -499,7 +910,11 @@ impl From<Value> for f64 {
     fn from(value: Value) -> Self {
         match value {
             Value::Real(r) => r.into(),
-            Value::Str(_) => 0.0,
+            Value::Str(_s) => {
+                #[cfg(feature = "lossy-warnings")]
+                gml::lossy::warn("f64", &_s);
+                0.0
+            },
         }
     }
 }
@@ -509,7 +924,11 @@ impl From<Value> for Real {
     fn from(value: Value) -> Self {
         match value {
             Value::Real(r) => r.into(),
-            Value::Str(_) => Self::from(0.0),
+            Value::Str(_s) => {
+                #[cfg(feature = "lossy-warnings")]
+                gml::lossy::warn("real", &_s);
+                Self::from(0.0)
+            },
         }
     }
 }
@@ -534,6 +953,58 @@ impl<'a> From<&'a Value> for &'a [u8] {
     }
 }
 
+/// How a builtin treats an argument of the wrong type, as GM8 isn't consistent about it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Coercion {
+    /// The wrong type is an error.
+    Strict,
+    /// Strings are parsed as numbers the way `real()` does, and it's an error if they aren't one.
+    CoerceReal,
+    /// Reals are formatted the way `string()` does.
+    CoerceString,
+}
+
+/// Strict conversion out of a Value, for builtin arguments where the wrong type is an error rather than 0 or "".
+pub trait TryFromValue: Sized {
+    fn try_from_value(value: &Value) -> gml::Result<Self>;
+}
+
+impl TryFromValue for Real {
+    fn try_from_value(value: &Value) -> gml::Result<Self> {
+        match value {
+            Value::Real(r) => Ok(*r),
+            Value::Str(_) => Err(gml::Error::WrongArgumentType("real".into(), value.clone())),
+        }
+    }
+}
+
+impl TryFromValue for f64 {
+    fn try_from_value(value: &Value) -> gml::Result<Self> {
+        Real::try_from_value(value).map(Into::into)
+    }
+}
+
+impl TryFromValue for i32 {
+    fn try_from_value(value: &Value) -> gml::Result<Self> {
+        Real::try_from_value(value).map(|r| r.round().to_i32())
+    }
+}
+
+impl TryFromValue for u32 {
+    fn try_from_value(value: &Value) -> gml::Result<Self> {
+        Real::try_from_value(value).map(|r| r.round().to_u32())
+    }
+}
+
+impl TryFromValue for gml::String {
+    fn try_from_value(value: &Value) -> gml::Result<Self> {
+        match value {
+            Value::Real(_) => Err(gml::Error::WrongArgumentType("string".into(), value.clone())),
+            Value::Str(s) => Ok(s.clone()),
+        }
+    }
+}
+
 impl Default for Value {
     fn default() -> Self {
         Self::Real(Real::from(0.0))
@@ -562,6 +1033,85 @@ impl From<dll::Value> for Value {
 mod tests {
     use super::*;
 
+    #[test]
+    fn buffer_bytes() {
+        let round_trip = |value: Value, kind| {
+            let bytes = value.to_bytes(kind);
+            let (read, len) = Value::from_bytes(kind, &bytes).unwrap();
+            assert_eq!(len, bytes.len());
+            (bytes, read)
+        };
+
+        let (bytes, read) = round_trip(Value::from(200), BufferType::U8);
+        assert_eq!((bytes, f64::from(read)), (vec![200], 200.0));
+        let (bytes, read) = round_trip(Value::from(-2), BufferType::S16);
+        assert_eq!((bytes, f64::from(read)), (vec![0xFE, 0xFF], -2.0));
+        let (bytes, read) = round_trip(Value::from(0.1), BufferType::F64);
+        assert_eq!((bytes, f64::from(read)), (0.1f64.to_le_bytes().to_vec(), 0.1));
+        // out of range numbers wrap, and strings are 0
+        assert_eq!(Value::from(300).to_bytes(BufferType::U8), vec![44]);
+        assert_eq!(Value::from(40000).to_bytes(BufferType::S16), (-25536i16).to_le_bytes());
+        assert_eq!(Value::from("12").to_bytes(BufferType::F64), 0f64.to_le_bytes());
+
+        let text = b"caf\xe9 \x01\xff";
+        let (bytes, read) = round_trip(Value::from(text.as_ref()), BufferType::String);
+        assert_eq!(bytes, [text.as_ref(), &[0]].concat());
+        assert_eq!(<&[u8]>::from(&read), text.as_ref());
+        assert_eq!(Value::from(2.5).to_bytes(BufferType::String), b"2.50\0");
+        let (bytes, read) = round_trip(Value::from(2.5), BufferType::String);
+        assert_eq!((bytes.len(), <&[u8]>::from(&read)), (5, b"2.50".as_ref()));
+
+        // an embedded null ends the string when it's written, so writing and reading agree on where it stops
+        let (bytes, read) = round_trip(Value::from(b"ab\0cd".as_ref()), BufferType::String);
+        assert_eq!(bytes, b"ab\0");
+        assert_eq!(<&[u8]>::from(&read), b"ab");
+
+        // a string stops at its terminator, and there has to be one
+        assert_eq!(Value::from_bytes(BufferType::String, b"ab\0cd\0").map(|(_, len)| len), Some(3));
+        assert!(Value::from_bytes(BufferType::String, b"ab").is_none());
+        assert!(Value::from_bytes(BufferType::S16, &[1]).is_none());
+        assert!(Value::from_bytes(BufferType::F64, &[0; 7]).is_none());
+    }
+
+    #[test]
+    fn numeric_strings_compare_as_text() {
+        let lt = |a: &str, b: &str| Value::from(a).gml_lt(Value::from(b)).unwrap().is_truthy();
+        assert!(lt("10", "9"));
+        assert!(!lt("9", "10"));
+        assert!(lt("-1", "-2") && lt("1.5", "1.50") && lt(" 2", "1"));
+        assert!(!lt("100", "100") && lt("", "0"));
+        assert!(Value::from("10").gml_gt(Value::from("9")).unwrap().almost_equals(&Value::FALSE));
+        // they're still not equal to the numbers they look like, or to each other
+        assert!(Value::from("10").gml_eq(Value::from(10)).is_err());
+        assert!(!Value::from("1.0").gml_eq(Value::from("1")).unwrap().is_truthy());
+
+        // bytes are unsigned, so accented Windows-1252 and UTF-8 characters sort after z
+        let bytes = |a: &[u8], b: &[u8]| Value::from(a).gml_lt(Value::from(b)).unwrap().is_truthy();
+        assert!(bytes(b"z", b"\xe9") && bytes(b"z", "\u{e9}".as_bytes()));
+        assert!(bytes(b"\x7f", b"\x80"));
+    }
+
+    #[test]
+    fn in_range() {
+        let in_range = |x: Value, lo: Value, hi: Value| x.in_range(lo, hi).unwrap().is_truthy();
+        assert!(in_range(5.into(), 1.into(), 10.into()));
+        assert!(!in_range(0.into(), 1.into(), 10.into()));
+        assert!(!in_range(11.into(), 1.into(), 10.into()));
+        // both bounds are inclusive, and just outside them by less than the epsilon still counts
+        let epsilon = Real::CMP_EPSILON.into_inner();
+        assert!(in_range(1.into(), 1.into(), 10.into()) && in_range(10.into(), 1.into(), 10.into()));
+        assert!(in_range((1.0 - epsilon / 2.0).into(), 1.into(), 10.into()));
+        assert!(in_range((10.0 + epsilon / 2.0).into(), 1.into(), 10.into()));
+        assert!(!in_range((10.0 + epsilon * 2.0).into(), 1.into(), 10.into()));
+        assert!(!in_range(5.into(), 10.into(), 1.into()));
+
+        assert!(in_range("b".into(), "a".into(), "c".into()));
+        assert!(!in_range("d".into(), "a".into(), "c".into()));
+        assert!(Value::from(5).in_range("a".into(), 10.into()).is_err());
+        // even when the other bound already rules it out
+        assert!(Value::from(50).in_range(1.into(), "z".into()).is_err());
+    }
+
     #[test]
     fn op_add() {
         let a = Value::Real(Real::from(0.1));
@@ -580,4 +1130,326 @@ mod tests {
         let b = Value::Str("owo".to_string().into());
         let _ = a.add(b).unwrap();
     }
+
+    #[test]
+    fn try_as() {
+        let a = Value::Real(Real::from(2.6));
+        assert_eq!(a.try_as::<i32>().unwrap(), 3);
+        assert_eq!(a.try_as::<f64>().unwrap(), 2.6);
+        assert!(a.try_as::<gml::String>().is_err());
+
+        let b = Value::Str("owo".to_string().into());
+        assert!(b.try_as::<Real>().is_err());
+        assert!(b.try_as::<i32>().is_err());
+        assert_eq!(b.try_as::<gml::String>().unwrap().as_ref(), b"owo");
+        assert_eq!(i32::from(b), 0);
+    }
+
+    #[test]
+    fn coerce() {
+        let numeric = Value::from(" 2.5 ");
+        assert!(numeric.coerce::<Real>(Coercion::Strict).is_err());
+        assert_eq!(numeric.coerce::<f64>(Coercion::CoerceReal).unwrap(), 2.5);
+        assert_eq!(numeric.coerce::<i32>(Coercion::CoerceReal).unwrap(), 3);
+        assert!(Value::from("2.5x").coerce::<Real>(Coercion::CoerceReal).is_err());
+        assert!(numeric.coerce::<Real>(Coercion::CoerceString).is_err());
+
+        // values that are already the right type go through untouched
+        let real = Value::from(2.5);
+        for &policy in &[Coercion::Strict, Coercion::CoerceReal, Coercion::CoerceString] {
+            assert_eq!(real.coerce::<f64>(policy).unwrap(), 2.5);
+            assert_eq!(numeric.coerce::<gml::String>(policy).unwrap().as_ref(), b" 2.5 ");
+        }
+        assert_eq!(real.coerce::<gml::String>(Coercion::CoerceString).unwrap().as_ref(), b"2.5");
+        assert!(real.coerce::<gml::String>(Coercion::Strict).is_err());
+    }
+
+    #[test]
+    fn saved_values() {
+        // what Values were serialized as before undefined existed
+        #[derive(Serialize)]
+        enum OldValue {
+            Real(Real),
+            Str(gml::String),
+        }
+        let old = vec![OldValue::Real(Real::from(2.5)), OldValue::Str("old".into())];
+        let values: Vec<Value> = bincode::deserialize(&bincode::serialize(&old).unwrap()).unwrap();
+        assert!(values[0].bit_eq(&Value::from(2.5)));
+        assert!(values[1].bit_eq(&Value::from("old")));
+        assert_eq!(bincode::serialize(&values).unwrap(), bincode::serialize(&old).unwrap());
+    }
+
+    #[test]
+    fn undefined() {
+        let undefined = Value::undefined();
+        assert!(undefined.is_undefined());
+        assert!(undefined.clone().is_undefined());
+        assert!(!Value::from("").is_undefined());
+        assert!(!Value::from(0).is_undefined());
+        assert!(undefined.clone().add(Value::from(1)).is_err());
+        assert!(undefined.clone().gml_eq(Value::from("")).unwrap().is_truthy());
+
+        let saved = bincode::serialize(&[undefined, Value::from("")]).unwrap();
+        let reloaded: Vec<Value> = bincode::deserialize(&saved).unwrap();
+        assert!(reloaded[0].is_undefined());
+        assert!(!reloaded[1].is_undefined());
+    }
+
+    #[test]
+    fn degree_trig() {
+        let real = |v: gml::Result<Value>| f64::from(v.unwrap());
+        assert_eq!(real(Value::from(30).dsin()), 0.5);
+        assert_eq!(real(Value::from(90).dcos()), 0.0);
+        assert_eq!(real(Value::from(180).dtan()), 0.0);
+        assert_eq!(real(Value::from(10).angle_difference(&Value::from(350))), 20.0);
+        assert_eq!(real(Value::from(-180).angle_difference(&Value::from(0))), 180.0);
+        assert!((real(Value::from(1).darctan2(&Value::from(-1))) - 135.0).abs() < 1e-12);
+        assert!(Value::from("30").dsin().is_err());
+        assert!(Value::from(30).angle_difference(&Value::from("a")).is_err());
+    }
+
+    #[test]
+    fn compare() {
+        let ops: [(Operator, fn(Value, Value) -> gml::Result<Value>); 6] = [
+            (Operator::Equal, Value::gml_eq),
+            (Operator::NotEqual, Value::gml_ne),
+            (Operator::LessThan, Value::gml_lt),
+            (Operator::LessThanOrEqual, Value::gml_lte),
+            (Operator::GreaterThan, Value::gml_gt),
+            (Operator::GreaterThanOrEqual, Value::gml_gte),
+        ];
+        let pairs = [
+            (Value::from(1.0), Value::from(2.0)),
+            (Value::from(2.0), Value::from(2.0)),
+            (Value::from(0.3), Value::from(0.1 + 0.2)),
+            (Value::from("a"), Value::from("b")),
+            (Value::from("b"), Value::from("b")),
+        ];
+        for (op, f) in ops.iter() {
+            for (a, b) in pairs.iter() {
+                let expected = f(a.clone(), b.clone()).unwrap();
+                assert!(a.clone().compare(b.clone(), *op).unwrap().almost_equals(&expected));
+            }
+            assert!(Value::from(1.0).compare(Value::from("a"), *op).is_err());
+        }
+        assert!(Value::from(1.0).compare(Value::from(1.0), Operator::Add).is_err());
+    }
+
+    #[test]
+    fn eq_case_sensitive() {
+        assert!(Value::from("abc").gml_eq(Value::from("abc")).unwrap().is_truthy());
+        assert!(!Value::from("abc").gml_eq(Value::from("ABC")).unwrap().is_truthy());
+    }
+
+    #[test]
+    fn from_encoded_bytes() {
+        // é in Windows-1252, and ソ in Shift-JIS
+        let string = |bytes: &[u8], charset, version| {
+            <&[u8]>::from(&Value::from_encoded_bytes(bytes, charset, version)).to_vec()
+        };
+        assert_eq!(string(b"caf\xe9", 0, Version::GameMaker8_0), b"caf\xe9");
+        assert_eq!(string(b"\x83\x5c", 128, Version::GameMaker8_0), b"\x83\x5c");
+        assert_eq!(string(b"caf\xe9", 0, Version::GameMaker8_1), "café".as_bytes());
+        assert_eq!(string(b"\x83\x5c", 128, Version::GameMaker8_1), "ソ".as_bytes());
+    }
+
+    #[test]
+    fn loose_numeric_eq() {
+        let strict = EqualityQuirks::default();
+        let loose = EqualityQuirks { loose_numeric: true, ..Default::default() };
+        assert!(Value::from(5.0).gml_eq_with(Value::from("5"), &strict).is_err());
+        assert!(Value::from(5.0).gml_eq_with(Value::from("5"), &loose).unwrap().is_truthy());
+        assert!(Value::from(" 5.0").gml_eq_with(Value::from(5.0), &loose).unwrap().is_truthy());
+        assert!(!Value::from(5.0).gml_eq_with(Value::from("five"), &loose).unwrap().is_truthy());
+        assert!(Value::from(5.0).gml_ne_with(Value::from("6"), &loose).unwrap().is_truthy());
+        assert!(!Value::from("5").gml_eq_with(Value::from("5.0"), &loose).unwrap().is_truthy());
+    }
+
+    #[test]
+    fn from_static() {
+        assert!(Value::from_static("abc").almost_equals(&Value::from("abc".to_string())));
+        assert!(Value::from_static("abc").almost_equals(&Value::from("abc")));
+    }
+
+    #[test]
+    fn comparison_constants() {
+        let bits = |v: Value| match v {
+            Value::Real(r) => r.into_inner().to_bits(),
+            Value::Str(_) => panic!("comparisons should return reals"),
+        };
+        let (t, f) = (bits(Value::TRUE), bits(Value::FALSE));
+        assert_eq!(t, 1.0f64.to_bits());
+        assert_eq!(f, 0.0f64.to_bits());
+
+        let one = Value::Real(Real::from(1.0));
+        let two = Value::Real(Real::from(2.0));
+        let s = Value::Str(b"a".as_ref().into());
+        assert_eq!(bits(one.clone().gml_eq(one.clone()).unwrap()), t);
+        assert_eq!(bits(one.clone().gml_ne(one.clone()).unwrap()), f);
+        assert_eq!(bits(one.clone().gml_lt(two.clone()).unwrap()), t);
+        assert_eq!(bits(two.clone().gml_lte(one.clone()).unwrap()), f);
+        assert_eq!(bits(two.clone().gml_gt(one.clone()).unwrap()), t);
+        assert_eq!(bits(one.clone().gml_gte(two).unwrap()), f);
+        assert_eq!(bits(s.clone().gml_eq(s.clone()).unwrap()), t);
+        assert_eq!(bits(Value::from(true)), t);
+        let quirks = EqualityQuirks { loose_numeric: true, ..Default::default() };
+        assert_eq!(bits(one.gml_eq_with(s, &quirks).unwrap()), f);
+    }
+
+    #[test]
+    fn diff_patch() {
+        let values: Vec<Value> = vec![
+            0.into(),
+            0.into(),
+            Real::from(-0.0).into(),
+            1.5.into(),
+            "hi".into(),
+            "hi".into(),
+            "hello".into(),
+            f64::NAN.into(),
+            (-f64::NAN).into(),
+            7.into(),
+        ];
+        let mut synced = Value::default();
+        let mut previous = Value::default();
+        let mut unchanged = 0;
+        for value in values {
+            let delta = previous.diff(&value);
+            if let ValueDelta::Unchanged = delta {
+                unchanged += 1;
+            }
+            synced = synced.patch(&delta);
+            match (&synced, &value) {
+                (Value::Real(a), Value::Real(b)) => assert_eq!(a.canonical_bits(), b.canonical_bits()),
+                (Value::Str(a), Value::Str(b)) => assert_eq!(a.as_ref(), b.as_ref()),
+                _ => panic!("{} was patched into {}", value, synced),
+            }
+            previous = value;
+        }
+        // Both zeroes match the default and each other, as do the two "hi"s and the two NaNs
+        assert_eq!(unchanged, 5);
+    }
+
+    #[test]
+    fn concat_many() {
+        let values = (0..1000).map(|i| Value::from(format!("{},", i))).collect::<Vec<_>>();
+        let joined = Value::concat_many(&values).unwrap();
+        let added = values.iter().cloned().fold(Value::from(""), |acc, v| acc.add(v).unwrap());
+        assert_eq!(<&[u8]>::from(&joined), <&[u8]>::from(&added));
+        assert!(<&[u8]>::from(&joined).starts_with(b"0,1,2,"));
+
+        assert_eq!(<&[u8]>::from(&Value::concat_many(&[]).unwrap()), b"");
+        match Value::concat_many(&["a".into(), "b".into(), 1.into(), "c".into()]) {
+            Err(gml::Error::InvalidOperandsBinary(Operator::Add, Value::Str(lhs), Value::Real(_))) => {
+                assert_eq!(lhs.as_ref(), b"ab")
+            },
+            _ => panic!("concatenating a real should fail"),
+        }
+    }
+
+    #[test]
+    fn trimmed_string_equality() {
+        let eq = |a: &str, b: &str, quirks: &EqualityQuirks| Value::from(a).gml_eq_with(b.into(), quirks).unwrap();
+        let ne = |a: &str, b: &str, quirks: &EqualityQuirks| Value::from(a).gml_ne_with(b.into(), quirks).unwrap();
+        let trim = EqualityQuirks { trim_strings: true, ..Default::default() };
+        assert!(!eq(" hi ", "hi", &Default::default()).is_truthy());
+        assert!(ne(" hi ", "hi", &Default::default()).is_truthy());
+        assert!(eq(" hi ", "hi", &trim).is_truthy());
+        assert!(!ne(" hi ", "hi", &trim).is_truthy());
+        assert!(eq("\thi\r\n", "hi", &trim).is_truthy());
+        assert!(eq("   ", "", &trim).is_truthy());
+        assert!(!eq("h i", "hi", &trim).is_truthy());
+    }
+
+    #[test]
+    fn explain_cmp() {
+        let explanation = Value::from(0.1 + 0.2).explain_cmp(&Value::from(0.3), Operator::Equal);
+        assert!(explanation.starts_with("0.30000000000000004 == 0.3 is true"), "{}", explanation);
+        assert!(explanation.contains("within the epsilon of 1e-13"), "{}", explanation);
+
+        let explanation = Value::from(1.0).explain_cmp(&Value::from(2.0), Operator::GreaterThanOrEqual);
+        assert_eq!(explanation, "1 >= 2 is false: 1 is less than 2 by 1e0, more than the epsilon of 1e-13");
+        let explanation = Value::from(1.0).explain_cmp(&Value::from(1.0 + 1e-15), Operator::LessThan);
+        assert!(explanation.starts_with("1 < 1.000000000000001 is false"), "{}", explanation);
+
+        let explanation = Value::from("abc").explain_cmp(&Value::from("abd"), Operator::LessThan);
+        assert_eq!(explanation, "\"abc\" < \"abd\" is true: the strings first differ at character 3 (0x63 vs 0x64)");
+        let explanation = Value::from("ab").explain_cmp(&Value::from("abc"), Operator::Equal);
+        assert!(explanation.ends_with("match up to character 2, where one of them ends"), "{}", explanation);
+
+        let explanation = Value::from(1.0).explain_cmp(&Value::from("1"), Operator::Equal);
+        assert!(explanation.starts_with("1 == \"1\" is an error"), "{}", explanation);
+    }
+
+    #[test]
+    fn bit_eq() {
+        let (zero, neg_zero) = (Value::from(0.0), Value::from(-0.0));
+        assert!(zero.clone().gml_eq(neg_zero.clone()).unwrap().is_truthy());
+        assert!(!zero.bit_eq(&neg_zero));
+        assert!(zero.bit_eq(&Value::from(0.0)));
+
+        let nan = Value::from(f64::NAN);
+        assert!(!nan.clone().gml_eq(nan.clone()).unwrap().is_truthy());
+        assert!(nan.bit_eq(&nan));
+        assert!(!nan.bit_eq(&Value::from(-f64::NAN)));
+
+        // no epsilon
+        assert!(Value::from(0.1 + 0.2).gml_eq(Value::from(0.3)).unwrap().is_truthy());
+        assert!(!Value::from(0.1 + 0.2).bit_eq(&Value::from(0.3)));
+
+        assert!(Value::from("a").bit_eq(&Value::from("a")));
+        assert!(!Value::from("1").bit_eq(&Value::from(1.0)));
+    }
+
+    #[test]
+    fn as_colour() {
+        assert_eq!(Value::from(0x123456).as_colour(), 0x123456);
+        assert_eq!(Value::from(0x1FFFFFF).as_colour(), 0xFFFFFF);
+        assert_eq!(Value::from(0x1000001).as_colour(), 0x000001);
+        assert_eq!(Value::from(-1).as_colour(), 0xFFFFFF);
+        assert_eq!(Value::from(-0x100).as_colour(), 0xFFFF00);
+        assert_eq!(Value::from(254.6).as_colour(), 255);
+        assert_eq!(Value::from("255").as_colour(), 0);
+    }
+
+    #[test]
+    fn compare_same_string() {
+        type Op = fn(Value, Value) -> gml::Result<Value>;
+        let ops: [Op; 6] =
+            [Value::gml_eq, Value::gml_ne, Value::gml_lt, Value::gml_lte, Value::gml_gt, Value::gml_gte];
+        let idle = Value::from("idle");
+        for op in ops.iter() {
+            // the same string gives the same result as an equal copy of it
+            let same = op(idle.clone(), idle.clone()).unwrap().is_truthy();
+            assert_eq!(same, op(idle.clone(), Value::from("idle")).unwrap().is_truthy());
+            let (undefined, empty) = (Value::undefined(), Value::from(""));
+            let undefined_same = op(undefined.clone(), undefined).unwrap().is_truthy();
+            assert_eq!(undefined_same, op(empty.clone(), empty).unwrap().is_truthy());
+        }
+    }
+}
+
+#[cfg(test)]
+mod bench {
+    use super::*;
+    use test::Bencher;
+
+    // a state compared against a state machine's constants, as `if state == "idle"` chains do each frame
+    // it's either the constant itself or an equal copy, which has to be compared byte by byte
+    fn compare_constants(b: &mut Bencher, same: bool) {
+        let names = ["idle", "walking", "running", "jumping", "falling", "attacking", "hurt", "dead"];
+        let constants = names.iter().map(|s| Value::from(s.repeat(8).as_str())).collect::<Vec<_>>();
+        let state = if same { constants[7].clone() } else { Value::from(names[7].repeat(8).as_str()) };
+        b.iter(|| constants.iter().filter(|c| state.clone().gml_eq((*c).clone()).unwrap().is_truthy()).count());
+    }
+
+    #[bench]
+    fn compare_same_string(b: &mut Bencher) {
+        compare_constants(b, true);
+    }
+
+    #[bench]
+    fn compare_equal_copy(b: &mut Bencher) {
+        compare_constants(b, false);
+    }
 }
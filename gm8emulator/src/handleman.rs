@@ -37,7 +37,7 @@ impl error::Error for Error {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Hash, Serialize, Deserialize)]
 pub struct HandleList<T>(Vec<Option<T>>);
 
 #[derive(Debug)]
@@ -59,6 +59,11 @@ impl<T> HandleList<T> {
     pub fn put(&mut self, handle: T) -> i32 {
         self.add(handle).unwrap()
     }
+
+    /// Iterates over all live handles in index order.
+    pub fn iter(&self) -> impl Iterator<Item = (i32, &T)> {
+        self.0.iter().enumerate().filter_map(|(i, x)| x.as_ref().map(|x| (i as i32, x)))
+    }
 }
 
 impl<T, const LEN: usize> HandleArray<T, LEN> {
@@ -1,6 +1,6 @@
 use crate::{
     asset::{Object, Sprite},
-    gml::{InstanceVariable, Value},
+    gml::{mappings, Compiler, InstanceVariable, Value},
     math::Real,
     types::ID,
     util,
@@ -10,9 +10,13 @@ use std::{
     cell::{Cell, RefCell},
     collections::{HashMap, HashSet},
     f64,
+    hash::{Hash, Hasher},
     rc::Rc,
 };
 
+// Number of elements in each row of a 2D array. GM8 stores array[i, j] at index i * ARRAY_ROW + j
+pub const ARRAY_ROW: u32 = 32000;
+
 // Default in GameMaker 8
 const BBOX_DEFAULT: i32 = -100000;
 // Rust can't represent this many decimal places yet I think. In GM8 it's a TBYTE definition
@@ -350,6 +354,11 @@ impl Instance {
 }
 
 impl Field {
+    /// Flattens a 2D array index the same way GM8 does.
+    pub fn index_2d(index1: u32, index2: u32) -> u32 {
+        index1 * ARRAY_ROW + index2
+    }
+
     pub fn new(index: u32, value: Value) -> Self {
         match index {
             0 => Self::Single(value),
@@ -361,13 +370,24 @@ impl Field {
         }
     }
 
+    /// Frees every value in this field, as Value::recycle does.
+    pub fn recycle(self) {
+        match self {
+            Self::Single(value) => value.recycle(),
+            Self::Array(array) => array.into_iter().for_each(|(_, value)| value.recycle()),
+        }
+    }
+
+    /// Arrays are sparse, as in GM8. An unwritten index reads as 0 if a higher index in the same row has been written,
+    /// otherwise it doesn't exist and the variable is treated as unknown.
     pub fn get(&self, index: u32) -> Option<Value> {
         match (self, index) {
             (Self::Single(v), 0) => Some(v.clone()),
             (Self::Array(m), i) => match m.get(&i) {
                 Some(v) => Some(v.clone()),
                 None => {
-                    if m.iter().any(|(k, _)| *k > i && *k < (((i / 32000) + 1) * 32000)) {
+                    let row_end = (i / ARRAY_ROW + 1) * ARRAY_ROW;
+                    if m.keys().any(|k| *k > i && *k < row_end) {
                         Some(Value::Real(Real::from(0.0)))
                     } else {
                         None
@@ -378,6 +398,20 @@ impl Field {
         }
     }
 
+    /// Gets the length of a row of the array: one more than the highest index written in it, with any unwritten
+    /// indices below that counted too, since they read as 0. A row nothing has been written to has a length of 0.
+    pub fn row_length(&self, row: u32) -> u32 {
+        match self {
+            Self::Single(_) => (row == 0).into(),
+            Self::Array(m) => m
+                .keys()
+                .filter(|k| **k / ARRAY_ROW == row)
+                .map(|k| k % ARRAY_ROW + 1)
+                .max()
+                .unwrap_or(0),
+        }
+    }
+
     pub fn set(&mut self, index: u32, value: Value) {
         match self {
             Self::Single(v) => match index {
@@ -398,8 +432,259 @@ impl Field {
     }
 }
 
+impl Hash for Field {
+    /// Array elements are hashed in index order, so the result doesn't depend on HashMap iteration order.
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self {
+            Self::Single(v) => {
+                state.write_u8(0);
+                v.hash(state);
+            },
+            Self::Array(m) => {
+                state.write_u8(1);
+                let mut entries = m.iter().collect::<Vec<_>>();
+                entries.sort_unstable_by_key(|(k, _)| **k);
+                entries.hash(state);
+            },
+        }
+    }
+}
+
 impl DummyFieldHolder {
     pub fn new() -> Self {
         Self { fields: HashMap::new(), vars: HashMap::new() }
     }
+
+    /// Checks whether a variable has been set, looking it up by name the same way compiled code would.
+    pub fn contains_name(&self, compiler: &Compiler, name: &[u8]) -> bool {
+        match mappings::get_instance_variable_by_name(name) {
+            Some(var) => self.vars.contains_key(var),
+            None => compiler.find_field_id(name).map_or(false, |i| self.fields.contains_key(&i)),
+        }
+    }
+
+    /// Gets a variable by name. Built-in variable names refer to the built-in variable, and anything else refers to
+    /// the same field as compiled code using that name, so `global.foo` and `variable_global_get("foo")` agree.
+    pub fn get_by_name(&self, compiler: &Compiler, name: &[u8], index: u32) -> Option<Value> {
+        let field = match mappings::get_instance_variable_by_name(name) {
+            Some(var) => self.vars.get(var),
+            None => compiler.find_field_id(name).and_then(|i| self.fields.get(&i)),
+        };
+        field.and_then(|f| f.get(index))
+    }
+
+    /// Sets a variable by name, creating it if it doesn't exist yet.
+    pub fn set_by_name(&mut self, compiler: &mut Compiler, name: &[u8], index: u32, value: Value) {
+        if let Some(var) = mappings::get_instance_variable_by_name(name) {
+            if let Some(field) = self.vars.get_mut(var) {
+                field.set(index, value);
+            } else {
+                self.vars.insert(*var, Field::new(index, value));
+            }
+        } else {
+            let field_id = compiler.get_field_id(name);
+            if let Some(field) = self.fields.get_mut(&field_id) {
+                field.set(index, value);
+            } else {
+                self.fields.insert(field_id, Field::new(index, value));
+            }
+        }
+    }
+}
+
+impl Hash for Instance {
+    /// Covers everything GML can read back from an instance. The bounding box is left out since it's
+    /// derived from the sprite, mask and transform, and may be stale.
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.get().hash(state);
+        self.object_index.get().hash(state);
+        self.solid.get().hash(state);
+        self.visible.get().hash(state);
+        self.persistent.get().hash(state);
+        self.sprite_index.get().hash(state);
+        self.image_blend.get().hash(state);
+        self.mask_index.get().hash(state);
+        self.path_index.get().hash(state);
+        self.path_endaction.get().hash(state);
+        self.timeline_index.get().hash(state);
+        self.timeline_running.get().hash(state);
+        self.timeline_loop.get().hash(state);
+        for real in &[
+            &self.depth,
+            &self.image_alpha,
+            &self.image_index,
+            &self.image_speed,
+            &self.image_xscale,
+            &self.image_yscale,
+            &self.image_angle,
+            &self.direction,
+            &self.friction,
+            &self.gravity,
+            &self.gravity_direction,
+            &self.hspeed,
+            &self.vspeed,
+            &self.speed,
+            &self.x,
+            &self.y,
+            &self.xprevious,
+            &self.yprevious,
+            &self.xstart,
+            &self.ystart,
+            &self.path_position,
+            &self.path_positionprevious,
+            &self.path_speed,
+            &self.path_scale,
+            &self.path_orientation,
+            &self.path_xstart,
+            &self.path_ystart,
+            &self.timeline_speed,
+            &self.timeline_position,
+        ] {
+            state.write_u64(real.get().canonical_bits());
+        }
+
+        let alarms = self.alarms.borrow();
+        let mut alarms = alarms.iter().collect::<Vec<_>>();
+        alarms.sort_unstable();
+        alarms.hash(state);
+        let fields = self.fields.borrow();
+        let mut fields = fields.iter().collect::<Vec<_>>();
+        fields.sort_unstable_by_key(|(k, _)| **k);
+        fields.hash(state);
+    }
+}
+
+impl Hash for DummyFieldHolder {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let mut fields = self.fields.iter().collect::<Vec<_>>();
+        fields.sort_unstable_by_key(|(k, _)| **k);
+        fields.hash(state);
+        let mut vars = self.vars.iter().collect::<Vec<_>>();
+        vars.sort_unstable_by_key(|(k, _)| **k as u32);
+        vars.hash(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn real(field: &Field, index: u32) -> Option<f64> {
+        field.get(index).map(|v| match v {
+            Value::Real(r) => r.into_inner(),
+            Value::Str(_) => panic!("expected a real at index {}", index),
+        })
+    }
+
+    #[test]
+    fn array_unwritten_index() {
+        let field = Field::new(10, Value::Real(Real::from(5.0)));
+        assert_eq!(real(&field, 10), Some(5.0));
+        // Anything below the highest written index in the row reads as 0
+        assert_eq!(real(&field, 0), Some(0.0));
+        assert_eq!(real(&field, 3), Some(0.0));
+        assert_eq!(real(&field, 11), None);
+
+        let field = Field::new(0, Value::Real(Real::from(1.0)));
+        assert_eq!(real(&field, 1), None);
+    }
+
+    #[test]
+    fn array_sparse_high_index() {
+        let mut field = Field::new(0, Value::Real(Real::from(1.0)));
+        field.set(31999, Value::Str(b"end".as_ref().into()));
+        match &field {
+            Field::Array(m) => assert_eq!(m.len(), 2),
+            Field::Single(_) => panic!("field should have become an array"),
+        }
+        assert_eq!(real(&field, 0), Some(1.0));
+        assert_eq!(real(&field, 16000), Some(0.0));
+        match field.get(31999) {
+            Some(Value::Str(s)) => assert_eq!(s.as_ref(), b"end"),
+            _ => panic!("expected a string at index 31999"),
+        }
+    }
+
+    #[test]
+    fn array_2d_rows() {
+        let mut field = Field::new(Field::index_2d(1, 5), Value::Real(Real::from(2.0)));
+        assert_eq!(real(&field, Field::index_2d(1, 5)), Some(2.0));
+        assert_eq!(real(&field, Field::index_2d(1, 2)), Some(0.0));
+        // Rows don't spill into each other
+        assert_eq!(real(&field, Field::index_2d(0, 7)), None);
+        assert_eq!(real(&field, Field::index_2d(1, 6)), None);
+        assert_eq!(real(&field, Field::index_2d(2, 0)), None);
+
+        // array[i] is the same as array[0, i]
+        field.set(4, Value::Real(Real::from(3.0)));
+        assert_eq!(real(&field, Field::index_2d(0, 4)), Some(3.0));
+        assert_eq!(real(&field, Field::index_2d(0, 1)), Some(0.0));
+    }
+
+    #[test]
+    fn array_row_length() {
+        let mut field = Field::new(0, Value::Real(Real::from(1.0)));
+        assert_eq!(field.row_length(0), 1);
+        assert_eq!(field.row_length(1), 0);
+
+        // holes below the highest index count towards the length
+        field.set(9, Value::Real(Real::from(2.0)));
+        field.set(4, Value::Real(Real::from(3.0)));
+        assert_eq!(field.row_length(0), 10);
+
+        field.set(Field::index_2d(3, 31999), Value::Real(Real::from(4.0)));
+        field.set(Field::index_2d(3, 2), Value::Real(Real::from(5.0)));
+        assert_eq!(field.row_length(3), 32000);
+        assert_eq!(field.row_length(2), 0);
+        assert_eq!(field.row_length(0), 10);
+
+        let field = Field::new(Field::index_2d(1, 6), Value::Real(Real::from(6.0)));
+        assert_eq!(field.row_length(0), 0);
+        assert_eq!(field.row_length(1), 7);
+    }
+
+    #[test]
+    fn globals_by_name() {
+        let mut compiler = Compiler::new();
+        let mut globals = DummyFieldHolder::new();
+        assert!(!globals.contains_name(&compiler, b"foo"));
+        assert!(globals.get_by_name(&compiler, b"foo", 0).is_none());
+
+        globals.set_by_name(&mut compiler, b"foo", 0, Value::Real(Real::from(5.0)));
+        assert!(globals.contains_name(&compiler, b"foo"));
+        assert_eq!(globals.get_by_name(&compiler, b"foo", 0).map(f64::from), Some(5.0));
+
+        // compiled code writing global.foo uses the same field id
+        let field_id = compiler.get_field_id(b"foo");
+        globals.fields.get_mut(&field_id).unwrap().set(2, Value::Real(Real::from(7.0)));
+        assert_eq!(globals.get_by_name(&compiler, b"foo", 2).map(f64::from), Some(7.0));
+        assert_eq!(globals.get_by_name(&compiler, b"foo", 1).map(f64::from), Some(0.0));
+
+        // built-in variable names go to the built-in variable, not to a field of the same name
+        globals.set_by_name(&mut compiler, b"x", 0, Value::Real(Real::from(1.0)));
+        assert!(compiler.find_field_id(b"x").is_none());
+        assert!(globals.vars.contains_key(&InstanceVariable::X));
+        assert_eq!(globals.get_by_name(&compiler, b"x", 0).map(f64::from), Some(1.0));
+    }
+
+    #[test]
+    fn room_change_recycles_strings() {
+        let mut list = crate::instancelist::InstanceList::new();
+        let handle = list.insert(Instance::new_dummy(None));
+        let name = Value::from("old room");
+        let ptr = match &name {
+            Value::Str(s) => s.as_ptr(),
+            Value::Real(_) => unreachable!(),
+        };
+        list.get(handle).fields.borrow_mut().insert(0, Field::Single(name));
+        list.get(handle).fields.borrow_mut().insert(1, Field::new(3, Value::from("kept")));
+        list.recycle_all();
+        assert_eq!(list.count_all(), 0);
+
+        // freed strings of the same length get their memory back
+        match Value::from("new room") {
+            Value::Str(s) => assert_eq!(s.as_ptr(), ptr),
+            Value::Real(_) => unreachable!(),
+        }
+    }
 }
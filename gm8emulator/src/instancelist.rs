@@ -434,6 +434,13 @@ impl InstanceList {
         }
         instances
     }
+
+    /// Removes every instance, recycling the strings in their variables so new ones can reuse the memory.
+    pub fn recycle_all(&mut self) {
+        for instance in self.remove_as_vec(|_| true) {
+            instance.fields.into_inner().into_iter().for_each(|(_, field)| field.recycle());
+        }
+    }
 }
 
 #[derive(Clone, Deserialize)]
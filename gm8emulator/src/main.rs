@@ -1,4 +1,8 @@
 #![feature(bindings_after_at, seek_stream_len)]
+#![cfg_attr(test, feature(test))]
+
+#[cfg(test)]
+extern crate test;
 
 mod action;
 mod asset;
@@ -15,8 +19,10 @@ mod tile;
 mod types;
 mod util;
 
+use asset::font;
 use game::{
     savestate::{self, SaveState},
+    statehash::StateHashLog,
     Game, PlayType, Replay,
 };
 use std::{
@@ -56,6 +62,13 @@ fn xmain() -> i32 {
     opts.optopt("n", "project-name", "name of TAS project to create or load", "NAME");
     opts.optopt("f", "replay-file", "path to savestate file to replay", "FILE");
     opts.optopt("o", "output-file", "output savestate name in replay mode", "FILE.bin");
+    opts.optopt("H", "hash-log", "writes the state hash after each frame in replay mode", "FILE");
+    opts.optopt("c", "check-hashes", "finds the first frame in replay mode whose hash differs from a -H log", "FILE");
+    opts.optopt("d", "max-call-depth", "how deeply code and events can nest before erroring", "DEPTH");
+    opts.optflag("u", "undefined", "failed lookups return undefined instead of 0");
+    opts.optflag("g", "graphemes", "string_length and string_char_at count grapheme clusters, such as emoji, as one");
+    opts.optopt("w", "font-weight", "makes font glyphs bolder, or lighter if negative, by this many pixels", "PIXELS");
+    opts.optflag("m", "alpha-fonts", "stores font glyphs as alpha only, using a quarter of the texture memory");
     opts.optmulti("a", "game-arg", "argument to pass to the game", "ARG");
 
     let matches = match opts.parse(&args[1..]) {
@@ -83,7 +96,31 @@ fn xmain() -> i32 {
     let spoof_time = !matches.opt_present("r");
     let frame_limiter = !matches.opt_present("l");
     let verbose = matches.opt_present("v");
+    let undefined_lookups = matches.opt_present("u");
+    let grapheme_strings = matches.opt_present("g");
     let output_bin = matches.opt_str("o").map(PathBuf::from);
+    let hash_log = matches.opt_str("H").map(PathBuf::from);
+    let check_hashes = match matches.opt_str("c").map(|f| StateHashLog::from_file(Path::new(&f))).transpose() {
+        Ok(log) => log,
+        Err(e) => {
+            eprintln!("couldn't read hash log for -c: {}", e);
+            return EXIT_FAILURE
+        },
+    };
+    let max_call_depth = match matches.opt_str("d").map(|d| d.parse::<usize>()).transpose() {
+        Ok(depth) => depth,
+        Err(e) => {
+            eprintln!("invalid call depth for -d: {}", e);
+            return EXIT_FAILURE
+        },
+    };
+    let font_options = match matches.opt_str("w").map(|w| w.parse::<i32>()).transpose() {
+        Ok(weight) => font::LoadOptions { weight: weight.unwrap_or(0), alpha_only: matches.opt_present("m") },
+        Err(e) => {
+            eprintln!("invalid font weight for -w: {}", e);
+            return EXIT_FAILURE
+        },
+    };
     let project_path = matches.opt_str("n").map(|name| {
         let mut p = env::current_dir().expect("std::env::current_dir() failed");
         p.push("projects");
@@ -219,14 +256,28 @@ fn xmain() -> i32 {
         PlayType::Normal
     };
 
-    let mut components =
-        match Game::launch(assets, absolute_path, game_args, temp_dir, encoding, frame_limiter, play_type) {
-            Ok(g) => g,
-            Err(e) => {
-                eprintln!("Failed to launch game: {}", e);
-                return EXIT_FAILURE
-            },
-        };
+    let mut components = match Game::launch(
+        assets,
+        absolute_path,
+        game_args,
+        temp_dir,
+        encoding,
+        frame_limiter,
+        font_options,
+        play_type,
+    ) {
+        Ok(g) => g,
+        Err(e) => {
+            eprintln!("Failed to launch game: {}", e);
+            return EXIT_FAILURE
+        },
+    };
+
+    if let Some(depth) = max_call_depth {
+        components.call_depth.limit = depth;
+    }
+    components.undefined_lookups = undefined_lookups;
+    components.grapheme_strings = grapheme_strings;
 
     let time_now = gml::datetime::now_as_nanos();
 
@@ -248,7 +299,26 @@ fn xmain() -> i32 {
             .map(|i| PathBuf::from(components.decode_str(i.name.as_ref()).into_owned()))
             .collect::<Vec<_>>();
         let result = if let Some(replay) = replay {
-            components.replay(replay, output_bin)
+            let mut hashes = (hash_log.is_some() || check_hashes.is_some()).then(StateHashLog::new);
+            let result = components.replay(replay, output_bin, hashes.as_mut());
+            // the hashes are still worth having if the game crashed, since that may be where it desynced
+            if let Some(hashes) = &hashes {
+                if let Some(path) = &hash_log {
+                    if let Err(e) = hashes.to_file(path) {
+                        eprintln!("couldn't write hash log {:?}: {}", path, e);
+                    }
+                }
+                if let Some(expected) = &check_hashes {
+                    match expected.first_divergence(hashes) {
+                        Some(frame) => println!("State hash first differs from the log at frame {}", frame),
+                        None => println!(
+                            "State hashes match the log for {} frames",
+                            hashes.hashes().len().min(expected.hashes().len())
+                        ),
+                    }
+                }
+            }
+            result
         } else {
             components.spoofed_time_nanos = if spoof_time { Some(time_now) } else { None };
             components.run()
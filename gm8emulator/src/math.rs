@@ -248,6 +248,11 @@ impl Real {
     /// The lenience between values when compared.
     pub const CMP_EPSILON: Self = Self(1e-13);
 
+    #[inline(always)]
+    pub const fn new(value: f64) -> Self {
+        Self(value)
+    }
+
     #[inline(always)]
     pub fn into_inner(self) -> f64 {
         self.0
@@ -304,6 +309,33 @@ impl Real {
         Self(self.0.fract())
     }
 
+    /// Gets the sine and cosine of an angle in degrees. The angle is reduced to within a quadrant first, so multiples
+    /// of 90 degrees give exactly 0 and ±1, and it's exactly ±0.5 where it should be at 30 and 60 degrees.
+    pub fn sin_cos_degrees(self) -> (Self, Self) {
+        let degrees = self.0.rem_euclid(360.0);
+        let quadrant = (degrees / 90.0).floor();
+        let (sin, cos) = match degrees - quadrant * 90.0 {
+            r if r == 0.0 => (0.0, 1.0),
+            r if r == 30.0 => (0.5, r.to_radians().cos()),
+            r if r == 60.0 => (r.to_radians().sin(), 0.5),
+            r => r.to_radians().sin_cos(),
+        };
+        // subtracting from 0.0 rather than negating so that cos(90) is 0.0, not -0.0
+        let (sin, cos) = match quadrant as i32 & 3 {
+            0 => (sin, cos),
+            1 => (cos, 0.0 - sin),
+            2 => (0.0 - sin, 0.0 - cos),
+            _ => (0.0 - cos, sin),
+        };
+        (Self(sin), Self(cos))
+    }
+
+    /// Gets the signed difference from one angle to another in degrees, in the range (-180, 180].
+    pub fn angle_difference(self, other: Self) -> Self {
+        let difference = (self.0 - other.0).rem_euclid(360.0);
+        Self(if difference > 180.0 { difference - 360.0 } else { difference })
+    }
+
     #[inline(always)]
     pub fn to_radians(self) -> Self {
         Self(self.0.to_radians())
@@ -334,6 +366,19 @@ impl Real {
         Self(self.0.rem_euclid(other.0))
     }
 
+    /// Bit pattern of this value with all zeroes and NaNs folded into a single representative each,
+    /// so values which can't be told apart by GML always produce the same bits.
+    #[inline]
+    pub fn canonical_bits(self) -> u64 {
+        if self.0.is_nan() {
+            f64::NAN.to_bits()
+        } else if self.0 == 0.0 {
+            0
+        } else {
+            self.0.to_bits()
+        }
+    }
+
     #[inline]
     pub fn cmp_nan_first(&self, other: &Self) -> Ordering {
         if self.0.is_nan() {
@@ -347,6 +392,17 @@ impl Real {
         }
         self.partial_cmp(other).unwrap()
     }
+
+    /// A total order with every NaN after every number, for sorting values that might hold NaN deterministically.
+    #[inline]
+    pub fn cmp_nan_last(&self, other: &Self) -> Ordering {
+        match (self.0.is_nan(), other.0.is_nan()) {
+            (true, true) => Ordering::Equal,
+            (true, false) => Ordering::Greater,
+            (false, true) => Ordering::Less,
+            (false, false) => self.partial_cmp(other).unwrap(),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -448,6 +504,14 @@ mod tests {
         }
     }
 
+    #[test]
+    fn canonical_bits() {
+        assert_eq!(Real(0.0).canonical_bits(), Real(-0.0).canonical_bits());
+        assert_eq!(Real(f64::NAN).canonical_bits(), Real(-f64::NAN).canonical_bits());
+        assert_eq!(Real(f64::NAN).canonical_bits(), Real(f64::from_bits(0x7FF0_0000_0000_0001)).canonical_bits());
+        assert_ne!(Real(1.0).canonical_bits(), Real(-1.0).canonical_bits());
+    }
+
     #[test]
     fn sin() {
         assert_eq!(Real(PI / 2.0).sin(), Real(1.0));
@@ -525,4 +589,35 @@ mod tests {
     fn to_radians() {
         assert_eq!(Real(180.0).to_radians(), Real(std::f64::consts::PI));
     }
+
+    #[test]
+    fn sin_cos_degrees() {
+        let exact = |degrees: f64| {
+            let (sin, cos) = Real(degrees).sin_cos_degrees();
+            (sin.0, cos.0)
+        };
+        assert_eq!(exact(30.0).0, 0.5);
+        assert_eq!(exact(60.0).1, 0.5);
+        assert_eq!(exact(90.0), (1.0, 0.0));
+        assert_eq!(exact(180.0), (0.0, -1.0));
+        assert_eq!(exact(-90.0), (-1.0, 0.0));
+        assert_eq!(exact(150.0).0, 0.5);
+        assert_eq!(exact(-330.0).0, 0.5);
+        assert_eq!(exact(720.0 + 210.0).0, -0.5);
+        for &degrees in &[12.5, 45.0, 100.0, 275.0, -33.0] {
+            let ((sin, cos), radians) = (exact(degrees), degrees.to_radians());
+            assert!((sin - radians.sin()).abs() < 1e-15 && (cos - radians.cos()).abs() < 1e-15);
+        }
+        assert!(exact(f64::NAN).0.is_nan());
+    }
+
+    #[test]
+    fn angle_difference() {
+        let diff = |a: f64, b: f64| Real(a).angle_difference(Real(b)).0;
+        assert_eq!(diff(10.0, 350.0), 20.0);
+        assert_eq!(diff(350.0, 10.0), -20.0);
+        assert_eq!(diff(0.0, 180.0), 180.0);
+        assert_eq!(diff(180.0, 0.0), 180.0);
+        assert_eq!(diff(90.0, 90.0 + 720.0), 0.0);
+    }
 }
@@ -187,8 +187,15 @@ pub trait RendererTrait {
         origin_x: i32,
         origin_y: i32,
     ) -> Result<AtlasRef, String>;
+    fn upload_deferred(&mut self, atlas_ref: &AtlasRef) -> Result<(), String>;
+    fn is_deferred(&self, atlas_ref: &AtlasRef) -> bool;
     fn duplicate_sprite(&mut self, atlas_ref: &AtlasRef) -> Result<AtlasRef, String>;
+    fn update_sprite(&mut self, atlas_ref: &AtlasRef, data: Box<[u8]>) -> Result<(), String>;
     fn delete_sprite(&mut self, atlas_ref: AtlasRef);
+    /// Gets the size of an atlas, or None if there's no atlas with that ID.
+    fn atlas_size(&self, atlas_id: u32) -> Option<(i32, i32)>;
+    /// Gets the fraction of an atlas covered by textures which haven't been deleted, or 0 if there's no such atlas.
+    fn atlas_occupancy(&self, atlas_id: u32) -> f32;
 
     /// Resizes the rendering target. Usually called when the window has been resized.
     /// The contents of the old framebuffer will be copied to the new one. If `store` is true, the old one
@@ -299,6 +306,17 @@ pub trait RendererTrait {
     fn finish(&mut self, window_width: u32, window_height: u32, clear_colour: Colour);
 
     fn dump_sprite(&self, atlas_ref: &AtlasRef) -> Box<[u8]>;
+    /// Reads back the pixels of several sprites, reading each cluster of them in an atlas all at once.
+    fn dump_sprites(&self, atlas_refs: &[AtlasRef]) -> Vec<Box<[u8]>> {
+        let mut out = vec![Box::default(); atlas_refs.len()];
+        for (region, indices) in atlas::readback_regions(atlas_refs) {
+            let data = self.dump_sprite(&region);
+            for i in indices {
+                out[i] = atlas::crop_region(&region, &data, &atlas_refs[i]);
+            }
+        }
+        out
+    }
     fn dump_sprite_part(&self, texture: &AtlasRef, part_x: i32, part_y: i32, part_w: i32, part_h: i32) -> Box<[u8]> {
         self.dump_sprite(&AtlasRef {
             atlas_id: texture.atlas_id,
@@ -317,6 +335,8 @@ pub trait RendererTrait {
     fn set_blend_mode(&mut self, src: BlendType, dst: BlendType);
     fn get_pixel_interpolation(&self) -> bool;
     fn set_pixel_interpolation(&mut self, lerping: bool);
+    fn get_mipmapping(&self) -> bool;
+    fn set_mipmapping(&mut self, mipmaps: bool);
     fn get_texture_repeat(&self) -> bool;
     fn set_texture_repeat(&mut self, repeat: bool);
 
@@ -556,14 +576,67 @@ impl Renderer {
         self.0.upload_sprite(data, width, height, origin_x, origin_y)
     }
 
+    /// Uploads the group of textures which the given one was packed into with `AtlasBuilder::deferred_texture`,
+    /// if they're still waiting.
+    pub fn upload_deferred(&mut self, atlas_ref: &AtlasRef) -> Result<(), String> {
+        self.0.upload_deferred(atlas_ref)
+    }
+
+    /// Checks whether a texture packed with `AtlasBuilder::deferred_texture` is still waiting to be uploaded.
+    pub fn is_deferred(&self, atlas_ref: &AtlasRef) -> bool {
+        self.0.is_deferred(atlas_ref)
+    }
+
     pub fn duplicate_sprite(&mut self, atlas_ref: &AtlasRef) -> Result<AtlasRef, String> {
         self.0.duplicate_sprite(atlas_ref)
     }
 
+    /// Uploads several sprites, given as (width, height, data), into one new texture, returning a region of it for
+    /// each. The regions are all the same sprite, so deleting any one of them deletes the whole texture.
+    pub fn upload_sprites(&mut self, sprites: Vec<(i32, i32, Box<[u8]>)>) -> Result<Vec<AtlasRef>, String> {
+        let sizes = sprites.iter().map(|&(w, h, _)| (w, h)).collect::<Vec<_>>();
+        let ((width, height), positions) = atlas::sheet_layout(&sizes, self.max_texture_size() as i32)
+            .ok_or("Too many sprites to upload into one texture")?;
+        let mut sheet = vec![0u8; (width * height * 4) as usize];
+        for ((w, h, data), &(x, y)) in sprites.iter().zip(&positions).filter(|((w, h, _), _)| *w > 0 && *h > 0) {
+            for (row, pixels) in data.chunks_exact(*w as usize * 4).take(*h as usize).enumerate() {
+                let at = ((y as usize + row) * width as usize + x as usize) * 4;
+                sheet[at..at + pixels.len()].copy_from_slice(pixels);
+            }
+        }
+        let sheet = self.upload_sprite(sheet.into_boxed_slice(), width, height, 0, 0)?;
+        Ok(sizes
+            .into_iter()
+            .zip(positions)
+            .map(|((w, h), (x, y))| AtlasRef {
+                x: sheet.x + x,
+                y: sheet.y + y,
+                w,
+                h,
+                origin_x: 0.0,
+                origin_y: 0.0,
+                ..sheet
+            })
+            .collect())
+    }
+
+    /// Overwrites the pixels of an existing sprite in place. The data must be the same size as the sprite.
+    pub fn update_sprite(&mut self, atlas_ref: &AtlasRef, data: Box<[u8]>) -> Result<(), String> {
+        self.0.update_sprite(atlas_ref, data)
+    }
+
     pub fn delete_sprite(&mut self, atlas_ref: AtlasRef) {
         self.0.delete_sprite(atlas_ref)
     }
 
+    pub fn atlas_size(&self, atlas_id: u32) -> Option<(i32, i32)> {
+        self.0.atlas_size(atlas_id)
+    }
+
+    pub fn atlas_occupancy(&self, atlas_id: u32) -> f32 {
+        self.0.atlas_occupancy(atlas_id)
+    }
+
     pub fn set_vsync(&self, vsync: bool) {
         self.0.set_vsync(vsync)
     }
@@ -847,6 +920,10 @@ impl Renderer {
         self.0.dump_sprite(atlas_ref)
     }
 
+    pub fn dump_sprites(&self, atlas_refs: &[AtlasRef]) -> Vec<Box<[u8]>> {
+        self.0.dump_sprites(atlas_refs)
+    }
+
     pub fn dump_sprite_part(
         &self,
         texture: &AtlasRef,
@@ -964,6 +1041,17 @@ impl Renderer {
         self.0.set_pixel_interpolation(lerping)
     }
 
+    /// Gets whether textures from the stock atlases are mipmapped when drawn scaled down.
+    pub fn get_mipmapping(&self) -> bool {
+        self.0.get_mipmapping()
+    }
+
+    /// Sets whether textures from the stock atlases are mipmapped when drawn scaled down. Mipmaps are generated for
+    /// each atlas the first time it's drawn from with this on. Textures uploaded later are never mipmapped.
+    pub fn set_mipmapping(&mut self, mipmaps: bool) {
+        self.0.set_mipmapping(mipmaps)
+    }
+
     pub fn get_texture_repeat(&self) -> bool {
         self.0.get_texture_repeat()
     }
@@ -1,18 +1,216 @@
-use rect_packer::DensePacker;
+use rect_packer::{DensePacker, Rect};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 #[inline]
 fn next_pow2(n: i32) -> i32 {
     2i32.pow((n as f32).log2().ceil() as _)
 }
 
+/// How textures are arranged within each atlas.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PackStrategy {
+    /// rect_packer's DensePacker.
+    Dense,
+    /// Rows of textures, each as tall as the tallest texture in it. Fast and predictable, but wastes the space
+    /// above shorter textures.
+    Shelf,
+    /// Places each texture as low as it can go on the outline of what's been packed so far, filling gaps left by
+    /// textures of differing heights. Best for large sets of small textures, such as big CJK fonts.
+    Skyline,
+}
+
+impl Default for PackStrategy {
+    fn default() -> Self {
+        Self::Dense
+    }
+}
+
+struct Shelf {
+    y: i32,
+    height: i32,
+    x: i32,
+}
+
+pub struct ShelfPacker {
+    width: i32,
+    height: i32,
+    shelves: Vec<Shelf>,
+}
+
+impl ShelfPacker {
+    fn new(width: i32, height: i32) -> Self {
+        Self { width, height, shelves: Vec::new() }
+    }
+
+    fn pack(&mut self, width: i32, height: i32) -> Option<Rect> {
+        // use whichever shelf it fits on with the least height to spare, or else start a new one
+        let atlas_width = self.width;
+        let shelf = self
+            .shelves
+            .iter_mut()
+            .filter(|s| height <= s.height && s.x + width <= atlas_width)
+            .min_by_key(|s| s.height - height);
+        let shelf = match shelf {
+            Some(shelf) => shelf,
+            None => {
+                let y = self.shelves.last().map_or(0, |s| s.y + s.height);
+                if width > self.width || y + height > self.height {
+                    return None
+                }
+                self.shelves.push(Shelf { y, height, x: 0 });
+                self.shelves.last_mut().unwrap()
+            },
+        };
+        let rect = Rect { x: shelf.x, y: shelf.y, width, height };
+        shelf.x += width;
+        Some(rect)
+    }
+}
+
+struct Segment {
+    x: i32,
+    y: i32,
+    width: i32,
+}
+
+pub struct SkylinePacker {
+    width: i32,
+    height: i32,
+    skyline: Vec<Segment>,
+}
+
+impl SkylinePacker {
+    fn new(width: i32, height: i32) -> Self {
+        Self { width, height, skyline: vec![Segment { x: 0, y: 0, width }] }
+    }
+
+    /// Finds the height a texture would rest at if its left edge was placed at the start of the given segment.
+    fn fit(&self, index: usize, width: i32, height: i32) -> Option<i32> {
+        let x = self.skyline[index].x;
+        if x + width > self.width {
+            return None
+        }
+        let y = self.skyline[index..].iter().take_while(|s| s.x < x + width).map(|s| s.y).max()?;
+        if y + height <= self.height { Some(y) } else { None }
+    }
+
+    fn pack(&mut self, width: i32, height: i32) -> Option<Rect> {
+        // bottom-left: the lowest resting place, then the leftmost
+        let (index, y) = (0..self.skyline.len())
+            .filter_map(|i| self.fit(i, width, height).map(|y| (i, y)))
+            .min_by_key(|&(i, y)| (y + height, self.skyline[i].x))?;
+        let x = self.skyline[index].x;
+
+        // raise the outline under the new texture
+        self.skyline.insert(index, Segment { x, y: y + height, width });
+        let right = x + width;
+        while let Some(next) = self.skyline.get_mut(index + 1) {
+            if next.x >= right {
+                break
+            }
+            if next.x + next.width <= right {
+                self.skyline.remove(index + 1);
+            } else {
+                next.width -= right - next.x;
+                next.x = right;
+                break
+            }
+        }
+
+        // merge neighbouring segments at the same height
+        let mut i = 0;
+        while i + 1 < self.skyline.len() {
+            if self.skyline[i].y == self.skyline[i + 1].y {
+                self.skyline[i].width += self.skyline[i + 1].width;
+                self.skyline.remove(i + 1);
+            } else {
+                i += 1;
+            }
+        }
+
+        Some(Rect { x, y, width, height })
+    }
+
+    fn resize(&mut self, width: i32, height: i32) {
+        if width > self.width {
+            self.skyline.push(Segment { x: self.width, y: 0, width: width - self.width });
+        }
+        self.width = width;
+        self.height = height;
+    }
+}
+
+/// The packer for a single atlas, using whichever strategy its builder was given.
+pub enum Packer {
+    Dense(DensePacker),
+    Shelf(ShelfPacker),
+    Skyline(SkylinePacker),
+}
+
+impl Packer {
+    fn new(strategy: PackStrategy, width: i32, height: i32) -> Self {
+        match strategy {
+            PackStrategy::Dense => Self::Dense(DensePacker::new(width, height)),
+            PackStrategy::Shelf => Self::Shelf(ShelfPacker::new(width, height)),
+            PackStrategy::Skyline => Self::Skyline(SkylinePacker::new(width, height)),
+        }
+    }
+
+    pub fn size(&self) -> (i32, i32) {
+        match self {
+            Self::Dense(packer) => packer.size(),
+            Self::Shelf(packer) => (packer.width, packer.height),
+            Self::Skyline(packer) => (packer.width, packer.height),
+        }
+    }
+
+    fn pack(&mut self, width: i32, height: i32) -> Option<Rect> {
+        match self {
+            Self::Dense(packer) => packer.pack(width, height, false),
+            Self::Shelf(packer) => packer.pack(width, height),
+            Self::Skyline(packer) => packer.pack(width, height),
+        }
+    }
+
+    fn resize(&mut self, width: i32, height: i32) {
+        match self {
+            Self::Dense(packer) => packer.resize(width, height),
+            Self::Shelf(packer) => {
+                packer.width = width;
+                packer.height = height;
+            },
+            Self::Skyline(packer) => packer.resize(width, height),
+        }
+    }
+
+    /// Doubles the shorter side, or else the longer one, if that fits within the maximum size.
+    fn grow(&mut self, max_size: i32) -> bool {
+        let (width, height) = self.size();
+        if width <= height && (width * 2) <= max_size {
+            self.resize(width * 2, height);
+        } else if (height * 2) <= max_size {
+            self.resize(width, height * 2);
+        } else {
+            return false
+        }
+        true
+    }
+}
+
 pub struct AtlasBuilder {
     max_size: i32,
-    packers: Vec<DensePacker>,
+    strategy: PackStrategy,
+    packers: Vec<Packer>,
+    /// Which atlases hold only glyphs' alpha, one byte per pixel, rather than RGBA.
+    alpha_only: Vec<bool>,
+    alpha_glyphs: bool,
     textures: Vec<(AtlasRef, Box<[u8]>)>,
+    deferred: HashMap<i32, Vec<(AtlasRef, Box<[u8]>)>>,
+    sprite_count: i32,
 }
 
-#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
 #[repr(C)]
 pub struct AtlasRef {
     pub(super) atlas_id: u32,
@@ -28,10 +226,157 @@ pub struct AtlasRef {
     pub(super) origin_y: f32,
 }
 
+impl AtlasRef {
+    #[inline(always)]
+    pub fn width(&self) -> i32 {
+        self.w
+    }
+
+    #[inline(always)]
+    pub fn height(&self) -> i32 {
+        self.h
+    }
+
+    #[inline(always)]
+    pub fn atlas_id(&self) -> u32 {
+        self.atlas_id
+    }
+
+    /// Checks whether this region lies entirely within an atlas of the given size.
+    pub fn fits_in(&self, width: i32, height: i32) -> bool {
+        let (x_ok, y_ok) = (self.x >= 0 && self.w >= 0, self.y >= 0 && self.h >= 0);
+        x_ok && y_ok && self.x + self.w <= width && self.y + self.h <= height
+    }
+}
+
+/// Lays out textures of the given sizes on one sheet, for copying a set of textures into a single texture of their own.
+/// Returns the size of the sheet and where each texture goes on it, or None if they don't fit within the maximum size.
+/// Empty textures take up no space, and are all put in the corner.
+pub fn sheet_layout(sizes: &[(i32, i32)], max_size: i32) -> Option<((i32, i32), Vec<(i32, i32)>)> {
+    let start = max_size.min(64);
+    let mut packer = Packer::new(PackStrategy::Skyline, start, start);
+    let mut positions = Vec::with_capacity(sizes.len());
+    for &(width, height) in sizes {
+        if width <= 0 || height <= 0 {
+            positions.push((0, 0));
+            continue
+        }
+        let rect = loop {
+            match packer.pack(width, height) {
+                Some(rect) => break rect,
+                None if packer.grow(max_size) => (),
+                None => return None,
+            }
+        };
+        positions.push((rect.x, rect.y));
+    }
+    Some((packer.size(), positions))
+}
+
+/// Groups regions to be read back from the GPU so that textures packed close together in an atlas, such as the frames
+/// of a sprite, can be read in one go instead of one at a time. A group is only read as one region if its bounding box
+/// isn't much bigger than the textures in it. Returns each region to read with the indices of the textures inside it.
+pub fn readback_regions(refs: &[AtlasRef]) -> Vec<(AtlasRef, Vec<usize>)> {
+    let mut atlases: Vec<(u32, Vec<usize>)> = Vec::new();
+    for (i, atlas_ref) in refs.iter().enumerate().filter(|(_, r)| r.w > 0 && r.h > 0) {
+        match atlases.iter_mut().find(|(id, _)| *id == atlas_ref.atlas_id) {
+            Some((_, indices)) => indices.push(i),
+            None => atlases.push((atlas_ref.atlas_id, vec![i])),
+        }
+    }
+    let mut regions = Vec::new();
+    for (atlas_id, indices) in atlases {
+        let left = indices.iter().map(|&i| refs[i].x).min().unwrap();
+        let top = indices.iter().map(|&i| refs[i].y).min().unwrap();
+        let right = indices.iter().map(|&i| refs[i].x + refs[i].w).max().unwrap();
+        let bottom = indices.iter().map(|&i| refs[i].y + refs[i].h).max().unwrap();
+        let area = indices.iter().map(|&i| i64::from(refs[i].w) * i64::from(refs[i].h)).sum::<i64>();
+        if indices.len() > 1 && i64::from(right - left) * i64::from(bottom - top) <= area * 2 {
+            let (w, h) = (right - left, bottom - top);
+            let region = AtlasRef { atlas_id, sprite_id: -1, x: left, y: top, w, h, origin_x: 0.0, origin_y: 0.0 };
+            regions.push((region, indices));
+        } else {
+            regions.extend(indices.into_iter().map(|i| (refs[i], vec![i])));
+        }
+    }
+    regions
+}
+
+/// Gets the area of each of the given number of atlases covered by the given textures.
+pub fn covered_area<'a>(refs: impl IntoIterator<Item = &'a AtlasRef>, atlas_count: usize) -> Vec<i64> {
+    let mut covered = vec![0i64; atlas_count];
+    for atlas_ref in refs {
+        if let Some(area) = covered.get_mut(atlas_ref.atlas_id as usize) {
+            *area += i64::from(atlas_ref.w) * i64::from(atlas_ref.h);
+        }
+    }
+    covered
+}
+
+/// Finds every pair of textures whose regions in the same atlas overlap, which means the packer gave out the same
+/// space twice. Textures with exactly the same region are the same texture used twice, so they don't count.
+/// Returns the indices of each pair, lowest first.
+pub fn overlapping_regions(refs: &[AtlasRef]) -> Vec<(usize, usize)> {
+    let mut order = (0..refs.len()).filter(|&i| refs[i].w > 0 && refs[i].h > 0).collect::<Vec<_>>();
+    order.sort_by_key(|&i| (refs[i].atlas_id, refs[i].x));
+    let mut pairs = Vec::new();
+    for (n, &i) in order.iter().enumerate() {
+        let a = &refs[i];
+        // everything after this in the same atlas starts further right, so stop at the first that starts past it
+        for &j in order[n + 1..].iter().take_while(|&&j| refs[j].atlas_id == a.atlas_id && refs[j].x < a.x + a.w) {
+            let b = &refs[j];
+            let same = (a.x, a.y, a.w, a.h) == (b.x, b.y, b.w, b.h);
+            if !same && b.y < a.y + a.h && a.y < b.y + b.h {
+                pairs.push((i.min(j), i.max(j)));
+            }
+        }
+    }
+    pairs.sort_unstable();
+    pairs
+}
+
+/// Gets the alpha of each pixel of an RGBA texture, if it's white throughout like a font glyph is.
+pub fn alpha_plane(data: &[u8]) -> Option<Box<[u8]>> {
+    if data.chunks_exact(4).all(|p| p[..3] == [0xFF; 3]) {
+        Some(data.chunks_exact(4).map(|p| p[3]).collect())
+    } else {
+        None
+    }
+}
+
+/// Turns the alpha of each pixel back into white RGBA pixels, as an alpha-only atlas is sampled.
+pub fn expand_alpha(alpha: &[u8]) -> Box<[u8]> {
+    alpha.iter().flat_map(|&a| vec![0xFF, 0xFF, 0xFF, a]).collect()
+}
+
+/// Copies a texture's RGBA pixels out of those read back for a region containing it.
+pub fn crop_region(region: &AtlasRef, data: &[u8], atlas_ref: &AtlasRef) -> Box<[u8]> {
+    let (x, y) = ((atlas_ref.x - region.x) as usize, (atlas_ref.y - region.y) as usize);
+    let (stride, width) = (region.w as usize * 4, atlas_ref.w as usize * 4);
+    let mut out = Vec::with_capacity(width * atlas_ref.h as usize);
+    for row in data.chunks_exact(stride).skip(y).take(atlas_ref.h as usize) {
+        out.extend_from_slice(&row[x * 4..x * 4 + width]);
+    }
+    out.into_boxed_slice()
+}
+
 impl AtlasBuilder {
     pub fn new(max_size: i32) -> Self {
+        Self::with_strategy(max_size, PackStrategy::default())
+    }
+
+    pub fn with_strategy(max_size: i32, strategy: PackStrategy) -> Self {
         assert_eq!(max_size, next_pow2(max_size));
-        AtlasBuilder { max_size, packers: Vec::new(), textures: Vec::new() }
+        AtlasBuilder {
+            max_size,
+            strategy,
+            packers: Vec::new(),
+            alpha_only: Vec::new(),
+            alpha_glyphs: false,
+            textures: Vec::new(),
+            deferred: HashMap::new(),
+            sprite_count: 0,
+        }
     }
 
     pub fn texture(
@@ -42,27 +387,68 @@ impl AtlasBuilder {
         origin_y: i32,
         data: Box<[u8]>,
     ) -> Option<AtlasRef> {
-        fn to_texture(
-            id: u32,
-            sprite_id: i32,
-            rect: rect_packer::Rect,
-            data: Box<[u8]>,
-            origin_x: i32,
-            origin_y: i32,
-        ) -> (AtlasRef, Box<[u8]>) {
-            (
-                AtlasRef {
-                    atlas_id: id,
-                    sprite_id,
-                    w: rect.width,
-                    h: rect.height,
-                    x: rect.x,
-                    y: rect.y,
-                    origin_x: (origin_x as f32 / rect.width as f32),
-                    origin_y: (origin_y as f32 / rect.height as f32),
-                },
-                data,
-            )
+        let atlas_ref = self.place(width, height, origin_x, origin_y, false)?;
+        if atlas_ref.sprite_id >= 0 {
+            self.textures.push((atlas_ref, data));
+        }
+        Some(atlas_ref)
+    }
+
+    /// Packs a texture like `texture`, but holds back its pixels until the Renderer is asked to upload the group.
+    /// Its AtlasRef is valid straight away, it just won't have anything in it until then.
+    pub fn deferred_texture(
+        &mut self,
+        group: i32,
+        width: i32,
+        height: i32,
+        origin_x: i32,
+        origin_y: i32,
+        data: Box<[u8]>,
+    ) -> Option<AtlasRef> {
+        let atlas_ref = self.place(width, height, origin_x, origin_y, false)?;
+        if atlas_ref.sprite_id >= 0 {
+            self.deferred.entry(group).or_default().push((atlas_ref, data));
+        }
+        Some(atlas_ref)
+    }
+
+    /// Stores font glyphs packed from now on in alpha-only atlases, a quarter of the size of RGBA ones, which the
+    /// Renderer samples as white with that alpha. Off by default.
+    pub fn set_alpha_glyphs(&mut self, alpha_glyphs: bool) {
+        self.alpha_glyphs = alpha_glyphs;
+    }
+
+    /// Packs a font glyph like `deferred_texture`. If alpha-only glyphs are on and the glyph is white, as glyphs made
+    /// from a system font are, just its alpha is kept, in an alpha-only atlas.
+    pub fn deferred_glyph(&mut self, group: i32, width: i32, height: i32, data: Box<[u8]>) -> Option<AtlasRef> {
+        let alpha = match self.alpha_glyphs {
+            true => alpha_plane(&data),
+            false => None,
+        };
+        match alpha {
+            Some(alpha) => {
+                let atlas_ref = self.place(width, height, 0, 0, true)?;
+                if atlas_ref.sprite_id >= 0 {
+                    self.deferred.entry(group).or_default().push((atlas_ref, alpha));
+                }
+                Some(atlas_ref)
+            },
+            None => self.deferred_texture(group, width, height, 0, 0, data),
+        }
+    }
+
+    fn place(&mut self, width: i32, height: i32, origin_x: i32, origin_y: i32, alpha: bool) -> Option<AtlasRef> {
+        fn to_ref(id: u32, sprite_id: i32, rect: Rect, origin_x: i32, origin_y: i32) -> AtlasRef {
+            AtlasRef {
+                atlas_id: id,
+                sprite_id,
+                w: rect.width,
+                h: rect.height,
+                x: rect.x,
+                y: rect.y,
+                origin_x: (origin_x as f32 / rect.width as f32),
+                origin_y: (origin_y as f32 / rect.height as f32),
+            }
         }
 
         if width <= 0 || height <= 0 {
@@ -82,39 +468,491 @@ impl AtlasBuilder {
             return None
         }
 
-        for (id, packer) in self.packers.iter_mut().enumerate() {
-            if let Some(rect) = packer.pack(width, height, false) {
-                let (atlas_ref, data) = to_texture(id as _, self.textures.len() as _, rect, data, origin_x, origin_y);
-                self.textures.push((atlas_ref.clone(), data));
-                return Some(atlas_ref)
+        let sprite_id = self.sprite_count;
+        let packers = self.packers.iter_mut().zip(&self.alpha_only).enumerate();
+        for (id, (packer, _)) in packers.filter(|(_, (_, &alpha_only))| alpha_only == alpha) {
+            if let Some(rect) = packer.pack(width, height) {
+                self.sprite_count += 1;
+                return Some(to_ref(id as _, sprite_id, rect, origin_x, origin_y))
             } else {
-                loop {
-                    let (pwidth, pheight) = packer.size();
-                    if pwidth <= pheight && (pwidth * 2) <= self.max_size {
-                        packer.resize(pwidth * 2, pheight);
-                    } else if (pheight * 2) <= self.max_size {
-                        packer.resize(pwidth, pheight * 2);
-                    } else {
-                        break
+                while packer.grow(self.max_size) {
+                    if let Some(rect) = packer.pack(width, height) {
+                        self.sprite_count += 1;
+                        return Some(to_ref(id as _, sprite_id, rect, origin_x, origin_y))
                     }
+                }
+            }
+        }
+
+        self.new_atlas(alpha);
+        self.place(width, height, origin_x, origin_y, alpha)
+    }
 
-                    if let Some(rect) = packer.pack(width, height, false) {
-                        let (atlas_ref, data) =
-                            to_texture(id as _, self.textures.len() as _, rect, data, origin_x, origin_y);
-                        self.textures.push((atlas_ref.clone(), data));
-                        return Some(atlas_ref)
+    fn new_atlas(&mut self, alpha: bool) {
+        let size = 4096.min(self.max_size);
+        self.packers.push(Packer::new(self.strategy, size, size));
+        self.alpha_only.push(alpha);
+    }
+
+    /// Makes room up front for about `count` more textures averaging the given size, such as the glyphs of a large
+    /// font, by growing the last atlas and starting new ones now rather than part way through packing them.
+    /// It's only an estimate: anything which doesn't fit is still packed as usual, and reserving too much leaves
+    /// empty atlases behind.
+    pub fn reserve(&mut self, count: usize, size: (i32, i32)) {
+        self.reserve_in(false, count, size)
+    }
+
+    /// Makes room like `reserve` for font glyphs packed with `deferred_glyph`, in whichever atlases they go into.
+    pub fn reserve_glyphs(&mut self, count: usize, size: (i32, i32)) {
+        self.reserve_in(self.alpha_glyphs, count, size)
+    }
+
+    fn reserve_in(&mut self, alpha: bool, count: usize, (avg_width, avg_height): (i32, i32)) {
+        // packing never fills an atlas completely, so leave some room for what's wasted
+        const FILL: f64 = 0.65;
+        if avg_width <= 0 || avg_height <= 0 || avg_width > self.max_size || avg_height > self.max_size {
+            return
+        }
+        let mut needed = count as f64 * f64::from(avg_width) * f64::from(avg_height);
+        let mut last = self.alpha_only.iter().rposition(|&a| a == alpha);
+        let mut used = last.map_or(0, |id| self.used_area()[id]) as f64;
+        let free = |packer: &Packer, used: f64| {
+            let (width, height) = packer.size();
+            f64::from(width) * f64::from(height) * FILL - used
+        };
+        while needed > 0.0 {
+            if let Some(id) = last {
+                let packer = &mut self.packers[id];
+                if free(packer, used) >= needed {
+                    break
+                } else if packer.grow(self.max_size) {
+                    continue
+                }
+                needed -= free(packer, used).max(0.0);
+            }
+            self.new_atlas(alpha);
+            last = Some(self.packers.len() - 1);
+            used = 0.0;
+        }
+    }
+
+    /// The number of atlases textures have been packed into so far.
+    pub fn atlas_count(&self) -> usize {
+        self.packers.len()
+    }
+
+    /// Gets the fraction of each atlas's area that's covered by textures.
+    pub fn occupancy(&self) -> Vec<f64> {
+        self.packers
+            .iter()
+            .zip(self.used_area())
+            .map(|(packer, used)| {
+                let (width, height) = packer.size();
+                used as f64 / (i64::from(width) * i64::from(height)) as f64
+            })
+            .collect()
+    }
+
+    /// Gets the area of each atlas that's covered by textures.
+    fn used_area(&self) -> Vec<i64> {
+        let textures = self.textures.iter().chain(self.deferred.values().flatten());
+        covered_area(textures.map(|(atlas_ref, _)| atlas_ref), self.packers.len())
+    }
+
+    /// Draws everything packed into an atlas so far, deferred textures included, returning its width, height and
+    /// RGBA pixels. If `tint_overlaps` is set, textures which overlap another one are tinted red to stand out.
+    #[cfg(any(test, feature = "atlas-debug"))]
+    pub fn debug_image(&self, atlas_id: u32, tint_overlaps: bool) -> Option<(i32, i32, Vec<u8>)> {
+        let (width, height) = self.packers.get(atlas_id as usize)?.size();
+        let mut data = vec![0u8; (width * height * 4) as usize];
+        let textures = self
+            .textures
+            .iter()
+            .chain(self.deferred.values().flatten())
+            .filter(|(atlas_ref, _)| atlas_ref.atlas_id == atlas_id)
+            .collect::<Vec<_>>();
+        let pixel_rows = |r: AtlasRef| (0..r.h).map(move |y| (((r.y + y) * width + r.x) * 4) as usize);
+        for (atlas_ref, pixels) in textures.iter() {
+            let pixels = match self.alpha_only[atlas_id as usize] {
+                true => expand_alpha(pixels),
+                false => pixels.clone(),
+            };
+            for (start, row) in pixel_rows(*atlas_ref).zip(pixels.chunks_exact(atlas_ref.w as usize * 4)) {
+                data[start..start + row.len()].copy_from_slice(row);
+            }
+        }
+        if tint_overlaps {
+            let refs = textures.iter().map(|(atlas_ref, _)| *atlas_ref).collect::<Vec<_>>();
+            for (i, j) in overlapping_regions(&refs) {
+                for atlas_ref in [refs[i], refs[j]].iter() {
+                    for start in pixel_rows(*atlas_ref) {
+                        for pixel in data[start..start + atlas_ref.w as usize * 4].chunks_exact_mut(4) {
+                            let tinted = [pixel[0] / 2 + 0x80, pixel[1] / 2, pixel[2] / 2, pixel[3].max(0x80)];
+                            pixel.copy_from_slice(&tinted);
+                        }
                     }
                 }
             }
         }
+        Some((width, height, data))
+    }
 
-        let size = 4096.min(self.max_size);
-        self.packers.push(DensePacker::new(size, size));
-        self.texture(width, height, origin_x, origin_y, data)
+    /// Which atlases are alpha-only, holding one byte per pixel instead of four.
+    pub fn alpha_atlases(&self) -> &[bool] {
+        &self.alpha_only
+    }
+
+    /// The number of textures packed so far, deferred or not.
+    pub fn sprite_count(&self) -> i32 {
+        self.sprite_count
     }
 
     #[allow(clippy::type_complexity)] // It's for the Renderer only.
-    pub fn into_inner(self) -> (Vec<DensePacker>, Vec<(AtlasRef, Box<[u8]>)>) {
-        (self.packers, self.textures)
+    pub fn into_inner(self) -> (Vec<Packer>, Vec<(AtlasRef, Box<[u8]>)>, HashMap<i32, Vec<(AtlasRef, Box<[u8]>)>>) {
+        (self.packers, self.textures, self.deferred)
+    }
+}
+
+/// Textures packed with `AtlasBuilder::deferred_texture` which haven't been uploaded yet, in their groups.
+/// They're looked up by any one of their AtlasRefs, since fonts with identical glyphs share them, so the font being
+/// drawn isn't necessarily the one whose group they were packed into.
+#[derive(Default)]
+pub struct DeferredTextures {
+    groups: HashMap<i32, Vec<(AtlasRef, Box<[u8]>)>>,
+    group_of: HashMap<i32, i32>,
+}
+
+impl DeferredTextures {
+    pub fn new(groups: HashMap<i32, Vec<(AtlasRef, Box<[u8]>)>>) -> Self {
+        let group_of = groups
+            .iter()
+            .flat_map(|(&group, textures)| textures.iter().map(move |(atlas_ref, _)| (atlas_ref.sprite_id, group)))
+            .collect();
+        Self { groups, group_of }
+    }
+
+    pub fn contains(&self, atlas_ref: &AtlasRef) -> bool {
+        self.group_of.contains_key(&atlas_ref.sprite_id)
+    }
+
+    /// Takes every texture in the same group as the given one, if they're still waiting, so they can be uploaded.
+    pub fn take_group(&mut self, atlas_ref: &AtlasRef) -> Option<Vec<(AtlasRef, Box<[u8]>)>> {
+        let group = self.group_of.get(&atlas_ref.sprite_id)?;
+        let textures = self.groups.remove(group)?;
+        for (atlas_ref, _) in &textures {
+            self.group_of.remove(&atlas_ref.sprite_id);
+        }
+        Some(textures)
+    }
+
+    /// Drops the pixels of a texture which is being deleted before it was ever uploaded.
+    pub fn remove(&mut self, atlas_ref: &AtlasRef) {
+        if let Some(group) = self.group_of.remove(&atlas_ref.sprite_id) {
+            if let Some(textures) = self.groups.get_mut(&group) {
+                textures.retain(|(r, _)| r.sprite_id != atlas_ref.sprite_id);
+                if textures.is_empty() {
+                    self.groups.remove(&group);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Glyph-like boxes of varying heights, as a font covering a large character range would have.
+    fn glyph_sizes(count: usize) -> Vec<(i32, i32)> {
+        let mut seed = 12345u32;
+        (0..count)
+            .map(|_| {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                let n = (seed >> 16) as i32;
+                (6 + n % 11, 4 + (n / 11) % 17)
+            })
+            .collect()
+    }
+
+    fn pack_all(strategy: PackStrategy, max_size: i32, sizes: &[(i32, i32)]) -> AtlasBuilder {
+        let mut atlases = AtlasBuilder::with_strategy(max_size, strategy);
+        for &(w, h) in sizes {
+            atlases.texture(w, h, 0, 0, vec![0; (w * h * 4) as usize].into_boxed_slice()).unwrap();
+        }
+        atlases
+    }
+
+    fn overlaps(a: &AtlasRef, b: &AtlasRef) -> bool {
+        a.atlas_id == b.atlas_id && a.x < b.x + b.w && b.x < a.x + a.w && a.y < b.y + b.h && b.y < a.y + a.h
+    }
+
+    #[test]
+    fn skyline_fits_more() {
+        // one tall texture per row leaves the rest of the row mostly empty unless the gaps are filled
+        let mut sizes = Vec::new();
+        for _ in 0..4 {
+            sizes.push((8, 16));
+            sizes.extend(std::iter::repeat((8, 4)).take(28));
+        }
+        let dense = pack_all(PackStrategy::Dense, 64, &sizes);
+        let skyline = pack_all(PackStrategy::Skyline, 64, &sizes);
+        assert!(dense.atlas_count() > 1);
+        assert_eq!(skyline.atlas_count(), 1);
+        assert_eq!(skyline.occupancy(), vec![1.0]);
+    }
+
+    #[test]
+    fn occupancy() {
+        let sizes = glyph_sizes(4000);
+        for &(strategy, expected) in &[(PackStrategy::Shelf, 0.65), (PackStrategy::Skyline, 0.85)] {
+            let atlases = pack_all(strategy, 256, &sizes);
+            let occupancy = atlases.occupancy();
+            let (_, textures, _) = atlases.into_inner();
+            for (i, (a, _)) in textures.iter().enumerate() {
+                assert!(a.fits_in(256, 256), "{:?} packed out of bounds with {:?}", a, strategy);
+                assert!(!textures[..i].iter().any(|(b, _)| overlaps(a, b)), "{:?} overlaps with {:?}", a, strategy);
+            }
+            // every atlas but the last one should be well used
+            assert!(occupancy[..occupancy.len() - 1].iter().all(|&o| o > expected), "{:?}: {:?}", strategy, occupancy);
+        }
+
+        let dense = pack_all(PackStrategy::Dense, 256, &sizes).atlas_count();
+        let skyline = pack_all(PackStrategy::Skyline, 256, &sizes).atlas_count();
+        assert!(skyline < dense, "skyline packing took {} atlases and dense packing took {}", skyline, dense);
+    }
+
+    #[test]
+    fn sheet() {
+        let mut sizes = glyph_sizes(200);
+        sizes[3] = (0, 12);
+        let ((width, height), positions) = sheet_layout(&sizes, 1024).unwrap();
+        let refs = sizes
+            .iter()
+            .zip(&positions)
+            .map(|(&(w, h), &(x, y))| AtlasRef { atlas_id: 0, sprite_id: 0, x, y, w, h, origin_x: 0.0, origin_y: 0.0 })
+            .collect::<Vec<_>>();
+        for (i, a) in refs.iter().enumerate() {
+            assert!(a.fits_in(width, height), "{:?} is outside the {}x{} sheet", a, width, height);
+            assert!(!refs[..i].iter().any(|b| overlaps(a, b)), "{:?} overlaps another texture", a);
+        }
+        assert_eq!(positions[3], (0, 0));
+        assert!(width <= 256 && height <= 256, "sheet grew to {}x{}", width, height);
+
+        assert!(sheet_layout(&[(40, 40), (40, 40)], 64).is_none());
+        assert_eq!(sheet_layout(&[], 1024).unwrap().1, vec![]);
+    }
+
+    #[test]
+    fn reserve() {
+        let sizes = glyph_sizes(4000);
+        let total = sizes.iter().fold((0, 0), |(w, h), size| (w + size.0, h + size.1));
+        let average = (total.0 / sizes.len() as i32, total.1 / sizes.len() as i32);
+        let texture = |atlases: &mut AtlasBuilder, (w, h): (i32, i32)| {
+            atlases.texture(w, h, 0, 0, vec![0; (w * h * 4) as usize].into_boxed_slice()).unwrap()
+        };
+
+        // every atlas the rest of the font needs is there before it's packed
+        let mut atlases = AtlasBuilder::with_strategy(256, PackStrategy::Shelf);
+        texture(&mut atlases, sizes[0]);
+        atlases.reserve(sizes.len() - 1, average);
+        let reserved = atlases.atlas_count();
+        for &size in &sizes[1..] {
+            texture(&mut atlases, size);
+            assert_eq!(atlases.atlas_count(), reserved);
+        }
+        let unreserved = pack_all(PackStrategy::Shelf, 256, &sizes).atlas_count();
+        assert!(reserved >= unreserved && reserved <= unreserved + 1, "{} atlases for {}", reserved, unreserved);
+
+        // an atlas which can get bigger is grown now instead
+        let mut atlases = AtlasBuilder::new(8192);
+        texture(&mut atlases, (16, 16));
+        atlases.reserve(3000, (64, 64));
+        assert_eq!(atlases.atlas_count(), 1);
+        assert_eq!(atlases.packers[0].size(), (8192, 4096));
+        atlases.reserve(0, (64, 64));
+        assert_eq!(atlases.atlas_count(), 1);
+    }
+
+    #[test]
+    fn covered_pages() {
+        let mut atlases = AtlasBuilder::new(256);
+        let font = crate::asset::font::load_default_font(&mut atlases).unwrap();
+        // leaves the last pages with nothing in them
+        atlases.reserve(100, (64, 64));
+        let count = atlases.atlas_count();
+        let (packers, textures, deferred) = atlases.into_inner();
+        let covered = covered_area(textures.iter().chain(deferred.values().flatten()).map(|(r, _)| r), count);
+        let occupancy = |page: usize| {
+            let (width, height) = packers[page].size();
+            covered[page] as f32 / (width * height) as f32
+        };
+        assert!(occupancy(count - 1) < 0.001);
+        let font_page = font.chars[0].atlas_ref.atlas_id as usize;
+        assert!(occupancy(font_page) > 0.05 && occupancy(font_page) < 1.0, "{}", occupancy(font_page));
+    }
+
+    #[test]
+    fn deferred_textures() {
+        let mut atlases = AtlasBuilder::new(256);
+        let eager = atlases.texture(8, 8, 0, 0, vec![0; 8 * 8 * 4].into_boxed_slice()).unwrap();
+        let glyphs = (0..10)
+            .map(|_| atlases.deferred_texture(3, 6, 9, 0, 0, vec![0; 6 * 9 * 4].into_boxed_slice()).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(atlases.deferred_texture(3, 0, 9, 0, 0, Box::new([])).unwrap().sprite_id, -1);
+        assert_eq!(atlases.sprite_count(), 11);
+        assert!(atlases.occupancy()[0] > 0.0);
+
+        // a font that's never drawn never has its glyphs uploaded
+        let (_, textures, deferred) = atlases.into_inner();
+        assert_eq!(textures.iter().map(|(r, _)| *r).collect::<Vec<_>>(), vec![eager]);
+        assert_eq!(deferred.len(), 1);
+        assert_eq!(deferred[&3].iter().map(|(r, _)| *r).collect::<Vec<_>>(), glyphs);
+        for (i, a) in glyphs.iter().enumerate() {
+            assert!(!overlaps(a, &eager) && !glyphs[..i].iter().any(|b| overlaps(a, b)));
+        }
+    }
+
+    #[test]
+    fn shared_deferred_group() {
+        let mut atlases = AtlasBuilder::new(256);
+        let first = (0..4)
+            .map(|i| atlases.deferred_glyph(0, 6, 9, vec![i; 6 * 9 * 4].into_boxed_slice()).unwrap())
+            .collect::<Vec<_>>();
+        let other = atlases.deferred_glyph(1, 6, 9, vec![9; 6 * 9 * 4].into_boxed_slice()).unwrap();
+        let mut deferred = DeferredTextures::new(atlases.into_inner().2);
+
+        // a second font with the same glyphs holds the first font's refs, and drawing only it uploads them
+        let duplicate = first.clone();
+        assert!(duplicate.iter().all(|r| deferred.contains(r)));
+        let uploaded = deferred.take_group(&duplicate[2]).unwrap();
+        assert_eq!(uploaded.iter().map(|(r, _)| *r).collect::<Vec<_>>(), first);
+        assert_eq!(uploaded[3].1[0], 3);
+        assert!(!first.iter().any(|r| deferred.contains(r)));
+        assert!(deferred.take_group(&first[0]).is_none());
+
+        // deleting a font's glyphs before they're drawn drops their pixels
+        assert!(deferred.contains(&other));
+        deferred.remove(&other);
+        assert!(!deferred.contains(&other) && deferred.take_group(&other).is_none());
+    }
+
+    #[test]
+    fn batched_readback() {
+        let mut atlases = AtlasBuilder::new(256);
+        let mut refs = (0..32)
+            .map(|_| atlases.texture(8, 12, 0, 0, vec![0; 8 * 12 * 4].into_boxed_slice()).unwrap())
+            .collect::<Vec<_>>();
+        refs.push(AtlasRef { atlas_id: 5, w: 4, h: 4, ..refs[0] });
+        refs.push(AtlasRef { w: 0, ..refs[0] });
+
+        let regions = readback_regions(&refs);
+        assert_eq!(regions.len(), 2);
+        assert_eq!(regions[0].1, (0..32).collect::<Vec<_>>());
+        assert_eq!(regions[1].1, vec![32]);
+
+        // fake a readback of each region where every pixel holds its position in the atlas
+        let pixels = |r: &AtlasRef| {
+            let (xs, ys) = (r.x..r.x + r.w, r.y..r.y + r.h);
+            ys.flat_map(|y| xs.clone().flat_map(move |x| vec![x as u8, y as u8, r.atlas_id as u8, 0xFF]))
+                .collect::<Vec<_>>()
+        };
+        for (region, indices) in &regions {
+            let data = pixels(region);
+            for &i in indices {
+                assert_eq!(*crop_region(region, &data, &refs[i]), *pixels(&refs[i]));
+            }
+        }
+
+        // far apart textures are read separately rather than reading everything between them
+        let spread = [refs[0], AtlasRef { x: 200, y: 200, ..refs[0] }];
+        assert_eq!(readback_regions(&spread).len(), 2);
+    }
+
+    #[test]
+    fn overlapping_regions_flagged() {
+        let region = |atlas_id, x, y, w, h| AtlasRef { atlas_id, x, y, w, h, ..Default::default() };
+        let refs = [
+            region(0, 0, 0, 8, 8),
+            region(0, 8, 0, 8, 8),   // touches the first but doesn't overlap it
+            region(0, 4, 4, 8, 8),   // overlaps both
+            region(1, 4, 4, 8, 8),   // in another atlas
+            region(0, 0, 0, 8, 8),   // the same texture as the first
+            region(0, 30, 30, 2, 2), // nowhere near anything
+            region(0, 31, 31, 0, 0), // empty
+        ];
+        assert_eq!(overlapping_regions(&refs), vec![(0, 2), (1, 2), (2, 4)]);
+        assert!(overlapping_regions(&refs[..2]).is_empty());
+
+        // a properly packed atlas has no overlaps
+        let atlases = pack_all(PackStrategy::Skyline, 1024, &glyph_sizes(500));
+        let packed = atlases.textures.iter().map(|(atlas_ref, _)| *atlas_ref).collect::<Vec<_>>();
+        assert!(overlapping_regions(&packed).is_empty());
+
+        // force two textures to overlap and check only they are tinted
+        let mut atlases = pack_all(PackStrategy::Shelf, 64, &[(4, 4), (4, 4), (4, 4)]);
+        for (_, data) in atlases.textures.iter_mut() {
+            *data = vec![0xFF; data.len()].into_boxed_slice();
+        }
+        atlases.textures[1].0.x = atlases.textures[0].0.x + 2;
+        atlases.textures[1].0.y = atlases.textures[0].0.y;
+        let (width, _, image) = atlases.debug_image(0, true).unwrap();
+        let pixel = |x: i32, y: i32| {
+            let i = ((y * width + x) * 4) as usize;
+            [image[i], image[i + 1], image[i + 2], image[i + 3]]
+        };
+        let [a, b, c] = [atlases.textures[0].0, atlases.textures[1].0, atlases.textures[2].0];
+        assert_eq!(pixel(a.x, a.y), [0xFF, 0x7F, 0x7F, 0xFF]);
+        assert_eq!(pixel(b.x + 3, b.y), [0xFF, 0x7F, 0x7F, 0xFF]);
+        assert_eq!(pixel(c.x, c.y), [0xFF; 4]);
+        let (_, _, untinted) = atlases.debug_image(0, false).unwrap();
+        assert!(untinted.iter().all(|&b| b == 0 || b == 0xFF));
+        assert!(atlases.debug_image(1, true).is_none());
+    }
+
+    #[test]
+    fn alpha_glyphs() {
+        // white glyphs with varying alpha, and one coloured glyph which can't be stored as just alpha
+        let glyph = |w: i32, h: i32, rgb: u8| {
+            (0..w * h).flat_map(|i| vec![rgb, 0xFF, 0xFF, (i * 37) as u8]).collect::<Vec<_>>().into_boxed_slice()
+        };
+        let sizes = [(5, 9), (7, 11), (3, 4), (8, 8)];
+        let pack = |alpha_glyphs| {
+            let mut atlases = AtlasBuilder::new(256);
+            atlases.set_alpha_glyphs(alpha_glyphs);
+            atlases.reserve_glyphs(sizes.len(), (8, 11));
+            let refs = sizes
+                .iter()
+                .enumerate()
+                .map(|(i, &(w, h))| atlases.deferred_glyph(0, w, h, glyph(w, h, if i == 3 { 0x80 } else { 0xFF })))
+                .collect::<Option<Vec<_>>>()
+                .unwrap();
+            (atlases, refs)
+        };
+        let (rgba, rgba_refs) = pack(false);
+        let (alpha, alpha_refs) = pack(true);
+        assert_eq!(rgba.alpha_atlases(), [false]);
+        assert_eq!(alpha.alpha_atlases(), [true, false]);
+        assert!(alpha_refs[..3].iter().all(|r| r.atlas_id == 0) && alpha_refs[3].atlas_id == 1);
+
+        // a quarter of the data, which the renderer expands to the same pixels as the RGBA atlas holds
+        let data = |atlases: &AtlasBuilder| atlases.deferred[&0].iter().map(|(_, d)| d.clone()).collect::<Vec<_>>();
+        let (rgba_data, alpha_data) = (data(&rgba), data(&alpha));
+        for i in 0..3 {
+            assert_eq!(alpha_data[i].len() * 4, rgba_data[i].len());
+            assert_eq!(expand_alpha(&alpha_data[i]), rgba_data[i]);
+        }
+        assert_eq!(alpha_data[3], rgba_data[3]);
+        assert!(alpha_plane(&rgba_data[3]).is_none());
+
+        // and each glyph looks the same in its atlas either way
+        let glyph_pixels = |atlases: &AtlasBuilder, r: &AtlasRef| {
+            let (width, _, image) = atlases.debug_image(r.atlas_id, false).unwrap();
+            (r.y..r.y + r.h)
+                .flat_map(|y| image[((y * width + r.x) * 4) as usize..((y * width + r.x + r.w) * 4) as usize].to_vec())
+                .collect::<Vec<_>>()
+        };
+        for (a, b) in rgba_refs.iter().zip(&alpha_refs) {
+            assert_eq!(glyph_pixels(&rgba, a), glyph_pixels(&alpha, b));
+        }
     }
 }
@@ -1,6 +1,6 @@
 use crate::{
     render::{
-        atlas::{AtlasBuilder, AtlasRef},
+        atlas::{self, AtlasBuilder, AtlasRef, DeferredTextures, Packer},
         mat4mult, BlendType, Fog, Light, PrimitiveBuilder, PrimitiveShape, PrimitiveType, RendererOptions,
         RendererTrait, SavedTexture, Scaling, Vertex, VertexBuffer,
     },
@@ -8,7 +8,6 @@ use crate::{
 };
 use memoffset::offset_of;
 use ramen::window::Window;
-use rect_packer::DensePacker;
 use std::{any::Any, collections::HashMap, f64::consts::PI, ffi::CStr, mem::size_of, ptr};
 
 /// Auto-generated OpenGL bindings from gl_generator
@@ -88,6 +87,7 @@ struct RenderState {
     blend_mode: (BlendType, BlendType),
     write_depth: bool,
     culling: bool,
+    mipmaps: bool,
 }
 
 impl Default for RenderState {
@@ -111,6 +111,7 @@ impl Default for RenderState {
             depth_test: false.into(),
             write_depth: false,
             culling: false,
+            mipmaps: false,
             fog_enabled: GLBool::False,
             fog_begin: 0.0,
             fog_end: 0.0,
@@ -162,13 +163,17 @@ pub struct RendererImpl {
     gl: gl::Gl,
     //program: GLuint,
     //vao: GLuint,
-    atlas_packers: Vec<DensePacker>,
+    atlas_packers: Vec<Packer>,
     texture_ids: Vec<Option<GLuint>>,
     zbuf_ids: Vec<Option<GLuint>>,
     fbo_ids: Vec<Option<GLuint>>,
     sprites: HashMap<i32, AtlasRef>,
     sprite_count: i32,
     stock_atlas_count: u32,
+    mipmapped_atlases: Vec<bool>,
+    alpha_atlases: Vec<bool>,
+    atlas_coverage: Vec<i64>, // area of each stock atlas covered by textures which haven't been deleted
+    deferred_textures: DeferredTextures,
     current_atlas: u32,
     framebuffer: Framebuffer,
     stored_framebuffer: Option<Framebuffer>,
@@ -248,6 +253,18 @@ fn make_view_matrix(x: f64, y: f64, z: f64, w: f64, h: f64, angle: f64) -> [f32;
     view_matrix
 }
 
+/// Gets the minification and magnification filters for drawing from a texture.
+/// Mipmaps only change the minification filter, so anything drawn at 1:1 or larger samples level 0 exactly as usual.
+fn texture_filters(interpolate_pixels: bool, mipmaps: bool) -> (GLenum, GLenum) {
+    let mag_filter = if interpolate_pixels { gl::LINEAR } else { gl::NEAREST };
+    (if mipmaps { gl::LINEAR_MIPMAP_LINEAR } else { mag_filter }, mag_filter)
+}
+
+/// Gets the format of the pixel data of an atlas, which is just alpha for an alpha-only one.
+fn pixel_format(alpha_only: bool) -> GLenum {
+    if alpha_only { gl::RED } else { gl::BGRA }
+}
+
 fn split_colour(rgb: i32, alpha: f64) -> [f32; 4] {
     [
         ((rgb & 0xFF) as f32) / 255.0,
@@ -376,6 +393,10 @@ impl ShapeBuilder {
 }
 
 impl RendererImpl {
+    fn is_alpha_atlas(&self, atlas_id: u32) -> bool {
+        self.alpha_atlases.get(atlas_id as usize).copied().unwrap_or(false)
+    }
+
     pub fn new(options: &RendererOptions, window: &Window, clear_colour: Colour) -> Result<Self, String> {
         unsafe {
             let imp = wgl::PlatformImpl::new(window)?;
@@ -485,6 +506,8 @@ impl RendererImpl {
 
             // Configure gl::ReadPixels() to align to 1 byte
             gl.PixelStorei(gl::PACK_ALIGNMENT, 1);
+            // and uploads too, as rows of alpha-only atlases are one byte per pixel
+            gl.PixelStorei(gl::UNPACK_ALIGNMENT, 1);
 
             // Create framebuffer
             let (mut framebuffer_texture, mut framebuffer_zbuf, mut framebuffer_fbo) = (0, 0, 0);
@@ -548,6 +571,10 @@ impl RendererImpl {
                 sprites: HashMap::new(),
                 sprite_count: 0,
                 stock_atlas_count: 0,
+                mipmapped_atlases: Vec::new(),
+                alpha_atlases: Vec::new(),
+                atlas_coverage: Vec::new(),
+                deferred_textures: Default::default(),
                 current_atlas: 0,
                 framebuffer: Framebuffer { texture: framebuffer_texture, zbuf: framebuffer_zbuf, fbo: framebuffer_fbo },
                 stored_framebuffer: None,
@@ -693,6 +720,22 @@ impl RendererImpl {
         self.vertex_queue.extend_from_slice(builder.get_vertices());
     }
 
+    /// Generates mipmaps for a stock atlas if it doesn't have them yet, and returns whether it has them.
+    /// Other textures can change after they're uploaded, which would leave their mipmaps stale, so they never do.
+    /// The texture must already be bound.
+    unsafe fn ensure_mipmaps(&mut self, atlas_id: u32) -> bool {
+        match self.mipmapped_atlases.get_mut(atlas_id as usize) {
+            Some(mipmapped) => {
+                if !*mipmapped {
+                    self.gl.GenerateMipmap(gl::TEXTURE_2D);
+                    *mipmapped = true;
+                }
+                true
+            },
+            None => false,
+        }
+    }
+
     fn draw_buffer(&mut self, atlas_id: u32, shape: PrimitiveShape, buffer: &[Vertex]) {
         if buffer.is_empty() {
             return
@@ -706,9 +749,10 @@ impl RendererImpl {
             }
 
             self.gl.BindTexture(gl::TEXTURE_2D, self.texture_ids[atlas_id as usize].unwrap());
-            let filter_mode = if self.queue_render_state.interpolate_pixels.into() { gl::LINEAR } else { gl::NEAREST };
-            self.gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, filter_mode as _);
-            self.gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, filter_mode as _);
+            let mipmaps = self.queue_render_state.mipmaps && self.ensure_mipmaps(atlas_id);
+            let (min_filter, mag_filter) = texture_filters(self.queue_render_state.interpolate_pixels.into(), mipmaps);
+            self.gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, min_filter as _);
+            self.gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, mag_filter as _);
 
             let mut commands_vbo: GLuint = 0;
             self.gl.GenBuffers(1, &mut commands_vbo);
@@ -807,7 +851,9 @@ impl RendererTrait for RendererImpl {
         self.reset_primitive_2d(PrimitiveType::PointList, None);
         self.reset_primitive_3d(PrimitiveType::PointList, None);
 
-        let (packers, sprites) = atl.into_inner();
+        let sprite_count = atl.sprite_count();
+        let alpha_atlases = atl.alpha_atlases().to_vec();
+        let (packers, sprites, deferred) = atl.into_inner();
 
         unsafe {
             let textures: Vec<GLuint> = {
@@ -823,16 +869,24 @@ impl RendererTrait for RendererImpl {
                     self.gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as _);
                     self.gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::REPEAT as _);
                     self.gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::REPEAT as _);
+                    let internal_format = if alpha_atlases[i] {
+                        // sampled as white, with the one channel stored as alpha
+                        let swizzle = [gl::ONE as GLint, gl::ONE as _, gl::ONE as _, gl::RED as _];
+                        self.gl.TexParameteriv(gl::TEXTURE_2D, gl::TEXTURE_SWIZZLE_RGBA, swizzle.as_ptr());
+                        gl::R8
+                    } else {
+                        gl::RGBA
+                    };
                     self.gl.TexImage2D(
-                        gl::TEXTURE_2D,    // target
-                        0,                 // level
-                        gl::RGBA as _,     // internalformat
-                        width as _,        // width
-                        height as _,       // height
-                        0,                 // border ("must be 0")
-                        gl::BGRA,          // format
-                        gl::UNSIGNED_BYTE, // type
-                        ptr::null(),       // data
+                        gl::TEXTURE_2D,                 // target
+                        0,                              // level
+                        internal_format as _,           // internalformat
+                        width as _,                     // width
+                        height as _,                    // height
+                        0,                              // border ("must be 0")
+                        pixel_format(alpha_atlases[i]), // format
+                        gl::UNSIGNED_BYTE,              // type
+                        ptr::null(),                    // data
                     );
                 }
                 buf
@@ -851,6 +905,7 @@ impl RendererTrait for RendererImpl {
                     self.current_atlas = atl_ref.atlas_id;
                 }
 
+                let format = pixel_format(alpha_atlases[atl_ref.atlas_id as usize]);
                 self.gl.TexSubImage2D(
                     gl::TEXTURE_2D,       // target
                     0,                    // level
@@ -858,13 +913,13 @@ impl RendererTrait for RendererImpl {
                     atl_ref.y as _,       // yoffset
                     atl_ref.w as _,       // width
                     atl_ref.h as _,       // height
-                    gl::BGRA,             // format
+                    format,               // format
                     gl::UNSIGNED_BYTE,    // type
                     pixels.as_ptr() as _, // pixels
                 );
-
-                self.sprite_count += 1;
             }
+            // deferred textures still take up their sprite IDs
+            self.sprite_count += sprite_count;
 
             // verify it actually worked
             match self.gl.GetError() {
@@ -894,10 +949,16 @@ impl RendererTrait for RendererImpl {
             self.zbuf_ids.resize(self.texture_ids.len(), None);
             self.fbo_ids = fbo_ids;
             self.stock_atlas_count = textures.len() as u32;
+            self.mipmapped_atlases = vec![false; textures.len()];
+            self.alpha_atlases = alpha_atlases;
         }
 
-        // store packers, discard pixeldata
+        let textures = sprites.iter().chain(deferred.values().flatten());
+        self.atlas_coverage = atlas::covered_area(textures.map(|(atlas_ref, _)| atlas_ref), packers.len());
+
+        // store packers, discard pixeldata except what's been deferred
         self.atlas_packers = packers;
+        self.deferred_textures = DeferredTextures::new(deferred);
 
         Ok(())
     }
@@ -947,7 +1008,60 @@ impl RendererTrait for RendererImpl {
         Ok(atlas_ref)
     }
 
+    fn upload_deferred(&mut self, atlas_ref: &AtlasRef) -> Result<(), String> {
+        let textures = match self.deferred_textures.take_group(atlas_ref) {
+            Some(textures) => textures,
+            None => return Ok(()),
+        };
+        unsafe {
+            // store previous
+            let mut prev_tex2d = 0;
+            self.gl.GetIntegerv(gl::TEXTURE_BINDING_2D, &mut prev_tex2d);
+
+            for (atl_ref, pixels) in &textures {
+                self.gl.BindTexture(gl::TEXTURE_2D, self.texture_ids[atl_ref.atlas_id as usize].unwrap());
+                let format = pixel_format(self.is_alpha_atlas(atl_ref.atlas_id));
+                self.gl.TexSubImage2D(
+                    gl::TEXTURE_2D,       // target
+                    0,                    // level
+                    atl_ref.x as _,       // xoffset
+                    atl_ref.y as _,       // yoffset
+                    atl_ref.w as _,       // width
+                    atl_ref.h as _,       // height
+                    format,               // format
+                    gl::UNSIGNED_BYTE,    // type
+                    pixels.as_ptr() as _, // pixels
+                );
+                // any mipmaps made before now don't have this texture in them
+                if let Some(mipmapped) = self.mipmapped_atlases.get_mut(atl_ref.atlas_id as usize) {
+                    *mipmapped = false;
+                }
+            }
+
+            // verify it actually worked
+            match self.gl.GetError() {
+                0 => (),
+                err => return Err(format!("Failed to upload textures to GPU! (OpenGL code {})", err)),
+            }
+
+            // cleanup
+            self.gl.BindTexture(gl::TEXTURE_2D, prev_tex2d as _);
+        }
+        Ok(())
+    }
+
+    fn is_deferred(&self, atlas_ref: &AtlasRef) -> bool {
+        self.deferred_textures.contains(atlas_ref)
+    }
+
     fn duplicate_sprite(&mut self, atlas_ref: &AtlasRef) -> Result<AtlasRef, String> {
+        if self.is_alpha_atlas(atlas_ref.atlas_id) {
+            // copying from the framebuffer would read the alpha as red, so go through RGBA instead
+            let origin_x = (atlas_ref.origin_x * atlas_ref.w as f32).round() as i32;
+            let origin_y = (atlas_ref.origin_y * atlas_ref.h as f32).round() as i32;
+            let data = self.dump_sprite(atlas_ref);
+            return self.upload_sprite(data, atlas_ref.w, atlas_ref.h, origin_x, origin_y)
+        }
         let new_sprite = self.create_surface(atlas_ref.w, atlas_ref.h, false)?;
         unsafe {
             // store previous
@@ -982,10 +1096,90 @@ impl RendererTrait for RendererImpl {
         Ok(new_sprite)
     }
 
+    fn update_sprite(&mut self, atlas_ref: &AtlasRef, data: Box<[u8]>) -> Result<(), String> {
+        if data.len() != (atlas_ref.w * atlas_ref.h * 4) as usize {
+            return Err(format!("Sprite data has wrong size for {}x{} sprite", atlas_ref.w, atlas_ref.h))
+        }
+        // anything already queued may still be sampling the old pixels
+        self.flush_queue();
+        let (data, format) = match self.is_alpha_atlas(atlas_ref.atlas_id) {
+            true => (data.chunks_exact(4).map(|p| p[3]).collect(), gl::RED),
+            false => (data, gl::RGBA),
+        };
+        unsafe {
+            // store previous
+            let mut prev_tex2d = 0;
+            self.gl.GetIntegerv(gl::TEXTURE_BINDING_2D, &mut prev_tex2d);
+
+            // upload texture
+            self.gl.BindTexture(
+                gl::TEXTURE_2D,
+                self.texture_ids[atlas_ref.atlas_id as usize].ok_or("Trying to update nonexistent sprite")?,
+            );
+            self.gl.TexSubImage2D(
+                gl::TEXTURE_2D,     // target
+                0,                  // level
+                atlas_ref.x as _,   // xoffset
+                atlas_ref.y as _,   // yoffset
+                atlas_ref.w as _,   // width
+                atlas_ref.h as _,   // height
+                format,             // format
+                gl::UNSIGNED_BYTE,  // type
+                data.as_ptr() as _, // pixels
+            );
+
+            // verify it actually worked
+            match self.gl.GetError() {
+                0 => (),
+                err => return Err(format!("Failed to update texture on GPU! (OpenGL code {})", err)),
+            }
+
+            // cleanup
+            self.gl.BindTexture(gl::TEXTURE_2D, prev_tex2d as _);
+            assert_eq!(self.gl.GetError(), 0);
+        }
+        Ok(())
+    }
+
+    fn atlas_size(&self, atlas_id: u32) -> Option<(i32, i32)> {
+        if let Some(packer) = self.atlas_packers.get(atlas_id as usize) {
+            return Some(packer.size())
+        }
+        let tex_id = (*self.texture_ids.get(atlas_id as usize)?)?;
+        unsafe {
+            let mut prev_tex2d = 0;
+            self.gl.GetIntegerv(gl::TEXTURE_BINDING_2D, &mut prev_tex2d);
+            let (mut width, mut height) = (0, 0);
+            self.gl.BindTexture(gl::TEXTURE_2D, tex_id);
+            self.gl.GetTexLevelParameteriv(gl::TEXTURE_2D, 0, gl::TEXTURE_WIDTH, &mut width);
+            self.gl.GetTexLevelParameteriv(gl::TEXTURE_2D, 0, gl::TEXTURE_HEIGHT, &mut height);
+            self.gl.BindTexture(gl::TEXTURE_2D, prev_tex2d as _);
+            Some((width, height))
+        }
+    }
+
+    fn atlas_occupancy(&self, atlas_id: u32) -> f32 {
+        match (self.atlas_packers.get(atlas_id as usize), self.atlas_coverage.get(atlas_id as usize)) {
+            (Some(packer), Some(&covered)) => {
+                let (width, height) = packer.size();
+                covered as f32 / (i64::from(width) * i64::from(height)) as f32
+            },
+            // every other atlas holds a single texture, which fills it until it's deleted
+            _ => match self.texture_ids.get(atlas_id as usize) {
+                Some(Some(_)) => 1.0,
+                _ => 0.0,
+            },
+        }
+    }
+
     fn delete_sprite(&mut self, atlas_ref: AtlasRef) {
         // this only deletes sprites created with upload_sprite
         self.flush_queue();
         self.sprites.remove(&atlas_ref.sprite_id);
+        self.deferred_textures.remove(&atlas_ref);
+        if let Some(covered) = self.atlas_coverage.get_mut(atlas_ref.atlas_id as usize) {
+            *covered = (*covered - i64::from(atlas_ref.w) * i64::from(atlas_ref.h)).max(0);
+        }
         if atlas_ref.atlas_id >= self.stock_atlas_count {
             let tex_id = self.texture_ids[atlas_ref.atlas_id as usize].unwrap();
             unsafe {
@@ -1395,7 +1589,8 @@ impl RendererTrait for RendererImpl {
             );
 
             // read data
-            let len = (atlas_ref.w * atlas_ref.h * 4) as usize;
+            let alpha = self.is_alpha_atlas(atlas_ref.atlas_id);
+            let len = (atlas_ref.w * atlas_ref.h * if alpha { 1 } else { 4 }) as usize;
             let mut data: Vec<u8> = Vec::with_capacity(len);
             data.set_len(len);
             self.gl.ReadPixels(
@@ -1403,7 +1598,7 @@ impl RendererTrait for RendererImpl {
                 atlas_ref.y,
                 atlas_ref.w,
                 atlas_ref.h,
-                gl::RGBA,
+                if alpha { gl::RED } else { gl::RGBA },
                 gl::UNSIGNED_BYTE,
                 data.as_mut_ptr().cast(),
             );
@@ -1414,7 +1609,7 @@ impl RendererTrait for RendererImpl {
             self.gl.BindFramebuffer(gl::READ_FRAMEBUFFER, prev_read_fbo as GLuint);
             assert_eq!(self.gl.GetError(), 0);
 
-            data.into_boxed_slice()
+            if alpha { atlas::expand_alpha(&data) } else { data.into_boxed_slice() }
         }
     }
 
@@ -2029,6 +2224,15 @@ impl RendererTrait for RendererImpl {
         self.render_state_updated = true;
     }
 
+    fn get_mipmapping(&self) -> bool {
+        self.next_render_state.mipmaps
+    }
+
+    fn set_mipmapping(&mut self, mipmaps: bool) {
+        self.next_render_state.mipmaps = mipmaps;
+        self.render_state_updated = true;
+    }
+
     fn get_texture_repeat(&self) -> bool {
         self.next_render_state.texture_repeat.into()
     }
@@ -2480,3 +2684,18 @@ impl RendererTrait for RendererImpl {
         self.setup_frame(clear_colour)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mipmap_filters() {
+        assert_eq!(texture_filters(false, false), (gl::NEAREST, gl::NEAREST));
+        assert_eq!(texture_filters(true, false), (gl::LINEAR, gl::LINEAR));
+        // minified text blends between mip levels, but with a NEAREST or LINEAR magnification filter GL switches to
+        // magnification at a level of detail of 0, so 1:1 text is still sampled from level 0 as before
+        assert_eq!(texture_filters(false, true), (gl::LINEAR_MIPMAP_LINEAR, gl::NEAREST));
+        assert_eq!(texture_filters(true, true), (gl::LINEAR_MIPMAP_LINEAR, gl::LINEAR));
+    }
+}
@@ -1435,6 +1435,18 @@ mod tests {
         assert_eq!(AST::expression(b"1").unwrap(), Expr::LiteralReal(1.0));
     }
 
+    #[test]
+    fn expression_literal_long_integer() {
+        // reals can't hold every integer this big, but it should be the closest one they can, and exactly that one
+        match AST::expression(b"1234567890123456789").unwrap() {
+            Expr::LiteralReal(x) => assert_eq!(x as i64, 1234567890123456768),
+            e => panic!("expected a real literal, got {:?}", e),
+        }
+        assert_eq!(AST::expression(b"9007199254740993").unwrap(), Expr::LiteralReal(9007199254740992.0));
+        assert_eq!(AST::expression(b"99999999999999999999").unwrap(), Expr::LiteralReal(1e20));
+        assert_eq!(AST::expression(b"12.5").unwrap(), Expr::LiteralReal(12.5));
+    }
+
     #[test]
     fn expression_literal_identifier() {
         // expression - literal identifier
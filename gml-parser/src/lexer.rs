@@ -142,7 +142,8 @@ impl<'a> Iterator for Lexer<'a> {
 
                 let mut result = 0.0f64;
                 let mut factor = 1.0f64;
-                while let Some(&(_, ch)) = self.iter.peek() {
+                let mut end = head.0;
+                while let Some(&(i, ch)) = self.iter.peek() {
                     match ch {
                         ch @ b'0'..=b'9' => {
                             let dec = ch - b'0';
@@ -154,10 +155,18 @@ impl<'a> Iterator for Lexer<'a> {
                         b'.' => point_seen = true,
                         _ => break,
                     }
+                    end = i + 1;
                     self.iter.next();
                 }
 
-                Token::Real(result * factor)
+                if point_seen {
+                    Token::Real(result * factor)
+                } else {
+                    // adding up digits rounds at every step once an integer's too big to be exact, so parse it
+                    // all at once to get the closest real instead
+                    let exact = str::from_utf8(sl(&self.src, head.0..end)).ok().and_then(|s| s.parse().ok());
+                    Token::Real(exact.unwrap_or(result))
+                }
             },
 
             // string literal